@@ -30,11 +30,18 @@ use self::chain_api::OutputHandler;
 use self::peers_api::PeerHandler;
 use self::peers_api::PeersAllHandler;
 use self::peers_api::PeersConnectedHandler;
+use self::pool_api::PoolConflictsHandler;
+use self::pool_api::PoolFeeEstimateHandler;
 use self::pool_api::PoolInfoHandler;
+use self::pool_api::PoolMineableHandler;
 use self::pool_api::PoolPushHandler;
+use self::pool_api::PoolPushPackageHandler;
+use self::pool_api::PoolTxStatusHandler;
+use self::pool_api::PoolTxsHandler;
 use self::server_api::IndexHandler;
 use self::server_api::KernelDownloadHandler;
 use self::server_api::StatusHandler;
+use self::server_api::SyncStatusHandler;
 use self::transactions_api::TxHashSetHandler;
 use self::version_api::VersionHandler;
 use crate::auth::{BasicAuthMiddleware, GRIN_BASIC_REALM};
@@ -43,6 +50,7 @@ use crate::p2p;
 use crate::pool;
 use crate::rest::*;
 use crate::router::{Router, RouterError};
+use crate::types::SyncProgressSource;
 use crate::util;
 use crate::util::RwLock;
 use std::net::SocketAddr;
@@ -61,11 +69,12 @@ pub fn start_rest_apis(
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: Arc<p2p::Peers>,
+	sync: Arc<dyn SyncProgressSource>,
 	api_secret: Option<String>,
 	tls_config: Option<TLSConfig>,
 ) -> bool {
 	let mut apis = ApiServer::new();
-	let mut router = build_router(chain, tx_pool, peers).expect("unable to build API router");
+	let mut router = build_router(chain, tx_pool, peers, sync).expect("unable to build API router");
 	if let Some(api_secret) = api_secret {
 		let api_basic_auth = format!("Basic {}", util::to_base64(&format!("grin:{}", api_secret)));
 		let basic_auth_middleware =
@@ -89,6 +98,7 @@ pub fn build_router(
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: Arc<p2p::Peers>,
+	sync: Arc<dyn SyncProgressSource>,
 ) -> Result<Router, RouterError> {
 	let route_list = vec![
 		"get blocks".to_string(),
@@ -99,6 +109,7 @@ pub fn build_router(
 		"get chain/outputs/byids?id=xxx,yyy,zzz".to_string(),
 		"get chain/outputs/byheight?start_height=101&end_height=200".to_string(),
 		"get status".to_string(),
+		"get status/sync".to_string(),
 		"get txhashset/roots".to_string(),
 		"get txhashset/lastoutputs?n=10".to_string(),
 		"get txhashset/lastrangeproofs".to_string(),
@@ -107,6 +118,14 @@ pub fn build_router(
 		"get txhashset/merkleproof?n=1".to_string(),
 		"get pool".to_string(),
 		"post pool/push_tx".to_string(),
+		"post pool/push_package".to_string(),
+		"get pool/fee_estimate?target_blocks=10".to_string(),
+		"get pool/txs".to_string(),
+		"get pool/txs?stem".to_string(),
+		"get pool/txs/xxx".to_string(),
+		"get pool/mineable".to_string(),
+		"get pool/tx/xxx/status".to_string(),
+		"get pool/conflicts".to_string(),
 		"post peers/a.b.c.d:p/ban".to_string(),
 		"post peers/a.b.c.d:p/unban".to_string(),
 		"get peers/all".to_string(),
@@ -139,6 +158,9 @@ pub fn build_router(
 		chain: Arc::downgrade(&chain),
 		peers: Arc::downgrade(&peers),
 	};
+	let sync_status_handler = SyncStatusHandler {
+		sync: Arc::downgrade(&sync),
+	};
 	let kernel_download_handler = KernelDownloadHandler {
 		peers: Arc::downgrade(&peers),
 	};
@@ -151,6 +173,24 @@ pub fn build_router(
 	let pool_push_handler = PoolPushHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_push_package_handler = PoolPushPackageHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_fee_estimate_handler = PoolFeeEstimateHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_txs_handler = PoolTxsHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_mineable_handler = PoolMineableHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_tx_status_handler = PoolTxStatusHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_conflicts_handler = PoolConflictsHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
 	let peers_all_handler = PeersAllHandler {
 		peers: Arc::downgrade(&peers),
 	};
@@ -175,9 +215,18 @@ pub fn build_router(
 	router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
 	router.add_route("/v1/txhashset/*", Arc::new(txhashset_handler))?;
 	router.add_route("/v1/status", Arc::new(status_handler))?;
+	router.add_route("/v1/status/sync", Arc::new(sync_status_handler))?;
 	router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
 	router.add_route("/v1/pool", Arc::new(pool_info_handler))?;
 	router.add_route("/v1/pool/push_tx", Arc::new(pool_push_handler))?;
+	router.add_route("/v1/pool/push_package", Arc::new(pool_push_package_handler))?;
+	router.add_route("/v1/pool/fee_estimate", Arc::new(pool_fee_estimate_handler))?;
+	let pool_txs_handler = Arc::new(pool_txs_handler);
+	router.add_route("/v1/pool/txs", pool_txs_handler.clone())?;
+	router.add_route("/v1/pool/txs/*", pool_txs_handler)?;
+	router.add_route("/v1/pool/mineable", Arc::new(pool_mineable_handler))?;
+	router.add_route("/v1/pool/tx/**", Arc::new(pool_tx_status_handler))?;
+	router.add_route("/v1/pool/conflicts", Arc::new(pool_conflicts_handler))?;
 	router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
 	router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
 	router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
@@ -58,14 +58,14 @@ impl Handler for PeerHandler {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
 		let command = right_path_element!(req);
 
-		// We support both "ip" and "ip:port" here for peer_addr.
+		// We support "ip", "ip:port" and "<onion host>:port" here for peer_addr.
 		// "ip:port" is only really useful for local usernet testing on loopback address.
 		// Normally we map peers to ip and only allow a single peer per ip address.
 		let peer_addr;
 		if let Ok(ip_addr) = command.parse() {
 			peer_addr = PeerAddr::from_ip(ip_addr);
 		} else if let Ok(addr) = command.parse() {
-			peer_addr = PeerAddr(addr);
+			peer_addr = addr;
 		} else {
 			return response(
 				StatusCode::BAD_REQUEST,
@@ -90,7 +90,7 @@ impl Handler for PeerHandler {
 				if let Ok(ip_addr) = a.parse() {
 					PeerAddr::from_ip(ip_addr)
 				} else if let Ok(addr) = a.parse() {
-					PeerAddr(addr)
+					addr
 				} else {
 					return response(
 						StatusCode::BAD_REQUEST,
@@ -18,15 +18,18 @@ use crate::core::core::Transaction;
 use crate::core::ser;
 use crate::pool;
 use crate::rest::*;
-use crate::router::{Handler, ResponseFuture};
+use crate::router::{Handler, RemoteAddr, ResponseFuture};
 use crate::types::*;
 use crate::util;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::RwLock;
 use crate::web::*;
 use failure::ResultExt;
 use futures::future::{err, ok};
 use futures::Future;
 use hyper::{Body, Request, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::slice;
 use std::sync::Weak;
 
 /// Get basic information about the transaction pool.
@@ -46,12 +49,263 @@ impl Handler for PoolInfoHandler {
 	}
 }
 
+// Printable entries of a pool, along with the entries of the other pools
+// they could depend on.
+fn printable_entries(
+	entries: &[pool::PoolEntry],
+	others: &[&pool::Pool],
+) -> Vec<PoolEntryPrintable> {
+	let mut excesses = HashMap::new();
+	for entry in others
+		.iter()
+		.flat_map(|x| x.entries.iter())
+		.chain(entries.iter())
+	{
+		if let Some(kernel) = entry.tx.kernels().first() {
+			let excess = util::to_hex(kernel.excess.0.to_vec());
+			for output in entry.tx.outputs() {
+				excesses.insert(output.commitment(), excess.clone());
+			}
+		}
+	}
+	entries
+		.iter()
+		.map(|entry| {
+			let depends_on = entry
+				.tx
+				.inputs()
+				.iter()
+				.filter_map(|x| excesses.get(&x.commitment()).cloned())
+				.collect::<HashSet<_>>()
+				.into_iter()
+				.collect();
+			PoolEntryPrintable::from_entry(entry, depends_on)
+		})
+		.collect()
+}
+
+/// List the entries of the transaction pool, or of the stempool with the
+/// `stem` query parameter, and get single transactions by kernel excess.
+/// GET /v1/pool/txs
+/// GET /v1/pool/txs?stem
+/// GET /v1/pool/txs/xxx
+pub struct PoolTxsHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolTxsHandler {
+	fn list(&self, stem: bool) -> Result<Vec<PoolEntryPrintable>, Error> {
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		if stem {
+			Ok(printable_entries(&pool.stempool.entries, &[&pool.txpool]))
+		} else {
+			Ok(printable_entries(&pool.txpool.entries, &[]))
+		}
+	}
+
+	fn get_tx(&self, excess: &str) -> Result<PoolTransaction, Error> {
+		let excess = util::from_hex(excess.to_owned())
+			.map_err(|_| ErrorKind::Argument(format!("Not a valid kernel excess: {}", excess)))?;
+		let excess = Commitment::from_vec(excess);
+
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		for (stem, inner) in &[(false, &pool.txpool), (true, &pool.stempool)] {
+			let kernel_hash = match inner
+				.entries
+				.iter()
+				.flat_map(|x| x.tx.kernels().iter())
+				.find(|x| x.excess == excess)
+			{
+				Some(kernel) => kernel.hash(),
+				None => continue,
+			};
+			let tx = inner
+				.retrieve_tx_by_kernel_hash(kernel_hash)
+				.ok_or(ErrorKind::NotFound)?;
+			let entry = inner
+				.entries
+				.iter()
+				.find(|x| x.tx == tx)
+				.ok_or(ErrorKind::NotFound)?;
+			// stem txs may depend on txpool ones
+			let others: &[&pool::Pool] = if *stem { &[&pool.txpool] } else { &[] };
+			let tx_hex = util::to_hex(
+				ser::ser_vec(&tx)
+					.context(ErrorKind::Internal("Failed to serialize tx".to_owned()))?,
+			);
+			return Ok(PoolTransaction {
+				entry: printable_entries(slice::from_ref(entry), others).remove(0),
+				stem: *stem,
+				tx_hex,
+			});
+		}
+		Err(ErrorKind::NotFound.into())
+	}
+}
+
+impl Handler for PoolTxsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		match right_path_element!(req) {
+			"txs" => {
+				let params = QueryParams::from(req.uri().query());
+				result_to_response(self.list(params.get("stem").is_some()))
+			}
+			excess => result_to_response(self.get_tx(excess)),
+		}
+	}
+}
+
+/// Lifecycle status of a transaction accepted by our pool, by kernel excess:
+/// in the stempool, fluffed, in our candidate block, confirmed, evicted,
+/// conflicted or reorged out.
+/// GET /v1/pool/tx/xxx/status
+pub struct PoolTxStatusHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolTxStatusHandler {
+	fn get_status(&self, excess: &str) -> Result<TxStatusPrintable, Error> {
+		let commit = util::from_hex(excess.to_owned())
+			.map_err(|_| ErrorKind::Argument(format!("Not a valid kernel excess: {}", excess)))?;
+		let commit = Commitment::from_vec(commit);
+
+		let pool_arc = w(&self.tx_pool)?;
+		let tracked = pool_arc
+			.read()
+			.tx_tracker
+			.status(&commit)
+			.ok_or(ErrorKind::NotFound)?;
+		Ok(TxStatusPrintable {
+			excess: excess.to_owned(),
+			status: tracked.status,
+			updated_at: tracked.updated_at.timestamp(),
+		})
+	}
+}
+
+impl Handler for PoolTxStatusHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let mut path_elems = req.uri().path().trim_end_matches('/').rsplit('/');
+		match (path_elems.next(), path_elems.next()) {
+			(Some("status"), Some(excess)) => result_to_response(self.get_status(excess)),
+			_ => response(StatusCode::BAD_REQUEST, "invalid url"),
+		}
+	}
+}
+
+/// Recent transactions received spending the same outputs as transactions in
+/// our pool, oldest first, with whether they replaced them or got rejected.
+/// GET /v1/pool/conflicts
+pub struct PoolConflictsHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolConflictsHandler {
+	fn get_conflicts(&self) -> Result<Vec<PoolConflictPrintable>, Error> {
+		let pool_arc = w(&self.tx_pool)?;
+		let conflicts = pool_arc.read().conflicts.all();
+		Ok(conflicts
+			.iter()
+			.map(PoolConflictPrintable::from_conflict)
+			.collect())
+	}
+}
+
+impl Handler for PoolConflictsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_conflicts())
+	}
+}
+
+/// Transactions of the pool that would currently get selected to build a
+/// block, in the order they would be included.
+/// GET /v1/pool/mineable
+pub struct PoolMineableHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolMineableHandler {
+	fn get_mineable(&self) -> Result<Vec<PoolEntryPrintable>, Error> {
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		let txs = pool
+			.txpool
+			.prepare_mineable_transactions(pool.config.mineable_max_weight)
+			.context(ErrorKind::Internal("Failed to select txs".to_owned()))?;
+		let by_hash = pool
+			.txpool
+			.entries
+			.iter()
+			.map(|x| (x.tx.hash(), x))
+			.collect::<HashMap<_, _>>();
+		let entries = txs
+			.iter()
+			.filter_map(|x| by_hash.get(&x.hash()).map(|x| (*x).clone()))
+			.collect::<Vec<_>>();
+		Ok(printable_entries(&entries, &[&pool.txpool]))
+	}
+}
+
+impl Handler for PoolMineableHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_mineable())
+	}
+}
+
+/// Recommended fee to get a transaction confirmed within a number of blocks,
+/// based on how long the transactions of our pool waited to be confirmed.
+/// GET /v1/pool/fee_estimate?target_blocks=N
+pub struct PoolFeeEstimateHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolFeeEstimateHandler {
+	fn estimate(&self, req: Request<Body>) -> Result<FeeEstimate, Error> {
+		let params = QueryParams::from(req.uri().query());
+		let target_blocks: u64 = parse_param!(params, "target_blocks", 1);
+		if target_blocks == 0 || target_blocks > pool::MAX_FEE_ESTIMATE_TARGET {
+			return Err(ErrorKind::RequestError(format!(
+				"target_blocks must be between 1 and {}",
+				pool::MAX_FEE_ESTIMATE_TARGET
+			))
+			.into());
+		}
+		let pool_arc = w(&self.tx_pool)?;
+		let fee_base = pool_arc.read().estimate_fee_base(target_blocks);
+		Ok(FeeEstimate {
+			target_blocks,
+			fee_base,
+		})
+	}
+}
+
+impl Handler for PoolFeeEstimateHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.estimate(req))
+	}
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
 struct TxWrapper {
 	tx_hex: String,
 }
 
+// Source of the txs pushed through the API, rate limited per client address
+// rather than all API clients sharing the same limits.
+fn tx_source(req: &Request<Body>) -> pool::TxSource {
+	let identifier = match req.extensions().get::<RemoteAddr>() {
+		Some(RemoteAddr(addr)) => addr.ip().to_string(),
+		None => "push-api".to_string(),
+	};
+	pool::TxSource {
+		debug_name: "push-api".to_string(),
+		identifier,
+	}
+}
+
 /// Push new transaction to our local transaction pool.
 /// POST /v1/pool/push_tx
 pub struct PoolPushHandler {
@@ -69,6 +323,7 @@ impl PoolPushHandler {
 			Err(e) => return Box::new(err(e)),
 		};
 
+		let source = tx_source(&req);
 		Box::new(
 			parse_body(req)
 				.and_then(move |wrapper: TxWrapper| {
@@ -80,10 +335,6 @@ impl PoolPushHandler {
 						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
 				})
 				.and_then(move |tx: Transaction| {
-					let source = pool::TxSource {
-						debug_name: "push-api".to_string(),
-						identifier: "?.?.?.?".to_string(),
-					};
 					info!(
 						"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
 						tx.hash(),
@@ -121,3 +372,83 @@ impl Handler for PoolPushHandler {
 		)
 	}
 }
+
+/// Dummy wrapper for an ordered list of hex-encoded serialized transactions.
+#[derive(Serialize, Deserialize)]
+struct TxPackageWrapper {
+	txs_hex: Vec<String>,
+}
+
+/// Push an ordered package of transactions, later ones possibly spending the
+/// outputs of earlier ones, to our local transaction pool. Either all of them
+/// get accepted or none, optionally aggregated into a single transaction.
+/// POST /v1/pool/push_package
+/// POST /v1/pool/push_package?fluff&aggregate
+pub struct PoolPushPackageHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl PoolPushPackageHandler {
+	fn update_pool(&self, req: Request<Body>) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+		let params = QueryParams::from(req.uri().query());
+
+		let fluff = params.get("fluff").is_some();
+		let aggregate = params.get("aggregate").is_some();
+		let pool_arc = match w(&self.tx_pool) {
+			Ok(p) => p,
+			Err(e) => return Box::new(err(e)),
+		};
+
+		let source = tx_source(&req);
+		Box::new(
+			parse_body(req)
+				.and_then(move |wrapper: TxPackageWrapper| {
+					wrapper
+						.txs_hex
+						.into_iter()
+						.map(|tx_hex| {
+							let tx_bin = util::from_hex(tx_hex).map_err(|e| {
+								ErrorKind::RequestError(format!("Bad request: {}", e))
+							})?;
+							ser::deserialize(&mut &tx_bin[..]).map_err(|e| {
+								ErrorKind::RequestError(format!("Bad request: {}", e)).into()
+							})
+						})
+						.collect::<Result<Vec<Transaction>, Error>>()
+				})
+				.and_then(move |txs| {
+					info!(
+						"Pushing package of {} transactions to pool (aggregate: {})",
+						txs.len(),
+						aggregate,
+					);
+
+					//  Push to tx pool.
+					let mut tx_pool = pool_arc.write();
+					let header = tx_pool
+						.blockchain
+						.chain_head()
+						.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+					let res = tx_pool
+						.add_package_to_pool(source, txs, aggregate, !fluff, &header)
+						.context(ErrorKind::Internal("Failed to update pool".to_owned()))?;
+					Ok(res)
+				}),
+		)
+	}
+}
+
+impl Handler for PoolPushPackageHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		Box::new(
+			self.update_pool(req)
+				.and_then(|_| ok(just_response(StatusCode::OK, "")))
+				.or_else(|e| {
+					ok(just_response(
+						StatusCode::INTERNAL_SERVER_ERROR,
+						format!("failed: {}", e),
+					))
+				}),
+		)
+	}
+}
@@ -83,3 +83,15 @@ impl Handler for StatusHandler {
 		result_to_response(self.get_status())
 	}
 }
+
+/// Sync progress handler, with rates and ETA of each sync stage
+/// GET /v1/status/sync
+pub struct SyncStatusHandler {
+	pub sync: Weak<dyn SyncProgressSource>,
+}
+
+impl Handler for SyncStatusHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response(&w_fut!(&self.sync).sync_progress())
+	}
+}
@@ -22,18 +22,21 @@ use crate::router::{Handler, HandlerObj, ResponseFuture, Router};
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::sync::oneshot;
-use futures::Stream;
+use futures::{future, Stream};
 use hyper::rt::Future;
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
 use hyper::{rt, Body, Request, Server, StatusCode};
 use rustls;
 use rustls::internal::pemfile;
+use rustls::ServerSession;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::{io, thread};
-use tokio_rustls::ServerConfigExt;
-use tokio_tcp;
+use tokio_rustls::{ServerConfigExt, TlsStream};
+use tokio_tcp::{self, TcpStream};
 
 /// Errors that can be returned by an ApiEndpoint implementation.
 #[derive(Debug)]
@@ -191,7 +194,9 @@ impl ApiServer {
 			.name("apis".to_string())
 			.spawn(move || {
 				let server = Server::bind(&addr)
-					.serve(router)
+					.serve(make_service_fn(move |conn: &AddrStream| {
+						future::ok::<_, hyper::Error>(router.for_remote_addr(conn.remote_addr()))
+					}))
 					// TODO graceful shutdown is unstable, investigate
 					//.with_graceful_shutdown(rx)
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
@@ -233,7 +238,15 @@ impl ApiServer {
 					})
 					.filter_map(|x| x);
 				let server = Server::builder(tls)
-					.serve(router)
+					.serve(make_service_fn(
+						move |conn: &TlsStream<TcpStream, ServerSession>| {
+							let router = match conn.get_ref().0.peer_addr() {
+								Ok(addr) => router.for_remote_addr(addr),
+								Err(_) => router.clone(),
+							};
+							future::ok::<_, hyper::Error>(router)
+						},
+					))
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
 
 				rt::run(server);
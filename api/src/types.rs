@@ -19,12 +19,14 @@ use crate::core::core::hash::Hashed;
 use crate::core::core::merkle_proof::MerkleProof;
 use crate::core::{core, ser};
 use crate::p2p;
+use crate::pool;
 use crate::util;
 use crate::util::secp::pedersen;
 use serde;
 use serde::de::MapAccess;
 use serde::ser::SerializeStruct;
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 macro_rules! no_dup {
 	($field:ident) => {
@@ -91,6 +93,45 @@ impl Status {
 	}
 }
 
+/// Progress of one of the sync stages
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncStageProgress {
+	/// The stage: header_sync, txhashset_download, txhashset_validation or
+	/// body_sync
+	pub stage: String,
+	/// What's being processed: headers, bytes, segments, items or blocks
+	pub unit: String,
+	/// When the stage started, as a unix timestamp
+	pub start_time: i64,
+	/// When the stage ended, if it did
+	pub end_time: Option<i64>,
+	/// Number of units processed so far
+	pub processed: u64,
+	/// Total number of units, 0 when not known yet
+	pub total: u64,
+	/// Percentage done
+	pub percent: u64,
+	/// Units processed per second since the stage started
+	pub rate: f64,
+	/// Estimated number of seconds left, when running and known
+	pub eta_secs: Option<i64>,
+}
+
+/// Sync status along with the progress of each sync stage run so far
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncProgress {
+	/// Current sync status
+	pub status: String,
+	/// Stages, in the order they run
+	pub stages: Vec<SyncStageProgress>,
+}
+
+/// Provides the sync progress served by the API, the server sync state.
+pub trait SyncProgressSource: Send + Sync {
+	/// Current sync progress
+	fn sync_progress(&self) -> SyncProgress;
+}
+
 /// TxHashSet
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TxHashSet {
@@ -697,6 +738,116 @@ pub struct PoolInfo {
 	pub pool_size: usize,
 }
 
+// Printable representation of a transaction pool entry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PoolEntryPrintable {
+	/// Kernel excesses of the transaction
+	pub kernels: Vec<String>,
+	/// Total fee of the transaction
+	pub fee: u64,
+	/// Weight of the transaction
+	pub weight: usize,
+	/// Fee to weight ratio, what the pool ranks transactions by
+	pub fee_to_weight: u64,
+	/// Where we got the transaction from
+	pub source: String,
+	/// Number of seconds since the transaction got in our pool
+	pub age_secs: i64,
+	/// Kernel excesses of the pool transactions this one spends outputs of
+	pub depends_on: Vec<String>,
+}
+
+impl PoolEntryPrintable {
+	pub fn from_entry(entry: &pool::PoolEntry, depends_on: Vec<String>) -> PoolEntryPrintable {
+		PoolEntryPrintable {
+			kernels: entry
+				.tx
+				.kernels()
+				.iter()
+				.map(|k| util::to_hex(k.excess.0.to_vec()))
+				.collect(),
+			fee: entry.tx.fee(),
+			weight: entry.tx.tx_weight(),
+			fee_to_weight: entry.tx.fee_to_weight(),
+			source: entry.src.debug_name.clone(),
+			age_secs: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|x| x.as_secs() as i64 - entry.tx_at.timestamp())
+				.unwrap_or(0),
+			depends_on,
+		}
+	}
+}
+
+// A single transaction of the pool, in full
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PoolTransaction {
+	/// Details of the pool entry
+	pub entry: PoolEntryPrintable,
+	/// Whether the transaction is in the stempool
+	pub stem: bool,
+	/// Hex-encoded serialized transaction, as pushed to pool/push_tx
+	pub tx_hex: String,
+}
+
+// Lifecycle status of a transaction that went through our pool
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxStatusPrintable {
+	/// Kernel excess the transaction is tracked by
+	pub excess: String,
+	/// Current status of the transaction
+	pub status: pool::TxStatus,
+	/// Time the transaction got to this status, in seconds since epoch
+	pub updated_at: i64,
+}
+
+// A transaction received spending the same outputs as transactions in our pool
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PoolConflictPrintable {
+	/// Hash of the transaction received
+	pub tx: String,
+	/// Outputs spent both by the transaction received and by pool transactions
+	pub inputs: Vec<String>,
+	/// Hashes of the pool transactions it conflicts with
+	pub conflicting_txs: Vec<String>,
+	/// Where the transaction received came from
+	pub source: String,
+	/// Whether it replaced the conflicting transactions or got rejected
+	pub replaced: bool,
+	/// Time the conflict was detected, in seconds since epoch
+	pub at: i64,
+}
+
+impl PoolConflictPrintable {
+	pub fn from_conflict(conflict: &pool::PoolConflict) -> PoolConflictPrintable {
+		PoolConflictPrintable {
+			tx: conflict.tx.to_hex(),
+			inputs: conflict
+				.inputs
+				.iter()
+				.map(|x| util::to_hex(x.0.to_vec()))
+				.collect(),
+			conflicting_txs: conflict
+				.conflicting_txs
+				.iter()
+				.map(|x| x.to_hex())
+				.collect(),
+			source: conflict.src.identifier.clone(),
+			replaced: conflict.replaced,
+			at: conflict.at.timestamp(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeeEstimate {
+	/// Number of blocks the estimate is for confirmation within
+	pub target_blocks: u64,
+	/// Recommended fee per unit of transaction weight, same unit as the
+	/// pool accept_fee_base
+	pub fee_base: u64,
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
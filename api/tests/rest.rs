@@ -80,6 +80,36 @@ fn test_start_api() {
 	thread::sleep(time::Duration::from_millis(1_000));
 }
 
+struct RemoteAddrHandler;
+
+impl Handler for RemoteAddrHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let addr = req
+			.extensions()
+			.get::<RemoteAddr>()
+			.map(|RemoteAddr(addr)| addr.ip().to_string());
+		json_response_pretty(&addr.into_iter().collect::<Vec<_>>())
+	}
+}
+
+#[test]
+fn test_remote_addr() {
+	util::init_test_logger();
+	let mut server = ApiServer::new();
+	let mut router = Router::new();
+	router
+		.add_route("/v1/remote", Arc::new(RemoteAddrHandler))
+		.expect("add_route failed");
+	let server_addr = "127.0.0.1:14435";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	assert!(server.start(addr, router, None).is_ok());
+	let url = format!("http://{}/v1/remote", server_addr);
+	let remote = request_with_retry(url.as_str()).unwrap();
+	assert_eq!(remote, vec!["127.0.0.1".to_string()]);
+	assert!(server.stop());
+	thread::sleep(time::Duration::from_millis(1_000));
+}
+
 // To enable this test you need a trusted PKCS12 (p12) certificate bundle
 // Hyper-tls client doesn't accept self-signed certificates. The easiest way is to use mkcert
 // https://github.com/FiloSottile/mkcert to install CA and generate a certificate on your local machine.
@@ -28,13 +28,14 @@ use crate::error::{Error, ErrorKind};
 use crate::pipe;
 use crate::store;
 use crate::txhashset;
-use crate::txhashset::TxHashSet;
+use crate::txhashset::{Desegmenter, Segmenter, TxHashSet};
 use crate::types::{
 	BlockStatus, ChainAdapter, NoStatus, Options, Tip, TxHashSetRoots, TxHashsetWriteStatus,
 };
 use crate::util::secp::pedersen::{Commitment, RangeProof};
 use crate::util::RwLock;
 use grin_store::Error::NotFoundErr;
+use grin_store::STORAGE_VERSION;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Read;
@@ -153,6 +154,8 @@ pub struct Chain {
 	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
 	archive_mode: bool,
 	genesis: BlockHeader,
+	// segments of the txhashset we serve to peers syncing through PIBD
+	pibd_segmenter: RwLock<Option<Segmenter>>,
 }
 
 impl Chain {
@@ -185,6 +188,7 @@ impl Chain {
 			verifier_cache,
 			archive_mode,
 			genesis: genesis.header.clone(),
+			pibd_segmenter: RwLock::new(None),
 		})
 	}
 
@@ -374,6 +378,19 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Checks the proof of work of a header, without its previous header to
+	/// check the difficulty against. Used to vet the header chain skeleton
+	/// received from a peer before syncing headers against it.
+	pub fn validate_header_pow(&self, header: &BlockHeader) -> Result<(), Error> {
+		if !header.pow.is_primary() && !header.pow.is_secondary() {
+			return Err(ErrorKind::LowEdgebits.into());
+		}
+		if (self.pow_verifier)(header).is_err() {
+			return Err(ErrorKind::InvalidPow.into());
+		}
+		Ok(())
+	}
+
 	fn new_ctx<'a>(
 		&self,
 		opts: Options,
@@ -647,7 +664,7 @@ impl Chain {
 	/// TODO - Write this data to disk and validate the rebuilt kernel MMR.
 	pub fn kernel_data_write(&self, reader: &mut Read) -> Result<(), Error> {
 		let mut count = 0;
-		let mut stream = StreamingReader::new(reader, Duration::from_secs(1));
+		let mut stream = StreamingReader::new(reader, STORAGE_VERSION, Duration::from_secs(1));
 		while let Ok(_kernel) = TxKernelEntry::read(&mut stream) {
 			count += 1;
 		}
@@ -667,6 +684,13 @@ impl Chain {
 		// The fast sync client does *not* have the necessary data
 		// to rewind after receiving the txhashset zip.
 		let header = self.get_block_header(&h)?;
+
+		// the zip is requested repeatedly when served in segments, no need
+		// to rewind again if we already built it
+		if let Some(zip) = txhashset::existing_zip(&self.db_root, &header) {
+			return Ok((header.output_mmr_size, header.kernel_mmr_size, zip));
+		}
+
 		{
 			let mut txhashset = self.txhashset.write();
 			txhashset::extending_readonly(&mut txhashset, |extension| {
@@ -685,6 +709,33 @@ impl Chain {
 		))
 	}
 
+	/// Provides the segments of the txhashset at the provided block hash,
+	/// which must be on our current chain and within the horizon (older
+	/// spent outputs may have been compacted away already).
+	pub fn segmenter(&self, h: Hash) -> Result<Segmenter, Error> {
+		let header = self.get_block_header(&h)?;
+		self.is_on_current_chain(&header)?;
+		let head = self.head()?;
+		if header.height + (global::cut_through_horizon() as u64) < head.height {
+			return Err(ErrorKind::TxHashSetErr(format!(
+				"segments requested for block {} beyond the horizon",
+				h
+			))
+			.into());
+		}
+
+		if let Some(ref segmenter) = *self.pibd_segmenter.read() {
+			if segmenter.header().hash() == h {
+				return Ok(segmenter.clone());
+			}
+		}
+
+		let leaf_set = self.txhashset.write().output_leaf_set(&header)?;
+		let segmenter = Segmenter::new(self.txhashset.clone(), header, leaf_set);
+		*self.pibd_segmenter.write() = Some(segmenter.clone());
+		Ok(segmenter)
+	}
+
 	// Special handling to make sure the whole kernel set matches each of its
 	// roots in each block header, without truncation. We go back header by
 	// header, rewind and check each root. This fixes a potential weakness in
@@ -899,6 +950,46 @@ impl Chain {
 		txhashset::clean_header_folder(&sandbox_dir);
 		txhashset::zip_write(sandbox_dir.clone(), txhashset_data.try_clone()?, &header)?;
 
+		self.txhashset_write_sandbox(header, sandbox_dir, status)
+	}
+
+	/// Prepares rebuilding the txhashset at the provided block from segments
+	/// downloaded from our peers, in the tmp dir.
+	pub fn desegmenter(&self, header: &BlockHeader) -> Result<Desegmenter, Error> {
+		let sandbox_dir = self.get_tmp_dir();
+		txhashset::clean_txhashset_folder(&sandbox_dir);
+		txhashset::clean_header_folder(&sandbox_dir);
+		Desegmenter::new(header.clone(), sandbox_dir)
+	}
+
+	/// Writes the txhashset rebuilt from segments, once all of them got
+	/// downloaded. Goes through the same full validation as a txhashset we
+	/// got as a zip file.
+	pub fn txhashset_write_segments(
+		&self,
+		desegmenter: Desegmenter,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
+		status.on_setup();
+
+		let mut hashes: Option<Vec<Hash>> = None;
+		if !self.check_txhashset_needed("txhashset_write_segments".to_owned(), &mut hashes)? {
+			warn!("txhashset_write_segments: txhashset rebuilt but it's not needed! ignored.");
+			return Err(ErrorKind::InvalidTxHashSet("not needed".to_owned()).into());
+		}
+
+		let (header, sandbox_dir) = desegmenter.finish()?;
+		self.txhashset_write_sandbox(header, sandbox_dir, status)
+	}
+
+	// Validates the txhashset written to the sandbox dir and, if it's all
+	// good, replaces ours with it.
+	fn txhashset_write_sandbox(
+		&self,
+		header: BlockHeader,
+		sandbox_dir: PathBuf,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
 		let mut txhashset = txhashset::TxHashSet::open(
 			sandbox_dir
 				.to_str()
@@ -908,7 +999,7 @@ impl Chain {
 			Some(&header),
 		)?;
 
-		// The txhashset.zip contains the output, rangeproof and kernel MMRs.
+		// The sandbox only contains the output, rangeproof and kernel MMRs.
 		// We must rebuild the header MMR ourselves based on the headers in our db.
 		self.rebuild_header_mmr(&Tip::from_header(&header), &mut txhashset)?;
 
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 //! Error types for chain
+use crate::core::core::pmmr::SegmentError;
 use crate::core::core::{block, committed, transaction};
 use crate::core::ser;
 use crate::keychain;
@@ -65,6 +66,9 @@ pub enum ErrorKind {
 	/// Block height is invalid (not previous + 1)
 	#[fail(display = "Invalid Block Height")]
 	InvalidBlockHeight,
+	/// Header conflicts with a checkpoint at the same height
+	#[fail(display = "Header conflicts with checkpoint at height {}", _0)]
+	CheckpointMismatch(u64),
 	/// One of the root hashes in the block is invalid
 	#[fail(display = "Invalid Root")]
 	InvalidRoot,
@@ -140,6 +144,9 @@ pub enum ErrorKind {
 	/// Internal Roaring Bitmap error
 	#[fail(display = "Roaring Bitmap error")]
 	Bitmap,
+	/// A txhashset segment is invalid or could not be built
+	#[fail(display = "Invalid Segment: {:?}", _0)]
+	InvalidSegment(SegmentError),
 }
 
 impl Display for Error {
@@ -248,6 +255,14 @@ impl From<committed::Error> for Error {
 	}
 }
 
+impl From<SegmentError> for Error {
+	fn from(error: SegmentError) -> Error {
+		Error {
+			inner: Context::new(ErrorKind::InvalidSegment(error)),
+		}
+	}
+}
+
 impl From<io::Error> for Error {
 	fn from(e: io::Error) -> Error {
 		Error {
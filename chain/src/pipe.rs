@@ -205,6 +205,12 @@ pub fn sync_block_headers(
 		false
 	};
 
+	// Refuse a header chain conflicting with a checkpoint before doing any
+	// work on it.
+	for header in headers {
+		check_checkpoint(header)?;
+	}
+
 	if !all_known {
 		let prev_header = ctx.batch.get_previous_header(&first_header)?;
 		txhashset::sync_extending(&mut ctx.txhashset, &mut ctx.batch, |extension| {
@@ -334,6 +340,22 @@ fn prev_header_store(
 	Ok(prev)
 }
 
+/// Check the header agrees with the checkpoint at its height, if any.
+fn check_checkpoint(header: &BlockHeader) -> Result<(), Error> {
+	match global::checkpoint(header.height) {
+		Some(hash) if hash != header.hash() => {
+			warn!(
+				"pipe: header {} at {} conflicts with checkpoint {}",
+				header.hash(),
+				header.height,
+				hash,
+			);
+			Err(ErrorKind::CheckpointMismatch(header.height).into())
+		}
+		_ => Ok(()),
+	}
+}
+
 /// First level of block validation that only needs to act on the block header
 /// to make it as cheap as possible. The different validations are also
 /// arranged by order of cost to have as little DoS surface as possible.
@@ -347,6 +369,8 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<(
 		return Err(ErrorKind::InvalidBlockVersion(header.version).into());
 	}
 
+	check_checkpoint(header)?;
+
 	// TODO: remove CI check from here somehow
 	if header.timestamp > Utc::now() + Duration::seconds(12 * (consensus::BLOCK_TIME_SEC as i64))
 		&& !global::is_automated_testing_mode()
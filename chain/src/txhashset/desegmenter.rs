@@ -0,0 +1,473 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilds the txhashset at a given block from segments of its output,
+//! rangeproof and kernel MMRs. Each segment is validated against the block
+//! header roots as soon as it comes in, then written to the sandbox
+//! backends once all the segments before it have been. The roots don't
+//! commit to which outputs are spent, so the spent leaves and pruned subtrees
+//! of the output and rangeproof segments are also checked against the output
+//! leaf set, received as a whole beforehand.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use croaring::Bitmap;
+
+use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{self, Backend, Segment, SegmentError, SegmentIdentifier};
+use crate::core::core::{BlockHeader, Output, OutputIdentifier, TxKernel, TxKernelEntry};
+use crate::core::ser::{PMMRIndexHashable, PMMRable};
+use crate::error::{Error, ErrorKind};
+use crate::txhashset;
+use crate::util::secp::pedersen::RangeProof;
+use grin_store::pmmr::PMMRBackend;
+
+/// Height of the output MMR segments, 2048 outputs.
+pub const OUTPUT_SEGMENT_HEIGHT: u8 = 11;
+
+/// Height of the rangeproof MMR segments, 512 rangeproofs.
+pub const RANGEPROOF_SEGMENT_HEIGHT: u8 = 9;
+
+/// Height of the kernel MMR segments, 2048 kernels.
+pub const KERNEL_SEGMENT_HEIGHT: u8 = 11;
+
+/// The MMRs a segment can be part of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentType {
+	/// Output MMR
+	Output,
+	/// Rangeproof MMR
+	RangeProof,
+	/// Kernel MMR
+	Kernel,
+}
+
+impl SegmentType {
+	/// Height of the segments we download for that MMR.
+	pub fn segment_height(&self) -> u8 {
+		match *self {
+			SegmentType::Output => OUTPUT_SEGMENT_HEIGHT,
+			SegmentType::RangeProof => RANGEPROOF_SEGMENT_HEIGHT,
+			SegmentType::Kernel => KERNEL_SEGMENT_HEIGHT,
+		}
+	}
+}
+
+/// Rebuilds the txhashset at a block from segments, see module doc.
+pub struct Desegmenter {
+	header: BlockHeader,
+	sandbox_dir: PathBuf,
+	leaf_set: Option<Bitmap>,
+	outputs: SegmentApplier<Output>,
+	rangeproofs: SegmentApplier<RangeProof>,
+	kernels: SegmentApplier<TxKernel>,
+}
+
+impl Desegmenter {
+	/// Starts rebuilding the txhashset as of the provided header in the
+	/// provided sandbox dir, which must have been cleaned up beforehand.
+	pub fn new(header: BlockHeader, sandbox_dir: PathBuf) -> Result<Desegmenter, Error> {
+		let root_dir = sandbox_dir
+			.to_str()
+			.ok_or_else(|| ErrorKind::Other("invalid sandbox folder".to_owned()))?
+			.to_owned();
+		let (outputs, rangeproofs, kernels) = txhashset::segment_backends(&root_dir)?;
+		Ok(Desegmenter {
+			outputs: SegmentApplier::new(
+				outputs,
+				header.output_mmr_size,
+				header.output_root,
+				OUTPUT_SEGMENT_HEIGHT,
+				true,
+			),
+			rangeproofs: SegmentApplier::new(
+				rangeproofs,
+				header.output_mmr_size,
+				header.range_proof_root,
+				RANGEPROOF_SEGMENT_HEIGHT,
+				true,
+			),
+			kernels: SegmentApplier::new(
+				kernels,
+				header.kernel_mmr_size,
+				header.kernel_root,
+				KERNEL_SEGMENT_HEIGHT,
+				false,
+			),
+			header,
+			sandbox_dir,
+			leaf_set: None,
+		})
+	}
+
+	/// Header of the block the txhashset is rebuilt for.
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	/// Sandbox dir the txhashset is rebuilt in.
+	pub fn sandbox_dir(&self) -> &PathBuf {
+		&self.sandbox_dir
+	}
+
+	/// Whether we got the output leaf set, which the output and rangeproof
+	/// segments are checked against.
+	pub fn has_leaf_set(&self) -> bool {
+		self.leaf_set.is_some()
+	}
+
+	/// A peer sent us the output leaf set as a bitset of the leaf insertion
+	/// indices (see Segmenter::output_bitmap). We rebuild the txhashset
+	/// against the first one we get, returns whether this one is the same.
+	/// Errors mean the bitset doesn't fit the output MMR.
+	pub fn output_bitmap_received(&mut self, bits: &[u8]) -> Result<bool, Error> {
+		let n_leaves = pmmr::n_leaves(self.header.output_mmr_size);
+		if bits.len() as u64 != (n_leaves + 7) / 8 {
+			return Err(SegmentError::Malformed.into());
+		}
+		let mut leaf_set = Bitmap::create();
+		for (i, byte) in bits.iter().enumerate() {
+			for j in 0..8 {
+				if byte & (1 << j) == 0 {
+					continue;
+				}
+				let idx = i as u64 * 8 + j;
+				if idx >= n_leaves {
+					return Err(SegmentError::Malformed.into());
+				}
+				leaf_set.add(pmmr::insertion_to_pmmr_index(idx + 1) as u32);
+			}
+		}
+		match self.leaf_set {
+			Some(ref ours) => Ok(*ours == leaf_set),
+			None => {
+				self.leaf_set = Some(leaf_set);
+				Ok(true)
+			}
+		}
+	}
+
+	/// Whether we got all the segments of all the MMRs.
+	pub fn is_complete(&self) -> bool {
+		self.outputs.is_complete() && self.rangeproofs.is_complete() && self.kernels.is_complete()
+	}
+
+	/// Number of segments applied so far and total number of segments.
+	pub fn progress(&self) -> (u64, u64) {
+		(
+			self.outputs.next_idx + self.rangeproofs.next_idx + self.kernels.next_idx,
+			self.outputs.segment_count()
+				+ self.rangeproofs.segment_count()
+				+ self.kernels.segment_count(),
+		)
+	}
+
+	/// The next segments we need of each MMR, skipping the ones already
+	/// received and waiting to be applied, at most max_count per MMR. No
+	/// output or rangeproof segments until we have the output leaf set.
+	pub fn next_desired_segments(&self, max_count: usize) -> Vec<(SegmentType, SegmentIdentifier)> {
+		let mut segments = vec![];
+		if self.has_leaf_set() {
+			for id in self.outputs.next_desired(max_count) {
+				segments.push((SegmentType::Output, id));
+			}
+			for id in self.rangeproofs.next_desired(max_count) {
+				segments.push((SegmentType::RangeProof, id));
+			}
+		}
+		for id in self.kernels.next_desired(max_count) {
+			segments.push((SegmentType::Kernel, id));
+		}
+		segments
+	}
+
+	/// Validates an output segment and applies it when possible. Errors
+	/// mean the segment, and so whoever sent it, is bad.
+	pub fn add_output_segment(&mut self, segment: Segment<OutputIdentifier>) -> Result<(), Error> {
+		match self.leaf_set {
+			Some(ref leaf_set) => self.outputs.add(segment, Some(leaf_set)),
+			// not something we asked for yet
+			None => Ok(()),
+		}
+	}
+
+	/// Validates a rangeproof segment and applies it when possible.
+	pub fn add_rangeproof_segment(&mut self, segment: Segment<RangeProof>) -> Result<(), Error> {
+		match self.leaf_set {
+			Some(ref leaf_set) => self.rangeproofs.add(segment, Some(leaf_set)),
+			None => Ok(()),
+		}
+	}
+
+	/// Validates a kernel segment and applies it when possible.
+	pub fn add_kernel_segment(&mut self, segment: Segment<TxKernelEntry>) -> Result<(), Error> {
+		self.kernels.add(segment, None)
+	}
+
+	/// Saves everything and releases the sandbox backends, the txhashset can
+	/// then be opened from the sandbox dir for the final validation.
+	pub fn finish(mut self) -> Result<(BlockHeader, PathBuf), Error> {
+		if !self.is_complete() {
+			return Err(ErrorKind::TxHashSetErr("missing segments".to_owned()).into());
+		}
+		self.outputs.finish()?;
+		self.rangeproofs.finish()?;
+		self.kernels.finish()?;
+		Ok((self.header, self.sandbox_dir))
+	}
+}
+
+// Validates the segments of a MMR and writes them to its backend in order,
+// computing the parents above the segments in between.
+struct SegmentApplier<T: PMMRable> {
+	backend: PMMRBackend<T>,
+	mmr_size: u64,
+	root: Hash,
+	height: u8,
+	prunable: bool,
+	// next segment to write
+	next_idx: u64,
+	// validated segments waiting for the ones before them
+	pending: BTreeMap<u64, Segment<T::E>>,
+	// last position written to the backend
+	last_pos: u64,
+	// roots of the subtrees written so far without their parent yet, and
+	// whether they're pruned
+	stack: Vec<(u64, Hash, bool)>,
+}
+
+impl<T> SegmentApplier<T>
+where
+	T: PMMRable,
+	T::E: PMMRIndexHashable,
+{
+	fn new(
+		backend: PMMRBackend<T>,
+		mmr_size: u64,
+		root: Hash,
+		height: u8,
+		prunable: bool,
+	) -> SegmentApplier<T> {
+		SegmentApplier {
+			backend,
+			mmr_size,
+			root,
+			height,
+			prunable,
+			next_idx: 0,
+			pending: BTreeMap::new(),
+			last_pos: 0,
+			stack: vec![],
+		}
+	}
+
+	fn segment_count(&self) -> u64 {
+		SegmentIdentifier::count_segments_required(self.mmr_size, self.height)
+	}
+
+	fn is_complete(&self) -> bool {
+		self.next_idx >= self.segment_count() && self.last_pos == self.mmr_size
+	}
+
+	fn next_desired(&self, max_count: usize) -> Vec<SegmentIdentifier> {
+		(self.next_idx..self.segment_count())
+			.filter(|idx| !self.pending.contains_key(idx))
+			.take(max_count)
+			.map(|idx| SegmentIdentifier {
+				height: self.height,
+				idx,
+			})
+			.collect()
+	}
+
+	fn add(&mut self, segment: Segment<T::E>, leaf_set: Option<&Bitmap>) -> Result<(), Error> {
+		let identifier = segment.identifier;
+		if identifier.height != self.height
+			|| identifier.idx < self.next_idx
+			|| self.pending.contains_key(&identifier.idx)
+		{
+			// not something we asked for (anymore)
+			return Ok(());
+		}
+		segment.validate(self.mmr_size, self.root, self.prunable)?;
+		if let Some(leaf_set) = leaf_set {
+			segment.validate_leaf_set(leaf_set)?;
+		}
+		if identifier.idx != self.next_idx {
+			self.pending.insert(identifier.idx, segment);
+			return Ok(());
+		}
+
+		self.apply(segment)?;
+		while let Some(segment) = self.pending.remove(&self.next_idx) {
+			if let Err(e) = self.apply(segment) {
+				if !e.is_bad_data() {
+					return Err(e);
+				}
+				// we don't know who sent it anymore, just ask for it again
+				debug!(
+					"desegmenter: dropping invalid segment {} of height {}: {}",
+					self.next_idx, self.height, e
+				);
+				break;
+			}
+		}
+		self.backend.sync()?;
+		Ok(())
+	}
+
+	// Works out what to write for the segment (and the parents above the
+	// segments at the end of the MMR) before writing anything, a segment
+	// that doesn't fit with the previous ones leaves everything untouched.
+	fn apply(&mut self, segment: Segment<T::E>) -> Result<(), Error> {
+		let last_segment = segment.identifier.idx + 1 >= self.segment_count();
+		let mut plan = Plan {
+			stack: self.stack.clone(),
+			last_pos: self.last_pos,
+			ops: vec![],
+		};
+		plan.add_segment(segment, self.mmr_size)?;
+		if last_segment {
+			plan.fill_parents(self.mmr_size)?;
+		}
+
+		for op in plan.ops {
+			match op {
+				Op::Leaf(pos, data, hash, spent) => {
+					self.backend.append_leaf(pos, &data, hash, spent)?
+				}
+				Op::Parent(hash) => self.backend.append_hash(hash)?,
+				Op::PrunedRoot(pos, hash) => self.backend.append_pruned_root(pos, hash)?,
+			}
+		}
+		self.stack = plan.stack;
+		self.last_pos = plan.last_pos;
+		self.next_idx += 1;
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<(), Error> {
+		self.backend.sync()?;
+		if self.prunable {
+			self.backend.sync_prune_list()?;
+		}
+		self.backend.release_files();
+		Ok(())
+	}
+}
+
+// Write to the backend of a MMR.
+enum Op<E> {
+	Leaf(u64, E, Hash, bool),
+	Parent(Hash),
+	PrunedRoot(u64, Hash),
+}
+
+// What gets written for a segment, tracking the subtree roots as we go.
+struct Plan<E> {
+	// roots of the subtrees written so far without their parent yet, and
+	// whether they're pruned
+	stack: Vec<(u64, Hash, bool)>,
+	// last position written
+	last_pos: u64,
+	ops: Vec<Op<E>>,
+}
+
+impl<E: PMMRIndexHashable> Plan<E> {
+	fn add_segment(&mut self, segment: Segment<E>, mmr_size: u64) -> Result<(), Error> {
+		let last_pos = segment.identifier.last_pos(mmr_size);
+		if last_pos <= self.last_pos {
+			// covered by a pruned subtree already written
+			return Ok(());
+		}
+		let spent: HashSet<u64> = segment.spent.iter().cloned().collect();
+		let mut hashes = segment.hashes.into_iter().peekable();
+		let mut leaves = segment.leaves.into_iter().peekable();
+		loop {
+			let next_leaf = match (leaves.peek(), hashes.peek()) {
+				(Some((leaf_pos, _)), Some((hash_pos, _))) => leaf_pos < hash_pos,
+				(Some(_), None) => true,
+				(None, Some(_)) => false,
+				(None, None) => break,
+			};
+			if next_leaf {
+				let (pos, data) = leaves.next().expect("peeked leaf");
+				if pos <= self.last_pos {
+					return Err(SegmentError::Malformed.into());
+				}
+				self.fill_parents(pos - 1)?;
+				let hash = data.hash_with_index(pos - 1);
+				self.ops
+					.push(Op::Leaf(pos, data, hash, spent.contains(&pos)));
+				self.push(pos, hash, false)?;
+			} else {
+				let (pos, hash) = hashes.next().expect("peeked hash");
+				let leftmost = pmmr::bintree_leftmost(pos);
+				if leftmost <= self.last_pos {
+					return Err(SegmentError::Malformed.into());
+				}
+				self.fill_parents(leftmost - 1)?;
+				self.ops.push(Op::PrunedRoot(pos, hash));
+				self.push(pos, hash, true)?;
+			}
+		}
+		self.fill_parents(last_pos)
+	}
+
+	// Adds the parents of the subtrees on the stack up to the provided
+	// position.
+	fn fill_parents(&mut self, to_pos: u64) -> Result<(), Error> {
+		while self.last_pos < to_pos {
+			let pos = self.last_pos + 1;
+			let height = pmmr::bintree_postorder_height(pos);
+			if height == 0 {
+				return Err(SegmentError::MissingLeaf(pos).into());
+			}
+			let right = self.stack.pop();
+			let left = self.stack.pop();
+			let (left, right) = match (left, right) {
+				(Some(left), Some(right))
+					if left.0 == pos - (1 << height) && right.0 == pos - 1 =>
+				{
+					(left, right)
+				}
+				_ => return Err(SegmentError::MissingHash(pos).into()),
+			};
+			let hash = (left.1, right.1).hash_with_index(pos - 1);
+			self.ops.push(Op::Parent(hash));
+			self.push(pos, hash, false)?;
+		}
+		Ok(())
+	}
+
+	fn push(&mut self, pos: u64, hash: Hash, pruned: bool) -> Result<(), Error> {
+		// Sibling pruned subtrees should have come as their pruned parent,
+		// the prune list would merge them otherwise.
+		if pruned && !pmmr::is_left_sibling(pos) {
+			if let Some(&(_, _, true)) = self.stack.last() {
+				return Err(SegmentError::Malformed.into());
+			}
+		}
+		self.stack.push((pos, hash, pruned));
+		self.last_pos = pos;
+		Ok(())
+	}
+}
+
+impl<T: PMMRable> Drop for SegmentApplier<T> {
+	fn drop(&mut self) {
+		self.backend.release_files();
+	}
+}
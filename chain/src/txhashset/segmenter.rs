@@ -0,0 +1,102 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builds the segments of the txhashset MMRs served to syncing peers.
+
+use std::sync::Arc;
+
+use croaring::Bitmap;
+
+use crate::core::core::pmmr::{self, Segment, SegmentIdentifier};
+use crate::core::core::{BlockHeader, OutputIdentifier, TxKernelEntry};
+use crate::error::Error;
+use crate::txhashset::TxHashSet;
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::RwLock;
+
+/// Segments of the output, rangeproof and kernel MMRs as of a given block.
+/// The output leaf set at that block is computed once, as it takes a rewind.
+#[derive(Clone)]
+pub struct Segmenter {
+	txhashset: Arc<RwLock<TxHashSet>>,
+	header: BlockHeader,
+	leaf_set: Arc<Bitmap>,
+}
+
+impl Segmenter {
+	/// Segmenter for the txhashset as of the provided header, given the
+	/// output leaf set at that header.
+	pub fn new(
+		txhashset: Arc<RwLock<TxHashSet>>,
+		header: BlockHeader,
+		leaf_set: Bitmap,
+	) -> Segmenter {
+		Segmenter {
+			txhashset,
+			header,
+			leaf_set: Arc::new(leaf_set),
+		}
+	}
+
+	/// Header of the block the segments are built for.
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	/// Output leaf set as a bitset of the leaf insertion indices, one bit per
+	/// output of the MMR, the lowest bit of each byte first.
+	pub fn output_bitmap(&self) -> Vec<u8> {
+		let n_leaves = pmmr::n_leaves(self.header.output_mmr_size);
+		let mut bits = vec![0u8; ((n_leaves + 7) / 8) as usize];
+		for pos in self.leaf_set.iter() {
+			let pos = pos as u64;
+			if pos > self.header.output_mmr_size || !pmmr::is_leaf(pos) {
+				continue;
+			}
+			let idx = pmmr::n_leaves(pos) - 1;
+			bits[(idx / 8) as usize] |= 1 << (idx % 8);
+		}
+		bits
+	}
+
+	/// Segment of the output MMR.
+	pub fn output_segment(
+		&self,
+		identifier: SegmentIdentifier,
+	) -> Result<Segment<OutputIdentifier>, Error> {
+		self.txhashset
+			.read()
+			.output_segment(&self.header, identifier, &self.leaf_set)
+	}
+
+	/// Segment of the rangeproof MMR.
+	pub fn rangeproof_segment(
+		&self,
+		identifier: SegmentIdentifier,
+	) -> Result<Segment<RangeProof>, Error> {
+		self.txhashset
+			.read()
+			.rangeproof_segment(&self.header, identifier, &self.leaf_set)
+	}
+
+	/// Segment of the kernel MMR.
+	pub fn kernel_segment(
+		&self,
+		identifier: SegmentIdentifier,
+	) -> Result<Segment<TxKernelEntry>, Error> {
+		self.txhashset
+			.read()
+			.kernel_segment(&self.header, identifier)
+	}
+}
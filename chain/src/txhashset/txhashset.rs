@@ -18,7 +18,9 @@
 use crate::core::core::committed::Committed;
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::merkle_proof::MerkleProof;
-use crate::core::core::pmmr::{self, Backend, ReadonlyPMMR, RewindablePMMR, PMMR};
+use crate::core::core::pmmr::{
+	self, Backend, ReadonlyPMMR, RewindablePMMR, Segment, SegmentIdentifier, PMMR,
+};
 use crate::core::core::{
 	Block, BlockHeader, Input, Output, OutputIdentifier, TxKernel, TxKernelEntry,
 };
@@ -293,6 +295,53 @@ impl TxHashSet {
 			.map_err(|_| ErrorKind::MerkleProof.into())
 	}
 
+	/// Output MMR leaf set as of the provided header, telling which outputs
+	/// had already been spent at that point.
+	pub fn output_leaf_set(&mut self, header: &BlockHeader) -> Result<Bitmap, Error> {
+		extending_readonly(self, |extension| {
+			extension.rewind(header)?;
+			let mut leaf_set = Bitmap::create();
+			for pos in extension.output_pmmr.leaf_pos_iter() {
+				leaf_set.add(pos as u32);
+			}
+			Ok(leaf_set)
+		})
+	}
+
+	/// Segment of the output MMR as of the provided header, the outputs
+	/// missing from the provided leaf set being spent.
+	pub fn output_segment(
+		&self,
+		header: &BlockHeader,
+		identifier: SegmentIdentifier,
+		leaf_set: &Bitmap,
+	) -> Result<Segment<OutputIdentifier>, Error> {
+		let pmmr = ReadonlyPMMR::at(&self.output_pmmr_h.backend, header.output_mmr_size);
+		Ok(Segment::from_pmmr(identifier, &pmmr, Some(leaf_set))?)
+	}
+
+	/// Segment of the rangeproof MMR as of the provided header, pruned the
+	/// same way as the output MMR.
+	pub fn rangeproof_segment(
+		&self,
+		header: &BlockHeader,
+		identifier: SegmentIdentifier,
+		leaf_set: &Bitmap,
+	) -> Result<Segment<RangeProof>, Error> {
+		let pmmr = ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, header.output_mmr_size);
+		Ok(Segment::from_pmmr(identifier, &pmmr, Some(leaf_set))?)
+	}
+
+	/// Segment of the kernel MMR as of the provided header.
+	pub fn kernel_segment(
+		&self,
+		header: &BlockHeader,
+		identifier: SegmentIdentifier,
+	) -> Result<Segment<TxKernelEntry>, Error> {
+		let pmmr = ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, header.kernel_mmr_size);
+		Ok(Segment::from_pmmr(identifier, &pmmr, None)?)
+	}
+
 	/// Compact the MMR data files and flush the rm logs
 	pub fn compact(&mut self, batch: &mut Batch<'_>) -> Result<(), Error> {
 		debug!("txhashset: starting compaction...");
@@ -1420,14 +1469,11 @@ impl<'a> Extension<'a> {
 /// Packages the txhashset data files into a zip and returns a Read to the
 /// resulting file
 pub fn zip_read(root_dir: String, header: &BlockHeader) -> Result<File, Error> {
-	let txhashset_zip = format!("{}_{}.zip", TXHASHSET_ZIP, header.hash().to_string());
-
 	let txhashset_path = Path::new(&root_dir).join(TXHASHSET_SUBDIR);
-	let zip_path = Path::new(&root_dir).join(txhashset_zip);
+	let zip_path = zip_file_path(&root_dir, header);
 
 	// if file exist, just re-use it
-	let zip_file = File::open(zip_path.clone());
-	if let Ok(zip) = zip_file {
+	if let Some(zip) = existing_zip(&root_dir, header) {
 		return Ok(zip);
 	} else {
 		// clean up old zips.
@@ -1459,9 +1505,20 @@ pub fn zip_read(root_dir: String, header: &BlockHeader) -> Result<File, Error> {
 		file::copy_dir_to(&txhashset_path, &temp_txhashset_path)?;
 		// Check and remove file that are not supposed to be there
 		check_and_remove_files(&temp_txhashset_path, header)?;
-		// Compress zip
-		zip::compress(&temp_txhashset_path, &File::create(zip_path.clone())?)
-			.map_err(|ze| ErrorKind::Other(ze.to_string()))?;
+		// Compress zip into a temp file first and only move it in place once
+		// complete, as existing zips are served without any lock
+		let temp_zip_path = zip_path.with_extension("zip.tmp");
+		let res = File::create(&temp_zip_path)
+			.map_err(|e| ErrorKind::Other(e.to_string()))
+			.and_then(|zip_file| {
+				zip::compress(&temp_txhashset_path, &zip_file)
+					.map_err(|ze| ErrorKind::Other(ze.to_string()))
+			});
+		if let Err(e) = res {
+			let _ = fs::remove_file(&temp_zip_path);
+			return Err(e.into());
+		}
+		fs::rename(&temp_zip_path, &zip_path)?;
 
 		temp_txhashset_path
 	};
@@ -1480,6 +1537,17 @@ pub fn zip_read(root_dir: String, header: &BlockHeader) -> Result<File, Error> {
 	Ok(zip_file)
 }
 
+/// Opens the txhashset zip file previously built for the provided header, if
+/// it's still around.
+pub fn existing_zip(root_dir: &str, header: &BlockHeader) -> Option<File> {
+	File::open(zip_file_path(root_dir, header)).ok()
+}
+
+fn zip_file_path(root_dir: &str, header: &BlockHeader) -> PathBuf {
+	let txhashset_zip = format!("{}_{}.zip", TXHASHSET_ZIP, header.hash().to_string());
+	Path::new(root_dir).join(txhashset_zip)
+}
+
 /// Extract the txhashset data from a zip file and writes the content into the
 /// txhashset storage dir
 pub fn zip_write(
@@ -1514,6 +1582,38 @@ pub fn txhashset_replace(from: PathBuf, to: PathBuf) -> Result<(), Error> {
 	}
 }
 
+/// Opens the output, rangeproof and kernel MMR backends under the provided
+/// root dir, to write the MMRs rebuilt from segments.
+pub fn segment_backends(
+	root_dir: &str,
+) -> Result<
+	(
+		PMMRBackend<Output>,
+		PMMRBackend<RangeProof>,
+		PMMRBackend<TxKernel>,
+	),
+	Error,
+> {
+	let output = PMMRHandle::new(root_dir, TXHASHSET_SUBDIR, OUTPUT_SUBDIR, true, true, None)?;
+	let rproof = PMMRHandle::new(
+		root_dir,
+		TXHASHSET_SUBDIR,
+		RANGE_PROOF_SUBDIR,
+		true,
+		true,
+		None,
+	)?;
+	let kernel = PMMRHandle::new(
+		root_dir,
+		TXHASHSET_SUBDIR,
+		KERNEL_SUBDIR,
+		false,
+		false,
+		None,
+	)?;
+	Ok((output.backend, rproof.backend, kernel.backend))
+}
+
 /// Clean the txhashset folder
 pub fn clean_txhashset_folder(root_dir: &PathBuf) {
 	let txhashset_path = root_dir.clone().join(TXHASHSET_SUBDIR);
@@ -0,0 +1,113 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::types::NoopAdapter;
+use self::chain::{Chain, ErrorKind, Options};
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader};
+use self::core::global::{self, ChainTypes};
+use self::core::libtx;
+use self::core::pow::{self, Difficulty};
+use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use self::util::RwLock;
+use chrono::Duration;
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+
+fn clean_output_dir(dir_name: &str) {
+	let _ = fs::remove_dir_all(dir_name);
+}
+
+fn setup(dir_name: &str) -> Chain {
+	util::init_test_logger();
+	clean_output_dir(dir_name);
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	chain::Chain::init(
+		dir_name.to_string(),
+		Arc::new(NoopAdapter {}),
+		pow::mine_genesis_block().unwrap(),
+		pow::verify_size,
+		verifier_cache,
+		false,
+	)
+	.unwrap()
+}
+
+fn prepare_block<K>(kc: &K, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block
+where
+	K: Keychain,
+{
+	let key_id = ExtKeychainPath::new(1, diff as u32, 0, 0, 0).to_identifier();
+	let reward =
+		libtx::reward::output(kc, &libtx::ProofBuilder::new(kc), &key_id, 0, false).unwrap();
+	let mut b = Block::new(prev, vec![], Difficulty::from_num(diff), reward).unwrap();
+	b.header.timestamp = prev.timestamp + Duration::seconds(60);
+	b.header.pow.total_difficulty = prev.total_difficulty() + Difficulty::from_num(diff);
+	b.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut b).unwrap();
+	b
+}
+
+#[test]
+fn refuse_headers_conflicting_with_checkpoint() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".grin_checkpoints";
+	{
+		let chain = setup(chain_dir);
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		let genesis = chain.head_header().unwrap();
+		let b1 = prepare_block(&kc, &genesis, &chain, 2);
+		// competing block at the same height, with more work
+		let b1_fork = prepare_block(&kc, &genesis, &chain, 3);
+
+		let mut checkpoints = BTreeMap::new();
+		checkpoints.insert(1, b1.hash());
+		global::set_extra_checkpoints(checkpoints);
+		assert_eq!(global::checkpoint(1), Some(b1.hash()));
+		assert_eq!(global::checkpoint(2), None);
+
+		let err = chain
+			.sync_block_headers(&[b1_fork.header.clone()], Options::SKIP_POW)
+			.unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::CheckpointMismatch(1));
+		assert!(err.is_bad_data());
+
+		let err = chain
+			.process_block_header(&b1_fork.header, Options::SKIP_POW)
+			.unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::CheckpointMismatch(1));
+
+		let err = chain.process_block(b1_fork, Options::SKIP_POW).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::CheckpointMismatch(1));
+		assert_eq!(chain.head().unwrap().height, 0);
+
+		// the checkpointed chain goes through, and so does what builds on it
+		chain
+			.sync_block_headers(&[b1.header.clone()], Options::SKIP_POW)
+			.unwrap();
+		chain.process_block(b1.clone(), Options::SKIP_POW).unwrap();
+		let b2 = prepare_block(&kc, &b1.header, &chain, 4);
+		chain.process_block(b2, Options::SKIP_POW).unwrap();
+		assert_eq!(chain.head().unwrap().height, 2);
+
+		global::set_extra_checkpoints(BTreeMap::new());
+	}
+	clean_output_dir(chain_dir);
+}
@@ -155,6 +155,15 @@ fn comments() -> HashMap<String, String> {
 #The url where a POST request will be sent when a new block is received by a peer.
 #block_received_url = \"http://127.0.0.1:8080/block\"
 
+#The url where a POST request will be sent when our node may be isolated from the rest of the network.
+#network_alert_url = \"http://127.0.0.1:8080/networkalert\"
+
+#The url where a POST request will be sent when a transaction accepted by our pool changes status.
+#tx_status_url = \"http://127.0.0.1:8080/txstatus\"
+
+#The url where a POST request will be sent when a transaction spending the same outputs as transactions in our pool is received.
+#tx_conflict_url = \"http://127.0.0.1:8080/txconflict\"
+
 #The number of worker threads that will be assigned to making the http requests.
 "
 		.to_string(),
@@ -197,7 +206,8 @@ fn comments() -> HashMap<String, String> {
 	retval.insert(
 		"embargo_secs".to_string(),
 		"
-#fluff and broadcast after embargo expires if tx not seen on network
+#fluff and broadcast after embargo expires if tx not seen on network,
+#each tx gets a random embargo averaging this many secs
 "
 		.to_string(),
 	);
@@ -215,6 +225,12 @@ fn comments() -> HashMap<String, String> {
 		"#test miner wallet URL (burns if this doesn't exist)
 #test_miner_wallet_url = \"http://127.0.0.1:3415\"
 
+#header checkpoints, refusing any header chain with another block at
+#the given height, on top of the ones hard-coded for the chain type
+#[[server.checkpoints]]
+#height = 100000
+#hash = \"<block hash>\"
+
 #########################################
 ### SERVER P2P CONFIGURATION          ###
 #########################################
@@ -273,12 +289,35 @@ fn comments() -> HashMap<String, String> {
 #until we get to at least this number
 #peer_min_preferred_count = 8
 
+#number of extra outbound connections only relaying headers and blocks
+#(no transactions, no peer addresses), on top of the ones above
+#block_relay_only_count = 2
+
+#maximum number of inbound connections from a single IP address and from a
+#single network group (IPv4 /16 or IPv6 /32), when full inbound peers get
+#evicted to make room for new ones, favoring diversity and useful peers
+#peer_max_inbound_per_ip = 1
+#peer_max_inbound_per_net_group = 8
+
 # 15 = Bit flags for FULL_NODE
 #This structure needs to be changed internally, to make it more configurable
 
 # A preferred dandelion_peer, mainly used for testing dandelion
 # dandelion_peer = \"10.0.0.1:13144\"
 
+#a socks5 proxy (typically a local Tor daemon) for outbound connections,
+#required to connect to onion peers (\"<onion v3 host>.onion:3414\")
+#socks5_proxy = \"127.0.0.1:9050\"
+
+#which outbound connections go through the socks5 proxy, can be
+#OnionOnly (default), Prefer (also favours onion peers) or Require
+#(only connects to onion peers, without looking up DNS seeds)
+#proxy_mode = \"OnionOnly\"
+
+#file every message exchanged with peers is appended to, to replay
+#what peers sent when diagnosing sync issues (grows quickly)
+#capture_file = \"p2p_capture.bin\"
+
 "
 		.to_string(),
 	);
@@ -304,7 +343,8 @@ fn comments() -> HashMap<String, String> {
 	retval.insert(
 		"max_pool_size".to_string(),
 		"
-#maximum number of transactions allowed in the pool
+#maximum number of transactions allowed in the pool, once full the ones
+#paying the lowest fee per weight get evicted to make room for better ones
 "
 		.to_string(),
 	);
@@ -325,6 +365,32 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"persist_interval_secs".to_string(),
+		"
+#how often (in seconds) the pool content gets saved to disk, to be restored
+#after a restart (it is always saved on shutdown, 0 to only save then)
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.pool_config.policy]".to_string(),
+		"
+#admission limits on the transactions submitted to us, not enforced unless set
+#maximum number of inputs and outputs of a transaction
+#max_inputs = 500
+#max_outputs = 500
+#maximum number of transactions a single peer (or the push API) can submit
+#per minute
+#max_txs_per_minute = 120
+#minimum fee per unit of weight by source (p2p or push-api)
+#[server.pool_config.policy.min_fee_base]
+#p2p = 1000000
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
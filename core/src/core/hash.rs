@@ -215,6 +215,10 @@ impl ser::Writer for HashWriter {
 		ser::SerializationMode::Hash
 	}
 
+	fn protocol_version(&self) -> ser::ProtocolVersion {
+		ser::ProtocolVersion::local()
+	}
+
 	fn write_fixed_bytes<T: AsFixedBytes>(&mut self, b32: &T) -> Result<(), ser::Error> {
 		self.state.update(b32.as_ref());
 		Ok(())
@@ -86,6 +86,26 @@ where
 		}
 	}
 
+	/// Get the hash at provided position in the MMR, ignoring the leaf set
+	/// (spent leaves still have a hash until they're compacted).
+	pub fn get_from_file(&self, pos: u64) -> Option<Hash> {
+		if pos > self.last_pos {
+			None
+		} else {
+			self.backend.get_from_file(pos)
+		}
+	}
+
+	/// Get the data element at provided position in the MMR, ignoring the
+	/// leaf set.
+	pub fn get_data_from_file(&self, pos: u64) -> Option<T::E> {
+		if pos > self.last_pos || !is_leaf(pos) {
+			None
+		} else {
+			self.backend.get_data_from_file(pos)
+		}
+	}
+
 	/// Is the MMR empty?
 	pub fn is_empty(&self) -> bool {
 		self.last_pos == 0
@@ -0,0 +1,475 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Segments of a PMMR. A segment covers a contiguous range of leaves of the
+//! MMR, holding the leaf data along with the hashes of the subtrees that have
+//! been pruned away, and comes with a proof against the MMR root. This lets a
+//! syncing node download and verify the txhashset piece by piece from many
+//! peers.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use croaring::Bitmap;
+
+use crate::core::hash::Hash;
+use crate::core::pmmr::pmmr::{
+	bintree_leftmost, bintree_postorder_height, family_branch, insertion_to_pmmr_index, is_leaf,
+	is_left_sibling, n_leaves, path, peaks,
+};
+use crate::core::pmmr::{Backend, ReadonlyPMMR};
+use crate::ser::{self, PMMRIndexHashable, PMMRable, Readable, Reader, Writeable, Writer};
+
+/// Maximum height of a segment, a segment can't hold more than 2^16 leaves.
+pub const MAX_SEGMENT_HEIGHT: u8 = 16;
+
+/// Maximum number of hashes in a segment proof, way more than the branch to
+/// a peak and all the other peaks of any MMR we could build.
+const MAX_SEGMENT_PROOF_SIZE: u64 = 128;
+
+/// Segment errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SegmentError {
+	/// The segment does not exist in a MMR of that size.
+	NonExistent,
+	/// A leaf is missing, either in the backend or in the segment.
+	MissingLeaf(u64),
+	/// A hash is missing, either in the backend or in the segment.
+	MissingHash(u64),
+	/// The segment content is inconsistent: out of range, unordered or
+	/// overlapping positions, pruned data in a non-prunable MMR...
+	Malformed,
+	/// The segment does not hash to the expected root.
+	RootMismatch,
+	/// The spent leaves or pruned subtrees of the segment don't match the
+	/// leaf set of the MMR.
+	LeafSetMismatch,
+}
+
+/// Identifies a segment, the range of 2^height leaves starting at leaf
+/// idx * 2^height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegmentIdentifier {
+	/// Height of the segment subtree.
+	pub height: u8,
+	/// Index of the segment among the segments of that height.
+	pub idx: u64,
+}
+
+impl Writeable for SegmentIdentifier {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.height)?;
+		writer.write_u64(self.idx)
+	}
+}
+
+impl Readable for SegmentIdentifier {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentIdentifier, ser::Error> {
+		let height = reader.read_u8()?;
+		if height > MAX_SEGMENT_HEIGHT {
+			return Err(ser::Error::CorruptedData);
+		}
+		let idx = reader.read_u64()?;
+		Ok(SegmentIdentifier { height, idx })
+	}
+}
+
+impl SegmentIdentifier {
+	/// Number of segments of the provided height needed to cover all the
+	/// leaves of a MMR of the provided size.
+	pub fn count_segments_required(mmr_size: u64, height: u8) -> u64 {
+		let capacity = 1u64 << height;
+		(n_leaves(mmr_size) + capacity - 1) / capacity
+	}
+
+	/// Whether this segment is part of a MMR of the provided size.
+	pub fn exists(&self, mmr_size: u64) -> bool {
+		self.height <= MAX_SEGMENT_HEIGHT
+			&& self.idx < SegmentIdentifier::count_segments_required(mmr_size, self.height)
+	}
+
+	/// Position of the first leaf of the segment.
+	pub fn first_pos(&self) -> u64 {
+		insertion_to_pmmr_index((self.idx << self.height) + 1)
+	}
+
+	/// Position of the root of the segment subtree. Only part of the MMR if
+	/// the segment is full, the last segment may not be.
+	pub fn root_pos(&self) -> u64 {
+		self.first_pos() + (2 << self.height) - 2
+	}
+
+	/// Position of the last node covered by the segment in a MMR of the
+	/// provided size.
+	pub fn last_pos(&self, mmr_size: u64) -> u64 {
+		cmp::min(self.root_pos(), mmr_size)
+	}
+}
+
+/// A segment of a MMR, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct Segment<T> {
+	/// Which segment this is.
+	pub identifier: SegmentIdentifier,
+	/// Positions and hashes of the roots of the pruned subtrees, in
+	/// increasing position order. A fully pruned segment may be a single
+	/// pruned subtree root above the segment.
+	pub hashes: Vec<(u64, Hash)>,
+	/// Positions and data of the leaves, in increasing position order.
+	pub leaves: Vec<(u64, T)>,
+	/// Positions of the leaves that were spent, but whose data is still
+	/// included as their sibling wasn't spent.
+	pub spent: Vec<u64>,
+	/// Sibling hashes from the segment up to its peak followed by the other
+	/// peaks of the MMR, in increasing position order.
+	pub proof: Vec<Hash>,
+}
+
+impl<T> Segment<T> {
+	/// Builds a segment from the provided MMR. Leaves missing from the
+	/// optional leaf set are considered spent, subtrees only made of spent
+	/// leaves are pruned. No leaf set means nothing is pruned, as for
+	/// the kernel MMR.
+	pub fn from_pmmr<U, B>(
+		identifier: SegmentIdentifier,
+		pmmr: &ReadonlyPMMR<'_, U, B>,
+		leaf_set: Option<&Bitmap>,
+	) -> Result<Segment<T>, SegmentError>
+	where
+		U: PMMRable<E = T>,
+		B: Backend<U>,
+	{
+		let mmr_size = pmmr.unpruned_size();
+		if !identifier.exists(mmr_size) {
+			return Err(SegmentError::NonExistent);
+		}
+		let mut segment = Segment {
+			identifier,
+			hashes: vec![],
+			leaves: vec![],
+			spent: vec![],
+			proof: vec![],
+		};
+		let get_hash = |pos: u64| {
+			pmmr.get_from_file(pos)
+				.ok_or(SegmentError::MissingHash(pos))
+		};
+
+		let root_pos = identifier.root_pos();
+		if root_pos <= mmr_size {
+			// A fully pruned segment may have been compacted away, climb
+			// up to the root of the pruned subtree it's part of.
+			let mut top = root_pos;
+			if is_pruned(leaf_set, top) {
+				while let Some(&(parent, _)) = family_branch(top, mmr_size).first() {
+					if !is_pruned(leaf_set, parent) {
+						break;
+					}
+					top = parent;
+				}
+			}
+			segment.add_subtree(top, pmmr, leaf_set)?;
+
+			let branch = family_branch(top, mmr_size);
+			for (_, sibling) in &branch {
+				segment.proof.push(get_hash(*sibling)?);
+			}
+			let peak = branch.last().map(|(parent, _)| *parent).unwrap_or(top);
+			for pos in peaks(mmr_size) {
+				if pos != peak {
+					segment.proof.push(get_hash(pos)?);
+				}
+			}
+		} else {
+			// The last segment isn't full, it covers the rightmost peaks.
+			let first_pos = identifier.first_pos();
+			for pos in peaks(mmr_size) {
+				if pos < first_pos {
+					segment.proof.push(get_hash(pos)?);
+				} else {
+					segment.add_subtree(pos, pmmr, leaf_set)?;
+				}
+			}
+		}
+		Ok(segment)
+	}
+
+	// Adds the subtree under the provided position, pruned subtrees only
+	// contributing their root hash.
+	fn add_subtree<U, B>(
+		&mut self,
+		pos: u64,
+		pmmr: &ReadonlyPMMR<'_, U, B>,
+		leaf_set: Option<&Bitmap>,
+	) -> Result<(), SegmentError>
+	where
+		U: PMMRable<E = T>,
+		B: Backend<U>,
+	{
+		let height = bintree_postorder_height(pos);
+		if height == 0 {
+			let data = pmmr
+				.get_data_from_file(pos)
+				.ok_or(SegmentError::MissingLeaf(pos))?;
+			if is_pruned(leaf_set, pos) {
+				self.spent.push(pos);
+			}
+			self.leaves.push((pos, data));
+		} else if is_pruned(leaf_set, pos) {
+			let hash = pmmr
+				.get_from_file(pos)
+				.ok_or(SegmentError::MissingHash(pos))?;
+			self.hashes.push((pos, hash));
+		} else {
+			self.add_subtree(pos - (1 << height), pmmr, leaf_set)?;
+			self.add_subtree(pos - 1, pmmr, leaf_set)?;
+		}
+		Ok(())
+	}
+}
+
+impl<T> Segment<T>
+where
+	T: PMMRIndexHashable,
+{
+	/// Validates the segment against the root of a MMR of the provided size,
+	/// rebuilding the segment subtree and hashing it up with the proof. Pruned
+	/// subtrees and spent leaves are only accepted in prunable MMRs.
+	pub fn validate(&self, mmr_size: u64, root: Hash, prunable: bool) -> Result<(), SegmentError> {
+		let identifier = self.identifier;
+		if !identifier.exists(mmr_size) {
+			return Err(SegmentError::NonExistent);
+		}
+		if !prunable && (!self.hashes.is_empty() || !self.spent.is_empty()) {
+			return Err(SegmentError::Malformed);
+		}
+		let first_pos = identifier.first_pos();
+		let root_pos = identifier.root_pos();
+		let last_pos = identifier.last_pos(mmr_size);
+
+		// A single pruned subtree root above the segment, which must then be
+		// on the path from the segment root to its peak.
+		let pruned_top = match self.hashes.first() {
+			Some(&(pos, _))
+				if self.hashes.len() == 1 && self.leaves.is_empty() && pos > last_pos =>
+			{
+				if root_pos > mmr_size || !path(root_pos, mmr_size).contains(&pos) {
+					return Err(SegmentError::Malformed);
+				}
+				Some(pos)
+			}
+			_ => None,
+		};
+
+		// Leaves and pruned subtrees must be disjoint subtrees of the
+		// segment, in increasing position order.
+		let mut known = HashMap::new();
+		let mut entries = self
+			.leaves
+			.iter()
+			.map(|(pos, data)| (*pos, data.hash_with_index(*pos - 1), true))
+			.chain(self.hashes.iter().map(|(pos, hash)| (*pos, *hash, false)))
+			.collect::<Vec<_>>();
+		entries.sort_by_key(|(pos, _, _)| *pos);
+		let mut prev_pos = 0;
+		for (pos, hash, leaf) in entries {
+			if leaf != is_leaf(pos) || bintree_leftmost(pos) <= prev_pos {
+				return Err(SegmentError::Malformed);
+			}
+			if pruned_top.is_none() && (bintree_leftmost(pos) < first_pos || pos > last_pos) {
+				return Err(SegmentError::Malformed);
+			}
+			known.insert(pos, hash);
+			prev_pos = pos;
+		}
+		let leaf_pos = self
+			.leaves
+			.iter()
+			.map(|(pos, _)| *pos)
+			.collect::<HashSet<_>>();
+		if self.spent.iter().any(|pos| !leaf_pos.contains(pos)) {
+			return Err(SegmentError::Malformed);
+		}
+
+		// Rebuild the segment subtree (or subtrees for the last segment).
+		if pruned_top.is_none() {
+			for pos in first_pos..=last_pos {
+				let height = bintree_postorder_height(pos);
+				if height == 0 || known.contains_key(&pos) {
+					continue;
+				}
+				let left = known.get(&(pos - (1 << height))).cloned();
+				let right = known.get(&(pos - 1)).cloned();
+				if let (Some(left), Some(right)) = (left, right) {
+					known.insert(pos, (left, right).hash_with_index(pos - 1));
+				}
+			}
+		}
+		let known_hash = |pos: u64| {
+			known
+				.get(&pos)
+				.cloned()
+				.ok_or(SegmentError::MissingHash(pos))
+		};
+
+		// Hash up to the peaks and bag them.
+		let mut proof = self.proof.iter().cloned();
+		let mut next_proof = |pos: u64| proof.next().ok_or(SegmentError::MissingHash(pos));
+		let mut peak_hashes = vec![];
+		if root_pos <= mmr_size {
+			let top = pruned_top.unwrap_or(root_pos);
+			let mut hash = known_hash(top)?;
+			let mut peak = top;
+			for (parent, sibling) in family_branch(top, mmr_size) {
+				let sibling_hash = next_proof(sibling)?;
+				hash = if is_left_sibling(sibling) {
+					(sibling_hash, hash).hash_with_index(parent - 1)
+				} else {
+					(hash, sibling_hash).hash_with_index(parent - 1)
+				};
+				peak = parent;
+			}
+			for pos in peaks(mmr_size) {
+				if pos == peak {
+					peak_hashes.push(hash);
+				} else {
+					peak_hashes.push(next_proof(pos)?);
+				}
+			}
+		} else {
+			for pos in peaks(mmr_size) {
+				if pos < first_pos {
+					peak_hashes.push(next_proof(pos)?);
+				} else {
+					peak_hashes.push(known_hash(pos)?);
+				}
+			}
+		}
+		if proof.next().is_some() {
+			return Err(SegmentError::Malformed);
+		}
+
+		let mut res = None;
+		for peak in peak_hashes.into_iter().rev() {
+			res = match res {
+				None => Some(peak),
+				Some(rhash) => Some((peak, rhash).hash_with_index(mmr_size)),
+			}
+		}
+		if res != Some(root) {
+			return Err(SegmentError::RootMismatch);
+		}
+		Ok(())
+	}
+
+	/// Checks the spent leaves and pruned subtrees of the segment against the
+	/// leaf set of the MMR, which the root doesn't commit to: a leaf is spent
+	/// if and only if it's missing from the leaf set, and a pruned subtree
+	/// can't hold any leaf of the leaf set.
+	pub fn validate_leaf_set(&self, leaf_set: &Bitmap) -> Result<(), SegmentError> {
+		let spent = self.spent.iter().cloned().collect::<HashSet<_>>();
+		for (pos, _) in &self.leaves {
+			if spent.contains(pos) == leaf_set.contains(*pos as u32) {
+				return Err(SegmentError::LeafSetMismatch);
+			}
+		}
+		if self
+			.hashes
+			.iter()
+			.any(|(pos, _)| !is_pruned(Some(leaf_set), *pos))
+		{
+			return Err(SegmentError::LeafSetMismatch);
+		}
+		Ok(())
+	}
+}
+
+impl<T: Writeable> Writeable for Segment<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.identifier.write(writer)?;
+		writer.write_u64(self.hashes.len() as u64)?;
+		for (pos, hash) in &self.hashes {
+			writer.write_u64(*pos)?;
+			hash.write(writer)?;
+		}
+		writer.write_u64(self.leaves.len() as u64)?;
+		for (pos, data) in &self.leaves {
+			writer.write_u64(*pos)?;
+			data.write(writer)?;
+		}
+		writer.write_u64(self.spent.len() as u64)?;
+		for pos in &self.spent {
+			writer.write_u64(*pos)?;
+		}
+		writer.write_u64(self.proof.len() as u64)?;
+		self.proof.write(writer)
+	}
+}
+
+impl<T: Readable> Readable for Segment<T> {
+	fn read(reader: &mut dyn Reader) -> Result<Segment<T>, ser::Error> {
+		let identifier = SegmentIdentifier::read(reader)?;
+		let max_count = 1u64 << identifier.height;
+
+		let count = read_count(reader, max_count)?;
+		let mut hashes = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let pos = reader.read_u64()?;
+			hashes.push((pos, Hash::read(reader)?));
+		}
+		let count = read_count(reader, max_count)?;
+		let mut leaves = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let pos = reader.read_u64()?;
+			leaves.push((pos, T::read(reader)?));
+		}
+		let count = read_count(reader, max_count)?;
+		let mut spent = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			spent.push(reader.read_u64()?);
+		}
+		let count = read_count(reader, MAX_SEGMENT_PROOF_SIZE)?;
+		let mut proof = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			proof.push(Hash::read(reader)?);
+		}
+
+		Ok(Segment {
+			identifier,
+			hashes,
+			leaves,
+			spent,
+			proof,
+		})
+	}
+}
+
+fn read_count(reader: &mut dyn Reader, max: u64) -> Result<u64, ser::Error> {
+	let count = reader.read_u64()?;
+	if count > max {
+		return Err(ser::Error::TooLargeReadErr);
+	}
+	Ok(count)
+}
+
+// Whether all the leaves under the provided position have been spent.
+fn is_pruned(leaf_set: Option<&Bitmap>, pos: u64) -> bool {
+	match leaf_set {
+		Some(leaf_set) => {
+			let from = bintree_leftmost(pos) - 1;
+			leaf_set.rank(pos as u32) == leaf_set.rank(from as u32)
+		}
+		None => false,
+	}
+}
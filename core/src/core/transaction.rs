@@ -337,6 +337,8 @@ impl Writeable for TxKernelEntry {
 	}
 }
 
+impl DefaultHashable for TxKernelEntry {}
+
 impl Readable for TxKernelEntry {
 	fn read(reader: &mut Reader) -> Result<TxKernelEntry, ser::Error> {
 		let kernel = TxKernel::read(reader)?;
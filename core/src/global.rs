@@ -23,6 +23,7 @@ use crate::consensus::{
 	SECOND_POW_EDGE_BITS, STATE_SYNC_THRESHOLD,
 };
 use crate::core::block::HeaderVersion;
+use crate::core::hash::Hash;
 use crate::pow::{
 	self, new_cuckaroo_ctx, new_cuckarood_ctx, new_cuckatoo_ctx, EdgeType, PoWContext,
 };
@@ -31,6 +32,7 @@ use crate::pow::{
 /// different sets of parameters for different purposes,
 /// e.g. CI, User testing, production values
 use crate::util::RwLock;
+use std::collections::BTreeMap;
 
 /// Define these here, as they should be developer-set, not really tweakable
 /// by users
@@ -85,6 +87,14 @@ pub const PEER_EXPIRATION_REMOVE_TIME: i64 = PEER_EXPIRATION_DAYS * 24 * 3600;
 /// For a node configured as "archival_mode = true" only the txhashset will be compacted.
 pub const COMPACTION_CHECK: u64 = DAY_HEIGHT;
 
+/// Hard-coded header checkpoints for mainnet, as (height, block hash). Any
+/// header chain disagreeing with one of them is refused during sync. Only
+/// blocks well past any plausible reorg get added here, with each release.
+pub const MAINNET_CHECKPOINTS: &[(u64, &str)] = &[];
+
+/// Hard-coded header checkpoints for floonet, as (height, block hash).
+pub const FLOONET_CHECKPOINTS: &[(u64, &str)] = &[];
+
 /// Types of chain a server can run with, dictates the genesis block and
 /// and mining parameters used.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -136,6 +146,10 @@ lazy_static! {
 	/// PoW context type to instantiate
 	pub static ref POW_CONTEXT_TYPE: RwLock<PoWContextTypes> =
 			RwLock::new(PoWContextTypes::Cuckoo);
+
+	/// Checkpoints configured on top of the hard-coded ones
+	pub static ref EXTRA_CHECKPOINTS: RwLock<BTreeMap<u64, Hash>> =
+			RwLock::new(BTreeMap::new());
 }
 
 /// Set the mining mode
@@ -328,6 +342,31 @@ pub fn chain_shortname() -> String {
 	param_ref.shortname()
 }
 
+/// Sets the checkpoints to enforce on top of the hard-coded ones for the
+/// chain type. A hard-coded checkpoint wins over one set here at the same
+/// height.
+pub fn set_extra_checkpoints(checkpoints: BTreeMap<u64, Hash>) {
+	let mut param_ref = EXTRA_CHECKPOINTS.write();
+	*param_ref = checkpoints;
+}
+
+fn hard_coded_checkpoints() -> &'static [(u64, &'static str)] {
+	match *CHAIN_TYPE.read() {
+		ChainTypes::Mainnet => MAINNET_CHECKPOINTS,
+		ChainTypes::Floonet => FLOONET_CHECKPOINTS,
+		_ => &[],
+	}
+}
+
+/// Block hash the header chain must have at the provided height, if there's
+/// a checkpoint there.
+pub fn checkpoint(height: u64) -> Option<Hash> {
+	match hard_coded_checkpoints().iter().find(|(h, _)| *h == height) {
+		Some((_, hash)) => Some(Hash::from_hex(hash).unwrap()),
+		None => EXTRA_CHECKPOINTS.read().get(&height).cloned(),
+	}
+}
+
 /// Converts an iterator of block difficulty data to more a more manageable
 /// vector and pads if needed (which will) only be needed for the first few
 /// blocks after genesis
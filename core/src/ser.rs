@@ -70,6 +70,8 @@ pub enum Error {
 	DuplicateError,
 	/// Block header version (hard-fork schedule).
 	InvalidBlockVersion,
+	/// Data can't be represented with the protocol version in use.
+	UnsupportedProtocolVersion,
 }
 
 impl From<io::Error> for Error {
@@ -93,6 +95,7 @@ impl fmt::Display for Error {
 			Error::TooLargeReadErr => f.write_str("too large read"),
 			Error::HexError(ref e) => write!(f, "hex error {:?}", e),
 			Error::InvalidBlockVersion => f.write_str("invalid block version"),
+			Error::UnsupportedProtocolVersion => f.write_str("unsupported protocol version"),
 		}
 	}
 }
@@ -116,10 +119,73 @@ impl error::Error for Error {
 			Error::TooLargeReadErr => "too large read",
 			Error::HexError(_) => "hex error",
 			Error::InvalidBlockVersion => "invalid block version",
+			Error::UnsupportedProtocolVersion => "unsupported protocol version",
 		}
 	}
 }
 
+/// Our local node protocol version.
+/// We will increment the protocol version with every change to p2p msg serialization
+/// so we will likely connect with peers with both higher and lower protocol versions.
+/// We need to be aware that some msg formats will be potentially incompatible and handle
+/// this for each individual peer connection.
+/// Note: A peer may disconnect and reconnect with an updated protocol version. Normally
+/// the protocol version will increase but we need to handle decreasing values also
+/// as a peer may rollback to previous version of the code.
+///
+/// Version history:
+/// 1: initial version
+/// 2: onion v3 peer addresses in PeerAddrs
+/// 3: chunked txhashset download (manifest and segment msgs)
+/// 4: output, rangeproof and kernel MMR segments (PIBD)
+/// 5: header chain skeleton, for parallel header sync
+/// 6: last seen time and capabilities of peer addresses in PeerAddrs
+const PROTOCOL_VERSION: u32 = 6;
+
+/// Protocol version used when serializing and deserializing, allowing
+/// encodings to vary between versions. Readers and writers carry the version
+/// so individual Readable and Writeable impls can branch on it.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialOrd, PartialEq, Serialize)]
+pub struct ProtocolVersion(pub u32);
+
+impl ProtocolVersion {
+	/// Our local node protocol version.
+	pub fn local() -> ProtocolVersion {
+		ProtocolVersion(PROTOCOL_VERSION)
+	}
+}
+
+impl Default for ProtocolVersion {
+	fn default() -> ProtocolVersion {
+		ProtocolVersion::local()
+	}
+}
+
+impl From<ProtocolVersion> for u32 {
+	fn from(v: ProtocolVersion) -> u32 {
+		v.0
+	}
+}
+
+impl fmt::Display for ProtocolVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl Writeable for ProtocolVersion {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_u32(self.0)
+	}
+}
+
+impl Readable for ProtocolVersion {
+	fn read(reader: &mut dyn Reader) -> Result<ProtocolVersion, Error> {
+		let version = reader.read_u32()?;
+		Ok(ProtocolVersion(version))
+	}
+}
+
 /// Signal to a serializable object how much of its data should be serialized
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum SerializationMode {
@@ -135,6 +201,9 @@ pub trait Writer {
 	/// The mode this serializer is writing in
 	fn serialization_mode(&self) -> SerializationMode;
 
+	/// The protocol version this serializer is writing for
+	fn protocol_version(&self) -> ProtocolVersion;
+
 	/// Writes a u8 as bytes
 	fn write_u8(&mut self, n: u8) -> Result<(), Error> {
 		self.write_fixed_bytes(&[n])
@@ -209,6 +278,8 @@ pub trait Reader {
 	/// Consumes a byte from the reader, producing an error if it doesn't have
 	/// the expected value
 	fn expect_u8(&mut self, val: u8) -> Result<u8, Error>;
+	/// The protocol version the data being read was written for
+	fn protocol_version(&self) -> ProtocolVersion;
 }
 
 /// Trait that every type that can be serialized as binary must implement.
@@ -286,29 +357,67 @@ where
 	fn read(reader: &mut dyn Reader) -> Result<Self, Error>;
 }
 
-/// Deserializes a Readable from any std::io::Read implementation.
+/// Deserializes a Readable from any std::io::Read implementation, using our
+/// local protocol version.
 pub fn deserialize<T: Readable>(source: &mut dyn Read) -> Result<T, Error> {
-	let mut reader = BinReader { source };
+	deserialize_with_version(source, ProtocolVersion::local())
+}
+
+/// Deserializes a Readable from any std::io::Read implementation, for the
+/// provided protocol version.
+pub fn deserialize_with_version<T: Readable>(
+	source: &mut dyn Read,
+	version: ProtocolVersion,
+) -> Result<T, Error> {
+	let mut reader = BinReader::new(source, version);
 	T::read(&mut reader)
 }
 
-/// Serializes a Writeable into any std::io::Write implementation.
+/// Serializes a Writeable into any std::io::Write implementation, using our
+/// local protocol version.
 pub fn serialize<W: Writeable>(sink: &mut dyn Write, thing: &W) -> Result<(), Error> {
-	let mut writer = BinWriter { sink };
+	serialize_with_version(sink, ProtocolVersion::local(), thing)
+}
+
+/// Serializes a Writeable into any std::io::Write implementation, for the
+/// provided protocol version.
+pub fn serialize_with_version<W: Writeable>(
+	sink: &mut dyn Write,
+	version: ProtocolVersion,
+	thing: &W,
+) -> Result<(), Error> {
+	let mut writer = BinWriter::new(sink, version);
 	thing.write(&mut writer)
 }
 
 /// Utility function to serialize a writeable directly in memory using a
 /// Vec<u8>.
 pub fn ser_vec<W: Writeable>(thing: &W) -> Result<Vec<u8>, Error> {
+	ser_vec_with_version(thing, ProtocolVersion::local())
+}
+
+/// Utility function to serialize a writeable directly in memory using a
+/// Vec<u8>, for the provided protocol version.
+pub fn ser_vec_with_version<W: Writeable>(
+	thing: &W,
+	version: ProtocolVersion,
+) -> Result<Vec<u8>, Error> {
 	let mut vec = vec![];
-	serialize(&mut vec, thing)?;
+	serialize_with_version(&mut vec, version, thing)?;
 	Ok(vec)
 }
 
 /// Utility to read from a binary source
 pub struct BinReader<'a> {
 	source: &'a mut dyn Read,
+	version: ProtocolVersion,
+}
+
+impl<'a> BinReader<'a> {
+	/// Constructor for a new BinReader for the provided source and protocol version.
+	pub fn new(source: &'a mut dyn Read, version: ProtocolVersion) -> BinReader<'a> {
+		BinReader { source, version }
+	}
 }
 
 fn map_io_err(err: io::Error) -> Error {
@@ -366,12 +475,17 @@ impl<'a> Reader for BinReader<'a> {
 			})
 		}
 	}
+
+	fn protocol_version(&self) -> ProtocolVersion {
+		self.version
+	}
 }
 
 /// A reader that reads straight off a stream.
 /// Tracks total bytes read so we can verify we read the right number afterwards.
 pub struct StreamingReader<'a> {
 	total_bytes_read: u64,
+	version: ProtocolVersion,
 	stream: &'a mut dyn Read,
 	timeout: Duration,
 }
@@ -379,9 +493,14 @@ pub struct StreamingReader<'a> {
 impl<'a> StreamingReader<'a> {
 	/// Create a new streaming reader with the provided underlying stream.
 	/// Also takes a duration to be used for each individual read_exact call.
-	pub fn new(stream: &'a mut dyn Read, timeout: Duration) -> StreamingReader<'a> {
+	pub fn new(
+		stream: &'a mut dyn Read,
+		version: ProtocolVersion,
+		timeout: Duration,
+	) -> StreamingReader<'a> {
 		StreamingReader {
 			total_bytes_read: 0,
+			version,
 			stream,
 			timeout,
 		}
@@ -450,6 +569,10 @@ impl<'a> Reader for StreamingReader<'a> {
 			})
 		}
 	}
+
+	fn protocol_version(&self) -> ProtocolVersion {
+		self.version
+	}
 }
 
 impl Readable for Commitment {
@@ -597,12 +720,16 @@ impl<T: Hashed> VerifySortedAndUnique<T> for Vec<T> {
 /// to write numbers, byte vectors, hashes, etc.
 pub struct BinWriter<'a> {
 	sink: &'a mut dyn Write,
+	version: ProtocolVersion,
 }
 
 impl<'a> BinWriter<'a> {
 	/// Wraps a standard Write in a new BinWriter
-	pub fn new(write: &'a mut dyn Write) -> BinWriter<'a> {
-		BinWriter { sink: write }
+	pub fn new(write: &'a mut dyn Write, version: ProtocolVersion) -> BinWriter<'a> {
+		BinWriter {
+			sink: write,
+			version,
+		}
 	}
 }
 
@@ -611,6 +738,10 @@ impl<'a> Writer for BinWriter<'a> {
 		SerializationMode::Full
 	}
 
+	fn protocol_version(&self) -> ProtocolVersion {
+		self.version
+	}
+
 	fn write_fixed_bytes<T: AsFixedBytes>(&mut self, fixed: &T) -> Result<(), Error> {
 		let bs = fixed.as_ref();
 		self.sink.write_all(bs)?;
@@ -0,0 +1,292 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod vec_backend;
+
+use self::core::core::hash::Hash;
+use self::core::core::pmmr::{self, ReadonlyPMMR, Segment, SegmentError, SegmentIdentifier, PMMR};
+use self::core::ser;
+use crate::vec_backend::{TestElem, VecBackend};
+use croaring::Bitmap;
+use grin_core as core;
+
+fn build_backend(n: u32) -> VecBackend<TestElem> {
+	let mut ba = VecBackend::new();
+	{
+		let mut pmmr = PMMR::new(&mut ba);
+		for x in 0..n {
+			pmmr.push(&TestElem([0, 0, 0, x])).unwrap();
+		}
+	}
+	ba
+}
+
+fn mmr_size(n: u32) -> u64 {
+	pmmr::insertion_to_pmmr_index(n as u64 + 1) - 1
+}
+
+fn segment(
+	ba: &VecBackend<TestElem>,
+	size: u64,
+	height: u8,
+	idx: u64,
+	leaf_set: Option<&Bitmap>,
+) -> Segment<TestElem> {
+	let pmmr = ReadonlyPMMR::<TestElem, _>::at(ba, size);
+	Segment::from_pmmr(SegmentIdentifier { height, idx }, &pmmr, leaf_set).unwrap()
+}
+
+#[test]
+fn segment_identifier() {
+	// 13 leaves
+	let size = mmr_size(13);
+	assert_eq!(size, 23);
+	assert_eq!(SegmentIdentifier::count_segments_required(size, 2), 4);
+	assert_eq!(SegmentIdentifier::count_segments_required(size, 0), 13);
+
+	let id = SegmentIdentifier { height: 2, idx: 1 };
+	assert_eq!(id.first_pos(), 8);
+	assert_eq!(id.root_pos(), 14);
+	assert_eq!(id.last_pos(size), 14);
+	assert!(id.exists(size));
+
+	let id = SegmentIdentifier { height: 2, idx: 3 };
+	assert_eq!(id.first_pos(), 23);
+	assert_eq!(id.root_pos(), 29);
+	assert_eq!(id.last_pos(size), 23);
+	assert!(id.exists(size));
+	assert!(!SegmentIdentifier { height: 2, idx: 4 }.exists(size));
+}
+
+#[test]
+fn segments_validate() {
+	for n in &[1, 2, 7, 8, 13, 16, 27] {
+		let ba = build_backend(*n);
+		let size = mmr_size(*n);
+		let root = ReadonlyPMMR::<TestElem, _>::at(&ba, size).root();
+		for height in 0..4 {
+			for idx in 0..SegmentIdentifier::count_segments_required(size, height) {
+				let segment = segment(&ba, size, height, idx, None);
+				assert!(segment.hashes.is_empty());
+				assert!(segment.spent.is_empty());
+				assert_eq!(segment.validate(size, root, false), Ok(()));
+				assert_eq!(segment.validate(size, root, true), Ok(()));
+			}
+		}
+	}
+}
+
+#[test]
+fn segment_non_existent() {
+	let ba = build_backend(8);
+	let size = mmr_size(8);
+	let pmmr = ReadonlyPMMR::<TestElem, _>::at(&ba, size);
+	let res = Segment::<TestElem>::from_pmmr(SegmentIdentifier { height: 2, idx: 2 }, &pmmr, None);
+	assert_eq!(res.err(), Some(SegmentError::NonExistent));
+}
+
+#[test]
+fn pruned_segments_validate() {
+	let n = 16;
+	let ba = build_backend(n);
+	let size = mmr_size(n);
+	let root = ReadonlyPMMR::<TestElem, _>::at(&ba, size).root();
+
+	// leaves 1, 2 and 5 to 8 spent, 3 spent but not its sibling 4
+	let mut leaf_set = Bitmap::create();
+	for leaf in (4..=4).chain(9..=n as u64) {
+		leaf_set.add(pmmr::insertion_to_pmmr_index(leaf) as u32);
+	}
+
+	let segment_0 = segment(&ba, size, 3, 0, Some(&leaf_set));
+	// leaves 1 and 2 under 3, leaves 5 to 8 under 14
+	assert_eq!(
+		segment_0
+			.hashes
+			.iter()
+			.map(|(pos, _)| *pos)
+			.collect::<Vec<_>>(),
+		vec![3, 14]
+	);
+	assert_eq!(
+		segment_0
+			.leaves
+			.iter()
+			.map(|(pos, _)| *pos)
+			.collect::<Vec<_>>(),
+		vec![4, 5]
+	);
+	assert_eq!(segment_0.spent, vec![4]);
+	assert_eq!(segment_0.validate(size, root, true), Ok(()));
+	assert_eq!(
+		segment_0.validate(size, root, false),
+		Err(SegmentError::Malformed)
+	);
+
+	// a fully pruned segment is a single hash, whatever its height
+	let segment_1 = segment(&ba, size, 1, 2, Some(&leaf_set));
+	assert_eq!(
+		segment_1
+			.hashes
+			.iter()
+			.map(|(pos, _)| *pos)
+			.collect::<Vec<_>>(),
+		vec![14]
+	);
+	assert!(segment_1.leaves.is_empty());
+	assert_eq!(segment_1.validate(size, root, true), Ok(()));
+
+	let segment_2 = segment(&ba, size, 3, 1, Some(&leaf_set));
+	assert!(segment_2.hashes.is_empty());
+	assert_eq!(segment_2.validate(size, root, true), Ok(()));
+}
+
+#[test]
+fn tampered_segments_rejected() {
+	let n = 13;
+	let ba = build_backend(n);
+	let size = mmr_size(n);
+	let root = ReadonlyPMMR::<TestElem, _>::at(&ba, size).root();
+	let good = segment(&ba, size, 2, 1, None);
+	assert_eq!(good.validate(size, root, false), Ok(()));
+
+	let mut bad = good.clone();
+	bad.leaves[1].1 = TestElem([1, 0, 0, 0]);
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::RootMismatch)
+	);
+
+	let mut bad = good.clone();
+	bad.proof[0] = Hash::default();
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::RootMismatch)
+	);
+
+	let mut bad = good.clone();
+	bad.proof.push(Hash::default());
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::Malformed)
+	);
+
+	let mut bad = good.clone();
+	bad.proof.pop();
+	assert!(bad.validate(size, root, false).is_err());
+
+	let mut bad = good.clone();
+	bad.leaves.remove(2);
+	assert!(bad.validate(size, root, false).is_err());
+
+	let mut bad = good.clone();
+	bad.leaves[1] = bad.leaves[0].clone();
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::Malformed)
+	);
+
+	// a leaf out of the segment range
+	let other = segment(&ba, size, 2, 0, None);
+	let mut bad = good.clone();
+	bad.leaves[0] = other.leaves[3].clone();
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::Malformed)
+	);
+
+	// spent leaves have to be part of the segment
+	let mut bad = good.clone();
+	bad.spent.push(1);
+	assert_eq!(bad.validate(size, root, true), Err(SegmentError::Malformed));
+
+	// leaves can't be replaced with their hash in a non-prunable MMR
+	let pmmr = ReadonlyPMMR::<TestElem, _>::at(&ba, size);
+	let mut bad = good.clone();
+	bad.leaves.truncate(2);
+	bad.hashes.push((13, pmmr.get_from_file(13).unwrap()));
+	assert_eq!(
+		bad.validate(size, root, false),
+		Err(SegmentError::Malformed)
+	);
+	assert_eq!(bad.validate(size, root, true), Ok(()));
+
+	// wrong MMR size
+	assert!(good.validate(mmr_size(n + 1), root, false).is_err());
+}
+
+#[test]
+fn segments_match_leaf_set() {
+	let n = 16;
+	let ba = build_backend(n);
+	let size = mmr_size(n);
+	let root = ReadonlyPMMR::<TestElem, _>::at(&ba, size).root();
+
+	// leaves 1 to 3 and 5 to 8 spent
+	let mut leaf_set = Bitmap::create();
+	for leaf in (4..=4).chain(9..=n as u64) {
+		leaf_set.add(pmmr::insertion_to_pmmr_index(leaf) as u32);
+	}
+	let good = segment(&ba, size, 3, 0, Some(&leaf_set));
+	assert_eq!(good.validate_leaf_set(&leaf_set), Ok(()));
+
+	// the root doesn't commit to which leaves are spent
+	let mut bad = good.clone();
+	bad.spent.push(5);
+	assert_eq!(bad.validate(size, root, true), Ok(()));
+	assert_eq!(
+		bad.validate_leaf_set(&leaf_set),
+		Err(SegmentError::LeafSetMismatch)
+	);
+
+	let mut bad = good.clone();
+	bad.spent.clear();
+	assert_eq!(bad.validate(size, root, true), Ok(()));
+	assert_eq!(
+		bad.validate_leaf_set(&leaf_set),
+		Err(SegmentError::LeafSetMismatch)
+	);
+
+	// nor to which subtrees are pruned, leaves 9 and 10 are unspent
+	let pmmr = ReadonlyPMMR::<TestElem, _>::at(&ba, size);
+	let mut bad = segment(&ba, size, 3, 1, Some(&leaf_set));
+	assert_eq!(bad.validate_leaf_set(&leaf_set), Ok(()));
+	bad.leaves.drain(0..2);
+	bad.hashes.push((18, pmmr.get_from_file(18).unwrap()));
+	assert_eq!(bad.validate(size, root, true), Ok(()));
+	assert_eq!(
+		bad.validate_leaf_set(&leaf_set),
+		Err(SegmentError::LeafSetMismatch)
+	);
+}
+
+#[test]
+fn segment_ser_deser() {
+	let ba = build_backend(13);
+	let size = mmr_size(13);
+	let mut leaf_set = Bitmap::create();
+	leaf_set.add(pmmr::insertion_to_pmmr_index(9) as u32);
+	let segment = segment(&ba, size, 2, 2, Some(&leaf_set));
+	assert_eq!(segment.spent.len(), 1);
+
+	let mut vec = Vec::new();
+	ser::serialize(&mut vec, &segment).expect("serialization failed");
+	let segment_2: Segment<TestElem> = ser::deserialize(&mut &vec[..]).unwrap();
+
+	assert_eq!(segment.identifier, segment_2.identifier);
+	assert_eq!(segment.hashes, segment_2.hashes);
+	assert_eq!(segment.leaves, segment_2.leaves);
+	assert_eq!(segment.spent, segment_2.spent);
+	assert_eq!(segment.proof, segment_2.proof);
+}
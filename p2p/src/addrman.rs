@@ -0,0 +1,322 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Address manager, decides which of the peer addresses we know about are
+//! worth keeping around and picks the ones we hand out or connect to.
+//!
+//! Addresses live in one of two tables made of fixed size buckets. The "new"
+//! table holds addresses we never successfully connected to, bucketed by the
+//! network group of both the address and the peer that advertised it. The
+//! "tried" table holds addresses we did connect to, bucketed by their own
+//! network group. Bucket positions are derived from a secret key so a single
+//! source (or a single network group) can only ever fill a small fraction of
+//! each table, making it expensive to flood us with addresses controlled by
+//! an attacker.
+//!
+//! Tables are rebuilt from the peer store on startup, the store itself
+//! remains the source of truth for peer data.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use chrono::Utc;
+use rand::{thread_rng, Rng};
+
+use crate::store::{PeerData, State};
+use crate::types::{NetGroup, PeerAddr, PEER_ADDR_MAX_AGE_SECS};
+
+/// Number of buckets in the new table.
+const NEW_BUCKET_COUNT: usize = 1024;
+
+/// Number of buckets in the tried table.
+const TRIED_BUCKET_COUNT: usize = 256;
+
+/// Maximum number of addresses in a bucket.
+const BUCKET_SIZE: usize = 64;
+
+/// Number of new buckets addresses advertised by a given source group can
+/// end up in.
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// Number of tried buckets addresses from a given group can end up in.
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Addresses we attempted to connect to recently are never considered
+/// terrible, regardless of their history.
+const RECENT_ATTEMPT_SECS: i64 = 60;
+
+/// Failed attempts after which an address we never connected to is
+/// considered terrible.
+const MAX_RETRIES: u32 = 3;
+
+/// Failed attempts since the last success after which an address we used to
+/// connect to is considered terrible.
+const MAX_FAILURES: u32 = 10;
+
+/// How long since the last success before a failing address we used to
+/// connect to can be considered terrible.
+const MIN_FAIL_SECS: i64 = 7 * 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Table {
+	New,
+	Tried,
+}
+
+struct Entry {
+	data: PeerData,
+	table: Table,
+	bucket: usize,
+}
+
+/// Keeps track of the addresses worth connecting to in bucketed new and
+/// tried tables.
+pub struct AddrManager {
+	key: RandomState,
+	entries: HashMap<PeerAddr, Entry>,
+	new_buckets: Vec<Vec<PeerAddr>>,
+	tried_buckets: Vec<Vec<PeerAddr>>,
+}
+
+impl AddrManager {
+	/// Builds an address manager with a fresh secret key from the peers in
+	/// our store.
+	pub fn new(peers: Vec<PeerData>) -> AddrManager {
+		let mut addr_manager = AddrManager {
+			key: RandomState::new(),
+			entries: HashMap::new(),
+			new_buckets: vec![vec![]; NEW_BUCKET_COUNT],
+			tried_buckets: vec![vec![]; TRIED_BUCKET_COUNT],
+		};
+		for p in peers {
+			addr_manager.update(p);
+		}
+		addr_manager
+	}
+
+	/// Number of addresses in the new and tried tables.
+	pub fn table_sizes(&self) -> (usize, usize) {
+		let tried = self
+			.entries
+			.values()
+			.filter(|e| e.table == Table::Tried)
+			.count();
+		(self.entries.len() - tried, tried)
+	}
+
+	/// Whether the address is currently held in one of our tables.
+	pub fn contains(&self, addr: PeerAddr) -> bool {
+		self.entries.contains_key(&addr)
+	}
+
+	/// Whether the address is currently held in the tried table.
+	pub fn is_tried(&self, addr: PeerAddr) -> bool {
+		self.entries
+			.get(&addr)
+			.map(|e| e.table == Table::Tried)
+			.unwrap_or(false)
+	}
+
+	/// Adds or refreshes a peer. Peers we successfully connected to go (or
+	/// get promoted) to the tried table, others to the new table. The peer
+	/// may not make it in a table at all if its bucket is full of better
+	/// candidates.
+	pub fn update(&mut self, data: PeerData) {
+		if let Some(entry) = self.entries.get_mut(&data.addr) {
+			let promote = entry.table == Table::New && data.successes > 0;
+			entry.data = data.clone();
+			if promote {
+				self.remove(data.addr);
+				self.add_tried(data);
+			}
+			return;
+		}
+		if data.successes > 0 {
+			self.add_tried(data);
+		} else {
+			self.add_new(data);
+		}
+	}
+
+	/// Removes an address from our tables.
+	pub fn remove(&mut self, addr: PeerAddr) {
+		if let Some(entry) = self.entries.remove(&addr) {
+			let bucket = match entry.table {
+				Table::New => &mut self.new_buckets[entry.bucket],
+				Table::Tried => &mut self.tried_buckets[entry.bucket],
+			};
+			bucket.retain(|a| *a != addr);
+		}
+	}
+
+	/// Picks up to `count` peers matching the provided filter, randomly
+	/// alternating between the new and tried tables. Peers in the excluded
+	/// network groups are skipped and we pick at most one peer per group,
+	/// unless `diverse_only` is false in which case we top up the selection
+	/// with peers from already picked groups when running out.
+	pub fn select<F>(
+		&self,
+		count: usize,
+		exclude: &HashSet<NetGroup>,
+		diverse_only: bool,
+		filter: F,
+	) -> Vec<PeerData>
+	where
+		F: Fn(&PeerData) -> bool,
+	{
+		let mut rng = thread_rng();
+		let non_empty = |buckets: &Vec<Vec<PeerAddr>>| {
+			buckets
+				.iter()
+				.filter(|b| !b.is_empty())
+				.cloned()
+				.collect::<Vec<_>>()
+		};
+		let mut new = non_empty(&self.new_buckets);
+		let mut tried = non_empty(&self.tried_buckets);
+
+		let mut groups = exclude.clone();
+		let mut selected = vec![];
+		let mut same_group = vec![];
+		while selected.len() < count && !(new.is_empty() && tried.is_empty()) {
+			let table = if tried.is_empty() {
+				&mut new
+			} else if new.is_empty() || rng.gen_bool(0.5) {
+				&mut tried
+			} else {
+				&mut new
+			};
+			let i = rng.gen_range(0, table.len());
+			let j = rng.gen_range(0, table[i].len());
+			let addr = table[i].swap_remove(j);
+			if table[i].is_empty() {
+				table.swap_remove(i);
+			}
+
+			let data = &self.entries[&addr].data;
+			if !filter(data) {
+				continue;
+			}
+			let group = addr.net_group();
+			if groups.insert(group.clone()) {
+				selected.push(data.clone());
+			} else if !diverse_only && !exclude.contains(&group) {
+				same_group.push(data.clone());
+			}
+		}
+
+		let missing = count - selected.len();
+		selected.extend(same_group.into_iter().take(missing));
+		selected
+	}
+
+	fn add_new(&mut self, data: PeerData) {
+		let bucket = self.new_bucket(&data);
+		if self.new_buckets[bucket].len() >= BUCKET_SIZE {
+			// make room by evicting a terrible address, otherwise what we
+			// already have is at least as good as the incoming address
+			let now = Utc::now().timestamp();
+			let terrible = self.new_buckets[bucket]
+				.iter()
+				.find(|a| is_terrible(&self.entries[*a].data, now))
+				.cloned();
+			match terrible {
+				Some(addr) => self.remove(addr),
+				None => {
+					trace!(
+						"addr_manager: new bucket {} full, drop {}",
+						bucket,
+						data.addr
+					);
+					return;
+				}
+			}
+		}
+		self.new_buckets[bucket].push(data.addr);
+		self.entries.insert(
+			data.addr,
+			Entry {
+				data,
+				table: Table::New,
+				bucket,
+			},
+		);
+	}
+
+	fn add_tried(&mut self, data: PeerData) {
+		let bucket = self.tried_bucket(data.addr);
+		if self.tried_buckets[bucket].len() >= BUCKET_SIZE {
+			// the address we connected to the longest time ago goes back to
+			// the new table
+			let oldest = self.tried_buckets[bucket]
+				.iter()
+				.min_by_key(|a| self.entries[*a].data.last_connected)
+				.cloned();
+			if let Some(addr) = oldest {
+				let evicted = self.entries[&addr].data.clone();
+				self.remove(addr);
+				self.add_new(evicted);
+			}
+		}
+		self.tried_buckets[bucket].push(data.addr);
+		self.entries.insert(
+			data.addr,
+			Entry {
+				data,
+				table: Table::Tried,
+				bucket,
+			},
+		);
+	}
+
+	/// The new bucket only depends on the source group and a bounded number
+	/// of slices per source group, so a single source can only ever reach
+	/// NEW_BUCKETS_PER_SOURCE_GROUP buckets.
+	fn new_bucket(&self, data: &PeerData) -> usize {
+		let group = data.addr.net_group();
+		let source_group = data.source.unwrap_or(data.addr).net_group();
+		let slice = self.hash(&(&group, &source_group)) % NEW_BUCKETS_PER_SOURCE_GROUP;
+		(self.hash(&(&source_group, slice)) % NEW_BUCKET_COUNT as u64) as usize
+	}
+
+	/// Similarly addresses from a single group can only ever reach
+	/// TRIED_BUCKETS_PER_GROUP tried buckets.
+	fn tried_bucket(&self, addr: PeerAddr) -> usize {
+		let slice = self.hash(&addr) % TRIED_BUCKETS_PER_GROUP;
+		(self.hash(&(&addr.net_group(), slice)) % TRIED_BUCKET_COUNT as u64) as usize
+	}
+
+	fn hash<T: Hash>(&self, t: &T) -> u64 {
+		let mut hasher = self.key.build_hasher();
+		t.hash(&mut hasher);
+		hasher.finish()
+	}
+}
+
+/// Whether an address is so unlikely to be useful it can be evicted to make
+/// room for another one.
+fn is_terrible(data: &PeerData, now: i64) -> bool {
+	if now - data.last_attempt < RECENT_ATTEMPT_SECS {
+		return false;
+	}
+	if data.flags != State::Healthy {
+		return true;
+	}
+	if data.successes == 0 {
+		let stale = data.last_seen > 0 && now - data.last_seen > PEER_ADDR_MAX_AGE_SECS;
+		return stale || data.attempts >= MAX_RETRIES;
+	}
+	data.attempts >= MAX_FAILURES && now - data.last_connected > MIN_FAIL_SECS
+}
@@ -0,0 +1,501 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture of the messages exchanged with peers and their replay, to
+//! reproduce exactly what peers sent us when diagnosing sync issues.
+//!
+//! When `capture_file` is set in the p2p config, every message received or
+//! sent after the handshake is appended to that file, with the time and the
+//! peer it was exchanged with. Attachments (txhashset archives) are left out.
+//! A capture is read back with `CaptureReader` and the received messages fed
+//! to a `NetAdapter` with `replay`, going through the same protocol handling
+//! as live messages. `ReplayAdapter` applies them to a fresh `Chain`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::prelude::{DateTime, TimeZone, Utc};
+
+use crate::chain;
+use crate::conn::{Message, MessageHandler, Tracker};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{self, OutputIdentifier, TxKernelEntry};
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, FixedLength, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use crate::msg::{MsgHeader, MsgHeaderWrapper, TxHashSetManifest, TxHashSetSegment};
+use crate::protocol::Protocol;
+use crate::types::{
+	Capabilities, ChainAdapter, Direction, Error, NetAdapter, PeerAddr, PeerAddrInfo, PeerInfo,
+	PeerLiveInfo, TxHashSetRead,
+};
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::{Mutex, RwLock};
+
+/// Whether a captured message was received from or sent to the peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsgDirection {
+	Received,
+	Sent,
+}
+
+/// A message exchanged with a peer, as captured.
+#[derive(Clone)]
+pub struct CapturedMessage {
+	/// When the message was received or sent
+	pub time: DateTime<Utc>,
+	/// The peer on the other end of the connection
+	pub peer: PeerAddr,
+	/// Protocol version negotiated with the peer, the body is serialized for it
+	pub version: ProtocolVersion,
+	/// Capabilities of the peer
+	pub capabilities: Capabilities,
+	pub direction: MsgDirection,
+	pub header: MsgHeader,
+	/// Raw message body
+	pub body: Vec<u8>,
+}
+
+impl Writeable for CapturedMessage {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.time.timestamp_millis())?;
+		self.peer.write(writer)?;
+		self.version.write(writer)?;
+		writer.write_u32(self.capabilities.bits())?;
+		writer.write_u8(match self.direction {
+			MsgDirection::Received => 0,
+			MsgDirection::Sent => 1,
+		})?;
+		self.header.write(writer)?;
+		writer.write_fixed_bytes(&self.body)
+	}
+}
+
+impl Readable for CapturedMessage {
+	fn read(reader: &mut dyn Reader) -> Result<CapturedMessage, ser::Error> {
+		let time = Utc
+			.timestamp_millis_opt(reader.read_i64()?)
+			.single()
+			.ok_or(ser::Error::CorruptedData)?;
+		let peer = PeerAddr::read(reader)?;
+		let version = ProtocolVersion::read(reader)?;
+		let capabilities = Capabilities::from_bits_truncate(reader.read_u32()?);
+		let direction = match reader.read_u8()? {
+			0 => MsgDirection::Received,
+			1 => MsgDirection::Sent,
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		let header = match MsgHeaderWrapper::read(reader)? {
+			MsgHeaderWrapper::Known(header) => header,
+			MsgHeaderWrapper::Unknown(_) => return Err(ser::Error::CorruptedData),
+		};
+		let body = reader.read_fixed_bytes(header.msg_len as usize)?;
+		Ok(CapturedMessage {
+			time,
+			peer,
+			version,
+			capabilities,
+			direction,
+			header,
+			body,
+		})
+	}
+}
+
+/// Appends the messages of all connections to a capture file.
+pub struct MessageRecorder {
+	file: Mutex<BufWriter<File>>,
+}
+
+impl MessageRecorder {
+	/// Recorder appending to the capture file at the provided path.
+	pub fn new<P: AsRef<Path>>(path: P) -> io::Result<MessageRecorder> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		Ok(MessageRecorder {
+			file: Mutex::new(BufWriter::new(file)),
+		})
+	}
+
+	/// Appends a message to the capture, flushed right away so the capture
+	/// is complete up to a crash.
+	pub fn record(&self, msg: &CapturedMessage) {
+		let mut file = self.file.lock();
+		let res = ser::serialize(&mut *file, msg)
+			.map_err(Error::from)
+			.and_then(|_| file.flush().map_err(Error::from));
+		if let Err(e) = res {
+			error!(
+				"capture: failed to record {:?}: {:?}",
+				msg.header.msg_type, e
+			);
+		}
+	}
+}
+
+/// Records the messages of a single connection.
+#[derive(Clone)]
+pub struct ConnRecorder {
+	recorder: Arc<MessageRecorder>,
+	peer: PeerAddr,
+	version: ProtocolVersion,
+	capabilities: Capabilities,
+}
+
+impl ConnRecorder {
+	pub fn new(recorder: Arc<MessageRecorder>, info: &PeerInfo) -> ConnRecorder {
+		ConnRecorder {
+			recorder,
+			peer: info.addr,
+			version: info.version,
+			capabilities: info.capabilities,
+		}
+	}
+
+	/// Records a message received, its body being read upfront.
+	pub fn received(&self, header: &MsgHeader, body: &[u8]) {
+		self.record(MsgDirection::Received, header.clone(), body);
+	}
+
+	/// Records a message sent, as written on the wire (header then body).
+	pub fn sent(&self, msg: &[u8]) {
+		if msg.len() < MsgHeader::LEN {
+			return;
+		}
+		match ser::deserialize::<MsgHeaderWrapper>(&mut &msg[..MsgHeader::LEN]) {
+			Ok(MsgHeaderWrapper::Known(header)) => {
+				self.record(MsgDirection::Sent, header, &msg[MsgHeader::LEN..])
+			}
+			_ => debug!("capture: skipping unknown message sent to {}", self.peer),
+		}
+	}
+
+	fn record(&self, direction: MsgDirection, header: MsgHeader, body: &[u8]) {
+		self.recorder.record(&CapturedMessage {
+			time: Utc::now(),
+			peer: self.peer,
+			version: self.version,
+			capabilities: self.capabilities,
+			direction,
+			header,
+			body: body.to_vec(),
+		});
+	}
+}
+
+/// Reads back the messages of a capture file, in the order they were
+/// recorded.
+pub struct CaptureReader {
+	reader: BufReader<File>,
+}
+
+impl CaptureReader {
+	/// Opens the capture file at the provided path.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureReader> {
+		Ok(CaptureReader {
+			reader: BufReader::new(File::open(path)?),
+		})
+	}
+}
+
+impl Iterator for CaptureReader {
+	type Item = Result<CapturedMessage, Error>;
+
+	fn next(&mut self) -> Option<Result<CapturedMessage, Error>> {
+		match self.reader.fill_buf() {
+			Ok(buf) if buf.is_empty() => None,
+			Ok(_) => Some(ser::deserialize(&mut self.reader).map_err(From::from)),
+			Err(e) => Some(Err(e.into())),
+		}
+	}
+}
+
+/// Outcome of a replay.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplayStats {
+	/// Received messages replayed
+	pub replayed: usize,
+	/// Received messages the protocol failed to handle
+	pub failed: usize,
+	/// Sent messages, skipped
+	pub skipped: usize,
+}
+
+/// Replays the received messages of a capture against the provided adapter,
+/// in order and without waiting between them, through the protocol handling
+/// of live messages. Responses are dropped.
+pub fn replay<I>(messages: I, adapter: Arc<dyn NetAdapter>) -> ReplayStats
+where
+	I: IntoIterator<Item = CapturedMessage>,
+{
+	let tracker = Arc::new(Tracker::new());
+	let mut stats = ReplayStats::default();
+	for captured in messages {
+		if captured.direction == MsgDirection::Sent {
+			stats.skipped += 1;
+			continue;
+		}
+		let info = PeerInfo {
+			capabilities: captured.capabilities,
+			user_agent: "replay".to_owned(),
+			version: captured.version,
+			addr: captured.peer,
+			direction: Direction::Inbound,
+			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::min()))),
+		};
+		let protocol = Protocol::new(adapter.clone(), info);
+		let msg_type = captured.header.msg_type;
+		let mut body = &captured.body[..];
+		let msg = Message::from_header(captured.header, &mut body, captured.version);
+		match protocol.consume(msg, &mut io::sink(), tracker.clone()) {
+			Ok(_) => stats.replayed += 1,
+			Err(e) => {
+				debug!(
+					"replay: {:?} from {} at {} failed: {:?}",
+					msg_type, captured.peer, captured.time, e
+				);
+				stats.failed += 1;
+			}
+		}
+	}
+	stats
+}
+
+/// Adapter applying replayed headers and blocks to a chain, for lack of a
+/// transaction pool transactions are ignored. Requests from the peer aren't
+/// served.
+pub struct ReplayAdapter {
+	chain: Arc<chain::Chain>,
+	opts: chain::Options,
+	rejected: AtomicUsize,
+}
+
+impl ReplayAdapter {
+	/// Adapter for the provided chain, processing with the provided options
+	/// (`SKIP_POW` for test chains).
+	pub fn new(chain: Arc<chain::Chain>, opts: chain::Options) -> ReplayAdapter {
+		ReplayAdapter {
+			chain,
+			opts,
+			rejected: AtomicUsize::new(0),
+		}
+	}
+
+	/// Number of headers or blocks the chain rejected as invalid, which would
+	/// have gotten the peer banned.
+	pub fn rejected(&self) -> usize {
+		self.rejected.load(Ordering::Relaxed)
+	}
+
+	fn processed<T>(&self, what: Hash, res: Result<T, chain::Error>) -> Result<bool, chain::Error> {
+		match res {
+			Ok(_) => Ok(true),
+			Err(e) => {
+				debug!("replay: {} not accepted: {:?}", what, e);
+				if e.is_bad_data() {
+					self.rejected.fetch_add(1, Ordering::Relaxed);
+					Ok(false)
+				} else {
+					Ok(true)
+				}
+			}
+		}
+	}
+}
+
+impl ChainAdapter for ReplayAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(self.chain.head()?.total_difficulty)
+	}
+
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(self.chain.head()?.height)
+	}
+
+	fn transaction_received(
+		&self,
+		_: core::Transaction,
+		_: bool,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+
+	fn get_transaction(&self, _: Hash) -> Option<core::Transaction> {
+		None
+	}
+
+	fn tx_kernel_received(&self, _: Hash, _: &PeerInfo) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+
+	fn block_received(&self, b: core::Block, _: &PeerInfo, _: bool) -> Result<bool, chain::Error> {
+		let hash = b.hash();
+		self.processed(hash, self.chain.process_block(b, self.opts))
+	}
+
+	fn compact_block_received(
+		&self,
+		cb: core::CompactBlock,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		// only blocks without transactions can be hydrated without a pool
+		if !cb.kern_ids().is_empty() {
+			return Ok(true);
+		}
+		let hash = cb.hash();
+		match core::Block::hydrate_from(cb, vec![]) {
+			Ok(b) => self.processed(hash, self.chain.process_block(b, self.opts)),
+			Err(e) => {
+				debug!("replay: invalid compact block {}: {:?}", hash, e);
+				self.rejected.fetch_add(1, Ordering::Relaxed);
+				Ok(false)
+			}
+		}
+	}
+
+	fn header_received(&self, bh: core::BlockHeader, _: &PeerInfo) -> Result<bool, chain::Error> {
+		self.processed(bh.hash(), self.chain.process_block_header(&bh, self.opts))
+	}
+
+	fn headers_received(
+		&self,
+		bhs: &[core::BlockHeader],
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = match bhs.first() {
+			Some(bh) => bh.hash(),
+			None => return Ok(true),
+		};
+		self.processed(
+			hash,
+			self.chain
+				.sync_block_headers(bhs, self.opts | chain::Options::SYNC),
+		)
+	}
+
+	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+
+	fn locate_skeleton(&self, _: Hash, _: u16) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+
+	fn skeleton_received(
+		&self,
+		_: &[core::BlockHeader],
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+
+	fn get_block(&self, _: Hash) -> Option<core::Block> {
+		None
+	}
+
+	fn kernel_data_read(&self) -> Result<File, chain::Error> {
+		Err(chain::ErrorKind::Other("not served on replay".to_owned()).into())
+	}
+
+	fn kernel_data_write(&self, _: &mut dyn io::Read) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
+	fn txhashset_read(&self, _: Hash) -> Option<TxHashSetRead> {
+		None
+	}
+
+	fn txhashset_manifest(&self, _: Hash) -> Option<TxHashSetManifest> {
+		None
+	}
+
+	fn txhashset_receive_ready(&self) -> bool {
+		false
+	}
+
+	fn txhashset_download_update(&self, _: DateTime<Utc>, _: u64, _: u64) -> bool {
+		false
+	}
+
+	fn txhashset_write(&self, _: Hash, _: File, _: &PeerInfo) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
+	fn txhashset_manifest_received(&self, _: TxHashSetManifest, _: &PeerInfo) {}
+
+	fn txhashset_segment_received(&self, _: TxHashSetSegment, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn get_output_segment(
+		&self,
+		_: Hash,
+		_: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>> {
+		None
+	}
+
+	fn get_rangeproof_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<RangeProof>> {
+		None
+	}
+
+	fn get_kernel_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<TxKernelEntry>> {
+		None
+	}
+
+	fn receive_output_segment(&self, _: Hash, _: Segment<OutputIdentifier>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn receive_rangeproof_segment(&self, _: Hash, _: Segment<RangeProof>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn receive_kernel_segment(&self, _: Hash, _: Segment<TxKernelEntry>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn get_output_bitmap(&self, _: Hash) -> Option<Vec<u8>> {
+		None
+	}
+
+	fn receive_output_bitmap(&self, _: Hash, _: Vec<u8>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn get_tmp_dir(&self) -> PathBuf {
+		self.chain.get_tmp_dir()
+	}
+
+	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
+		self.chain.get_tmpfile_pathname(tmpfile_name)
+	}
+}
+
+impl NetAdapter for ReplayAdapter {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddrInfo> {
+		vec![]
+	}
+
+	fn peer_addrs_received(&self, _: Vec<PeerAddrInfo>, _: PeerAddr) {}
+
+	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
+
+	fn is_banned(&self, _: PeerAddr) -> bool {
+		false
+	}
+}
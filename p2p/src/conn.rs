@@ -30,11 +30,12 @@ use std::{
 	time,
 };
 
+use crate::capture::ConnRecorder;
 use crate::core::ser;
 use crate::core::ser::FixedLength;
 use crate::msg::{
 	read_body, read_discard, read_header, read_item, write_to_buf, MsgHeader, MsgHeaderWrapper,
-	Type,
+	ProtocolVersion, Type,
 };
 use crate::types::Error;
 use crate::util::read_write::{read_exact, write_all};
@@ -65,8 +66,8 @@ macro_rules! try_break {
 			Err(ref e) => {
 				debug!("try_break: exit the loop: {:?}", e);
 				break;
-				}
 			}
+		}
 	};
 }
 
@@ -75,22 +76,31 @@ macro_rules! try_break {
 pub struct Message<'a> {
 	pub header: MsgHeader,
 	stream: &'a mut dyn Read,
+	version: ProtocolVersion,
 }
 
 impl<'a> Message<'a> {
-	fn from_header(header: MsgHeader, stream: &'a mut dyn Read) -> Message<'a> {
-		Message { header, stream }
+	pub(crate) fn from_header(
+		header: MsgHeader,
+		stream: &'a mut dyn Read,
+		version: ProtocolVersion,
+	) -> Message<'a> {
+		Message {
+			header,
+			stream,
+			version,
+		}
 	}
 
 	/// Read the message body from the underlying connection
 	pub fn body<T: ser::Readable>(&mut self) -> Result<T, Error> {
-		read_body(&self.header, self.stream)
+		read_body(&self.header, self.stream, self.version)
 	}
 
 	/// Read a single "thing" from the underlying connection.
 	/// Return the thing and the total bytes read.
 	pub fn streaming_read<T: ser::Readable>(&mut self) -> Result<(T, u64), Error> {
-		read_item(self.stream)
+		read_item(self.stream, self.version)
 	}
 
 	pub fn copy_attachment(&mut self, len: usize, writer: &mut dyn Write) -> Result<usize, Error> {
@@ -122,10 +132,11 @@ pub struct Response<'a> {
 impl<'a> Response<'a> {
 	pub fn new<T: ser::Writeable>(
 		resp_type: Type,
+		version: ProtocolVersion,
 		body: T,
 		stream: &'a mut dyn Write,
 	) -> Result<Response<'a>, Error> {
-		let body = ser::ser_vec(&body)?;
+		let body = ser::ser_vec_with_version(&body, version)?;
 		Ok(Response {
 			resp_type,
 			body,
@@ -134,11 +145,18 @@ impl<'a> Response<'a> {
 		})
 	}
 
-	fn write(mut self, tracker: Arc<Tracker>) -> Result<(), Error> {
+	fn write(
+		mut self,
+		tracker: Arc<Tracker>,
+		recorder: Option<&ConnRecorder>,
+	) -> Result<(), Error> {
 		let mut msg = ser::ser_vec(&MsgHeader::new(self.resp_type, self.body.len() as u64))?;
 		msg.append(&mut self.body);
 		write_all(&mut self.stream, &msg[..], time::Duration::from_secs(10))?;
 		tracker.inc_sent(msg.len() as u64);
+		if let Some(recorder) = recorder {
+			recorder.sent(&msg);
+		}
 
 		if let Some(mut file) = self.attachment {
 			let mut buf = [0u8; 8000];
@@ -202,6 +220,8 @@ impl StopHandle {
 pub struct ConnHandle {
 	/// Channel to allow sending data through the connection
 	pub send_channel: mpsc::SyncSender<Vec<u8>>,
+	/// Protocol version negotiated with the peer, msgs are serialized for it
+	pub version: ProtocolVersion,
 }
 
 impl ConnHandle {
@@ -209,7 +229,7 @@ impl ConnHandle {
 	where
 		T: ser::Writeable,
 	{
-		let buf = write_to_buf(body, msg_type)?;
+		let buf = write_to_buf(body, msg_type, self.version)?;
 		let buf_len = buf.len();
 		self.send_channel.try_send(buf)?;
 		Ok(buf_len as u64)
@@ -252,11 +272,13 @@ impl Tracker {
 
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
-/// itself.
+/// itself. Messages go to the recorder as well, if any.
 pub fn listen<H>(
 	stream: TcpStream,
+	version: ProtocolVersion,
 	tracker: Arc<Tracker>,
 	handler: H,
+	recorder: Option<ConnRecorder>,
 ) -> io::Result<(ConnHandle, StopHandle)>
 where
 	H: MessageHandler,
@@ -267,11 +289,14 @@ where
 	stream
 		.set_nonblocking(true)
 		.expect("Non-blocking IO not available.");
-	let peer_thread = poll(stream, handler, send_rx, close_rx, tracker)?;
+	let peer_thread = poll(
+		stream, version, handler, send_rx, close_rx, tracker, recorder,
+	)?;
 
 	Ok((
 		ConnHandle {
 			send_channel: send_tx,
+			version,
 		},
 		StopHandle {
 			close_channel: close_tx,
@@ -282,10 +307,12 @@ where
 
 fn poll<H>(
 	conn: TcpStream,
+	version: ProtocolVersion,
 	handler: H,
 	send_rx: mpsc::Receiver<Vec<u8>>,
 	close_rx: mpsc::Receiver<()>,
 	tracker: Arc<Tracker>,
+	recorder: Option<ConnRecorder>,
 ) -> io::Result<JoinHandle<()>>
 where
 	H: MessageHandler,
@@ -303,7 +330,25 @@ where
 				// check the read end
 				match try_break!(read_header(&mut reader, None)) {
 					Some(MsgHeaderWrapper::Known(header)) => {
-						let msg = Message::from_header(header, &mut reader);
+						// When capturing, the body is read upfront to be recorded, the
+						// handler then reads it from there and any attachment from the
+						// connection.
+						let mut body = vec![];
+						if let Some(ref recorder) = recorder {
+							body = vec![0u8; header.msg_len as usize];
+							if let Err(e) = read_exact(
+								&mut reader,
+								&mut body,
+								time::Duration::from_secs(20),
+								true,
+							) {
+								debug!("failed to read message body: {:?}", e);
+								break;
+							}
+							recorder.received(&header, &body);
+						}
+						let mut stream = (&body[..]).chain(&mut reader);
+						let msg = Message::from_header(header, &mut stream, version);
 
 						trace!(
 							"Received message header, type {:?}, len {}.",
@@ -317,7 +362,7 @@ where
 						if let Some(Some(resp)) =
 							try_break!(handler.consume(msg, &mut writer, tracker.clone()))
 						{
-							try_break!(resp.write(tracker.clone()));
+							try_break!(resp.write(tracker.clone(), recorder.as_ref()));
 						}
 					}
 					Some(MsgHeaderWrapper::Unknown(msg_len)) => {
@@ -339,8 +384,10 @@ where
 						std::time::Duration::from_secs(10)
 					)
 					.map_err(&From::from));
-					if written.is_none() {
-						retry_send = Ok(data);
+					match (written, recorder.as_ref()) {
+						(None, _) => retry_send = Ok(data),
+						(Some(_), Some(recorder)) => recorder.sent(&data),
+						(Some(_), None) => {}
 					}
 				}
 
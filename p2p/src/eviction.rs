@@ -0,0 +1,112 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inbound eviction policy, decides which inbound peer gets disconnected to
+//! make room for a new one once all our slots are taken.
+//!
+//! A few peers are protected for each of several characteristics that are
+//! hard for an attacker to fake all at once: a distinct network group (picked
+//! through a secret key), a low latency, recently sending us new
+//! transactions or blocks and a long uptime. The peer to evict is then the
+//! most recently connected one of the network group with the most remaining
+//! connections, so flooding us from a single group only ever displaces peers
+//! from that group.
+
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+
+use crate::types::{NetGroup, PeerAddr};
+
+/// Number of peers protected by distinct network group
+const PROTECT_BY_NET_GROUP: usize = 4;
+
+/// Number of peers protected by lowest latency
+const PROTECT_BY_LATENCY: usize = 8;
+
+/// Number of peers protected for most recently sending us a new transaction
+const PROTECT_BY_TX: usize = 4;
+
+/// Number of peers protected for most recently sending us a new block
+const PROTECT_BY_BLOCK: usize = 4;
+
+/// What the eviction policy gets to know about an inbound peer.
+#[derive(Debug, Clone)]
+pub struct EvictionCandidate {
+	/// Address of the peer
+	pub addr: PeerAddr,
+	/// Network group of the peer address
+	pub net_group: NetGroup,
+	/// Keyed hash of the network group, unpredictable to other peers
+	pub net_group_key: u64,
+	/// When the peer connected to us
+	pub connected_at: DateTime<Utc>,
+	/// Round trip time of the last ping, if the peer answered one yet
+	pub latency: Option<Duration>,
+	/// Last time the peer sent us a block extending our chain
+	pub last_block: Option<DateTime<Utc>>,
+	/// Last time the peer sent us a transaction accepted in our pool
+	pub last_tx: Option<DateTime<Utc>>,
+}
+
+/// Picks the peer to disconnect among the provided inbound ones, none if all
+/// of them end up protected.
+pub fn select_to_evict(mut candidates: Vec<EvictionCandidate>) -> Option<PeerAddr> {
+	protect(&mut candidates, PROTECT_BY_NET_GROUP, |a, b| {
+		a.net_group_key.cmp(&b.net_group_key)
+	});
+	protect(&mut candidates, PROTECT_BY_LATENCY, |a, b| {
+		latency_ms(a).cmp(&latency_ms(b))
+	});
+	protect(&mut candidates, PROTECT_BY_TX, |a, b| {
+		b.last_tx.cmp(&a.last_tx)
+	});
+	protect(&mut candidates, PROTECT_BY_BLOCK, |a, b| {
+		b.last_block.cmp(&a.last_block)
+	});
+	// half of whoever is left, the ones connected for the longest
+	let half = candidates.len() / 2;
+	protect(&mut candidates, half, |a, b| {
+		a.connected_at.cmp(&b.connected_at)
+	});
+
+	let mut groups: HashMap<&NetGroup, Vec<&EvictionCandidate>> = HashMap::new();
+	for c in &candidates {
+		groups.entry(&c.net_group).or_insert_with(Vec::new).push(c);
+	}
+	// ties between groups go to the one with the most recent connection
+	groups
+		.values()
+		.max_by_key(|g| (g.len(), g.iter().map(|c| c.connected_at).max()))
+		.and_then(|g| g.iter().max_by_key(|c| c.connected_at))
+		.map(|c| c.addr)
+}
+
+// Removes the first `count` candidates in the provided order from the ones
+// that can be evicted.
+fn protect<F>(candidates: &mut Vec<EvictionCandidate>, count: usize, order: F)
+where
+	F: FnMut(&EvictionCandidate, &EvictionCandidate) -> Ordering,
+{
+	candidates.sort_by(order);
+	let count = cmp::min(count, candidates.len());
+	candidates.drain(..count);
+}
+
+// Peers that never answered a ping come last.
+fn latency_ms(c: &EvictionCandidate) -> i64 {
+	c.latency.map_or(i64::max_value(), |l| l.num_milliseconds())
+}
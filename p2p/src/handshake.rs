@@ -19,6 +19,7 @@ use crate::peer::Peer;
 use crate::types::{Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerLiveInfo};
 use crate::util::RwLock;
 use rand::{thread_rng, Rng};
+use std::cmp;
 use std::collections::VecDeque;
 use std::net::{SocketAddr, TcpStream};
 use std::sync::Arc;
@@ -45,6 +46,9 @@ pub struct Handshake {
 	/// ok).
 	genesis: Hash,
 	config: P2PConfig,
+	/// Our local protocol version, the version negotiated with each peer is
+	/// the lowest of theirs and ours.
+	protocol_version: ProtocolVersion,
 }
 
 impl Handshake {
@@ -55,6 +59,7 @@ impl Handshake {
 			addrs: Arc::new(RwLock::new(VecDeque::with_capacity(ADDRS_CAP))),
 			genesis,
 			config,
+			protocol_version: ProtocolVersion::local(),
 		}
 	}
 
@@ -63,40 +68,59 @@ impl Handshake {
 		capab: Capabilities,
 		total_difficulty: Difficulty,
 		self_addr: PeerAddr,
+		peer_addr: PeerAddr,
 		conn: &mut TcpStream,
 	) -> Result<PeerInfo, Error> {
 		// prepare the first part of the handshake
 		let nonce = self.next_nonce();
-		let peer_addr = match conn.peer_addr() {
-			Ok(pa) => PeerAddr(pa),
-			Err(e) => return Err(Error::Connection(e)),
+
+		// The receiver addr is informational only. An onion address would not
+		// be readable by older peers (and we do not know the peer version yet)
+		// so use the address of the socket instead (the proxy) in that case.
+		let receiver_addr = match peer_addr {
+			PeerAddr::Ip(_) => peer_addr,
+			PeerAddr::Onion(_) => match conn.peer_addr() {
+				Ok(pa) => PeerAddr::Ip(pa),
+				Err(e) => return Err(Error::Connection(e)),
+			},
 		};
 
 		let hand = Hand {
-			version: ProtocolVersion::default(),
+			version: self.protocol_version,
 			capabilities: capab,
 			nonce: nonce,
 			genesis: self.genesis,
 			total_difficulty: total_difficulty,
 			sender_addr: self_addr,
-			receiver_addr: peer_addr,
+			receiver_addr,
 			user_agent: USER_AGENT.to_string(),
 		};
 
 		// write and read the handshake response
-		write_message(conn, hand, Type::Hand)?;
-		let shake: Shake = read_message(conn, Type::Shake)?;
+		// The handshake itself is always exchanged using our local protocol version,
+		// the encoding of Hand and Shake does not vary between versions.
+		write_message(conn, hand, Type::Hand, self.protocol_version)?;
+		let shake: Shake = read_message(conn, self.protocol_version, Type::Shake)?;
 		if shake.genesis != self.genesis {
 			return Err(Error::GenesisMismatch {
 				us: self.genesis,
 				peer: shake.genesis,
 			});
 		}
+		let negotiated_version = self.negotiate_protocol_version(shake.version);
+
+		// the peer does not know (or care) about the kind of connection we
+		// opened, keep track of it on our side
+		let mut capabilities = shake.capabilities;
+		if capab.contains(Capabilities::BLOCK_RELAY_ONLY) {
+			capabilities |= Capabilities::BLOCK_RELAY_ONLY;
+		}
+
 		let peer_info = PeerInfo {
-			capabilities: shake.capabilities,
+			capabilities,
 			user_agent: shake.user_agent,
 			addr: peer_addr,
-			version: shake.version,
+			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(shake.total_difficulty))),
 			direction: Direction::Outbound,
 		};
@@ -108,13 +132,13 @@ impl Handshake {
 		}
 
 		debug!(
-			"Connected! Cumulative {} offered from {:?} {:?} {:?}",
+			"Connected! Cumulative {} offered from {:?} {:?} {:?} (protocol version {})",
 			shake.total_difficulty.to_num(),
 			peer_info.addr,
 			peer_info.user_agent,
-			peer_info.capabilities
+			peer_info.capabilities,
+			peer_info.version,
 		);
-		// when more than one protocol version is supported, choosing should go here
 		Ok(peer_info)
 	}
 
@@ -124,7 +148,7 @@ impl Handshake {
 		total_difficulty: Difficulty,
 		conn: &mut TcpStream,
 	) -> Result<PeerInfo, Error> {
-		let hand: Hand = read_message(conn, Type::Hand)?;
+		let hand: Hand = read_message(conn, self.protocol_version, Type::Hand)?;
 
 		// all the reasons we could refuse this connection for
 		if hand.genesis != self.genesis {
@@ -147,12 +171,14 @@ impl Handshake {
 			}
 		}
 
+		let negotiated_version = self.negotiate_protocol_version(hand.version);
+
 		// all good, keep peer info
 		let peer_info = PeerInfo {
 			capabilities: hand.capabilities,
 			user_agent: hand.user_agent,
 			addr: resolve_peer_addr(hand.sender_addr, &conn),
-			version: hand.version,
+			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(hand.total_difficulty))),
 			direction: Direction::Inbound,
 		};
@@ -165,22 +191,40 @@ impl Handshake {
 			return Err(Error::ConnectionClose);
 		}
 
-		// send our reply with our info
+		// send our reply with our info, including our own version so the peer
+		// can negotiate the same version on its side
 		let shake = Shake {
-			version: ProtocolVersion::default(),
+			version: self.protocol_version,
 			capabilities: capab,
 			genesis: self.genesis,
 			total_difficulty: total_difficulty,
 			user_agent: USER_AGENT.to_string(),
 		};
 
-		write_message(conn, shake, Type::Shake)?;
-		trace!("Success handshake with {}.", peer_info.addr);
+		write_message(conn, shake, Type::Shake, self.protocol_version)?;
+		trace!(
+			"Success handshake with {} (protocol version {}).",
+			peer_info.addr,
+			peer_info.version
+		);
 
-		// when more than one protocol version is supported, choosing should go here
 		Ok(peer_info)
 	}
 
+	/// Negotiate the protocol version to use with a peer. Both sides settle on
+	/// the lowest of the two versions, each of us knowing how to read and write
+	/// msgs for any version up to our own.
+	fn negotiate_protocol_version(&self, other: ProtocolVersion) -> ProtocolVersion {
+		let version = cmp::min(self.protocol_version, other);
+		trace!(
+			"negotiate_protocol_version: local {}, other {}, negotiated {}",
+			self.protocol_version,
+			other,
+			version
+		);
+		version
+	}
+
 	/// Generate a new random nonce and store it in our ring buffer
 	fn next_nonce(&self) -> u64 {
 		let nonce = thread_rng().gen();
@@ -195,10 +239,17 @@ impl Handshake {
 }
 
 /// Resolve the correct peer_addr based on the connection and the advertised port.
+/// An advertised onion address is only trusted on connections forwarded to us
+/// by the local Tor daemon from our onion service, which come from loopback
+/// and tell us nothing about the peer. Anyone else could claim any onion
+/// address so gets identified by the ip they connect from.
 fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> PeerAddr {
-	let port = advertised.0.port();
 	if let Ok(addr) = conn.peer_addr() {
-		PeerAddr(SocketAddr::new(addr.ip(), port))
+		if advertised.is_onion() && addr.ip().is_loopback() {
+			advertised
+		} else {
+			PeerAddr::Ip(SocketAddr::new(addr.ip(), advertised.port()))
+		}
 	} else {
 		advertised
 	}
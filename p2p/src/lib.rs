@@ -36,7 +36,10 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+mod addrman;
+pub mod capture;
 mod conn;
+mod eviction;
 pub mod handshake;
 pub mod msg;
 mod peer;
@@ -44,14 +47,19 @@ mod peers;
 mod protocol;
 mod serv;
 mod store;
+mod tor;
 pub mod types;
 
+pub use crate::addrman::AddrManager;
 pub use crate::conn::SEND_CHANNEL_CAP;
+pub use crate::eviction::{select_to_evict, EvictionCandidate};
 pub use crate::peer::Peer;
 pub use crate::peers::Peers;
 pub use crate::serv::{DummyAdapter, Server};
 pub use crate::store::{PeerData, State};
+pub use crate::tor::OnionAddr;
 pub use crate::types::{
-	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Direction, Error, NetGroup, P2PConfig, PeerAddr, PeerAddrInfo,
+	PeerAddrParseError, PeerInfo, ProxyMode, ReasonForBan, Seeding, TxHashSetRead,
+	MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_OUTPUT_BITMAP_SIZE, MAX_PEER_ADDRS, MAX_SKELETON_HEADERS,
 };
@@ -15,29 +15,27 @@
 //! Message types that transit over the network and related serialization code.
 
 use num::FromPrimitive;
-use std::fmt;
-use std::io::{Read, Write};
-use std::time;
+use std::io::{self, Read, Write};
+use std::{cmp, time};
 
-use crate::core::core::hash::Hash;
-use crate::core::core::BlockHeader;
+use crate::chain::txhashset::{
+	KERNEL_SEGMENT_HEIGHT, OUTPUT_SEGMENT_HEIGHT, RANGEPROOF_SEGMENT_HEIGHT,
+};
+use crate::core::core::hash::{Hash, HashWriter};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{BlockHeader, OutputIdentifier, TxKernelEntry};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, FixedLength, Readable, Reader, StreamingReader, Writeable, Writer};
+
+pub use crate::core::ser::ProtocolVersion;
 use crate::core::{consensus, global};
 use crate::types::{
-	Capabilities, Error, PeerAddr, ReasonForBan, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, Error, PeerAddr, PeerAddrInfo, ReasonForBan, MAX_BLOCK_HEADERS, MAX_LOCATORS,
+	MAX_OUTPUT_BITMAP_SIZE, MAX_PEER_ADDRS, MAX_SKELETON_HEADERS, MAX_TXHASHSET_SEGMENTS,
+	TXHASHSET_SEGMENT_SIZE,
 };
 use crate::util::read_write::read_exact;
-
-/// Our local node protocol version.
-/// We will increment the protocol version with every change to p2p msg serialization
-/// so we will likely connect with peers with both higher and lower protocol versions.
-/// We need to be aware that some msg formats will be potentially incompatible and handle
-/// this for each individual peer connection.
-/// Note: A peer may disconnect and reconnect with an updated protocol version. Normally
-/// the protocol version will increase but we need to handle decreasing values also
-/// as a peer may rollback to previous version of the code.
-const PROTOCOL_VERSION: u32 = 1;
+use crate::util::secp::pedersen::RangeProof;
 
 /// Grin's user agent with current version
 pub const USER_AGENT: &'static str = concat!("MW/Grin ", env!("CARGO_PKG_VERSION"));
@@ -76,6 +74,20 @@ enum_from_primitive! {
 		TransactionKernel = 20,
 		KernelDataRequest = 21,
 		KernelDataResponse = 22,
+		TxHashSetManifestRequest = 23,
+		TxHashSetManifest = 24,
+		TxHashSetSegmentRequest = 25,
+		TxHashSetSegment = 26,
+		GetOutputSegment = 27,
+		OutputSegment = 28,
+		GetRangeProofSegment = 29,
+		RangeProofSegment = 30,
+		GetKernelSegment = 31,
+		KernelSegment = 32,
+		GetHeaderSkeleton = 33,
+		HeaderSkeleton = 34,
+		GetOutputBitmap = 35,
+		OutputBitmap = 36,
 	}
 }
 
@@ -90,6 +102,13 @@ fn default_max_msg_size() -> u64 {
 }
 
 // Max msg size for each msg type.
+// Block hash, segment identifier, a position and an element (or hash) for
+// each leaf, the spent positions and the proof.
+fn max_segment_size(height: u8, elmt_len: usize) -> u64 {
+	let elmt_len = cmp::max(elmt_len, Hash::LEN) as u64;
+	32 + 9 + 4 * 8 + (1u64 << height) * (16 + elmt_len) + 128 * 32
+}
+
 fn max_msg_size(msg_type: Type) -> u64 {
 	match msg_type {
 		Type::Error => 0,
@@ -98,7 +117,7 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::Ping => 16,
 		Type::Pong => 16,
 		Type::GetPeerAddrs => 4,
-		Type::PeerAddrs => 4 + (1 + 16 + 2) * MAX_PEER_ADDRS as u64,
+		Type::PeerAddrs => 4 + (1 + 32 + 2 + 4 + 8) * MAX_PEER_ADDRS as u64,
 		Type::GetHeaders => 1 + 32 * MAX_LOCATORS as u64,
 		Type::Header => 365,
 		Type::Headers => 2 + 365 * MAX_BLOCK_HEADERS as u64,
@@ -115,6 +134,20 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::TransactionKernel => 32,
 		Type::KernelDataRequest => 0,
 		Type::KernelDataResponse => 8,
+		Type::TxHashSetManifestRequest => 40,
+		Type::TxHashSetManifest => 64 + 32 * MAX_TXHASHSET_SEGMENTS,
+		Type::TxHashSetSegmentRequest => 40,
+		Type::TxHashSetSegment => 48 + TXHASHSET_SEGMENT_SIZE,
+		Type::GetOutputSegment => 41,
+		Type::OutputSegment => max_segment_size(OUTPUT_SEGMENT_HEIGHT, OutputIdentifier::LEN),
+		Type::GetRangeProofSegment => 41,
+		Type::RangeProofSegment => max_segment_size(RANGEPROOF_SEGMENT_HEIGHT, RangeProof::LEN),
+		Type::GetKernelSegment => 41,
+		Type::KernelSegment => max_segment_size(KERNEL_SEGMENT_HEIGHT, TxKernelEntry::LEN),
+		Type::GetHeaderSkeleton => 34,
+		Type::HeaderSkeleton => 2 + 365 * MAX_SKELETON_HEADERS as u64,
+		Type::GetOutputBitmap => 32,
+		Type::OutputBitmap => 40 + MAX_OUTPUT_BITMAP_SIZE,
 	}
 }
 
@@ -149,19 +182,26 @@ pub fn read_header(
 /// Read a single item from the provided stream, always blocking until we
 /// have a result (or timeout).
 /// Returns the item and the total bytes read.
-pub fn read_item<T: Readable>(stream: &mut dyn Read) -> Result<(T, u64), Error> {
+pub fn read_item<T: Readable>(
+	stream: &mut dyn Read,
+	version: ProtocolVersion,
+) -> Result<(T, u64), Error> {
 	let timeout = time::Duration::from_secs(20);
-	let mut reader = StreamingReader::new(stream, timeout);
+	let mut reader = StreamingReader::new(stream, version, timeout);
 	let res = T::read(&mut reader)?;
 	Ok((res, reader.total_bytes_read()))
 }
 
 /// Read a message body from the provided stream, always blocking
 /// until we have a result (or timeout).
-pub fn read_body<T: Readable>(h: &MsgHeader, stream: &mut dyn Read) -> Result<T, Error> {
+pub fn read_body<T: Readable>(
+	h: &MsgHeader,
+	stream: &mut dyn Read,
+	version: ProtocolVersion,
+) -> Result<T, Error> {
 	let mut body = vec![0u8; h.msg_len as usize];
 	read_exact(stream, &mut body, time::Duration::from_secs(20), true)?;
-	ser::deserialize(&mut &body[..]).map_err(From::from)
+	ser::deserialize_with_version(&mut &body[..], version).map_err(From::from)
 }
 
 /// Read (an unknown) message from the provided stream and discard it.
@@ -172,11 +212,15 @@ pub fn read_discard(msg_len: u64, stream: &mut dyn Read) -> Result<(), Error> {
 }
 
 /// Reads a full message from the underlying stream.
-pub fn read_message<T: Readable>(stream: &mut dyn Read, msg_type: Type) -> Result<T, Error> {
+pub fn read_message<T: Readable>(
+	stream: &mut dyn Read,
+	version: ProtocolVersion,
+	msg_type: Type,
+) -> Result<T, Error> {
 	match read_header(stream, Some(msg_type))? {
 		MsgHeaderWrapper::Known(header) => {
 			if header.msg_type == msg_type {
-				read_body(&header, stream)
+				read_body(&header, stream, version)
 			} else {
 				Err(Error::BadMessage)
 			}
@@ -188,10 +232,14 @@ pub fn read_message<T: Readable>(stream: &mut dyn Read, msg_type: Type) -> Resul
 	}
 }
 
-pub fn write_to_buf<T: Writeable>(msg: T, msg_type: Type) -> Result<Vec<u8>, Error> {
+pub fn write_to_buf<T: Writeable>(
+	msg: T,
+	msg_type: Type,
+	version: ProtocolVersion,
+) -> Result<Vec<u8>, Error> {
 	// prepare the body first so we know its serialized length
 	let mut body_buf = vec![];
-	ser::serialize(&mut body_buf, &msg)?;
+	ser::serialize_with_version(&mut body_buf, version, &msg)?;
 
 	// build and serialize the header using the body size
 	let mut msg_buf = vec![];
@@ -206,8 +254,9 @@ pub fn write_message<T: Writeable>(
 	stream: &mut dyn Write,
 	msg: T,
 	msg_type: Type,
+	version: ProtocolVersion,
 ) -> Result<(), Error> {
-	let buf = write_to_buf(msg, msg_type)?;
+	let buf = write_to_buf(msg, msg_type, version)?;
 	stream.write_all(&buf[..])?;
 	Ok(())
 }
@@ -309,40 +358,6 @@ impl Readable for MsgHeaderWrapper {
 	}
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialOrd, PartialEq, Serialize)]
-pub struct ProtocolVersion(pub u32);
-
-impl Default for ProtocolVersion {
-	fn default() -> ProtocolVersion {
-		ProtocolVersion(PROTOCOL_VERSION)
-	}
-}
-
-impl From<ProtocolVersion> for u32 {
-	fn from(v: ProtocolVersion) -> u32 {
-		v.0
-	}
-}
-
-impl fmt::Display for ProtocolVersion {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{}", self.0)
-	}
-}
-
-impl Writeable for ProtocolVersion {
-	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		writer.write_u32(self.0)
-	}
-}
-
-impl Readable for ProtocolVersion {
-	fn read(reader: &mut dyn Reader) -> Result<ProtocolVersion, ser::Error> {
-		let version = reader.read_u32()?;
-		Ok(ProtocolVersion(version))
-	}
-}
-
 /// First part of a handshake, sender advertises its version and
 /// characteristics.
 pub struct Hand {
@@ -475,17 +490,29 @@ impl Readable for GetPeerAddrs {
 }
 
 /// Peer addresses we know of that are fresh enough, in response to
-/// GetPeerAddrs.
+/// GetPeerAddrs or relayed as we learn about them. The capabilities and last
+/// seen time of each address are only exchanged from protocol version 6.
 #[derive(Debug)]
 pub struct PeerAddrs {
-	pub peers: Vec<PeerAddr>,
+	pub peers: Vec<PeerAddrInfo>,
 }
 
 impl Writeable for PeerAddrs {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		writer.write_u32(self.peers.len() as u32)?;
-		for p in &self.peers {
-			p.write(writer)?;
+		// Onion addresses cannot be represented prior to protocol version 2,
+		// simply leave them out for older peers.
+		let peers = self
+			.peers
+			.iter()
+			.filter(|p| !p.addr.is_onion() || writer.protocol_version() >= ProtocolVersion(2))
+			.collect::<Vec<_>>();
+		writer.write_u32(peers.len() as u32)?;
+		for p in peers {
+			p.addr.write(writer)?;
+			if writer.protocol_version() >= ProtocolVersion(6) {
+				writer.write_u32(p.capabilities.bits())?;
+				writer.write_i64(p.last_seen)?;
+			}
 		}
 		Ok(())
 	}
@@ -501,7 +528,12 @@ impl Readable for PeerAddrs {
 		}
 		let mut peers = Vec::with_capacity(peer_count as usize);
 		for _ in 0..peer_count {
-			peers.push(PeerAddr::read(reader)?);
+			let mut peer = PeerAddrInfo::new(PeerAddr::read(reader)?);
+			if reader.protocol_version() >= ProtocolVersion(6) {
+				peer.capabilities = Capabilities::from_bits_truncate(reader.read_u32()?);
+				peer.last_seen = reader.read_i64()?;
+			}
+			peers.push(peer);
 		}
 		Ok(PeerAddrs { peers: peers })
 	}
@@ -719,6 +751,188 @@ impl Readable for TxHashSetArchive {
 	}
 }
 
+/// Manifest of a txhashset archive, sent in response to a
+/// TxHashSetManifestRequest (whose body is a TxHashSetRequest). Lists the
+/// hashes of all the fixed size segments the archive is made of so they can
+/// be downloaded (possibly from different peers serving the same archive) and
+/// verified independently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxHashSetManifest {
+	/// Hash of the block for which the txhashset is provided
+	pub hash: Hash,
+	/// Height of the corresponding block
+	pub height: u64,
+	/// Total size in bytes of the archive
+	pub total_size: u64,
+	/// Size in bytes of each segment, only the last one may be shorter
+	pub segment_size: u64,
+	/// Hashes of the archive segments, in order
+	pub segment_hashes: Vec<Hash>,
+}
+
+impl TxHashSetManifest {
+	/// Builds the manifest of the archive provided by the reader.
+	pub fn from_reader(
+		hash: Hash,
+		height: u64,
+		reader: &mut dyn Read,
+	) -> Result<TxHashSetManifest, io::Error> {
+		let mut total_size = 0;
+		let mut segment_hashes = vec![];
+		loop {
+			let mut data = vec![];
+			let size = (&mut *reader)
+				.take(TXHASHSET_SEGMENT_SIZE)
+				.read_to_end(&mut data)?;
+			if size == 0 {
+				break;
+			}
+			total_size += size as u64;
+			segment_hashes.push(segment_hash(&data));
+		}
+		Ok(TxHashSetManifest {
+			hash,
+			height,
+			total_size,
+			segment_size: TXHASHSET_SEGMENT_SIZE,
+			segment_hashes,
+		})
+	}
+
+	/// Number of segments in the archive.
+	pub fn segment_count(&self) -> u64 {
+		self.segment_hashes.len() as u64
+	}
+
+	/// Expected size of the segment at the provided index.
+	pub fn segment_len(&self, index: u64) -> u64 {
+		let start = index * self.segment_size;
+		cmp::min(self.segment_size, self.total_size.saturating_sub(start))
+	}
+
+	/// Whether the provided data is the segment at the provided index.
+	pub fn verify_segment(&self, index: u64, data: &[u8]) -> bool {
+		match self.segment_hashes.get(index as usize) {
+			Some(h) => data.len() as u64 == self.segment_len(index) && *h == segment_hash(data),
+			None => false,
+		}
+	}
+}
+
+impl Writeable for TxHashSetManifest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_u64, self.height],
+			[write_u64, self.total_size],
+			[write_u64, self.segment_size],
+			[write_u64, self.segment_hashes.len() as u64]
+		);
+		for h in &self.segment_hashes {
+			h.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetManifest {
+	fn read(reader: &mut dyn Reader) -> Result<TxHashSetManifest, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let (height, total_size, segment_size, count) =
+			ser_multiread!(reader, read_u64, read_u64, read_u64, read_u64);
+		if segment_size == 0
+			|| segment_size > TXHASHSET_SEGMENT_SIZE
+			|| count > MAX_TXHASHSET_SEGMENTS
+			|| total_size > MAX_TXHASHSET_SEGMENTS * segment_size
+			|| count != (total_size + segment_size - 1) / segment_size
+		{
+			return Err(ser::Error::CorruptedData);
+		}
+		let mut segment_hashes = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			segment_hashes.push(Hash::read(reader)?);
+		}
+		Ok(TxHashSetManifest {
+			hash,
+			height,
+			total_size,
+			segment_size,
+			segment_hashes,
+		})
+	}
+}
+
+/// Request for a single segment of a txhashset archive.
+pub struct TxHashSetSegmentRequest {
+	/// Hash of the block for which the txhashset is provided
+	pub hash: Hash,
+	/// Index of the requested segment
+	pub index: u64,
+}
+
+impl Writeable for TxHashSetSegmentRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.index)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetSegmentRequest {
+	fn read(reader: &mut dyn Reader) -> Result<TxHashSetSegmentRequest, ser::Error> {
+		Ok(TxHashSetSegmentRequest {
+			hash: Hash::read(reader)?,
+			index: reader.read_u64()?,
+		})
+	}
+}
+
+/// A single segment of a txhashset archive, to be verified against the
+/// archive manifest.
+pub struct TxHashSetSegment {
+	/// Hash of the block for which the txhashset is provided
+	pub hash: Hash,
+	/// Index of the segment
+	pub index: u64,
+	/// Segment content
+	pub data: Vec<u8>,
+}
+
+impl Writeable for TxHashSetSegment {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.index)?;
+		writer.write_bytes(&self.data)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetSegment {
+	fn read(reader: &mut dyn Reader) -> Result<TxHashSetSegment, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let (index, len) = ser_multiread!(reader, read_u64, read_u64);
+		if len > TXHASHSET_SEGMENT_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		// readers limit how much can be read at once, segments are larger
+		let mut data = Vec::with_capacity(len as usize);
+		while (data.len() as u64) < len {
+			let chunk = cmp::min(len - data.len() as u64, 100_000);
+			data.extend(reader.read_fixed_bytes(chunk as usize)?);
+		}
+		Ok(TxHashSetSegment { hash, index, data })
+	}
+}
+
+/// Hash of a txhashset archive segment.
+pub fn segment_hash(data: &[u8]) -> Hash {
+	let mut hasher = HashWriter::default();
+	// writing to a hasher never fails
+	let _ = hasher.write_fixed_bytes(&data);
+	hasher.into_hash()
+}
+
 pub struct KernelDataRequest {}
 
 impl Writeable for KernelDataRequest {
@@ -745,3 +959,161 @@ impl Readable for KernelDataResponse {
 		Ok(KernelDataResponse { bytes })
 	}
 }
+
+/// Request for a segment of one of the txhashset MMRs at a given block, the
+/// MMR is given by the message type.
+pub struct SegmentRequest {
+	/// Hash of the block the txhashset is requested for
+	pub block_hash: Hash,
+	/// Which segment
+	pub identifier: SegmentIdentifier,
+}
+
+impl Writeable for SegmentRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.identifier.write(writer)
+	}
+}
+
+impl Readable for SegmentRequest {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentRequest, ser::Error> {
+		Ok(SegmentRequest {
+			block_hash: Hash::read(reader)?,
+			identifier: SegmentIdentifier::read(reader)?,
+		})
+	}
+}
+
+/// A segment of one of the txhashset MMRs at a given block.
+pub struct SegmentResponse<T> {
+	/// Hash of the block the txhashset is provided for
+	pub block_hash: Hash,
+	/// The segment, with its proof against the block header root
+	pub segment: Segment<T>,
+}
+
+impl<T: Writeable> Writeable for SegmentResponse<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.segment.write(writer)
+	}
+}
+
+impl<T: Readable> Readable for SegmentResponse<T> {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentResponse<T>, ser::Error> {
+		Ok(SegmentResponse {
+			block_hash: Hash::read(reader)?,
+			segment: Segment::read(reader)?,
+		})
+	}
+}
+
+/// Request for a skeleton of the header chain, every MAX_BLOCK_HEADERS-th
+/// header following the provided one.
+pub struct HeaderSkeletonRequest {
+	/// Hash of the header the skeleton starts from
+	pub hash: Hash,
+	/// Number of skeleton headers requested
+	pub count: u16,
+}
+
+impl Writeable for HeaderSkeletonRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u16(self.count)
+	}
+}
+
+impl Readable for HeaderSkeletonRequest {
+	fn read(reader: &mut dyn Reader) -> Result<HeaderSkeletonRequest, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let count = reader.read_u16()?;
+		if count as u32 > MAX_SKELETON_HEADERS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		Ok(HeaderSkeletonRequest { hash, count })
+	}
+}
+
+/// Skeleton of the header chain, see HeaderSkeletonRequest.
+pub struct HeaderSkeleton {
+	pub headers: Vec<BlockHeader>,
+}
+
+impl Writeable for HeaderSkeleton {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u16(self.headers.len() as u16)?;
+		for h in &self.headers {
+			h.write(writer)?
+		}
+		Ok(())
+	}
+}
+
+impl Readable for HeaderSkeleton {
+	fn read(reader: &mut dyn Reader) -> Result<HeaderSkeleton, ser::Error> {
+		let len = reader.read_u16()?;
+		if len as u32 > MAX_SKELETON_HEADERS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut headers = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			headers.push(BlockHeader::read(reader)?);
+		}
+		Ok(HeaderSkeleton { headers })
+	}
+}
+
+/// Request for the output leaf set at a given block, for a peer rebuilding
+/// the txhashset from MMR segments.
+pub struct OutputBitmapRequest {
+	/// Hash of the block the leaf set is requested for
+	pub block_hash: Hash,
+}
+
+impl Writeable for OutputBitmapRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)
+	}
+}
+
+impl Readable for OutputBitmapRequest {
+	fn read(reader: &mut dyn Reader) -> Result<OutputBitmapRequest, ser::Error> {
+		Ok(OutputBitmapRequest {
+			block_hash: Hash::read(reader)?,
+		})
+	}
+}
+
+/// The output leaf set at a given block, as a bitset with a bit per output.
+pub struct OutputBitmapResponse {
+	/// Hash of the block the leaf set is provided for
+	pub block_hash: Hash,
+	/// Bitset of the unspent outputs, by insertion index
+	pub bitmap: Vec<u8>,
+}
+
+impl Writeable for OutputBitmapResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		writer.write_bytes(&self.bitmap)
+	}
+}
+
+impl Readable for OutputBitmapResponse {
+	fn read(reader: &mut dyn Reader) -> Result<OutputBitmapResponse, ser::Error> {
+		let block_hash = Hash::read(reader)?;
+		let len = reader.read_u64()?;
+		if len > MAX_OUTPUT_BITMAP_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		// readers limit how much can be read at once
+		let mut bitmap = Vec::with_capacity(len as usize);
+		while (bitmap.len() as u64) < len {
+			let chunk = cmp::min(len - bitmap.len() as u64, 100_000);
+			bitmap.extend(reader.read_fixed_bytes(chunk as usize)?);
+		}
+		Ok(OutputBitmapResponse { block_hash, bitmap })
+	}
+}
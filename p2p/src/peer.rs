@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::{Mutex, RwLock};
 use std::fmt;
 use std::fs::File;
@@ -20,20 +21,26 @@ use std::net::{Shutdown, TcpStream};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::capture::{ConnRecorder, MessageRecorder};
 use crate::chain;
+use crate::chain::txhashset::SegmentType;
 use crate::conn;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{OutputIdentifier, TxKernelEntry};
 use crate::core::pow::Difficulty;
 use crate::core::ser::Writeable;
 use crate::core::{core, global};
 use crate::handshake::Handshake;
 use crate::msg::{
-	self, BanReason, GetPeerAddrs, KernelDataRequest, Locator, Ping, TxHashSetRequest, Type,
+	self, BanReason, GetPeerAddrs, HeaderSkeletonRequest, KernelDataRequest, Locator,
+	OutputBitmapRequest, PeerAddrs, Ping, ProtocolVersion, SegmentRequest, TxHashSetManifest,
+	TxHashSetRequest, TxHashSetSegment, TxHashSetSegmentRequest, Type,
 };
 use crate::protocol::Protocol;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerAddrInfo, PeerInfo,
+	ReasonForBan, TxHashSetRead,
 };
 use chrono::prelude::{DateTime, Utc};
 
@@ -70,12 +77,18 @@ impl fmt::Debug for Peer {
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, conn: TcpStream, adapter: Arc<dyn NetAdapter>) -> std::io::Result<Peer> {
+	fn new(
+		info: PeerInfo,
+		conn: TcpStream,
+		adapter: Arc<dyn NetAdapter>,
+		recorder: Option<Arc<MessageRecorder>>,
+	) -> std::io::Result<Peer> {
 		let state = Arc::new(RwLock::new(State::Connected));
 		let tracking_adapter = TrackingAdapter::new(adapter);
 		let handler = Protocol::new(Arc::new(tracking_adapter.clone()), info.clone());
 		let tracker = Arc::new(conn::Tracker::new());
-		let (sendh, stoph) = conn::listen(conn, tracker.clone(), handler)?;
+		let recorder = recorder.map(|r| ConnRecorder::new(r, &info));
+		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler, recorder)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
 		Ok(Peer {
@@ -94,11 +107,12 @@ impl Peer {
 		total_difficulty: Difficulty,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		recorder: Option<Arc<MessageRecorder>>,
 	) -> Result<Peer, Error> {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
 		let info = hs.accept(capab, total_difficulty, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, conn, adapter, recorder)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -118,18 +132,19 @@ impl Peer {
 		capab: Capabilities,
 		total_difficulty: Difficulty,
 		self_addr: PeerAddr,
+		peer_addr: PeerAddr,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		recorder: Option<Arc<MessageRecorder>>,
 	) -> Result<Peer, Error> {
-		debug!("connect: handshaking with {:?}", conn.peer_addr());
-		let info = hs.initiate(capab, total_difficulty, self_addr, &mut conn);
+		debug!("connect: handshaking with {}", peer_addr);
+		let info = hs.initiate(capab, total_difficulty, self_addr, peer_addr, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, conn, adapter, recorder)?),
 			Err(e) => {
 				debug!(
-					"connect: handshaking with {:?} failed with error: {:?}",
-					conn.peer_addr(),
-					e
+					"connect: handshaking with {} failed with error: {:?}",
+					peer_addr, e
 				);
 				if let Err(e) = conn.shutdown(Shutdown::Both) {
 					debug!("Error shutting down conn: {:?}", e);
@@ -236,7 +251,9 @@ impl Peer {
 			total_difficulty,
 			height,
 		};
-		self.send(ping_msg, msg::Type::Ping)
+		self.send(ping_msg, msg::Type::Ping)?;
+		self.info.ping_sent();
+		Ok(())
 	}
 
 	/// Send the ban reason before banning
@@ -348,6 +365,24 @@ impl Peer {
 		self.send(&Locator { hashes: locator }, msg::Type::GetHeaders)
 	}
 
+	/// Whether the peer serves header chain skeletons.
+	pub fn supports_header_skeleton(&self) -> bool {
+		self.info.version >= ProtocolVersion(5)
+	}
+
+	/// Sends a request for a skeleton of the header chain following the
+	/// provided header
+	pub fn send_header_skeleton_request(&self, hash: Hash, count: u16) -> Result<(), Error> {
+		debug!(
+			"Requesting header skeleton of {} from {} from peer {}.",
+			count, hash, self.info.addr
+		);
+		self.send(
+			&HeaderSkeletonRequest { hash, count },
+			msg::Type::GetHeaderSkeleton,
+		)
+	}
+
 	pub fn send_tx_request(&self, h: Hash) -> Result<(), Error> {
 		debug!(
 			"Requesting tx (kernel hash) {} from peer {}.",
@@ -370,12 +405,29 @@ impl Peer {
 	}
 
 	pub fn send_peer_request(&self, capab: Capabilities) -> Result<(), Error> {
+		if self.info.is_block_relay_only() {
+			trace!("Not asking {} for peers (block relay only)", self.info.addr);
+			return Ok(());
+		}
 		trace!("Asking {} for more peers {:?}", self.info.addr, capab);
 		self.send(
 			&GetPeerAddrs {
 				capabilities: capab,
 			},
 			msg::Type::GetPeerAddrs,
+		)?;
+		self.info.addrs_requested();
+		Ok(())
+	}
+
+	/// Relays peer addresses we just learned about.
+	pub fn send_peer_addrs(&self, addrs: &[PeerAddrInfo]) -> Result<(), Error> {
+		trace!("Relaying {} peer addrs to {}", addrs.len(), self.info.addr);
+		self.send(
+			&PeerAddrs {
+				peers: addrs.to_vec(),
+			},
+			msg::Type::PeerAddrs,
 		)
 	}
 
@@ -390,6 +442,79 @@ impl Peer {
 		)
 	}
 
+	/// Whether the peer supports downloading the txhashset archive in
+	/// segments.
+	pub fn supports_txhashset_segments(&self) -> bool {
+		self.info.version >= ProtocolVersion(3)
+	}
+
+	pub fn send_txhashset_manifest_request(&self, height: u64, hash: Hash) -> Result<(), Error> {
+		debug!(
+			"Asking {} for txhashset manifest at {} {}.",
+			self.info.addr, height, hash
+		);
+		self.send(
+			&TxHashSetRequest { hash, height },
+			msg::Type::TxHashSetManifestRequest,
+		)
+	}
+
+	pub fn send_txhashset_segment_request(&self, hash: Hash, index: u64) -> Result<(), Error> {
+		trace!(
+			"Asking {} for txhashset segment {} of {}.",
+			self.info.addr,
+			index,
+			hash
+		);
+		self.send(
+			&TxHashSetSegmentRequest { hash, index },
+			msg::Type::TxHashSetSegmentRequest,
+		)
+	}
+
+	/// Whether the peer serves segments of the txhashset MMRs.
+	pub fn supports_segments(&self) -> bool {
+		self.info.version >= ProtocolVersion(4)
+	}
+
+	pub fn send_segment_request(
+		&self,
+		segment_type: SegmentType,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		trace!(
+			"Asking {} for {:?} segment {:?} of {}.",
+			self.info.addr,
+			segment_type,
+			identifier,
+			block_hash
+		);
+		let msg_type = match segment_type {
+			SegmentType::Output => msg::Type::GetOutputSegment,
+			SegmentType::RangeProof => msg::Type::GetRangeProofSegment,
+			SegmentType::Kernel => msg::Type::GetKernelSegment,
+		};
+		self.send(
+			&SegmentRequest {
+				block_hash,
+				identifier,
+			},
+			msg_type,
+		)
+	}
+
+	pub fn send_output_bitmap_request(&self, block_hash: Hash) -> Result<(), Error> {
+		debug!(
+			"Asking {} for the output bitmap of {}.",
+			self.info.addr, block_hash
+		);
+		self.send(
+			&OutputBitmapRequest { block_hash },
+			msg::Type::GetOutputBitmap,
+		)
+	}
+
 	pub fn send_kernel_data_request(&self) -> Result<(), Error> {
 		debug!("Asking {} for kernel data.", self.info.addr);
 		self.send(&KernelDataRequest {}, msg::Type::KernelDataRequest)
@@ -494,6 +619,7 @@ impl ChainAdapter for TrackingAdapter {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// Do not track the tx hash for stem txs.
 		// Otherwise we fail to handle the subsequent fluff or embargo expiration
@@ -502,7 +628,7 @@ impl ChainAdapter for TrackingAdapter {
 			let kernel = &tx.kernels()[0];
 			self.push_recv(kernel.hash());
 		}
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -546,6 +672,22 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.locate_headers(locator)
 	}
 
+	fn locate_skeleton(
+		&self,
+		hash: Hash,
+		count: u16,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		self.adapter.locate_skeleton(hash, count)
+	}
+
+	fn skeleton_received(
+		&self,
+		headers: &[core::BlockHeader],
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.skeleton_received(headers, peer_info)
+	}
+
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		self.adapter.get_block(h)
 	}
@@ -562,6 +704,10 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.txhashset_read(h)
 	}
 
+	fn txhashset_manifest(&self, h: Hash) -> Option<TxHashSetManifest> {
+		self.adapter.txhashset_manifest(h)
+	}
+
 	fn txhashset_receive_ready(&self) -> bool {
 		self.adapter.txhashset_receive_ready()
 	}
@@ -585,6 +731,83 @@ impl ChainAdapter for TrackingAdapter {
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
 
+	fn txhashset_manifest_received(&self, manifest: TxHashSetManifest, peer_info: &PeerInfo) {
+		self.adapter
+			.txhashset_manifest_received(manifest, peer_info)
+	}
+
+	fn txhashset_segment_received(&self, segment: TxHashSetSegment, peer_info: &PeerInfo) -> bool {
+		self.adapter.txhashset_segment_received(segment, peer_info)
+	}
+
+	fn get_output_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>> {
+		self.adapter.get_output_segment(block_hash, identifier)
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.adapter.get_rangeproof_segment(block_hash, identifier)
+	}
+
+	fn get_kernel_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<TxKernelEntry>> {
+		self.adapter.get_kernel_segment(block_hash, identifier)
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_output_segment(block_hash, segment, peer_info)
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_rangeproof_segment(block_hash, segment, peer_info)
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernelEntry>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_kernel_segment(block_hash, segment, peer_info)
+	}
+
+	fn get_output_bitmap(&self, block_hash: Hash) -> Option<Vec<u8>> {
+		self.adapter.get_output_bitmap(block_hash)
+	}
+
+	fn receive_output_bitmap(
+		&self,
+		block_hash: Hash,
+		bitmap: Vec<u8>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_output_bitmap(block_hash, bitmap, peer_info)
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.adapter.get_tmp_dir()
 	}
@@ -595,12 +818,12 @@ impl ChainAdapter for TrackingAdapter {
 }
 
 impl NetAdapter for TrackingAdapter {
-	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr> {
+	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddrInfo> {
 		self.adapter.find_peer_addrs(capab)
 	}
 
-	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>) {
-		self.adapter.peer_addrs_received(addrs)
+	fn peer_addrs_received(&self, addrs: Vec<PeerAddrInfo>, source: PeerAddr) {
+		self.adapter.peer_addrs_received(addrs, source)
 	}
 
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64) {
@@ -12,9 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::RwLock;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::hash::{BuildHasher, Hash as StdHash, Hasher};
 use std::io::Read;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -22,36 +26,75 @@ use std::sync::Arc;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
+use crate::addrman::AddrManager;
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{OutputIdentifier, TxKernelEntry};
 use crate::core::global;
 use crate::core::pow::Difficulty;
+use crate::eviction::{self, EvictionCandidate};
+use crate::msg::{TxHashSetManifest, TxHashSetSegment};
 use crate::peer::Peer;
 use crate::store::{PeerData, PeerStore, State};
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerAddrInfo, PeerInfo,
+	ReasonForBan, TxHashSetRead, MAX_PEER_ADDRS, PEER_ADDR_MAX_AGE_SECS,
 };
 use chrono::prelude::*;
 use chrono::Duration;
 
 const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
+/// Maximum number of outbound peers we keep as anchors across restarts.
+const MAX_ANCHORS: usize = 2;
+
+/// How long an outbound peer needs to have been connected for before it can
+/// become an anchor (outside of production, any outbound peer can).
+const ANCHOR_MIN_CONNECTED_SECS: i64 = 600;
+
+/// Peer addresses messages with more addresses than this are responses to
+/// our requests rather than announcements, and aren't relayed further.
+const MAX_PEER_ADDRS_TO_RELAY: usize = 10;
+
+/// Newly learned addresses are only relayed if they were seen this recently.
+const PEER_ADDR_RELAY_MAX_AGE_SECS: i64 = 600;
+
+/// Number of peers each newly learned address gets relayed to.
+const PEER_ADDR_RELAY_COUNT: usize = 2;
+
 pub struct Peers {
 	pub adapter: Arc<dyn ChainAdapter>,
 	store: PeerStore,
+	addrs: RwLock<AddrManager>,
 	peers: RwLock<HashMap<PeerAddr, Arc<Peer>>>,
 	config: P2PConfig,
+	// secret key for the network groups protected from inbound eviction
+	eviction_key: RandomState,
 }
 
 impl Peers {
 	pub fn new(store: PeerStore, adapter: Arc<dyn ChainAdapter>, config: P2PConfig) -> Peers {
+		let addrs = match store.all_peers() {
+			Ok(peers) => AddrManager::new(peers),
+			Err(e) => {
+				error!("failed to load peers for the address manager: {:?}", e);
+				AddrManager::new(vec![])
+			}
+		};
+		let (new_count, tried_count) = addrs.table_sizes();
+		debug!(
+			"Address manager loaded with {} new and {} tried peers.",
+			new_count, tried_count
+		);
 		Peers {
 			adapter,
 			store,
+			addrs: RwLock::new(addrs),
 			config,
 			peers: RwLock::new(HashMap::new()),
+			eviction_key: RandomState::new(),
 		}
 	}
 
@@ -65,15 +108,22 @@ impl Peers {
 				return Err(Error::Timeout);
 			}
 		};
-		let peer_data = PeerData {
-			addr: peer.info.addr,
-			capabilities: peer.info.capabilities,
-			user_agent: peer.info.user_agent.clone(),
-			flags: State::Healthy,
-			last_banned: 0,
-			ban_reason: ReasonForBan::None,
-			last_connected: Utc::now().timestamp(),
-		};
+		// keep the connection stats we already have for this peer
+		let mut peer_data = self
+			.store
+			.get_peer(peer.info.addr)
+			.unwrap_or_else(|_| PeerData::new(peer.info.addr, None));
+		peer_data.capabilities = peer.info.capabilities - Capabilities::BLOCK_RELAY_ONLY;
+		peer_data.user_agent = peer.info.user_agent.clone();
+		peer_data.flags = State::Healthy;
+		peer_data.last_banned = 0;
+		peer_data.ban_reason = ReasonForBan::None;
+		peer_data.last_connected = Utc::now().timestamp();
+		peer_data.last_seen = peer_data.last_connected;
+		if peer.info.is_outbound() {
+			peer_data.attempts = 0;
+			peer_data.successes += 1;
+		}
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		self.save_peer(&peer_data)?;
 		peers.insert(peer_data.addr, peer.clone());
@@ -85,13 +135,10 @@ impl Peers {
 	/// handshake
 	pub fn add_banned(&self, addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
 		let peer_data = PeerData {
-			addr,
-			capabilities: Capabilities::UNKNOWN,
-			user_agent: "".to_string(),
 			flags: State::Banned,
 			last_banned: Utc::now().timestamp(),
 			ban_reason,
-			last_connected: Utc::now().timestamp(),
+			..PeerData::new(addr, None)
 		};
 		debug!("Banning peer {}.", addr);
 		self.save_peer(&peer_data)
@@ -126,10 +173,20 @@ impl Peers {
 		res
 	}
 
+	/// Block-relay-only outbound connections are extra slots on top of these,
+	/// see `block_relay_only_peers`.
 	pub fn outgoing_connected_peers(&self) -> Vec<Arc<Peer>> {
 		self.connected_peers()
 			.into_iter()
-			.filter(|x| x.info.is_outbound())
+			.filter(|x| x.info.is_outbound() && !x.info.is_block_relay_only())
+			.collect()
+	}
+
+	/// Outbound connections only relaying headers and blocks.
+	pub fn block_relay_only_peers(&self) -> Vec<Arc<Peer>> {
+		self.connected_peers()
+			.into_iter()
+			.filter(|x| x.info.is_outbound() && x.info.is_block_relay_only())
 			.collect()
 	}
 
@@ -150,6 +207,87 @@ impl Peers {
 		self.connected_peers().len() as u32
 	}
 
+	/// Whether accepting an inbound connection from the provided address
+	/// would go over our per IP or per network group inbound limits.
+	/// Loopback addresses aren't limited.
+	pub fn inbound_limit_reached(&self, addr: PeerAddr) -> bool {
+		let ip_of = |addr: &PeerAddr| match addr {
+			PeerAddr::Ip(a) => Some(a.ip()),
+			PeerAddr::Onion(_) => None,
+		};
+		let ip = ip_of(&addr);
+		if ip.map_or(false, |ip| ip.is_loopback()) {
+			return false;
+		}
+		let net_group = addr.net_group();
+		let inbound = self
+			.connected_peers()
+			.into_iter()
+			.filter(|p| !p.info.is_outbound())
+			.collect::<Vec<_>>();
+		let same_ip = inbound
+			.iter()
+			.filter(|p| ip.is_some() && ip_of(&p.info.addr) == ip)
+			.count();
+		let same_group = inbound
+			.iter()
+			.filter(|p| p.info.addr.net_group() == net_group)
+			.count();
+		same_ip >= self.config.peer_max_inbound_per_ip() as usize
+			|| same_group >= self.config.peer_max_inbound_per_net_group() as usize
+	}
+
+	/// Makes room for a new inbound connection if we're already at our max
+	/// peer count, disconnecting the inbound peer we'd miss the least (see
+	/// the `eviction` module). Our preferred peers are never evicted. Returns
+	/// false if we're full and no peer could be evicted.
+	pub fn make_room_for_inbound(&self) -> bool {
+		let counted = self
+			.connected_peers()
+			.into_iter()
+			.filter(|p| !(p.info.is_outbound() && p.info.is_block_relay_only()))
+			.collect::<Vec<_>>();
+		if counted.len() < self.config.peer_max_count() as usize {
+			return true;
+		}
+		let preferred = self.config.peers_preferred.clone().unwrap_or_default();
+		let candidates = counted
+			.iter()
+			.filter(|p| !p.info.is_outbound() && !preferred.contains(&p.info.addr))
+			.map(|p| {
+				let net_group = p.info.addr.net_group();
+				let mut hasher = self.eviction_key.build_hasher();
+				net_group.hash(&mut hasher);
+				let live_info = p.info.live_info.read();
+				EvictionCandidate {
+					addr: p.info.addr,
+					net_group,
+					net_group_key: hasher.finish(),
+					connected_at: live_info.first_seen,
+					latency: live_info.latency,
+					last_block: live_info.last_block_received,
+					last_tx: live_info.last_tx_received,
+				}
+			})
+			.collect::<Vec<_>>();
+		let addr = match eviction::select_to_evict(candidates) {
+			Some(addr) => addr,
+			None => return false,
+		};
+		debug!("Evicting inbound peer {} to make room for a new one", addr);
+		let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
+			Some(peers) => peers,
+			None => {
+				error!("make_room_for_inbound: failed to get peers lock");
+				return false;
+			}
+		};
+		if let Some(peer) = peers.remove(&addr) {
+			peer.stop();
+		}
+		true
+	}
+
 	/// Number of outbound peers currently connected to.
 	pub fn peer_outbound_count(&self) -> u32 {
 		self.outgoing_connected_peers().len() as u32
@@ -357,9 +495,15 @@ impl Peers {
 	/// want to broadcast to a random subset of peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
+	/// Block-relay-only connections are skipped.
 	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
 		let num_peers = self.config.peer_max_count();
-		let count = self.broadcast("transaction", num_peers, |p| p.send_transaction(tx));
+		let count = self.broadcast("transaction", num_peers, |p| {
+			if p.info.is_block_relay_only() {
+				return Ok(false);
+			}
+			p.send_transaction(tx)
+		});
 		debug!(
 			"broadcast_transaction: {} to {} peers, done.",
 			tx.hash(),
@@ -367,6 +511,25 @@ impl Peers {
 		);
 	}
 
+	/// Relays newly learned peer addresses to a couple of our peers, other
+	/// than the one we learned them from. Block-relay-only connections are
+	/// skipped.
+	fn relay_peer_addrs(&self, addrs: &[PeerAddrInfo], source: PeerAddr) {
+		let count = self.broadcast("peer addrs", PEER_ADDR_RELAY_COUNT as u32, |p| {
+			if p.info.addr == source || p.info.is_block_relay_only() {
+				return Ok(false);
+			}
+			p.send_peer_addrs(addrs)?;
+			Ok(true)
+		});
+		trace!(
+			"relay_peer_addrs: {} addrs from {} to {} peers, done.",
+			addrs.len(),
+			source,
+			count,
+		);
+	}
+
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
 	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
@@ -397,7 +560,9 @@ impl Peers {
 		}
 	}
 
-	/// Find peers in store (not necessarily connected) and return their data
+	/// Find peers in store (not necessarily connected) and return their data.
+	/// The address manager only keeps a bounded subset of them, for picking
+	/// outbound connections, so queries by state always go to the store.
 	pub fn find_peers(&self, state: State, cap: Capabilities, count: usize) -> Vec<PeerData> {
 		match self.store.find_peers(state, cap, count) {
 			Ok(peers) => peers,
@@ -408,6 +573,26 @@ impl Peers {
 		}
 	}
 
+	/// Healthy peers we are not connected to yet and are worth an outbound
+	/// connection attempt. We pick at most one peer per network group and
+	/// skip the groups we already have outbound connections to, so a single
+	/// operator can't easily take over all our outbound connections.
+	pub fn outbound_candidates(&self, count: usize) -> Vec<PeerData> {
+		let connected = self.connected_peers();
+		let exclude = connected
+			.iter()
+			.filter(|p| p.info.is_outbound())
+			.map(|p| p.info.addr.net_group())
+			.collect::<HashSet<_>>();
+		let connected = connected
+			.iter()
+			.map(|p| p.info.addr)
+			.collect::<HashSet<_>>();
+		self.addrs.read().select(count, &exclude, true, |p| {
+			p.flags == State::Healthy && !connected.contains(&p.addr)
+		})
+	}
+
 	/// Get peer in store by address
 	pub fn get_peer(&self, peer_addr: PeerAddr) -> Result<PeerData, Error> {
 		self.store.get_peer(peer_addr).map_err(From::from)
@@ -420,14 +605,27 @@ impl Peers {
 
 	/// Saves updated information about a peer
 	pub fn save_peer(&self, p: &PeerData) -> Result<(), Error> {
-		self.store.save_peer(p).map_err(From::from)
+		self.store.save_peer(p)?;
+		self.addrs.write().update(p.clone());
+		Ok(())
 	}
 
 	/// Updates the state of a peer in store
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
-		self.store
-			.update_state(peer_addr, new_state)
-			.map_err(From::from)
+		let peer = self.store.update_state(peer_addr, new_state)?;
+		self.addrs.write().update(peer);
+		Ok(())
+	}
+
+	/// Records an outbound connection attempt to a peer we already know
+	/// about, successful attempts are recorded when the peer gets connected.
+	pub fn record_attempt(&self, peer_addr: PeerAddr) -> Result<(), Error> {
+		let peer = self.store.update_peer(peer_addr, |p| {
+			p.attempts += 1;
+			p.last_attempt = Utc::now().timestamp();
+		})?;
+		self.addrs.write().update(peer);
+		Ok(())
 	}
 
 	/// Iterate over the peer list and prune all peers we have
@@ -477,14 +675,20 @@ impl Peers {
 			}
 		}
 
-		// ensure we do not still have too many connected peers
-		let excess_count = (self.peer_count() as usize)
+		// ensure we do not still have too many connected peers, our outbound
+		// block-relay-only connections do not count towards the max
+		let counted = self
+			.connected_peers()
+			.into_iter()
+			.filter(|p| !(p.info.is_outbound() && p.info.is_block_relay_only()))
+			.collect::<Vec<_>>();
+		let excess_count = counted
+			.len()
 			.saturating_sub(rm.len())
 			.saturating_sub(max_count);
 		if excess_count > 0 {
 			// map peers to addrs in a block to bound how long we keep the read lock for
-			let mut addrs = self
-				.connected_peers()
+			let mut addrs = counted
 				.iter()
 				.take(excess_count)
 				.map(|x| x.info.addr.clone())
@@ -508,6 +712,48 @@ impl Peers {
 		}
 	}
 
+	/// Saves our longest lived, well behaved outbound peers as anchors so we
+	/// can reconnect to them first on our next start, instead of relying on
+	/// whoever answers first among our seeds.
+	pub fn save_anchors(&self) {
+		let now = Utc::now();
+		let min_connected = if global::is_production_mode() {
+			Duration::seconds(ANCHOR_MIN_CONNECTED_SECS)
+		} else {
+			Duration::zero()
+		};
+		let mut candidates = self
+			.outgoing_connected_peers()
+			.into_iter()
+			.filter(|p| now - p.info.first_seen() >= min_connected)
+			.filter(|p| !self.is_banned(p.info.addr))
+			.collect::<Vec<_>>();
+		candidates.sort_by_key(|p| p.info.first_seen());
+		let anchors = candidates
+			.iter()
+			.take(MAX_ANCHORS)
+			.map(|p| p.info.addr)
+			.collect::<Vec<_>>();
+
+		debug!("Saving {} anchors: {:?}", anchors.len(), anchors);
+		if let Err(e) = self.store.save_anchors(&anchors) {
+			error!("failed to save anchors: {:?}", e);
+		}
+	}
+
+	/// Loads the anchors saved on our last shutdown. They are kept in the
+	/// store, should we not shut down cleanly again, until our next clean
+	/// shutdown overwrites them.
+	pub fn anchors(&self) -> Vec<PeerAddr> {
+		match self.store.anchors() {
+			Ok(anchors) => anchors,
+			Err(e) => {
+				error!("failed to load anchors: {:?}", e);
+				vec![]
+			}
+		}
+	}
+
 	pub fn stop(&self) {
 		let mut peers = self.peers.write();
 		for peer in peers.values() {
@@ -533,7 +779,7 @@ impl Peers {
 		let now = Utc::now();
 
 		// Delete defunct peers from storage
-		let _ = self.store.delete_peers(|peer| {
+		let removed = self.store.delete_peers(|peer| {
 			let diff = now - Utc.timestamp(peer.last_connected, 0);
 
 			let should_remove = peer.flags == State::Defunct
@@ -551,6 +797,13 @@ impl Peers {
 
 			should_remove
 		});
+
+		if let Ok(removed) = removed {
+			let mut addrs = self.addrs.write();
+			for addr in removed {
+				addrs.remove(addr);
+			}
+		}
 	}
 }
 
@@ -579,8 +832,9 @@ impl ChainAdapter for Peers {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -658,6 +912,28 @@ impl ChainAdapter for Peers {
 		self.adapter.locate_headers(hs)
 	}
 
+	fn locate_skeleton(
+		&self,
+		hash: Hash,
+		count: u16,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		self.adapter.locate_skeleton(hash, count)
+	}
+
+	fn skeleton_received(
+		&self,
+		headers: &[core::BlockHeader],
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if !self.adapter.skeleton_received(headers, peer_info)? {
+			// the skeleton headers are intrinsically bad, as for headers
+			self.ban_peer(peer_info.addr, ReasonForBan::BadBlockHeader);
+			Ok(false)
+		} else {
+			Ok(true)
+		}
+	}
+
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		self.adapter.get_block(h)
 	}
@@ -674,6 +950,10 @@ impl ChainAdapter for Peers {
 		self.adapter.txhashset_read(h)
 	}
 
+	fn txhashset_manifest(&self, h: Hash) -> Option<TxHashSetManifest> {
+		self.adapter.txhashset_manifest(h)
+	}
+
 	fn txhashset_receive_ready(&self) -> bool {
 		self.adapter.txhashset_receive_ready()
 	}
@@ -706,6 +986,83 @@ impl ChainAdapter for Peers {
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
 
+	fn txhashset_manifest_received(&self, manifest: TxHashSetManifest, peer_info: &PeerInfo) {
+		self.adapter
+			.txhashset_manifest_received(manifest, peer_info)
+	}
+
+	fn txhashset_segment_received(&self, segment: TxHashSetSegment, peer_info: &PeerInfo) -> bool {
+		self.adapter.txhashset_segment_received(segment, peer_info)
+	}
+
+	fn get_output_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>> {
+		self.adapter.get_output_segment(block_hash, identifier)
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.adapter.get_rangeproof_segment(block_hash, identifier)
+	}
+
+	fn get_kernel_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<TxKernelEntry>> {
+		self.adapter.get_kernel_segment(block_hash, identifier)
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_output_segment(block_hash, segment, peer_info)
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_rangeproof_segment(block_hash, segment, peer_info)
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernelEntry>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_kernel_segment(block_hash, segment, peer_info)
+	}
+
+	fn get_output_bitmap(&self, block_hash: Hash) -> Option<Vec<u8>> {
+		self.adapter.get_output_bitmap(block_hash)
+	}
+
+	fn receive_output_bitmap(
+		&self,
+		block_hash: Hash,
+		bitmap: Vec<u8>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.adapter
+			.receive_output_bitmap(block_hash, bitmap, peer_info)
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.adapter.get_tmp_dir()
 	}
@@ -717,35 +1074,85 @@ impl ChainAdapter for Peers {
 
 impl NetAdapter for Peers {
 	/// Find good peers we know with the provided capability and return their
-	/// addresses.
-	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr> {
-		let peers = self.find_peers(State::Healthy, capab, MAX_PEER_ADDRS as usize);
+	/// addresses, leaving out the ones not known to be reachable lately.
+	/// Peers we're connected to are seen right now.
+	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddrInfo> {
+		let now = Utc::now().timestamp();
+		let connected = self
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.addr)
+			.collect::<HashSet<_>>();
+		let wanted = |p: &PeerData| {
+			p.flags == State::Healthy
+				&& p.capabilities.contains(capab)
+				&& (connected.contains(&p.addr) || now - p.last_seen <= PEER_ADDR_MAX_AGE_SECS)
+		};
+		let peers =
+			self.addrs
+				.read()
+				.select(MAX_PEER_ADDRS as usize, &HashSet::new(), false, wanted);
 		trace!("find_peer_addrs: {} healthy peers picked", peers.len());
-		map_vec!(peers, |p| p.addr)
+		map_vec!(peers, |p| PeerAddrInfo {
+			addr: p.addr,
+			capabilities: p.capabilities,
+			last_seen: if connected.contains(&p.addr) {
+				now
+			} else {
+				p.last_seen
+			},
+		})
 	}
 
-	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>) {
-		trace!("Received {} peer addrs, saving.", peer_addrs.len());
-		for pa in peer_addrs {
-			if let Ok(e) = self.exists_peer(pa) {
-				if e {
-					continue;
-				}
+	/// A list of peers has been received from one of our peers. Stale
+	/// addresses are ignored, known ones get their last seen time refreshed
+	/// and new ones are saved. The new ones that are fresh get relayed to a
+	/// couple of our peers, unless they came in a response to our request.
+	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddrInfo>, source: PeerAddr) {
+		trace!(
+			"Received {} peer addrs from {}, saving.",
+			peer_addrs.len(),
+			source
+		);
+		let now = Utc::now().timestamp();
+		let relay = peer_addrs.len() <= MAX_PEER_ADDRS_TO_RELAY;
+		let mut to_relay = vec![];
+		for mut pa in peer_addrs {
+			// nobody gets to claim a peer will be seen in the future
+			pa.last_seen = cmp::min(pa.last_seen, now);
+			if pa.last_seen > 0 && now - pa.last_seen > PEER_ADDR_MAX_AGE_SECS {
+				continue;
 			}
-			let peer = PeerData {
-				addr: pa,
-				capabilities: Capabilities::UNKNOWN,
-				user_agent: "".to_string(),
-				flags: State::Healthy,
-				last_banned: 0,
-				ban_reason: ReasonForBan::None,
-				last_connected: Utc::now().timestamp(),
+			let peer = match self.exists_peer(pa.addr) {
+				Ok(true) => match self.get_peer(pa.addr) {
+					Ok(peer) if pa.last_seen > peer.last_seen => {
+						let mut peer = peer;
+						peer.last_seen = pa.last_seen;
+						if peer.capabilities == Capabilities::UNKNOWN {
+							peer.capabilities = pa.capabilities;
+						}
+						peer
+					}
+					_ => continue,
+				},
+				_ => {
+					if relay && now - pa.last_seen <= PEER_ADDR_RELAY_MAX_AGE_SECS {
+						to_relay.push(pa);
+					}
+					PeerData {
+						capabilities: pa.capabilities,
+						last_seen: pa.last_seen,
+						..PeerData::new(pa.addr, Some(source))
+					}
+				}
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
 			}
 		}
+		if !to_relay.is_empty() {
+			self.relay_peer_addrs(&to_relay, source);
+		}
 	}
 
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64) {
@@ -12,19 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::chain::txhashset::SegmentType;
 use crate::conn::{Message, MessageHandler, Response, Tracker};
-use crate::core::core::{self, hash::Hash, CompactBlock};
+use crate::core::core::{self, hash::Hash, CompactBlock, OutputIdentifier, TxKernelEntry};
 
 use crate::msg::{
-	BanReason, GetPeerAddrs, Headers, KernelDataResponse, Locator, PeerAddrs, Ping, Pong,
-	TxHashSetArchive, TxHashSetRequest, Type,
+	BanReason, GetPeerAddrs, HeaderSkeleton, HeaderSkeletonRequest, Headers, KernelDataResponse,
+	Locator, OutputBitmapRequest, OutputBitmapResponse, PeerAddrs, Ping, Pong, SegmentRequest,
+	SegmentResponse, TxHashSetArchive, TxHashSetManifest, TxHashSetRequest, TxHashSetSegment,
+	TxHashSetSegmentRequest, Type,
 };
+use crate::types::TXHASHSET_SEGMENT_SIZE;
 use crate::types::{Error, NetAdapter, PeerInfo};
+use crate::util::secp::pedersen::RangeProof;
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::cmp;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
 use tempfile::tempfile;
 
@@ -59,6 +64,26 @@ impl MessageHandler for Protocol {
 			return Ok(None);
 		}
 
+		// Block-relay-only connections never exchange transactions or peer
+		// addresses, drop anything of the sort.
+		if self.peer_info.is_block_relay_only() {
+			match msg.header.msg_type {
+				Type::TransactionKernel
+				| Type::GetTransaction
+				| Type::Transaction
+				| Type::StemTransaction
+				| Type::GetPeerAddrs
+				| Type::PeerAddrs => {
+					debug!(
+						"handler: consume: block relay only peer {:?}, received: {:?}, dropping.",
+						self.peer_info.addr, msg.header.msg_type,
+					);
+					return Ok(None);
+				}
+				_ => {}
+			}
+		}
+
 		match msg.header.msg_type {
 			Type::Ping => {
 				let ping: Ping = msg.body()?;
@@ -66,6 +91,7 @@ impl MessageHandler for Protocol {
 
 				Ok(Some(Response::new(
 					Type::Pong,
+					self.peer_info.version,
 					Pong {
 						total_difficulty: adapter.total_difficulty()?,
 						height: adapter.total_height()?,
@@ -76,6 +102,7 @@ impl MessageHandler for Protocol {
 
 			Type::Pong => {
 				let pong: Pong = msg.body()?;
+				self.peer_info.pong_received();
 				adapter.peer_difficulty(self.peer_info.addr, pong.total_difficulty, pong.height);
 				Ok(None)
 			}
@@ -104,7 +131,12 @@ impl MessageHandler for Protocol {
 				);
 				let tx = adapter.get_transaction(h);
 				if let Some(tx) = tx {
-					Ok(Some(Response::new(Type::Transaction, tx, writer)?))
+					Ok(Some(Response::new(
+						Type::Transaction,
+						self.peer_info.version,
+						tx,
+						writer,
+					)?))
 				} else {
 					Ok(None)
 				}
@@ -116,7 +148,9 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, false)?;
+				if adapter.transaction_received(tx, false, &self.peer_info)? {
+					self.peer_info.tx_delivered();
+				}
 				Ok(None)
 			}
 
@@ -126,7 +160,9 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, true)?;
+				if adapter.transaction_received(tx, true, &self.peer_info)? {
+					self.peer_info.tx_delivered();
+				}
 				Ok(None)
 			}
 
@@ -140,7 +176,12 @@ impl MessageHandler for Protocol {
 
 				let bo = adapter.get_block(h);
 				if let Some(b) = bo {
-					return Ok(Some(Response::new(Type::Block, b, writer)?));
+					return Ok(Some(Response::new(
+						Type::Block,
+						self.peer_info.version,
+						b,
+						writer,
+					)?));
 				}
 				Ok(None)
 			}
@@ -154,7 +195,11 @@ impl MessageHandler for Protocol {
 
 				// we can't know at this level whether we requested the block or not,
 				// the boolean should be properly set in higher level adapter
+				let total_difficulty = adapter.total_difficulty()?;
 				adapter.block_received(b, &self.peer_info, false)?;
+				if adapter.total_difficulty()? > total_difficulty {
+					self.peer_info.block_delivered();
+				}
 				Ok(None)
 			}
 
@@ -162,7 +207,12 @@ impl MessageHandler for Protocol {
 				let h: Hash = msg.body()?;
 				if let Some(b) = adapter.get_block(h) {
 					let cb: CompactBlock = b.into();
-					Ok(Some(Response::new(Type::CompactBlock, cb, writer)?))
+					Ok(Some(Response::new(
+						Type::CompactBlock,
+						self.peer_info.version,
+						cb,
+						writer,
+					)?))
 				} else {
 					Ok(None)
 				}
@@ -175,7 +225,11 @@ impl MessageHandler for Protocol {
 				);
 				let b: core::CompactBlock = msg.body()?;
 
+				let total_difficulty = adapter.total_difficulty()?;
 				adapter.compact_block_received(b, &self.peer_info)?;
+				if adapter.total_difficulty()? > total_difficulty {
+					self.peer_info.block_delivered();
+				}
 				Ok(None)
 			}
 
@@ -187,6 +241,7 @@ impl MessageHandler for Protocol {
 				// serialize and send all the headers over
 				Ok(Some(Response::new(
 					Type::Headers,
+					self.peer_info.version,
 					Headers { headers },
 					writer,
 				)?))
@@ -208,7 +263,10 @@ impl MessageHandler for Protocol {
 				total_bytes_read += bytes_read;
 
 				// Read chunks of headers off the stream and pass them off to the adapter.
+				// Once a chunk is refused (and the peer banned for it) the rest
+				// is only read off the stream.
 				let chunk_size = 32;
+				let mut refused = false;
 				for chunk in (0..count).collect::<Vec<_>>().chunks(chunk_size) {
 					let mut headers = vec![];
 					for _ in chunk {
@@ -216,7 +274,9 @@ impl MessageHandler for Protocol {
 						headers.push(header);
 						total_bytes_read += bytes_read;
 					}
-					adapter.headers_received(&headers, &self.peer_info)?;
+					if !refused && !adapter.headers_received(&headers, &self.peer_info)? {
+						refused = true;
+					}
 				}
 
 				// Now check we read the correct total number of bytes off the stream.
@@ -227,19 +287,47 @@ impl MessageHandler for Protocol {
 				Ok(None)
 			}
 
+			Type::GetHeaderSkeleton => {
+				let req: HeaderSkeletonRequest = msg.body()?;
+				let headers = adapter.locate_skeleton(req.hash, req.count)?;
+				Ok(Some(Response::new(
+					Type::HeaderSkeleton,
+					self.peer_info.version,
+					HeaderSkeleton { headers },
+					writer,
+				)?))
+			}
+
+			Type::HeaderSkeleton => {
+				let skeleton: HeaderSkeleton = msg.body()?;
+				adapter.skeleton_received(&skeleton.headers, &self.peer_info)?;
+				Ok(None)
+			}
+
 			Type::GetPeerAddrs => {
 				let get_peers: GetPeerAddrs = msg.body()?;
 				let peers = adapter.find_peer_addrs(get_peers.capabilities);
 				Ok(Some(Response::new(
 					Type::PeerAddrs,
+					self.peer_info.version,
 					PeerAddrs { peers },
 					writer,
 				)?))
 			}
 
 			Type::PeerAddrs => {
-				let peer_addrs: PeerAddrs = msg.body()?;
-				adapter.peer_addrs_received(peer_addrs.peers);
+				let mut peer_addrs: PeerAddrs = msg.body()?;
+				let allowed = self.peer_info.take_addr_tokens(peer_addrs.peers.len());
+				if allowed < peer_addrs.peers.len() {
+					debug!(
+						"Dropping {} of {} peer addrs from {}, over the rate limit",
+						peer_addrs.peers.len() - allowed,
+						peer_addrs.peers.len(),
+						self.peer_info.addr
+					);
+					peer_addrs.peers.truncate(allowed);
+				}
+				adapter.peer_addrs_received(peer_addrs.peers, self.peer_info.addr);
 				Ok(None)
 			}
 
@@ -248,8 +336,12 @@ impl MessageHandler for Protocol {
 				let kernel_data = self.adapter.kernel_data_read()?;
 				let bytes = kernel_data.metadata()?.len();
 				let kernel_data_response = KernelDataResponse { bytes };
-				let mut response =
-					Response::new(Type::KernelDataResponse, &kernel_data_response, writer)?;
+				let mut response = Response::new(
+					Type::KernelDataResponse,
+					self.peer_info.version,
+					&kernel_data_response,
+					writer,
+				)?;
 				response.add_attachment(kernel_data);
 				Ok(Some(response))
 			}
@@ -304,6 +396,7 @@ impl MessageHandler for Protocol {
 					let file_sz = txhashset.reader.metadata()?.len();
 					let mut resp = Response::new(
 						Type::TxHashSetArchive,
+						self.peer_info.version,
 						&TxHashSetArchive {
 							height: sm_req.height as u64,
 							hash: sm_req.hash,
@@ -397,6 +490,247 @@ impl MessageHandler for Protocol {
 
 				Ok(None)
 			}
+			Type::TxHashSetManifestRequest => {
+				let sm_req: TxHashSetRequest = msg.body()?;
+				debug!(
+					"handle_payload: txhashset manifest req for {} at {}",
+					sm_req.hash, sm_req.height
+				);
+
+				if let Some(manifest) = self.adapter.txhashset_manifest(sm_req.hash) {
+					Ok(Some(Response::new(
+						Type::TxHashSetManifest,
+						self.peer_info.version,
+						manifest,
+						writer,
+					)?))
+				} else {
+					Ok(None)
+				}
+			}
+
+			Type::TxHashSetManifest => {
+				let manifest: TxHashSetManifest = msg.body()?;
+				debug!(
+					"handle_payload: txhashset manifest for {} at {}, {} segments",
+					manifest.hash,
+					manifest.height,
+					manifest.segment_count()
+				);
+				self.adapter
+					.txhashset_manifest_received(manifest, &self.peer_info);
+				Ok(None)
+			}
+
+			Type::TxHashSetSegmentRequest => {
+				let sm_req: TxHashSetSegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: txhashset segment req {} for {}",
+					sm_req.index,
+					sm_req.hash
+				);
+
+				let txhashset = match self.adapter.txhashset_read(sm_req.hash) {
+					Some(txhashset) => txhashset,
+					None => return Ok(None),
+				};
+				let mut reader = txhashset.reader;
+				let start = sm_req.index.saturating_mul(TXHASHSET_SEGMENT_SIZE);
+				if start >= reader.metadata()?.len() {
+					debug!(
+						"handle_payload: txhashset segment {} out of range for {}",
+						sm_req.index, sm_req.hash
+					);
+					return Ok(None);
+				}
+				reader.seek(SeekFrom::Start(start))?;
+				let mut data = vec![];
+				reader.take(TXHASHSET_SEGMENT_SIZE).read_to_end(&mut data)?;
+				Ok(Some(Response::new(
+					Type::TxHashSetSegment,
+					self.peer_info.version,
+					TxHashSetSegment {
+						hash: sm_req.hash,
+						index: sm_req.index,
+						data,
+					},
+					writer,
+				)?))
+			}
+
+			Type::TxHashSetSegment => {
+				let segment: TxHashSetSegment = msg.body()?;
+				trace!(
+					"handle_payload: txhashset segment {} for {}, size={}",
+					segment.index,
+					segment.hash,
+					segment.data.len()
+				);
+
+				if !self
+					.adapter
+					.txhashset_segment_received(segment, &self.peer_info)
+				{
+					return Err(Error::BadMessage);
+				}
+				Ok(None)
+			}
+
+			Type::GetOutputSegment | Type::GetRangeProofSegment | Type::GetKernelSegment => {
+				let msg_type = msg.header.msg_type;
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: {:?} {:?} for {}",
+					msg_type,
+					req.identifier,
+					req.block_hash
+				);
+
+				// larger segments than the ones we download ourselves could
+				// exceed the max msg size
+				let max_height = match msg_type {
+					Type::GetOutputSegment => SegmentType::Output.segment_height(),
+					Type::GetRangeProofSegment => SegmentType::RangeProof.segment_height(),
+					_ => SegmentType::Kernel.segment_height(),
+				};
+				if req.identifier.height > max_height {
+					debug!(
+						"handle_payload: segment of height {} too large, ignoring",
+						req.identifier.height
+					);
+					return Ok(None);
+				}
+
+				let (block_hash, identifier) = (req.block_hash, req.identifier);
+				let version = self.peer_info.version;
+				match msg_type {
+					Type::GetOutputSegment => {
+						match self.adapter.get_output_segment(block_hash, identifier) {
+							Some(segment) => Ok(Some(Response::new(
+								Type::OutputSegment,
+								version,
+								SegmentResponse {
+									block_hash,
+									segment,
+								},
+								writer,
+							)?)),
+							None => Ok(None),
+						}
+					}
+					Type::GetRangeProofSegment => {
+						match self.adapter.get_rangeproof_segment(block_hash, identifier) {
+							Some(segment) => Ok(Some(Response::new(
+								Type::RangeProofSegment,
+								version,
+								SegmentResponse {
+									block_hash,
+									segment,
+								},
+								writer,
+							)?)),
+							None => Ok(None),
+						}
+					}
+					_ => match self.adapter.get_kernel_segment(block_hash, identifier) {
+						Some(segment) => Ok(Some(Response::new(
+							Type::KernelSegment,
+							version,
+							SegmentResponse {
+								block_hash,
+								segment,
+							},
+							writer,
+						)?)),
+						None => Ok(None),
+					},
+				}
+			}
+
+			Type::OutputSegment => {
+				let res: SegmentResponse<OutputIdentifier> = msg.body()?;
+				trace!(
+					"handle_payload: output segment {:?} for {}",
+					res.segment.identifier,
+					res.block_hash
+				);
+				if !self.adapter.receive_output_segment(
+					res.block_hash,
+					res.segment,
+					&self.peer_info,
+				) {
+					return Err(Error::BadMessage);
+				}
+				Ok(None)
+			}
+
+			Type::RangeProofSegment => {
+				let res: SegmentResponse<RangeProof> = msg.body()?;
+				trace!(
+					"handle_payload: rangeproof segment {:?} for {}",
+					res.segment.identifier,
+					res.block_hash
+				);
+				if !self.adapter.receive_rangeproof_segment(
+					res.block_hash,
+					res.segment,
+					&self.peer_info,
+				) {
+					return Err(Error::BadMessage);
+				}
+				Ok(None)
+			}
+
+			Type::KernelSegment => {
+				let res: SegmentResponse<TxKernelEntry> = msg.body()?;
+				trace!(
+					"handle_payload: kernel segment {:?} for {}",
+					res.segment.identifier,
+					res.block_hash
+				);
+				if !self.adapter.receive_kernel_segment(
+					res.block_hash,
+					res.segment,
+					&self.peer_info,
+				) {
+					return Err(Error::BadMessage);
+				}
+				Ok(None)
+			}
+
+			Type::GetOutputBitmap => {
+				let req: OutputBitmapRequest = msg.body()?;
+				trace!("handle_payload: GetOutputBitmap for {}", req.block_hash);
+				match self.adapter.get_output_bitmap(req.block_hash) {
+					Some(bitmap) => Ok(Some(Response::new(
+						Type::OutputBitmap,
+						self.peer_info.version,
+						OutputBitmapResponse {
+							block_hash: req.block_hash,
+							bitmap,
+						},
+						writer,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::OutputBitmap => {
+				let res: OutputBitmapResponse = msg.body()?;
+				trace!(
+					"handle_payload: output bitmap of {} bytes for {}",
+					res.bitmap.len(),
+					res.block_hash
+				);
+				if !self
+					.adapter
+					.receive_output_bitmap(res.block_hash, res.bitmap, &self.peer_info)
+				{
+					return Err(Error::BadMessage);
+				}
+				Ok(None)
+			}
+
 			Type::Error | Type::Hand | Type::Shake => {
 				debug!("Received an unexpected msg: {:?}", msg.header.msg_type);
 				Ok(None)
@@ -20,19 +20,25 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use crate::capture::MessageRecorder;
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{OutputIdentifier, TxKernelEntry};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::handshake::Handshake;
+use crate::msg::{TxHashSetManifest, TxHashSetSegment};
 use crate::peer::Peer;
 use crate::peers::Peers;
 use crate::store::PeerStore;
+use crate::tor;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerAddrInfo, PeerInfo,
+	ProxyMode, ReasonForBan, TxHashSetRead,
 };
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::StopState;
 use chrono::prelude::{DateTime, Utc};
 
@@ -44,6 +50,7 @@ pub struct Server {
 	handshake: Arc<Handshake>,
 	pub peers: Arc<Peers>,
 	stop_state: Arc<StopState>,
+	recorder: Option<Arc<MessageRecorder>>,
 }
 
 // TODO TLS
@@ -57,12 +64,20 @@ impl Server {
 		genesis: Hash,
 		stop_state: Arc<StopState>,
 	) -> Result<Server, Error> {
+		let recorder = match config.capture_file {
+			Some(ref path) => {
+				info!("Capturing p2p messages to {}", path);
+				Some(Arc::new(MessageRecorder::new(path)?))
+			}
+			None => None,
+		};
 		Ok(Server {
 			config: config.clone(),
 			capabilities: capab,
 			handshake: Arc::new(Handshake::new(genesis, config.clone())),
 			peers: Arc::new(Peers::new(PeerStore::new(db_root)?, adapter, config)),
 			stop_state,
+			recorder,
 		})
 	}
 
@@ -84,7 +99,7 @@ impl Server {
 
 			match listener.accept() {
 				Ok((stream, peer_addr)) => {
-					let peer_addr = PeerAddr(peer_addr);
+					let peer_addr = PeerAddr::Ip(peer_addr);
 
 					if self.check_undesirable(&stream) {
 						continue;
@@ -116,6 +131,17 @@ impl Server {
 	/// Asks the server to connect to a new peer. Directly returns the peer if
 	/// we're already connected to the provided address.
 	pub fn connect(&self, addr: PeerAddr) -> Result<Arc<Peer>, Error> {
+		self.connect_with(addr, self.capabilities)
+	}
+
+	/// Asks the server to open a block-relay-only connection to a new peer,
+	/// only exchanging headers and blocks with it. Directly returns the peer
+	/// if we're already connected to the provided address.
+	pub fn connect_block_relay_only(&self, addr: PeerAddr) -> Result<Arc<Peer>, Error> {
+		self.connect_with(addr, self.capabilities | Capabilities::BLOCK_RELAY_ONLY)
+	}
+
+	fn connect_with(&self, addr: PeerAddr, capab: Capabilities) -> Result<Arc<Peer>, Error> {
 		if self.stop_state.is_stopped() {
 			return Err(Error::ConnectionClose);
 		}
@@ -146,18 +172,22 @@ impl Server {
 			self.config.port,
 			addr
 		);
-		match TcpStream::connect_timeout(&addr.0, Duration::from_secs(10)) {
+		// only tracked if we already know about this peer
+		let _ = self.peers.record_attempt(addr);
+		match self.open_stream(addr) {
 			Ok(stream) => {
-				let addr = SocketAddr::new(self.config.host, self.config.port);
+				let self_addr = SocketAddr::new(self.config.host, self.config.port);
 				let total_diff = self.peers.total_difficulty()?;
 
 				let peer = Peer::connect(
 					stream,
-					self.capabilities,
+					capab,
 					total_diff,
-					PeerAddr(addr),
+					PeerAddr::Ip(self_addr),
+					addr,
 					&self.handshake,
 					self.peers.clone(),
+					self.recorder.clone(),
 				)?;
 				let peer = Arc::new(peer);
 				self.peers.add_connected(peer.clone())?;
@@ -176,6 +206,35 @@ impl Server {
 		}
 	}
 
+	/// Opens the underlying TCP stream to the peer, going through the
+	/// SOCKS5 proxy when the address or our proxy mode requires it. Only
+	/// onion peers get connected to when the proxy is required.
+	fn open_stream(&self, addr: PeerAddr) -> io::Result<TcpStream> {
+		let timeout = Duration::from_secs(10);
+		let proxy_mode = self.config.proxy_mode();
+		match (addr, self.config.socks5_proxy) {
+			(PeerAddr::Onion(_), Some(proxy)) => tor::socks5_connect(proxy, &addr, timeout),
+			(PeerAddr::Onion(_), None) => Err(io::Error::new(
+				io::ErrorKind::Other,
+				"onion peer requires a socks5 proxy",
+			)),
+			(PeerAddr::Ip(_), _) if proxy_mode == ProxyMode::Require => Err(io::Error::new(
+				io::ErrorKind::Other,
+				"proxy required, refusing to connect to a non onion peer",
+			)),
+			(PeerAddr::Ip(sa), Some(proxy)) if proxy_mode == ProxyMode::Prefer => {
+				tor::socks5_connect(proxy, &addr, timeout).or_else(|e| {
+					debug!(
+						"connect_peer: proxy failed for {}: {:?}, connecting directly",
+						addr, e
+					);
+					TcpStream::connect_timeout(&sa, timeout)
+				})
+			}
+			(PeerAddr::Ip(sa), _) => TcpStream::connect_timeout(&sa, timeout),
+		}
+	}
+
 	fn handle_new_peer(&self, stream: TcpStream) -> Result<(), Error> {
 		if self.stop_state.is_stopped() {
 			return Err(Error::ConnectionClose);
@@ -189,6 +248,7 @@ impl Server {
 			total_diff,
 			&self.handshake,
 			self.peers.clone(),
+			self.recorder.clone(),
 		)?;
 		self.peers.add_connected(Arc::new(peer))?;
 		Ok(())
@@ -203,9 +263,13 @@ impl Server {
 	/// addresses (NAT), network distribution is improved if they choose
 	/// different sets of peers themselves. In addition, it prevent potential
 	/// duplicate connections, malicious or not.
+	/// 3. We already have as many inbound connections from the same IP or
+	/// network group as we're willing to, see `P2PConfig`.
+	/// 4. We're full and none of our inbound peers can be evicted to make room
+	/// for the new one.
 	fn check_undesirable(&self, stream: &TcpStream) -> bool {
 		if let Ok(peer_addr) = stream.peer_addr() {
-			let peer_addr = PeerAddr(peer_addr);
+			let peer_addr = PeerAddr::Ip(peer_addr);
 			if self.peers.is_banned(peer_addr) {
 				debug!("Peer {} banned, refusing connection.", peer_addr);
 				if let Err(e) = stream.shutdown(Shutdown::Both) {
@@ -220,12 +284,30 @@ impl Server {
 				}
 				return true;
 			}
+			if self.peers.inbound_limit_reached(peer_addr) {
+				debug!(
+					"Peer {} over the inbound limits for its IP or network group, refusing connection.",
+					peer_addr
+				);
+				if let Err(e) = stream.shutdown(Shutdown::Both) {
+					debug!("Error shutting down conn: {:?}", e);
+				}
+				return true;
+			}
+			if !self.peers.make_room_for_inbound() {
+				debug!("No room for peer {}, refusing connection.", peer_addr);
+				if let Err(e) = stream.shutdown(Shutdown::Both) {
+					debug!("Error shutting down conn: {:?}", e);
+				}
+				return true;
+			}
 		}
 		false
 	}
 
 	pub fn stop(&self) {
 		self.stop_state.stop();
+		self.peers.save_anchors();
 		self.peers.stop();
 	}
 
@@ -259,6 +341,7 @@ impl ChainAdapter for DummyAdapter {
 		&self,
 		_: core::Transaction,
 		_stem: bool,
+		_peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
@@ -289,6 +372,16 @@ impl ChainAdapter for DummyAdapter {
 	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error> {
 		Ok(vec![])
 	}
+	fn locate_skeleton(&self, _: Hash, _: u16) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+	fn skeleton_received(
+		&self,
+		_: &[core::BlockHeader],
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
 	fn get_block(&self, _: Hash) -> Option<core::Block> {
 		None
 	}
@@ -302,6 +395,10 @@ impl ChainAdapter for DummyAdapter {
 		unimplemented!()
 	}
 
+	fn txhashset_manifest(&self, _h: Hash) -> Option<TxHashSetManifest> {
+		None
+	}
+
 	fn txhashset_receive_ready(&self) -> bool {
 		false
 	}
@@ -324,6 +421,48 @@ impl ChainAdapter for DummyAdapter {
 		false
 	}
 
+	fn txhashset_manifest_received(&self, _: TxHashSetManifest, _: &PeerInfo) {}
+
+	fn txhashset_segment_received(&self, _: TxHashSetSegment, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn get_output_segment(
+		&self,
+		_: Hash,
+		_: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>> {
+		None
+	}
+
+	fn get_rangeproof_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<RangeProof>> {
+		None
+	}
+
+	fn get_kernel_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<TxKernelEntry>> {
+		None
+	}
+
+	fn receive_output_segment(&self, _: Hash, _: Segment<OutputIdentifier>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn receive_rangeproof_segment(&self, _: Hash, _: Segment<RangeProof>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn receive_kernel_segment(&self, _: Hash, _: Segment<TxKernelEntry>, _: &PeerInfo) -> bool {
+		false
+	}
+
+	fn get_output_bitmap(&self, _: Hash) -> Option<Vec<u8>> {
+		None
+	}
+
+	fn receive_output_bitmap(&self, _: Hash, _: Vec<u8>, _: &PeerInfo) -> bool {
+		false
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		unimplemented!()
 	}
@@ -334,10 +473,10 @@ impl ChainAdapter for DummyAdapter {
 }
 
 impl NetAdapter for DummyAdapter {
-	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddrInfo> {
 		vec![]
 	}
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_addrs_received(&self, _: Vec<PeerAddrInfo>, _: PeerAddr) {}
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
@@ -27,6 +27,7 @@ const DB_NAME: &'static str = "peer";
 const STORE_SUBPATH: &'static str = "peers";
 
 const PEER_PREFIX: u8 = 'P' as u8;
+const ANCHOR_PREFIX: u8 = 'A' as u8;
 
 // Types of messages
 enum_from_primitive! {
@@ -44,7 +45,7 @@ pub struct PeerData {
 	/// Network address of the peer.
 	pub addr: PeerAddr,
 	/// What capabilities the peer advertises. Unknown until a successful
-	/// connection.
+	/// connection or until another peer tells us about them.
 	pub capabilities: Capabilities,
 	/// The peer user agent.
 	pub user_agent: String,
@@ -56,6 +57,43 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// Outbound connection attempts since our last successful one.
+	#[serde(default)]
+	pub attempts: u32,
+	/// Total number of successful outbound connections to this peer.
+	#[serde(default)]
+	pub successes: u32,
+	/// Time of our last outbound connection attempt.
+	#[serde(default)]
+	pub last_attempt: i64,
+	/// The peer that advertised this address to us, if we did not learn
+	/// about it directly.
+	#[serde(default)]
+	pub source: Option<PeerAddr>,
+	/// Last time the peer was known to be reachable, either by us or by the
+	/// peers that told us about it. 0 when unknown.
+	#[serde(default)]
+	pub last_seen: i64,
+}
+
+impl PeerData {
+	/// Data for a peer we just learned about and never connected to.
+	pub fn new(addr: PeerAddr, source: Option<PeerAddr>) -> PeerData {
+		PeerData {
+			addr,
+			capabilities: Capabilities::UNKNOWN,
+			user_agent: "".to_string(),
+			flags: State::Healthy,
+			last_banned: 0,
+			ban_reason: ReasonForBan::None,
+			last_connected: Utc::now().timestamp(),
+			attempts: 0,
+			successes: 0,
+			last_attempt: 0,
+			source,
+			last_seen: 0,
+		}
+	}
 }
 
 impl Writeable for PeerData {
@@ -68,8 +106,19 @@ impl Writeable for PeerData {
 			[write_u8, self.flags as u8],
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
-			[write_i64, self.last_connected]
+			[write_i64, self.last_connected],
+			[write_u32, self.attempts],
+			[write_u32, self.successes],
+			[write_i64, self.last_attempt]
 		);
+		match self.source {
+			Some(source) => {
+				writer.write_u8(1)?;
+				source.write(writer)?;
+			}
+			None => writer.write_u8(0)?,
+		}
+		writer.write_i64(self.last_seen)?;
 		Ok(())
 	}
 }
@@ -88,6 +137,11 @@ impl Readable for PeerData {
 			Err(_) => Utc::now().timestamp(),
 			Ok(lc) => lc,
 		};
+		// same as above, connection stats were added later on
+		let (attempts, successes, last_attempt, source) =
+			read_connection_stats(reader).unwrap_or((0, 0, 0, None));
+		// and last seen after that, defaulting to the last time we connected
+		let last_seen = reader.read_i64().unwrap_or(last_connected);
 
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
@@ -102,12 +156,29 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				attempts,
+				successes,
+				last_attempt,
+				source,
+				last_seen,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
 	}
 }
 
+fn read_connection_stats(
+	reader: &mut dyn Reader,
+) -> Result<(u32, u32, i64, Option<PeerAddr>), ser::Error> {
+	let (attempts, successes, last_attempt) = ser_multiread!(reader, read_u32, read_u32, read_i64);
+	let source = match reader.read_u8()? {
+		0 => None,
+		1 => Some(PeerAddr::read(reader)?),
+		_ => return Err(ser::Error::CorruptedData),
+	};
+	Ok((attempts, successes, last_attempt, source))
+}
+
 /// Storage facility for peer data.
 pub struct PeerStore {
 	db: grin_store::Store,
@@ -176,24 +247,61 @@ impl PeerStore {
 
 	/// Convenience method to load a peer data, update its status and save it
 	/// back. If new state is Banned its last banned time will be updated too.
-	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
+	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<PeerData, Error> {
+		self.update_peer(peer_addr, |peer| {
+			peer.flags = new_state;
+			if new_state == State::Banned {
+				peer.last_banned = Utc::now().timestamp();
+			}
+		})
+	}
+
+	/// Loads a peer data, applies the provided update to it and saves it back
+	/// in a single batch. Returns the updated peer data.
+	pub fn update_peer<F>(&self, peer_addr: PeerAddr, update: F) -> Result<PeerData, Error>
+	where
+		F: FnOnce(&mut PeerData),
+	{
 		let batch = self.db.batch()?;
 
 		let mut peer = option_to_not_found(
 			batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]),
 			&format!("Peer at address: {}", peer_addr),
 		)?;
-		peer.flags = new_state;
-		if new_state == State::Banned {
-			peer.last_banned = Utc::now().timestamp();
-		}
+		update(&mut peer);
 
 		batch.put_ser(&peer_key(peer_addr)[..], &peer)?;
+		batch.commit()?;
+		Ok(peer)
+	}
+
+	/// Replaces our anchors, the outbound peers we want to reconnect to first
+	/// on our next start.
+	pub fn save_anchors(&self, anchors: &[PeerAddr]) -> Result<(), Error> {
+		let key = to_key(ANCHOR_PREFIX, &mut "".to_string().into_bytes());
+		let batch = self.db.batch()?;
+		for (k, _) in self.db.iter::<PeerAddr>(&key)? {
+			batch.delete(&k)?;
+		}
+		for addr in anchors {
+			batch.put_ser(&anchor_key(*addr)[..], addr)?;
+		}
 		batch.commit()
 	}
 
-	/// Deletes peers from the storage that satisfy some condition `predicate`
-	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
+	/// Anchors saved on our last shutdown.
+	pub fn anchors(&self) -> Result<Vec<PeerAddr>, Error> {
+		let key = to_key(ANCHOR_PREFIX, &mut "".to_string().into_bytes());
+		Ok(self
+			.db
+			.iter::<PeerAddr>(&key)?
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>())
+	}
+
+	/// Deletes peers from the storage that satisfy some condition `predicate`,
+	/// returns the addresses of the deleted peers.
+	pub fn delete_peers<F>(&self, predicate: F) -> Result<Vec<PeerAddr>, Error>
 	where
 		F: Fn(&PeerData) -> bool,
 	{
@@ -209,14 +317,14 @@ impl PeerStore {
 		if !to_remove.is_empty() {
 			let batch = self.db.batch()?;
 
-			for peer in to_remove {
+			for peer in &to_remove {
 				batch.delete(&peer_key(peer.addr)[..])?;
 			}
 
 			batch.commit()?;
 		}
 
-		Ok(())
+		Ok(to_remove.into_iter().map(|p| p.addr).collect())
 	}
 }
 
@@ -224,3 +332,7 @@ impl PeerStore {
 fn peer_key(peer_addr: PeerAddr) -> Vec<u8> {
 	to_key(PEER_PREFIX, &mut peer_addr.as_key().into_bytes())
 }
+
+fn anchor_key(peer_addr: PeerAddr) -> Vec<u8> {
+	to_key(ANCHOR_PREFIX, &mut peer_addr.as_key().into_bytes())
+}
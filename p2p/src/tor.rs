@@ -0,0 +1,213 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Onion v3 service addresses and outbound connections through a SOCKS5
+//! proxy (typically the one exposed by a local Tor daemon).
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use sha3::{Digest, Sha3_256};
+
+use crate::types::PeerAddr;
+
+/// Length of the ed25519 public key identifying an onion v3 service.
+pub const ONION_V3_PUBKEY_LEN: usize = 32;
+
+/// Version byte embedded in every onion v3 hostname.
+const ONION_V3_VERSION: u8 = 3;
+
+/// Length of the base32 part of an onion v3 hostname
+/// (pubkey + 2 bytes checksum + 1 byte version).
+const ONION_V3_HOST_LEN: usize = 56;
+
+const ONION_SUFFIX: &'static str = ".onion";
+
+const BASE32_ALPHABET: &'static [u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+// SOCKS5 protocol constants, see RFC 1928.
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+const SOCKS_REPLY_SUCCEEDED: u8 = 0;
+
+/// An onion v3 service address, the ed25519 public key of the service along
+/// with the port it listens on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnionAddr {
+	pub pubkey: [u8; ONION_V3_PUBKEY_LEN],
+	pub port: u16,
+}
+
+impl OnionAddr {
+	/// The "<base32>.onion" hostname of the service.
+	pub fn host(&self) -> String {
+		let mut data = self.pubkey.to_vec();
+		data.extend_from_slice(&onion_checksum(&self.pubkey));
+		data.push(ONION_V3_VERSION);
+		format!("{}{}", base32_encode(&data), ONION_SUFFIX)
+	}
+
+	/// Parses a "<base32>.onion" hostname, checking both the embedded version
+	/// and checksum.
+	pub fn from_host(host: &str, port: u16) -> Option<OnionAddr> {
+		let host = host.to_ascii_lowercase();
+		if !host.ends_with(ONION_SUFFIX) {
+			return None;
+		}
+		let encoded = &host[..host.len() - ONION_SUFFIX.len()];
+		if encoded.len() != ONION_V3_HOST_LEN {
+			return None;
+		}
+		let data = base32_decode(encoded)?;
+		if data[ONION_V3_PUBKEY_LEN + 2] != ONION_V3_VERSION {
+			return None;
+		}
+		let mut pubkey = [0u8; ONION_V3_PUBKEY_LEN];
+		pubkey.copy_from_slice(&data[..ONION_V3_PUBKEY_LEN]);
+		if data[ONION_V3_PUBKEY_LEN..ONION_V3_PUBKEY_LEN + 2] != onion_checksum(&pubkey) {
+			return None;
+		}
+		Some(OnionAddr { pubkey, port })
+	}
+}
+
+/// First 2 bytes of SHA3_256(".onion checksum" | pubkey | version).
+fn onion_checksum(pubkey: &[u8; ONION_V3_PUBKEY_LEN]) -> [u8; 2] {
+	let mut hasher = Sha3_256::new();
+	hasher.input(b".onion checksum");
+	hasher.input(&pubkey[..]);
+	hasher.input(&[ONION_V3_VERSION]);
+	let hash = hasher.result();
+	[hash[0], hash[1]]
+}
+
+fn base32_encode(data: &[u8]) -> String {
+	let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+	let mut buffer: u32 = 0;
+	let mut bits = 0;
+	for b in data {
+		buffer = (buffer << 8) | *b as u32;
+		bits += 8;
+		while bits >= 5 {
+			bits -= 5;
+			out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+		}
+	}
+	if bits > 0 {
+		out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+	}
+	out
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+	let mut buffer: u32 = 0;
+	let mut bits = 0;
+	for c in encoded.bytes() {
+		let v = BASE32_ALPHABET.iter().position(|x| *x == c)? as u32;
+		buffer = (buffer << 5) | v;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((buffer >> bits) as u8);
+		}
+	}
+	Some(out)
+}
+
+/// Opens a connection to the provided peer address through the SOCKS5 proxy.
+/// Onion addresses are passed to the proxy as hostnames so they get resolved
+/// on the proxy side, never locally.
+pub fn socks5_connect(
+	proxy: SocketAddr,
+	addr: &PeerAddr,
+	timeout: Duration,
+) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+
+	// greeting, we only support the "no authentication" method
+	stream.write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTH])?;
+	let mut method = [0u8; 2];
+	stream.read_exact(&mut method)?;
+	if method[0] != SOCKS_VERSION || method[1] != SOCKS_NO_AUTH {
+		return Err(socks_error("proxy refused authentication method"));
+	}
+
+	// connect request
+	let mut req = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+	match addr {
+		PeerAddr::Ip(sa) => {
+			match sa.ip() {
+				IpAddr::V4(ip) => {
+					req.push(SOCKS_ATYP_IPV4);
+					req.extend_from_slice(&ip.octets());
+				}
+				IpAddr::V6(ip) => {
+					req.push(SOCKS_ATYP_IPV6);
+					req.extend_from_slice(&ip.octets());
+				}
+			}
+			req.extend_from_slice(&sa.port().to_be_bytes());
+		}
+		PeerAddr::Onion(onion) => {
+			let host = onion.host();
+			req.push(SOCKS_ATYP_DOMAIN);
+			req.push(host.len() as u8);
+			req.extend_from_slice(host.as_bytes());
+			req.extend_from_slice(&onion.port.to_be_bytes());
+		}
+	}
+	stream.write_all(&req)?;
+
+	// reply, we do not care about the bound address but still need to
+	// consume it off the stream
+	let mut reply = [0u8; 4];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != SOCKS_VERSION {
+		return Err(socks_error("unexpected proxy reply version"));
+	}
+	if reply[1] != SOCKS_REPLY_SUCCEEDED {
+		return Err(socks_error(&format!(
+			"proxy connect failed, code {}",
+			reply[1]
+		)));
+	}
+	let bound_len = match reply[3] {
+		SOCKS_ATYP_IPV4 => 4,
+		SOCKS_ATYP_IPV6 => 16,
+		SOCKS_ATYP_DOMAIN => {
+			let mut len = [0u8; 1];
+			stream.read_exact(&mut len)?;
+			len[0] as usize
+		}
+		_ => return Err(socks_error("unexpected proxy bound address type")),
+	};
+	let mut bound = vec![0u8; bound_len + 2];
+	stream.read_exact(&mut bound)?;
+
+	stream.set_read_timeout(None)?;
+	stream.set_write_timeout(None)?;
+	Ok(stream)
+}
+
+fn socks_error(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("socks5: {}", msg))
+}
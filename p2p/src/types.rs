@@ -13,29 +13,41 @@
 // limitations under the License.
 
 use crate::util::RwLock;
+use std::cmp;
 use std::convert::From;
 use std::fs::File;
 use std::io::{self, Read};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use std::sync::mpsc;
 use std::sync::Arc;
 
 use chrono::prelude::*;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{OutputIdentifier, TxKernelEntry};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
-use crate::msg::ProtocolVersion;
+use crate::msg::{ProtocolVersion, TxHashSetManifest, TxHashSetSegment};
+use crate::tor::{OnionAddr, ONION_V3_PUBKEY_LEN};
+use crate::util::secp::pedersen::RangeProof;
 use grin_store;
 
 /// Maximum number of block headers a peer should ever send
 pub const MAX_BLOCK_HEADERS: u32 = 512;
 
+/// Maximum number of headers in a header chain skeleton, each one
+/// MAX_BLOCK_HEADERS apart
+pub const MAX_SKELETON_HEADERS: u32 = 64;
+
 /// Maximum number of block bodies a peer should ever ask for and send
 #[allow(dead_code)]
 pub const MAX_BLOCK_BODIES: u32 = 16;
@@ -43,9 +55,24 @@ pub const MAX_BLOCK_BODIES: u32 = 16;
 /// Maximum number of peer addresses a peer should ever send
 pub const MAX_PEER_ADDRS: u32 = 256;
 
+/// Peer addresses not known to have been reachable for longer than this are
+/// neither handed out to other peers nor accepted from them.
+pub const PEER_ADDR_MAX_AGE_SECS: i64 = 7 * 24 * 3600;
+
 /// Maximum number of block header hashes to send as part of a locator
 pub const MAX_LOCATORS: u32 = 20;
 
+/// Size of the segments a txhashset archive is split into for chunked
+/// download
+pub const TXHASHSET_SEGMENT_SIZE: u64 = 1 << 20;
+
+/// Maximum number of segments a txhashset archive can be split into
+pub const MAX_TXHASHSET_SEGMENTS: u64 = 1 << 16;
+
+/// Maximum size of the output leaf set bitset sent to peers syncing from
+/// MMR segments, one bit per output
+pub const MAX_OUTPUT_BITMAP_SIZE: u64 = 1 << 24;
+
 /// How long a banned peer should be banned for
 const BAN_WINDOW: i64 = 10800;
 
@@ -55,6 +82,22 @@ const PEER_MAX_COUNT: u32 = 125;
 /// min preferred peer count
 const PEER_MIN_PREFERRED_COUNT: u32 = 8;
 
+/// Number of extra outbound block-relay-only connections
+const BLOCK_RELAY_ONLY_COUNT: u32 = 2;
+
+/// Max inbound connections from a single IP address
+const PEER_MAX_INBOUND_PER_IP: u32 = 1;
+
+/// Max inbound connections from a single network group
+const PEER_MAX_INBOUND_PER_NET_GROUP: u32 = 8;
+
+/// Rate at which a peer earns the right to send us peer addresses we didn't
+/// ask for, per second
+const PEER_ADDR_RATE_PER_SEC: f64 = 0.1;
+
+/// Maximum number of unrequested peer addresses a peer can save up
+const MAX_PEER_ADDR_TOKENS: f64 = 1000.0;
+
 #[derive(Debug)]
 pub enum Error {
 	Serialization(ser::Error),
@@ -104,13 +147,18 @@ impl<T> From<mpsc::TrySendError<T>> for Error {
 	}
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct PeerAddr(pub SocketAddr);
+/// Network address of a peer, either a regular socket address or an onion
+/// v3 service only reachable through a SOCKS5 proxy.
+#[derive(Debug, Clone, Copy)]
+pub enum PeerAddr {
+	Ip(SocketAddr),
+	Onion(OnionAddr),
+}
 
 impl Writeable for PeerAddr {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		match self.0 {
-			SocketAddr::V4(sav4) => {
+		match self {
+			PeerAddr::Ip(SocketAddr::V4(sav4)) => {
 				ser_multiwrite!(
 					writer,
 					[write_u8, 0],
@@ -118,13 +166,24 @@ impl Writeable for PeerAddr {
 					[write_u16, sav4.port()]
 				);
 			}
-			SocketAddr::V6(sav6) => {
+			PeerAddr::Ip(SocketAddr::V6(sav6)) => {
 				writer.write_u8(1)?;
 				for seg in &sav6.ip().segments() {
 					writer.write_u16(*seg)?;
 				}
 				writer.write_u16(sav6.port())?;
 			}
+			PeerAddr::Onion(onion) => {
+				if writer.protocol_version() < ProtocolVersion(2) {
+					return Err(ser::Error::UnsupportedProtocolVersion);
+				}
+				ser_multiwrite!(
+					writer,
+					[write_u8, 2],
+					[write_fixed_bytes, &onion.pubkey.to_vec()],
+					[write_u16, onion.port]
+				);
+			}
 		}
 		Ok(())
 	}
@@ -132,23 +191,33 @@ impl Writeable for PeerAddr {
 
 impl Readable for PeerAddr {
 	fn read(reader: &mut dyn Reader) -> Result<PeerAddr, ser::Error> {
-		let v4_or_v6 = reader.read_u8()?;
-		if v4_or_v6 == 0 {
-			let ip = reader.read_fixed_bytes(4)?;
-			let port = reader.read_u16()?;
-			Ok(PeerAddr(SocketAddr::V4(SocketAddrV4::new(
-				Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]),
-				port,
-			))))
-		} else {
-			let ip = try_iter_map_vec!(0..8, |_| reader.read_u16());
-			let port = reader.read_u16()?;
-			Ok(PeerAddr(SocketAddr::V6(SocketAddrV6::new(
-				Ipv6Addr::new(ip[0], ip[1], ip[2], ip[3], ip[4], ip[5], ip[6], ip[7]),
-				port,
-				0,
-				0,
-			))))
+		match reader.read_u8()? {
+			0 => {
+				let ip = reader.read_fixed_bytes(4)?;
+				let port = reader.read_u16()?;
+				Ok(PeerAddr::Ip(SocketAddr::V4(SocketAddrV4::new(
+					Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]),
+					port,
+				))))
+			}
+			1 => {
+				let ip = try_iter_map_vec!(0..8, |_| reader.read_u16());
+				let port = reader.read_u16()?;
+				Ok(PeerAddr::Ip(SocketAddr::V6(SocketAddrV6::new(
+					Ipv6Addr::new(ip[0], ip[1], ip[2], ip[3], ip[4], ip[5], ip[6], ip[7]),
+					port,
+					0,
+					0,
+				))))
+			}
+			2 if reader.protocol_version() >= ProtocolVersion(2) => {
+				let bytes = reader.read_fixed_bytes(ONION_V3_PUBKEY_LEN)?;
+				let port = reader.read_u16()?;
+				let mut pubkey = [0u8; ONION_V3_PUBKEY_LEN];
+				pubkey.copy_from_slice(&bytes);
+				Ok(PeerAddr::Onion(OnionAddr { pubkey, port }))
+			}
+			_ => Err(ser::Error::CorruptedData),
 		}
 	}
 }
@@ -156,11 +225,17 @@ impl Readable for PeerAddr {
 impl std::hash::Hash for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
+	/// Onion addresses are identified by their service key only.
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-		if self.0.ip().is_loopback() {
-			self.0.hash(state);
-		} else {
-			self.0.ip().hash(state);
+		match self {
+			PeerAddr::Ip(addr) => {
+				if addr.ip().is_loopback() {
+					addr.hash(state);
+				} else {
+					addr.ip().hash(state);
+				}
+			}
+			PeerAddr::Onion(onion) => onion.pubkey.hash(state),
 		}
 	}
 }
@@ -168,11 +243,18 @@ impl std::hash::Hash for PeerAddr {
 impl PartialEq for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
+	/// Onion addresses are identified by their service key only.
 	fn eq(&self, other: &PeerAddr) -> bool {
-		if self.0.ip().is_loopback() {
-			self.0 == other.0
-		} else {
-			self.0.ip() == other.0.ip()
+		match (self, other) {
+			(PeerAddr::Ip(a), PeerAddr::Ip(b)) => {
+				if a.ip().is_loopback() {
+					a == b
+				} else {
+					a.ip() == b.ip()
+				}
+			}
+			(PeerAddr::Onion(a), PeerAddr::Onion(b)) => a.pubkey == b.pubkey,
+			_ => false,
 		}
 	}
 }
@@ -181,7 +263,54 @@ impl Eq for PeerAddr {}
 
 impl std::fmt::Display for PeerAddr {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{}", self.0)
+		match self {
+			PeerAddr::Ip(addr) => write!(f, "{}", addr),
+			PeerAddr::Onion(onion) => write!(f, "{}:{}", onion.host(), onion.port),
+		}
+	}
+}
+
+/// Error parsing a peer address from its "host:port" string representation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerAddrParseError(pub String);
+
+impl std::fmt::Display for PeerAddrParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "invalid peer address: {}", self.0)
+	}
+}
+
+impl FromStr for PeerAddr {
+	type Err = PeerAddrParseError;
+
+	/// Parses either a regular socket address or an "<base32>.onion:port"
+	/// onion v3 address.
+	fn from_str(s: &str) -> Result<PeerAddr, PeerAddrParseError> {
+		if let Ok(addr) = s.parse::<SocketAddr>() {
+			return Ok(PeerAddr::Ip(addr));
+		}
+		let err = || PeerAddrParseError(s.to_string());
+		let idx = s.rfind(':').ok_or_else(err)?;
+		let port = s[idx + 1..].parse::<u16>().map_err(|_| err())?;
+		OnionAddr::from_host(&s[..idx], port)
+			.map(PeerAddr::Onion)
+			.ok_or_else(err)
+	}
+}
+
+// Peer addresses are (de)serialized as "host:port" strings, this is what
+// SocketAddr does for human readable formats and keeps the config and api
+// formats unchanged for regular addresses.
+impl Serialize for PeerAddr {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for PeerAddr {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PeerAddr, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(de::Error::custom)
 	}
 }
 
@@ -190,20 +319,104 @@ impl PeerAddr {
 	/// defaults to port 3414 on mainnet and 13414 on floonet.
 	pub fn from_ip(addr: IpAddr) -> PeerAddr {
 		let port = if global::is_floonet() { 13414 } else { 3414 };
-		PeerAddr(SocketAddr::new(addr, port))
+		PeerAddr::Ip(SocketAddr::new(addr, port))
+	}
+
+	/// The port the peer listens on.
+	pub fn port(&self) -> u16 {
+		match self {
+			PeerAddr::Ip(addr) => addr.port(),
+			PeerAddr::Onion(onion) => onion.port,
+		}
+	}
+
+	/// Whether this is an onion address, only reachable via a proxy.
+	pub fn is_onion(&self) -> bool {
+		match self {
+			PeerAddr::Onion(_) => true,
+			PeerAddr::Ip(_) => false,
+		}
 	}
 
 	/// If the ip is loopback then our key is "ip:port" (mainly for local usernet testing).
 	/// Otherwise we only care about the ip (we disallow multiple peers on the same ip address).
+	/// Onion addresses are keyed on their hostname.
 	pub fn as_key(&self) -> String {
-		if self.0.ip().is_loopback() {
-			format!("{}:{}", self.0.ip(), self.0.port())
-		} else {
-			format!("{}", self.0.ip())
+		match self {
+			PeerAddr::Ip(addr) => {
+				if addr.ip().is_loopback() {
+					format!("{}:{}", addr.ip(), addr.port())
+				} else {
+					format!("{}", addr.ip())
+				}
+			}
+			PeerAddr::Onion(onion) => onion.host(),
+		}
+	}
+
+	/// The network group this address belongs to, roughly the part of the
+	/// address space a single operator can easily get addresses from. IPv4
+	/// addresses are grouped by /16 and IPv6 ones by /32. Loopback addresses
+	/// each get their own group (mainly for local usernet testing).
+	pub fn net_group(&self) -> NetGroup {
+		match self {
+			PeerAddr::Ip(addr) if addr.ip().is_loopback() => {
+				let mut group = vec![NET_GROUP_LOCAL];
+				group.extend_from_slice(&addr.port().to_be_bytes());
+				NetGroup(group)
+			}
+			PeerAddr::Ip(SocketAddr::V4(addr)) => {
+				let ip = addr.ip().octets();
+				NetGroup(vec![NET_GROUP_IPV4, ip[0], ip[1]])
+			}
+			PeerAddr::Ip(SocketAddr::V6(addr)) => {
+				let ip = addr.ip().octets();
+				// ipv4-mapped addresses group with their ipv4 counterpart
+				if ip[..12] == [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff] {
+					NetGroup(vec![NET_GROUP_IPV4, ip[12], ip[13]])
+				} else {
+					NetGroup(vec![NET_GROUP_IPV6, ip[0], ip[1], ip[2], ip[3]])
+				}
+			}
+			PeerAddr::Onion(onion) => NetGroup(vec![NET_GROUP_ONION, onion.pubkey[0] >> 4]),
 		}
 	}
 }
 
+/// A peer address as exchanged with other peers, along with what's known of
+/// the peer behind it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerAddrInfo {
+	/// Address of the peer
+	pub addr: PeerAddr,
+	/// Capabilities the peer advertised, unknown when received from peers
+	/// prior to protocol version 6
+	pub capabilities: Capabilities,
+	/// Last time the peer was known to be reachable (unix timestamp), 0 when
+	/// unknown
+	pub last_seen: i64,
+}
+
+impl PeerAddrInfo {
+	/// An address we know nothing else about.
+	pub fn new(addr: PeerAddr) -> PeerAddrInfo {
+		PeerAddrInfo {
+			addr,
+			capabilities: Capabilities::UNKNOWN,
+			last_seen: 0,
+		}
+	}
+}
+
+const NET_GROUP_LOCAL: u8 = 0;
+const NET_GROUP_IPV4: u8 = 1;
+const NET_GROUP_IPV6: u8 = 2;
+const NET_GROUP_ONION: u8 = 3;
+
+/// Opaque identifier of a network group, see `PeerAddr::net_group`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetGroup(Vec<u8>);
+
 /// Configuration for the peer-to-peer server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct P2PConfig {
@@ -234,7 +447,30 @@ pub struct P2PConfig {
 
 	pub peer_min_preferred_count: Option<u32>,
 
+	/// Number of extra outbound connections only used to relay headers and
+	/// blocks, on top of our regular outbound connections.
+	pub block_relay_only_count: Option<u32>,
+
+	/// Max inbound connections accepted from a single IP address, loopback
+	/// addresses excepted.
+	pub peer_max_inbound_per_ip: Option<u32>,
+
+	/// Max inbound connections accepted from a single network group (an
+	/// IPv4 /16 or IPv6 /32), loopback addresses excepted.
+	pub peer_max_inbound_per_net_group: Option<u32>,
+
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// Address of a SOCKS5 proxy (a local Tor daemon for example) used for
+	/// outbound connections, required to connect to onion peers.
+	pub socks5_proxy: Option<SocketAddr>,
+
+	/// Which outbound connections go through the SOCKS5 proxy.
+	pub proxy_mode: Option<ProxyMode>,
+
+	/// File every message exchanged with peers gets appended to, for later
+	/// replay when diagnosing sync issues.
+	pub capture_file: Option<String>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -253,7 +489,13 @@ impl Default for P2PConfig {
 			ban_window: None,
 			peer_max_count: None,
 			peer_min_preferred_count: None,
+			block_relay_only_count: None,
+			peer_max_inbound_per_ip: None,
+			peer_max_inbound_per_net_group: None,
 			dandelion_peer: None,
+			socks5_proxy: None,
+			proxy_mode: None,
+			capture_file: None,
 		}
 	}
 }
@@ -284,6 +526,31 @@ impl P2PConfig {
 			None => PEER_MIN_PREFERRED_COUNT,
 		}
 	}
+
+	/// return block_relay_only_count
+	pub fn block_relay_only_count(&self) -> u32 {
+		match self.block_relay_only_count {
+			Some(n) => n,
+			None => BLOCK_RELAY_ONLY_COUNT,
+		}
+	}
+
+	/// return peer_max_inbound_per_ip
+	pub fn peer_max_inbound_per_ip(&self) -> u32 {
+		self.peer_max_inbound_per_ip
+			.unwrap_or(PEER_MAX_INBOUND_PER_IP)
+	}
+
+	/// return peer_max_inbound_per_net_group
+	pub fn peer_max_inbound_per_net_group(&self) -> u32 {
+		self.peer_max_inbound_per_net_group
+			.unwrap_or(PEER_MAX_INBOUND_PER_NET_GROUP)
+	}
+
+	/// return proxy_mode
+	pub fn proxy_mode(&self) -> ProxyMode {
+		self.proxy_mode.unwrap_or_default()
+	}
 }
 
 /// Type of seeding the server will use to find other peers on the network.
@@ -305,6 +572,25 @@ impl Default for Seeding {
 	}
 }
 
+/// How outbound connections make use of the configured SOCKS5 proxy.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ProxyMode {
+	/// Only onion peers are reached through the proxy, others directly.
+	OnionOnly,
+	/// All outbound connections go through the proxy, falling back to a
+	/// direct connection if the proxy fails. Onion peers are tried first.
+	Prefer,
+	/// We only connect to onion peers, through the proxy. Connections to
+	/// other peers get refused and DNS seeds aren't looked up.
+	Require,
+}
+
+impl Default for ProxyMode {
+	fn default() -> ProxyMode {
+		ProxyMode::OnionOnly
+	}
+}
+
 bitflags! {
 	/// Options for what type of interaction a peer supports
 	#[derive(Serialize, Deserialize)]
@@ -321,6 +607,10 @@ bitflags! {
 		const PEER_LIST = 0b00000100;
 		/// Can broadcast and request txs by kernel hash.
 		const TX_KERNEL_HASH = 0b00001000;
+		/// Only headers and blocks are exchanged on this connection, no
+		/// transactions and no peer addresses. Set by the initiator of a
+		/// block-relay-only connection, never advertised for a node itself.
+		const BLOCK_RELAY_ONLY = 0b00010000;
 
 		/// All nodes right now are "full nodes".
 		/// Some nodes internally may maintain longer block histories (archival_mode)
@@ -364,6 +654,19 @@ pub struct PeerLiveInfo {
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
+	/// Number of peer addresses the peer can still send us unrequested
+	pub addr_tokens: f64,
+	pub addr_tokens_updated: DateTime<Utc>,
+	/// Whether we asked the peer for addresses and it hasn't answered yet
+	pub addrs_requested: bool,
+	/// When we sent the ping the peer hasn't answered yet, if any
+	pub ping_sent: Option<DateTime<Utc>>,
+	/// Round trip time of the last ping the peer answered
+	pub latency: Option<chrono::Duration>,
+	/// Last time the peer sent us a block extending our chain
+	pub last_block_received: Option<DateTime<Utc>>,
+	/// Last time the peer sent us a transaction we accepted
+	pub last_tx_received: Option<DateTime<Utc>>,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -385,6 +688,13 @@ impl PeerLiveInfo {
 			first_seen: Utc::now(),
 			last_seen: Utc::now(),
 			stuck_detector: Utc::now(),
+			addr_tokens: 1.0,
+			addr_tokens_updated: Utc::now(),
+			addrs_requested: false,
+			ping_sent: None,
+			latency: None,
+			last_block_received: None,
+			last_tx_received: None,
 		}
 	}
 }
@@ -399,6 +709,11 @@ impl PeerInfo {
 		self.direction == Direction::Outbound
 	}
 
+	/// Whether this connection only relays headers and blocks.
+	pub fn is_block_relay_only(&self) -> bool {
+		self.capabilities.contains(Capabilities::BLOCK_RELAY_ONLY)
+	}
+
 	/// The current height of the peer.
 	pub fn height(&self) -> u64 {
 		self.live_info.read().height
@@ -425,6 +740,68 @@ impl PeerInfo {
 		live_info.total_difficulty = total_difficulty;
 		live_info.last_seen = Utc::now()
 	}
+
+	/// Takes up to `count` from the peer addresses the peer is allowed to
+	/// send us, returning how many we accept. The answer to our outstanding
+	/// request, if any, can hold up to MAX_PEER_ADDRS addresses, unrequested
+	/// ones are taken from an allowance slowly refilling over time.
+	pub fn take_addr_tokens(&self, count: usize) -> usize {
+		let mut live_info = self.live_info.write();
+		if live_info.addrs_requested {
+			live_info.addrs_requested = false;
+			return cmp::min(count, MAX_PEER_ADDRS as usize);
+		}
+		let now = Utc::now();
+		if live_info.addr_tokens < MAX_PEER_ADDR_TOKENS {
+			let elapsed = (now - live_info.addr_tokens_updated).num_milliseconds() as f64 / 1000.0;
+			live_info.addr_tokens = (live_info.addr_tokens
+				+ elapsed.max(0.0) * PEER_ADDR_RATE_PER_SEC)
+				.min(MAX_PEER_ADDR_TOKENS);
+		}
+		live_info.addr_tokens_updated = now;
+		let taken = cmp::min(count, live_info.addr_tokens as usize);
+		live_info.addr_tokens -= taken as f64;
+		taken
+	}
+
+	/// We asked the peer for addresses, let it send a full response. Only
+	/// one request is outstanding at a time, asking again before getting an
+	/// answer doesn't allow for more addresses.
+	pub fn addrs_requested(&self) {
+		self.live_info.write().addrs_requested = true;
+	}
+
+	/// A ping was just sent to the peer. Only the first of unanswered pings
+	/// counts, so a slow peer doesn't look faster than it is.
+	pub fn ping_sent(&self) {
+		let mut live_info = self.live_info.write();
+		if live_info.ping_sent.is_none() {
+			live_info.ping_sent = Some(Utc::now());
+		}
+	}
+
+	/// The peer answered our ping, update its latency.
+	pub fn pong_received(&self) {
+		let mut live_info = self.live_info.write();
+		if let Some(sent) = live_info.ping_sent.take() {
+			live_info.latency = Some(Utc::now() - sent);
+		}
+	}
+
+	/// Round trip time of the last ping the peer answered.
+	pub fn latency(&self) -> Option<chrono::Duration> {
+		self.live_info.read().latency
+	}
+
+	/// The peer just sent us a block extending our chain.
+	pub fn block_delivered(&self) {
+		self.live_info.write().last_block_received = Some(Utc::now());
+	}
+
+	/// The peer just sent us a transaction we accepted.
+	pub fn tx_delivered(&self) {
+		self.live_info.write().last_tx_received = Some(Utc::now());
+	}
 }
 
 /// Flatten out a PeerInfo and nested PeerLiveInfo (taking a read lock on it)
@@ -476,8 +853,12 @@ pub trait ChainAdapter: Sync + Send {
 	fn total_height(&self) -> Result<u64, chain::Error>;
 
 	/// A valid transaction has been received from one of our peers
-	fn transaction_received(&self, tx: core::Transaction, stem: bool)
-		-> Result<bool, chain::Error>;
+	fn transaction_received(
+		&self,
+		tx: core::Transaction,
+		stem: bool,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
@@ -524,6 +905,22 @@ pub trait ChainAdapter: Sync + Send {
 	/// immediately.
 	fn locate_headers(&self, locator: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error>;
 
+	/// Finds every MAX_BLOCK_HEADERS-th header following the provided one on
+	/// our header chain, at most count of them.
+	fn locate_skeleton(
+		&self,
+		hash: Hash,
+		count: u16,
+	) -> Result<Vec<core::BlockHeader>, chain::Error>;
+
+	/// A skeleton of the header chain has been received, in response to a
+	/// skeleton request.
+	fn skeleton_received(
+		&self,
+		headers: &[core::BlockHeader],
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	/// Gets a full block by its hash.
 	fn get_block(&self, h: Hash) -> Option<core::Block>;
 
@@ -536,6 +933,11 @@ pub trait ChainAdapter: Sync + Send {
 	/// at the provided block hash.
 	fn txhashset_read(&self, h: Hash) -> Option<TxHashSetRead>;
 
+	/// Provides the manifest of our txhashset archive at the provided block
+	/// hash. Hashing the whole archive is expensive, implementations should
+	/// only do it once per archive.
+	fn txhashset_manifest(&self, h: Hash) -> Option<TxHashSetManifest>;
+
 	/// Whether the node is ready to accept a new txhashset. If this isn't the
 	/// case, the archive is provided without being requested and likely an
 	/// attack attempt. This should be checked *before* downloading the whole
@@ -561,6 +963,76 @@ pub trait ChainAdapter: Sync + Send {
 		peer_peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
+	/// A peer sent us the manifest of its txhashset archive, listing the
+	/// segments we can request from it.
+	fn txhashset_manifest_received(&self, manifest: TxHashSetManifest, peer_info: &PeerInfo);
+
+	/// A peer sent us a segment of its txhashset archive. Returns false if
+	/// the segment does not match the manifest we are downloading against.
+	fn txhashset_segment_received(&self, segment: TxHashSetSegment, peer_info: &PeerInfo) -> bool;
+
+	/// Provides a segment of the output MMR at the provided block, for a
+	/// peer syncing through PIBD.
+	fn get_output_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>>;
+
+	/// Provides a segment of the rangeproof MMR at the provided block.
+	fn get_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>>;
+
+	/// Provides a segment of the kernel MMR at the provided block.
+	fn get_kernel_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<TxKernelEntry>>;
+
+	/// A peer sent us a segment of the output MMR. Returns false if the
+	/// segment is invalid.
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> bool;
+
+	/// A peer sent us a segment of the rangeproof MMR. Returns false if the
+	/// segment is invalid.
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> bool;
+
+	/// A peer sent us a segment of the kernel MMR. Returns false if the
+	/// segment is invalid.
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernelEntry>,
+		peer_info: &PeerInfo,
+	) -> bool;
+
+	/// Provides the output leaf set at the provided block as a bitset of the
+	/// leaf insertion indices, for a peer syncing through PIBD.
+	fn get_output_bitmap(&self, block_hash: Hash) -> Option<Vec<u8>>;
+
+	/// A peer sent us the output leaf set at the provided block. Returns
+	/// false if it doesn't fit the output MMR.
+	fn receive_output_bitmap(
+		&self,
+		block_hash: Hash,
+		bitmap: Vec<u8>,
+		peer_info: &PeerInfo,
+	) -> bool;
+
 	/// Get the Grin specific tmp dir
 	fn get_tmp_dir(&self) -> PathBuf;
 
@@ -574,10 +1046,11 @@ pub trait ChainAdapter: Sync + Send {
 pub trait NetAdapter: ChainAdapter {
 	/// Find good peers we know with the provided capability and return their
 	/// addresses.
-	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr>;
+	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddrInfo>;
 
-	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>);
+	/// A list of peers has been received from one of our peers, the source
+	/// being the address of the peer that sent it.
+	fn peer_addrs_received(&self, _: Vec<PeerAddrInfo>, source: PeerAddr);
 
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64);
@@ -0,0 +1,106 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr};
+
+use crate::p2p::{AddrManager, PeerAddr, PeerData, State};
+
+fn ip_addr(a: u8, b: u8, c: u8, d: u8) -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new(Ipv4Addr::new(a, b, c, d).into(), 3414))
+}
+
+// A single source can only ever fill a small part of the new table, however
+// many addresses it advertises.
+#[test]
+fn new_table_limits_single_source() {
+	let source = ip_addr(1, 2, 3, 4);
+	let mut addrs = AddrManager::new(vec![]);
+	for i in 0..50_000u32 {
+		let b = i.to_be_bytes();
+		addrs.update(PeerData::new(
+			ip_addr(10 + b[1], b[2], b[3], 1),
+			Some(source),
+		));
+	}
+	let (new_count, tried_count) = addrs.table_sizes();
+	assert!(new_count <= 64 * 64);
+	assert_eq!(tried_count, 0);
+
+	// addresses learned from many different sources spread a lot more
+	let mut addrs = AddrManager::new(vec![]);
+	for i in 0..50_000u32 {
+		let b = i.to_be_bytes();
+		let source = ip_addr(100 + b[3] % 100, b[2], 0, 1);
+		addrs.update(PeerData::new(
+			ip_addr(10 + b[1], b[2], b[3], 1),
+			Some(source),
+		));
+	}
+	assert!(addrs.table_sizes().0 > 64 * 64);
+}
+
+#[test]
+fn successful_peers_move_to_tried() {
+	let addr = ip_addr(5, 6, 7, 8);
+	let mut addrs = AddrManager::new(vec![PeerData::new(addr, None)]);
+	assert!(addrs.contains(addr));
+	assert!(!addrs.is_tried(addr));
+
+	let mut data = PeerData::new(addr, None);
+	data.successes = 1;
+	addrs.update(data);
+	assert!(addrs.is_tried(addr));
+	assert_eq!(addrs.table_sizes(), (0, 1));
+
+	addrs.remove(addr);
+	assert!(!addrs.contains(addr));
+	assert_eq!(addrs.table_sizes(), (0, 0));
+}
+
+#[test]
+fn select_diverse_groups() {
+	// 3 groups of 10 addresses each
+	let peers = (0..30u8)
+		.map(|i| PeerData::new(ip_addr(20 + i % 3, 1, i, 1), None))
+		.collect::<Vec<_>>();
+	let addrs = AddrManager::new(peers);
+	let healthy = |p: &PeerData| p.flags == State::Healthy;
+
+	let selected = addrs.select(10, &HashSet::new(), true, healthy);
+	assert_eq!(selected.len(), 3);
+	let groups = selected
+		.iter()
+		.map(|p| p.addr.net_group())
+		.collect::<HashSet<_>>();
+	assert_eq!(groups.len(), 3);
+
+	// excluded groups are never picked
+	let mut exclude = HashSet::new();
+	exclude.insert(ip_addr(20, 1, 0, 1).net_group());
+	let selected = addrs.select(10, &exclude, true, healthy);
+	assert_eq!(selected.len(), 2);
+	let selected = addrs.select(30, &exclude, false, healthy);
+	assert_eq!(selected.len(), 20);
+	assert!(selected
+		.iter()
+		.all(|p| !exclude.contains(&p.addr.net_group())));
+
+	// and the filter always applies
+	assert!(addrs
+		.select(30, &HashSet::new(), false, |p| p.flags == State::Banned)
+		.is_empty());
+}
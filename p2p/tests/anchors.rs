@@ -0,0 +1,47 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use grin_core as core;
+use grin_util as util;
+
+use crate::common::*;
+use crate::core::global::{self, ChainTypes};
+
+// Outbound peers are saved as anchors, kept until they get saved again.
+#[test]
+fn save_and_load_anchors() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+
+	let (listener, listener_addr) = new_server(".grin_anchors_listen", test_config());
+	start_listening(&listener);
+
+	let (server, _) = new_server(".grin_anchors_connect", test_config());
+	server.connect(listener_addr).unwrap();
+
+	// the listener only has an inbound peer, nothing to anchor to
+	listener.peers.save_anchors();
+	assert!(listener.peers.anchors().is_empty());
+
+	server.peers.save_anchors();
+	assert_eq!(server.peers.anchors(), vec![listener_addr]);
+	assert_eq!(server.peers.anchors(), vec![listener_addr]);
+
+	// saving them again overwrites them
+	server.peers.stop();
+	server.peers.save_anchors();
+	assert!(server.peers.anchors().is_empty());
+}
@@ -0,0 +1,55 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use grin_p2p as p2p;
+use grin_util as util;
+
+use std::{thread, time};
+
+use crate::common::*;
+
+// A block-relay-only connection is flagged on both ends and kept apart from
+// our regular outbound connections.
+#[test]
+fn block_relay_only_connection() {
+	util::init_test_logger();
+
+	let (listener, listener_addr) = new_server(".grin_block_relay_listen", test_config());
+	start_listening(&listener);
+
+	let (server, _) = new_server(".grin_block_relay_connect", test_config());
+	let peer = server.connect_block_relay_only(listener_addr).unwrap();
+	assert!(peer.info.is_block_relay_only());
+	assert!(peer.info.is_outbound());
+	assert!(server.peers.outgoing_connected_peers().is_empty());
+	assert_eq!(server.peers.block_relay_only_peers().len(), 1);
+
+	// asking for peers is a no-op on such a connection
+	peer.send_peer_request(p2p::Capabilities::PEER_LIST)
+		.unwrap();
+
+	thread::sleep(time::Duration::from_secs(1));
+	let inbound = listener.peers.connected_peers();
+	assert_eq!(inbound.len(), 1);
+	assert!(inbound[0].info.is_block_relay_only());
+	assert!(!inbound[0].info.is_outbound());
+
+	// the flag describes the connection, not the peer
+	let data = server.peers.get_peer(listener_addr).unwrap();
+	assert!(!data
+		.capabilities
+		.contains(p2p::Capabilities::BLOCK_RELAY_ONLY));
+}
@@ -0,0 +1,275 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_p2p as p2p;
+use grin_util as util;
+
+use std::fs;
+use std::sync::Arc;
+use std::{thread, time};
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+
+use crate::chain::types::NoopAdapter;
+use crate::chain::{Chain, Options};
+use crate::common::*;
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::core::{Block, BlockHeader};
+use crate::core::global::{self, ChainTypes};
+use crate::core::libtx;
+use crate::core::pow::{self, Difficulty};
+use crate::core::ser::{self, ProtocolVersion};
+use crate::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use crate::p2p::capture::{
+	replay, CaptureReader, CapturedMessage, MessageRecorder, MsgDirection, ReplayAdapter,
+	ReplayStats,
+};
+use crate::p2p::msg::{Headers, MsgHeader, Ping, Type};
+use crate::p2p::{Capabilities, PeerAddr};
+use crate::util::RwLock;
+
+fn clean_output_dir(dir_name: &str) {
+	let _ = fs::remove_dir_all(dir_name);
+}
+
+fn setup(dir_name: &str, genesis: Block) -> Arc<Chain> {
+	util::init_test_logger();
+	clean_output_dir(dir_name);
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	Arc::new(
+		Chain::init(
+			dir_name.to_string(),
+			Arc::new(NoopAdapter {}),
+			genesis,
+			pow::verify_size,
+			verifier_cache,
+			false,
+		)
+		.unwrap(),
+	)
+}
+
+fn prepare_block<K>(kc: &K, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block
+where
+	K: Keychain,
+{
+	let key_id = ExtKeychainPath::new(1, diff as u32, 0, 0, 0).to_identifier();
+	let reward =
+		libtx::reward::output(kc, &libtx::ProofBuilder::new(kc), &key_id, 0, false).unwrap();
+	let mut b = Block::new(prev, vec![], Difficulty::from_num(diff), reward).unwrap();
+	b.header.timestamp = prev.timestamp + Duration::seconds(60);
+	b.header.pow.total_difficulty = prev.total_difficulty() + Difficulty::from_num(diff);
+	b.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut b).unwrap();
+	b
+}
+
+fn captured<T: ser::Writeable>(
+	direction: MsgDirection,
+	msg_type: Type,
+	msg: &T,
+) -> CapturedMessage {
+	let body = ser::ser_vec(msg).unwrap();
+	CapturedMessage {
+		time: Utc::now(),
+		peer: PeerAddr::Ip("127.0.0.1:13414".parse().unwrap()),
+		version: ProtocolVersion::local(),
+		capabilities: Capabilities::FULL_NODE,
+		direction,
+		header: MsgHeader::new(msg_type, body.len() as u64),
+		body,
+	}
+}
+
+// Headers and blocks received from a peer are captured, read back and
+// replayed against a fresh chain, which ends up where the original one is.
+#[test]
+fn capture_and_replay() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let test_dir = ".grin_capture";
+	clean_output_dir(test_dir);
+	fs::create_dir_all(test_dir).unwrap();
+	let capture_file = format!("{}/capture.bin", test_dir);
+	{
+		let genesis = pow::mine_genesis_block().unwrap();
+		let source = setup(&format!("{}/source", test_dir), genesis.clone());
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		let mut blocks = vec![];
+		for n in 1..4 {
+			let prev = source.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &source, n + 1);
+			source.process_block(b.clone(), Options::SKIP_POW).unwrap();
+			blocks.push(b);
+		}
+
+		let mut messages = vec![captured(
+			MsgDirection::Received,
+			Type::Headers,
+			&Headers {
+				headers: blocks.iter().map(|b| b.header.clone()).collect(),
+			},
+		)];
+		messages.push(captured(
+			MsgDirection::Sent,
+			Type::Ping,
+			&Ping {
+				total_difficulty: Difficulty::min(),
+				height: 0,
+			},
+		));
+		for b in &blocks {
+			messages.push(captured(MsgDirection::Received, Type::Block, b));
+		}
+		// a truncated block fails to deserialize
+		let mut truncated = messages.last().unwrap().clone();
+		truncated.body.truncate(10);
+		truncated.header = MsgHeader::new(Type::Block, 10);
+		messages.push(truncated);
+
+		{
+			let recorder = MessageRecorder::new(&capture_file).unwrap();
+			for msg in &messages {
+				recorder.record(msg);
+			}
+		}
+		let read = CaptureReader::open(&capture_file)
+			.unwrap()
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+		assert_eq!(read.len(), messages.len());
+		for (msg, read) in messages.iter().zip(read.iter()) {
+			assert_eq!(read.time.timestamp_millis(), msg.time.timestamp_millis());
+			assert_eq!(read.peer, msg.peer);
+			assert_eq!(read.direction, msg.direction);
+			assert_eq!(read.header.msg_type, msg.header.msg_type);
+			assert_eq!(read.body, msg.body);
+		}
+
+		let target = setup(&format!("{}/target", test_dir), genesis);
+		let adapter = Arc::new(ReplayAdapter::new(target.clone(), Options::SKIP_POW));
+		let stats = replay(read, adapter.clone());
+		assert_eq!(
+			stats,
+			ReplayStats {
+				replayed: 4,
+				failed: 1,
+				skipped: 1,
+			}
+		);
+		assert_eq!(adapter.rejected(), 0);
+		assert_eq!(target.head().unwrap(), source.head().unwrap());
+		assert_eq!(
+			target.header_head().unwrap().last_block_h,
+			source.head().unwrap().last_block_h
+		);
+	}
+	clean_output_dir(test_dir);
+}
+
+// A p2p server capturing the messages of all its connections to its db root.
+fn capturing_server(dir_name: &str) -> (Arc<p2p::Server>, PeerAddr) {
+	let config = p2p::P2PConfig {
+		capture_file: Some(format!("{}/capture.bin", dir_name)),
+		..test_config()
+	};
+	new_server(dir_name, config)
+}
+
+fn read_capture(dir_name: &str) -> Vec<CapturedMessage> {
+	CaptureReader::open(format!("{}/capture.bin", dir_name))
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap()
+}
+
+// Messages exchanged over a live connection get captured on both ends, the
+// ones received with their body and the responses written back, and the
+// capture of the receiving end replays against a fresh chain.
+#[test]
+fn capture_live_connection() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let test_dir = ".grin_capture_live";
+	clean_output_dir(test_dir);
+	{
+		let genesis = pow::mine_genesis_block().unwrap();
+		let source = setup(&format!("{}/source", test_dir), genesis.clone());
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		let mut headers = vec![];
+		for n in 1..4 {
+			let prev = source.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &source, n + 1);
+			source.process_block(b.clone(), Options::SKIP_POW).unwrap();
+			headers.push(b.header);
+		}
+
+		let listen_dir = format!("{}/listen", test_dir);
+		let connect_dir = format!("{}/connect", test_dir);
+		fs::create_dir_all(&listen_dir).unwrap();
+		fs::create_dir_all(&connect_dir).unwrap();
+
+		let (listener, listener_addr) = capturing_server(&listen_dir);
+		start_listening(&listener);
+
+		let (server, _) = capturing_server(&connect_dir);
+		let peer = server.connect(listener_addr).unwrap();
+		for bh in &headers {
+			assert!(peer.send_header(bh).unwrap());
+		}
+		peer.send_ping(Difficulty::min(), 0).unwrap();
+		thread::sleep(time::Duration::from_secs(2));
+
+		let header_bodies = headers
+			.iter()
+			.map(|bh| ser::ser_vec(bh).unwrap())
+			.collect::<Vec<_>>();
+		let with = |msgs: &[CapturedMessage], direction: MsgDirection, msg_type: Type| {
+			msgs.iter()
+				.filter(|m| m.direction == direction && m.header.msg_type == msg_type)
+				.map(|m| m.body.clone())
+				.collect::<Vec<_>>()
+		};
+
+		// the connecting end captured what it wrote and the pong it got back
+		let sent = read_capture(&connect_dir);
+		assert_eq!(with(&sent, MsgDirection::Sent, Type::Header), header_bodies);
+		assert_eq!(with(&sent, MsgDirection::Sent, Type::Ping).len(), 1);
+		assert_eq!(with(&sent, MsgDirection::Received, Type::Pong).len(), 1);
+
+		// the listening end captured the bodies read and the pong response
+		let received = read_capture(&listen_dir);
+		assert_eq!(
+			with(&received, MsgDirection::Received, Type::Header),
+			header_bodies
+		);
+		assert_eq!(with(&received, MsgDirection::Received, Type::Ping).len(), 1);
+		assert_eq!(with(&received, MsgDirection::Sent, Type::Pong).len(), 1);
+
+		let target = setup(&format!("{}/target", test_dir), genesis);
+		let adapter = Arc::new(ReplayAdapter::new(target.clone(), Options::SKIP_POW));
+		let stats = replay(received, adapter.clone());
+		assert_eq!(stats.failed, 0);
+		assert_eq!(adapter.rejected(), 0);
+		assert_eq!(
+			target.header_head().unwrap().last_block_h,
+			source.head().unwrap().last_block_h
+		);
+	}
+	clean_output_dir(test_dir);
+}
@@ -0,0 +1,66 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Common test functions
+
+use self::core::core::hash::Hash;
+use self::p2p::types::PeerAddr;
+use self::util::StopState;
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util as util;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::{thread, time};
+
+pub fn open_port() -> u16 {
+	// use port 0 to allow the OS to assign an open port
+	// TcpListener's Drop impl will unbind the port as soon as
+	// listener goes out of scope
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+/// Default config, listening on an open port of localhost.
+pub fn test_config() -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	}
+}
+
+/// Server with a no-op adapter, along with the address it listens on.
+pub fn new_server(db_root: &str, config: p2p::P2PConfig) -> (Arc<p2p::Server>, PeerAddr) {
+	let addr = PeerAddr::Ip(SocketAddr::new(config.host, config.port));
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	);
+	(server, addr)
+}
+
+/// Starts accepting connections on the server, giving it a second to be up.
+pub fn start_listening(server: &Arc<p2p::Server>) {
+	let server = server.clone();
+	let _ = thread::spawn(move || server.listen());
+	thread::sleep(time::Duration::from_secs(1));
+}
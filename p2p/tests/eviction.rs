@@ -0,0 +1,83 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+
+use crate::p2p::{select_to_evict, EvictionCandidate, PeerAddr};
+
+fn candidate(a: u8, b: u8, c: u8, age_secs: i64) -> EvictionCandidate {
+	let addr = PeerAddr::Ip(SocketAddr::new(Ipv4Addr::new(a, b, c, 1).into(), 3414));
+	EvictionCandidate {
+		addr,
+		net_group: addr.net_group(),
+		net_group_key: a as u64 * 256 + b as u64,
+		connected_at: Utc::now() - Duration::seconds(age_secs),
+		latency: None,
+		last_block: None,
+		last_tx: None,
+	}
+}
+
+// Nobody gets evicted while all candidates are protected.
+#[test]
+fn all_protected() {
+	let candidates = (0..20)
+		.map(|i| candidate(10 + i, 0, 0, 100))
+		.collect::<Vec<_>>();
+	assert_eq!(select_to_evict(candidates), None);
+	assert_eq!(select_to_evict(vec![]), None);
+}
+
+// A network group flooding us only ever gets its own peers evicted, the most
+// recent one first, while useful peers from other groups stay protected.
+#[test]
+fn flooding_group_evicted() {
+	let mut candidates = vec![];
+	for i in 0..10 {
+		let mut c = candidate(20 + i, 0, 0, 1000);
+		c.latency = Some(Duration::milliseconds(50 + i as i64));
+		candidates.push(c);
+	}
+	let mut useful = candidate(90, 0, 0, 10);
+	useful.last_block = Some(Utc::now());
+	candidates.push(useful.clone());
+	for i in 0..30 {
+		candidates.push(candidate(1, 2, i, 500 - i as i64));
+	}
+
+	assert_eq!(
+		select_to_evict(candidates.clone()),
+		Some(candidate(1, 2, 29, 0).addr)
+	);
+
+	// keep on evicting, the honest peers never get picked
+	let attacker = candidate(1, 2, 0, 0).net_group;
+	while let Some(addr) = select_to_evict(candidates.clone()) {
+		assert_eq!(addr.net_group(), attacker);
+		candidates.retain(|c| c.addr != addr);
+	}
+	assert_eq!(
+		candidates
+			.iter()
+			.filter(|c| c.net_group != attacker)
+			.count(),
+		11
+	);
+	assert!(candidates.iter().any(|c| c.addr == useful.addr));
+}
@@ -0,0 +1,55 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util as util;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerLiveInfo;
+use crate::p2p::{Capabilities, Direction, PeerAddr, PeerInfo, MAX_PEER_ADDRS};
+use crate::util::RwLock;
+
+fn peer_info() -> PeerInfo {
+	PeerInfo {
+		capabilities: Capabilities::FULL_NODE,
+		user_agent: "test".to_string(),
+		version: p2p::msg::ProtocolVersion::local(),
+		addr: PeerAddr::Ip(SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 3414)),
+		direction: Direction::Outbound,
+		live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::min()))),
+	}
+}
+
+// Asking a peer for addresses over and over without it answering doesn't let
+// it send us more than a single full response.
+#[test]
+fn one_outstanding_addrs_request() {
+	let info = peer_info();
+	let max = MAX_PEER_ADDRS as usize;
+	for _ in 0..10 {
+		info.addrs_requested();
+	}
+	assert_eq!(info.take_addr_tokens(10 * max), max);
+
+	// anything else is unrequested
+	assert!(info.take_addr_tokens(max) < 2);
+
+	// the next answer only once asked again
+	info.addrs_requested();
+	assert_eq!(info.take_addr_tokens(max), max);
+}
@@ -69,18 +69,21 @@ fn peer_handshake() {
 	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
 	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
 
-	let my_addr = PeerAddr("127.0.0.1:5000".parse().unwrap());
+	let my_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
 	let peer = Peer::connect(
 		socket,
 		p2p::Capabilities::UNKNOWN,
 		Difficulty::min(),
 		my_addr,
+		PeerAddr::Ip(addr),
 		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
 		net_adapter,
+		None,
 	)
 	.unwrap();
 
 	assert!(peer.info.user_agent.ends_with(env!("CARGO_PKG_VERSION")));
+	assert_eq!(peer.info.version, p2p::msg::ProtocolVersion::local());
 
 	thread::sleep(time::Duration::from_secs(1));
 
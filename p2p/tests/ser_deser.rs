@@ -12,6 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use grin_core::core::hash::Hash;
+use grin_core::core::pmmr::{Segment, SegmentIdentifier};
+use grin_core::core::{BlockHeader, TxKernelEntry};
+use grin_core::pow::Difficulty;
+use grin_core::ser;
 use grin_p2p as p2p;
 
 use num::FromPrimitive;
@@ -60,9 +65,13 @@ fn test_capabilities() {
 		p2p::types::Capabilities::FULL_NODE
 	);
 	assert_eq!(
-		p2p::types::Capabilities::from_bits_truncate(0b11111111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b11101111 as u32),
 		p2p::types::Capabilities::FULL_NODE
 	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b11111111 as u32),
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::BLOCK_RELAY_ONLY
+	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32),
 		p2p::types::Capabilities::FULL_NODE
@@ -78,3 +87,321 @@ fn test_capabilities() {
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
 }
+
+#[test]
+fn test_peer_addr_ser_deser() {
+	let addrs = vec![
+		p2p::PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()),
+		p2p::PeerAddr::Ip("[2001:db8::1]:3414".parse().unwrap()),
+		p2p::PeerAddr::Onion(p2p::OnionAddr {
+			pubkey: [7; 32],
+			port: 3414,
+		}),
+	];
+	for addr in addrs {
+		let bytes = ser::ser_vec(&addr).unwrap();
+		let addr2: p2p::PeerAddr = ser::deserialize(&mut &bytes[..]).unwrap();
+		assert_eq!(addr, addr2);
+		assert_eq!(addr.port(), addr2.port());
+
+		let addr3: p2p::PeerAddr = addr.to_string().parse().unwrap();
+		assert_eq!(addr, addr3);
+	}
+
+	// unknown address type
+	assert!(ser::deserialize::<p2p::PeerAddr>(&mut &[3u8, 0, 0][..]).is_err());
+}
+
+#[test]
+fn test_onion_addr_parse() {
+	let onion = p2p::OnionAddr {
+		pubkey: [42; 32],
+		port: 13414,
+	};
+	let host = onion.host();
+	assert_eq!(host.len(), 56 + ".onion".len());
+	assert_eq!(p2p::OnionAddr::from_host(&host, 13414), Some(onion));
+	assert_eq!(
+		p2p::OnionAddr::from_host(&host.to_uppercase(), 13414),
+		Some(onion)
+	);
+
+	// a corrupted character fails the checksum
+	let mut corrupted = host.clone().into_bytes();
+	corrupted[0] = if corrupted[0] == b'a' { b'b' } else { b'a' };
+	let corrupted = String::from_utf8(corrupted).unwrap();
+	assert_eq!(p2p::OnionAddr::from_host(&corrupted, 13414), None);
+
+	assert!(format!("{}:3414", &host[1..])
+		.parse::<p2p::PeerAddr>()
+		.is_err());
+	assert!("not an address".parse::<p2p::PeerAddr>().is_err());
+}
+
+fn protocol_versions() -> Vec<ser::ProtocolVersion> {
+	vec![ser::ProtocolVersion(1), ser::ProtocolVersion::local()]
+}
+
+fn round_trip<T: ser::Writeable + ser::Readable>(thing: &T, version: ser::ProtocolVersion) -> T {
+	let bytes = ser::ser_vec_with_version(thing, version).unwrap();
+	ser::deserialize_with_version(&mut &bytes[..], version).unwrap()
+}
+
+#[test]
+fn test_hand_shake_ser_deser() {
+	for version in protocol_versions() {
+		let hand = p2p::msg::Hand {
+			version,
+			capabilities: p2p::Capabilities::FULL_NODE,
+			nonce: 42,
+			genesis: Hash::default(),
+			total_difficulty: Difficulty::min(),
+			sender_addr: p2p::PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()),
+			receiver_addr: p2p::PeerAddr::Ip("10.0.0.2:3414".parse().unwrap()),
+			user_agent: "test".to_string(),
+		};
+		let hand2 = round_trip(&hand, version);
+		assert_eq!(hand2.version, version);
+		assert_eq!(hand2.capabilities, hand.capabilities);
+		assert_eq!(hand2.nonce, hand.nonce);
+		assert_eq!(hand2.genesis, hand.genesis);
+		assert_eq!(hand2.total_difficulty, hand.total_difficulty);
+		assert_eq!(hand2.sender_addr, hand.sender_addr);
+		assert_eq!(hand2.receiver_addr, hand.receiver_addr);
+		assert_eq!(hand2.user_agent, hand.user_agent);
+
+		let shake = p2p::msg::Shake {
+			version,
+			capabilities: p2p::Capabilities::FULL_NODE,
+			genesis: Hash::default(),
+			total_difficulty: Difficulty::min(),
+			user_agent: "test".to_string(),
+		};
+		let shake2 = round_trip(&shake, version);
+		assert_eq!(shake2.version, version);
+		assert_eq!(shake2.capabilities, shake.capabilities);
+		assert_eq!(shake2.genesis, shake.genesis);
+		assert_eq!(shake2.total_difficulty, shake.total_difficulty);
+		assert_eq!(shake2.user_agent, shake.user_agent);
+	}
+}
+
+#[test]
+fn test_ping_pong_ser_deser() {
+	for version in protocol_versions() {
+		let ping = p2p::msg::Ping {
+			total_difficulty: Difficulty::min(),
+			height: 12,
+		};
+		let ping2 = round_trip(&ping, version);
+		assert_eq!(ping2.total_difficulty, ping.total_difficulty);
+		assert_eq!(ping2.height, ping.height);
+
+		let pong = p2p::msg::Pong {
+			total_difficulty: Difficulty::min(),
+			height: 13,
+		};
+		let pong2 = round_trip(&pong, version);
+		assert_eq!(pong2.total_difficulty, pong.total_difficulty);
+		assert_eq!(pong2.height, pong.height);
+	}
+}
+
+#[test]
+fn test_peer_addrs_ser_deser() {
+	let ip = p2p::PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let onion = p2p::PeerAddr::Onion(p2p::OnionAddr {
+		pubkey: [9; 32],
+		port: 3414,
+	});
+	let info = |addr| p2p::PeerAddrInfo {
+		addr,
+		capabilities: p2p::Capabilities::FULL_NODE,
+		last_seen: 1_500_000_000,
+	};
+	let peer_addrs = p2p::msg::PeerAddrs {
+		peers: vec![info(ip), info(onion)],
+	};
+
+	// onion addresses are left out for version 1 peers
+	let v1 = round_trip(&peer_addrs, ser::ProtocolVersion(1));
+	assert_eq!(v1.peers, vec![p2p::PeerAddrInfo::new(ip)]);
+	assert!(ser::ser_vec_with_version(&onion, ser::ProtocolVersion(1)).is_err());
+
+	// capabilities and last seen times only make it from version 6
+	let v5 = round_trip(&peer_addrs, ser::ProtocolVersion(5));
+	assert_eq!(
+		v5.peers,
+		vec![p2p::PeerAddrInfo::new(ip), p2p::PeerAddrInfo::new(onion)]
+	);
+	let v6 = round_trip(&peer_addrs, ser::ProtocolVersion(6));
+	assert_eq!(v6.peers, peer_addrs.peers);
+
+	// an onion address is not valid data for a version 1 reader
+	let bytes = ser::ser_vec_with_version(&onion, ser::ProtocolVersion(2)).unwrap();
+	assert!(ser::deserialize_with_version::<p2p::PeerAddr>(
+		&mut &bytes[..],
+		ser::ProtocolVersion(1)
+	)
+	.is_err());
+}
+
+#[test]
+fn test_peer_data_ser_deser() {
+	let addr = p2p::PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let source = p2p::PeerAddr::Ip("10.1.0.1:3414".parse().unwrap());
+	let mut data = p2p::PeerData::new(addr, Some(source));
+	data.attempts = 2;
+	data.successes = 5;
+	data.last_attempt = 1_500_000_000;
+	data.last_seen = 1_600_000_000;
+
+	let bytes = ser::ser_vec(&data).unwrap();
+	let data2: p2p::PeerData = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(data2.addr, addr);
+	assert_eq!(data2.attempts, 2);
+	assert_eq!(data2.successes, 5);
+	assert_eq!(data2.last_attempt, 1_500_000_000);
+	assert_eq!(data2.source, Some(source));
+	assert_eq!(data2.last_seen, 1_600_000_000);
+
+	// peers saved before last seen times were tracked
+	let data4: p2p::PeerData = ser::deserialize(&mut &bytes[..bytes.len() - 8]).unwrap();
+	assert_eq!(data4.source, Some(source));
+	assert_eq!(data4.last_seen, data.last_connected);
+
+	// peers saved before connection stats were tracked
+	let old_len = bytes.len() - 8 - (4 + 4 + 8 + 1 + 1 + 4 + 2);
+	let data3: p2p::PeerData = ser::deserialize(&mut &bytes[..old_len]).unwrap();
+	assert_eq!(data3.addr, addr);
+	assert_eq!(data3.last_connected, data.last_connected);
+	assert_eq!(data3.attempts, 0);
+	assert_eq!(data3.successes, 0);
+	assert_eq!(data3.source, None);
+}
+
+#[test]
+fn test_txhashset_manifest_ser_deser() {
+	let segment_size = p2p::types::TXHASHSET_SEGMENT_SIZE as usize;
+	let archive = (0..segment_size * 2 + 10)
+		.map(|i| (i % 251) as u8)
+		.collect::<Vec<_>>();
+	let hash = Hash::from_vec(&[1; 32]);
+	let manifest = p2p::msg::TxHashSetManifest::from_reader(hash, 100, &mut &archive[..]).unwrap();
+	assert_eq!(manifest.total_size, archive.len() as u64);
+	assert_eq!(manifest.segment_count(), 3);
+	assert_eq!(manifest.segment_len(2), 10);
+
+	for version in protocol_versions() {
+		assert_eq!(round_trip(&manifest, version), manifest);
+	}
+
+	// segments only verify at their own index and with their exact content
+	let first = &archive[..segment_size];
+	let last = &archive[segment_size * 2..];
+	assert!(manifest.verify_segment(0, first));
+	assert!(manifest.verify_segment(2, last));
+	assert!(!manifest.verify_segment(1, first));
+	assert!(!manifest.verify_segment(3, last));
+	assert!(!manifest.verify_segment(0, &first[1..]));
+
+	// inconsistent segment counts are rejected
+	let mut bad = manifest.clone();
+	bad.segment_hashes.pop();
+	let bytes = ser::ser_vec(&bad).unwrap();
+	assert!(ser::deserialize::<p2p::msg::TxHashSetManifest>(&mut &bytes[..]).is_err());
+}
+
+#[test]
+fn test_txhashset_segment_ser_deser() {
+	let segment = p2p::msg::TxHashSetSegment {
+		hash: Hash::from_vec(&[2; 32]),
+		index: 7,
+		data: (0..p2p::types::TXHASHSET_SEGMENT_SIZE)
+			.map(|i| (i % 7) as u8)
+			.collect(),
+	};
+	let bytes = ser::ser_vec(&segment).unwrap();
+	let segment2: p2p::msg::TxHashSetSegment = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(segment2.hash, segment.hash);
+	assert_eq!(segment2.index, 7);
+	assert_eq!(segment2.data, segment.data);
+}
+
+#[test]
+fn test_segment_request_ser_deser() {
+	let request = p2p::msg::SegmentRequest {
+		block_hash: Hash::from_vec(&[3; 32]),
+		identifier: SegmentIdentifier { height: 11, idx: 5 },
+	};
+	let bytes = ser::ser_vec(&request).unwrap();
+	let request2: p2p::msg::SegmentRequest = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(request2.block_hash, request.block_hash);
+	assert_eq!(request2.identifier, request.identifier);
+
+	// segments can't be higher than 2^16 leaves
+	let mut bytes = bytes;
+	bytes[32] = 17;
+	assert!(ser::deserialize::<p2p::msg::SegmentRequest>(&mut &bytes[..]).is_err());
+
+	let response = p2p::msg::SegmentResponse {
+		block_hash: request.block_hash,
+		segment: Segment::<TxKernelEntry> {
+			identifier: request.identifier,
+			hashes: vec![],
+			leaves: vec![],
+			spent: vec![],
+			proof: vec![Hash::from_vec(&[4; 32]), Hash::from_vec(&[5; 32])],
+		},
+	};
+	let bytes = ser::ser_vec(&response).unwrap();
+	let response2: p2p::msg::SegmentResponse<TxKernelEntry> =
+		ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(response2.block_hash, response.block_hash);
+	assert_eq!(response2.segment.identifier, request.identifier);
+	assert_eq!(response2.segment.proof, response.segment.proof);
+}
+
+#[test]
+fn test_header_skeleton_ser_deser() {
+	let request = p2p::msg::HeaderSkeletonRequest {
+		hash: Hash::from_vec(&[6; 32]),
+		count: p2p::MAX_SKELETON_HEADERS as u16,
+	};
+	let bytes = ser::ser_vec(&request).unwrap();
+	let request2: p2p::msg::HeaderSkeletonRequest = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(request2.hash, request.hash);
+	assert_eq!(request2.count, request.count);
+
+	// skeletons are bounded
+	let request = p2p::msg::HeaderSkeletonRequest {
+		hash: request.hash,
+		count: p2p::MAX_SKELETON_HEADERS as u16 + 1,
+	};
+	let bytes = ser::ser_vec(&request).unwrap();
+	assert!(ser::deserialize::<p2p::msg::HeaderSkeletonRequest>(&mut &bytes[..]).is_err());
+
+	let skeleton = p2p::msg::HeaderSkeleton {
+		headers: vec![BlockHeader::default(), BlockHeader::default()],
+	};
+	let bytes = ser::ser_vec(&skeleton).unwrap();
+	let skeleton2: p2p::msg::HeaderSkeleton = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(skeleton2.headers, skeleton.headers);
+}
+
+#[test]
+fn test_output_bitmap_ser_deser() {
+	let response = p2p::msg::OutputBitmapResponse {
+		block_hash: Hash::from_vec(&[7; 32]),
+		bitmap: vec![0b1011_0001; 300_000],
+	};
+	let bytes = ser::ser_vec(&response).unwrap();
+	let response2: p2p::msg::OutputBitmapResponse = ser::deserialize(&mut &bytes[..]).unwrap();
+	assert_eq!(response2.block_hash, response.block_hash);
+	assert_eq!(response2.bitmap, response.bitmap);
+
+	// too large
+	let mut bytes = bytes;
+	bytes[32..40].copy_from_slice(&(p2p::MAX_OUTPUT_BITMAP_SIZE + 1).to_be_bytes());
+	assert!(ser::deserialize::<p2p::msg::OutputBitmapResponse>(&mut &bytes[..]).is_err());
+}
@@ -0,0 +1,130 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use grin_p2p as p2p;
+use grin_util as util;
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::{thread, time};
+
+use crate::common::*;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::OnionAddr;
+
+// Minimal SOCKS5 stand-in, accepts a single "no auth" connection and relays
+// it to the provided target whatever the requested destination is.
+fn socks5_stand_in(target: SocketAddr) -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy_addr = listener.local_addr().unwrap();
+	thread::spawn(move || {
+		let (mut client, _) = listener.accept().unwrap();
+
+		let mut greeting = [0u8; 3];
+		client.read_exact(&mut greeting).unwrap();
+		assert_eq!(greeting, [5, 1, 0]);
+		client.write_all(&[5, 0]).unwrap();
+
+		let mut req = [0u8; 4];
+		client.read_exact(&mut req).unwrap();
+		assert_eq!(req[..3], [5, 1, 0]);
+		// onion addresses must be sent as hostnames
+		assert_eq!(req[3], 3);
+		let mut len = [0u8; 1];
+		client.read_exact(&mut len).unwrap();
+		let mut host = vec![0u8; len[0] as usize + 2];
+		client.read_exact(&mut host).unwrap();
+		assert!(String::from_utf8_lossy(&host[..len[0] as usize]).ends_with(".onion"));
+
+		let server = TcpStream::connect(target).unwrap();
+		client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+
+		let mut client_r = client.try_clone().unwrap();
+		let mut server_w = server.try_clone().unwrap();
+		thread::spawn(move || io::copy(&mut client_r, &mut server_w));
+		let mut server_r = server;
+		let _ = io::copy(&mut server_r, &mut client);
+	});
+	proxy_addr
+}
+
+// Connects to an onion peer through a local SOCKS5 stand-in relaying to a
+// regular server and checks the peer is tracked under its onion address.
+#[test]
+fn connect_onion_peer_via_socks5() {
+	util::init_test_logger();
+
+	let listen_config = test_config();
+	let listen_addr = SocketAddr::new(listen_config.host, listen_config.port);
+	let (listener, _) = new_server(".grin_socks_listen", listen_config);
+	start_listening(&listener);
+
+	let proxy = socks5_stand_in(listen_addr);
+	let config = p2p::P2PConfig {
+		socks5_proxy: Some(proxy),
+		..test_config()
+	};
+	let (server, _) = new_server(".grin_socks_connect", config);
+
+	let onion = PeerAddr::Onion(OnionAddr {
+		pubkey: [3; 32],
+		port: 3414,
+	});
+	let peer = server.connect(onion).unwrap();
+	assert_eq!(peer.info.addr, onion);
+	assert!(server.peers.get_connected_peer(onion).is_some());
+
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(listener.peers.peer_count(), 1);
+}
+
+// Onion peers cannot be reached without a proxy.
+#[test]
+fn connect_onion_peer_without_proxy() {
+	util::init_test_logger();
+
+	let (server, _) = new_server(".grin_socks_none", test_config());
+	let onion = PeerAddr::Onion(OnionAddr {
+		pubkey: [4; 32],
+		port: 3414,
+	});
+	assert!(server.connect(onion).is_err());
+}
+
+// Only onion peers get connected to when the proxy is required, whether a
+// proxy is configured or not.
+#[test]
+fn connect_ip_peer_with_proxy_required() {
+	util::init_test_logger();
+
+	let (listener, listener_addr) = new_server(".grin_socks_require_listen", test_config());
+	start_listening(&listener);
+
+	for (i, proxy) in vec![None, Some("127.0.0.1:9050".parse().unwrap())]
+		.into_iter()
+		.enumerate()
+	{
+		let config = p2p::P2PConfig {
+			socks5_proxy: proxy,
+			proxy_mode: Some(p2p::ProxyMode::Require),
+			..test_config()
+		};
+		let (server, _) = new_server(&format!(".grin_socks_require_{}", i), config);
+		assert!(server.connect(listener_addr).is_err());
+	}
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(listener.peers.peer_count(), 0);
+}
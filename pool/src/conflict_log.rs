@@ -0,0 +1,77 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Log of the txs received spending the same outputs as txs in our pool, for
+//! those accepting unconfirmed txs to learn about double spend attempts.
+
+use std::collections::VecDeque;
+
+use self::core::core::hash::Hash;
+use self::util::RwLock;
+use crate::types::PoolConflict;
+use grin_core as core;
+use grin_util as util;
+
+/// Number of conflicts we keep, the oldest ones being dropped first.
+const MAX_CONFLICTS: usize = 1_000;
+
+/// Bounded log of the most recent conflicts detected by our pool.
+#[derive(Default)]
+pub struct ConflictLog {
+	conflicts: RwLock<VecDeque<PoolConflict>>,
+}
+
+impl ConflictLog {
+	/// Records a conflict, forgetting the oldest one when full. A conflict
+	/// between the same tx and the same pool txs as an already logged one
+	/// updates it instead. Returns whether the conflict is a new one.
+	pub fn record(&self, conflict: PoolConflict) -> bool {
+		let mut conflicts = self.conflicts.write();
+		let key = conflict_key(&conflict);
+		if let Some(existing) = conflicts
+			.iter_mut()
+			.find(|x| x.tx == conflict.tx && conflict_key(x) == key)
+		{
+			*existing = conflict;
+			return false;
+		}
+		conflicts.push_back(conflict);
+		if conflicts.len() > MAX_CONFLICTS {
+			let _ = conflicts.pop_front();
+		}
+		true
+	}
+
+	/// The conflicts logged, oldest first.
+	pub fn all(&self) -> Vec<PoolConflict> {
+		self.conflicts.read().iter().cloned().collect()
+	}
+
+	/// Number of conflicts currently logged.
+	pub fn len(&self) -> usize {
+		self.conflicts.read().len()
+	}
+
+	/// Whether no conflict got logged.
+	pub fn is_empty(&self) -> bool {
+		self.conflicts.read().is_empty()
+	}
+}
+
+// The pool txs a conflict is with, regardless of the order they got found in.
+fn conflict_key(conflict: &PoolConflict) -> Vec<Hash> {
+	let mut hashes = conflict.conflicting_txs.clone();
+	hashes.sort();
+	hashes
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fee estimation based on how long the txs of our pool waited to get
+//! confirmed depending on the fee_to_weight they paid.
+//!
+//! For every block, the pool txs it confirms are recorded along with the
+//! number of blocks they waited for. An estimate for a target number of
+//! blocks is then the lowest fee_to_weight for which most of the txs paying
+//! at least as much got confirmed within that target, txs still waiting in
+//! the pool for longer than the target counting as misses.
+
+use std::collections::VecDeque;
+
+use chrono::prelude::{DateTime, Utc};
+
+use self::core::consensus;
+use crate::types::PoolEntry;
+use grin_core as core;
+
+/// Number of recent blocks estimates are based on (a day worth)
+const TRACKED_BLOCKS: usize = consensus::DAY_HEIGHT as usize;
+
+/// Highest confirmation target estimates can be requested for
+pub const MAX_FEE_ESTIMATE_TARGET: u64 = consensus::HOUR_HEIGHT * 2;
+
+/// Share of txs (in percent) paying at least the estimated fee_to_weight
+/// that need to have been confirmed within the target.
+const SUCCESS_PERCENT: usize = 85;
+
+/// Minimum number of confirmed txs to get an estimate from
+const MIN_SAMPLES: usize = 10;
+
+struct FeeSample {
+	fee_to_weight: u64,
+	waited: u64,
+}
+
+/// Tracks the fee_to_weight of the pool txs confirmed by recent blocks and
+/// how long they waited for it.
+#[derive(Default)]
+pub struct FeeEstimator {
+	blocks: VecDeque<Vec<FeeSample>>,
+}
+
+impl FeeEstimator {
+	/// Records the entries of our pool confirmed by a block produced at the
+	/// provided time.
+	pub fn block_confirmed(&mut self, at: DateTime<Utc>, confirmed: &[PoolEntry]) {
+		let samples = confirmed
+			.iter()
+			.map(|x| FeeSample {
+				fee_to_weight: x.tx.fee_to_weight(),
+				waited: waited_blocks(x.tx_at, at),
+			})
+			.collect();
+		self.blocks.push_back(samples);
+		while self.blocks.len() > TRACKED_BLOCKS {
+			self.blocks.pop_front();
+		}
+	}
+
+	/// Lowest fee_to_weight that got most txs confirmed within the target
+	/// number of blocks, given the entries still waiting in the pool. None
+	/// if we haven't seen enough txs confirmed yet.
+	pub fn estimate(&self, target_blocks: u64, pending: &[PoolEntry]) -> Option<u64> {
+		let target_blocks = target_blocks.max(1).min(MAX_FEE_ESTIMATE_TARGET);
+		let now = Utc::now();
+
+		// (fee_to_weight, confirmed within target)
+		let mut samples = self
+			.blocks
+			.iter()
+			.flatten()
+			.map(|x| (x.fee_to_weight, x.waited <= target_blocks))
+			.collect::<Vec<_>>();
+		if samples.len() < MIN_SAMPLES {
+			return None;
+		}
+		samples.extend(
+			pending
+				.iter()
+				.filter(|x| waited_blocks(x.tx_at, now) > target_blocks)
+				.map(|x| (x.tx.fee_to_weight(), false)),
+		);
+		samples.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+		let mut estimate = None;
+		let mut successes = 0;
+		for (i, (fee_to_weight, success)) in samples.iter().enumerate() {
+			if *success {
+				successes += 1;
+			}
+			let last_of_fee = samples.get(i + 1).map_or(true, |x| x.0 != *fee_to_weight);
+			if last_of_fee && successes * 100 >= (i + 1) * SUCCESS_PERCENT {
+				estimate = Some(*fee_to_weight);
+			}
+		}
+		// Not even the best paying txs made it, best we can do is to pay
+		// as much as them.
+		estimate.or_else(|| samples.first().map(|x| x.0))
+	}
+}
+
+// Number of blocks between the time a tx got in our pool and the provided
+// time, at least 1 as a tx can't be confirmed before the next block.
+fn waited_blocks(tx_at: DateTime<Utc>, at: DateTime<Utc>) -> u64 {
+	let secs = (at - tx_at).num_seconds().max(0) as u64;
+	((secs + consensus::BLOCK_TIME_SEC - 1) / consensus::BLOCK_TIME_SEC).max(1)
+}
@@ -30,12 +30,21 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+mod conflict_log;
+mod fee_estimator;
+mod policy;
 mod pool;
 pub mod transaction_pool;
+mod tx_tracker;
 pub mod types;
 
+pub use crate::conflict_log::ConflictLog;
+pub use crate::fee_estimator::{FeeEstimator, MAX_FEE_ESTIMATE_TARGET};
+pub use crate::policy::LimitsPolicy;
 pub use crate::pool::Pool;
 pub use crate::transaction_pool::TransactionPool;
+pub use crate::tx_tracker::{TrackedTx, TxTracker};
 pub use crate::types::{
-	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
+	BlockChain, DandelionConfig, PolicyConfig, PoolAdapter, PoolConfig, PoolConflict, PoolEntry,
+	PoolError, PoolPolicy, TxSource, TxStatus,
 };
@@ -0,0 +1,111 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Default pool admission policy, enforcing the limits of the pool config.
+
+use std::collections::HashMap;
+
+use chrono::prelude::{DateTime, Utc};
+
+use self::core::core::transaction::Transaction;
+use self::util::Mutex;
+use crate::types::{PolicyConfig, PoolPolicy, TxSource};
+use grin_core as core;
+use grin_util as util;
+
+/// Number of sources we keep track of the submission rate of before
+/// forgetting the idle ones.
+const MAX_TRACKED_SOURCES: usize = 1_000;
+
+// Submissions a source still has room for, refilled over time.
+struct Allowance {
+	tokens: f64,
+	updated: DateTime<Utc>,
+}
+
+/// Pool policy enforcing the limits of a `PolicyConfig`: size of txs, minimum
+/// fee by source and submission rate by source identifier.
+pub struct LimitsPolicy {
+	config: PolicyConfig,
+	allowances: Mutex<HashMap<String, Allowance>>,
+}
+
+impl LimitsPolicy {
+	pub fn new(config: PolicyConfig) -> LimitsPolicy {
+		LimitsPolicy {
+			config,
+			allowances: Mutex::new(HashMap::new()),
+		}
+	}
+
+	// Takes one submission from the allowance of the source, false if it
+	// went over its rate.
+	fn take_allowance(&self, identifier: &str, per_minute: u32) -> bool {
+		let max = per_minute as f64;
+		let now = Utc::now();
+		let mut allowances = self.allowances.lock();
+
+		if allowances.len() >= MAX_TRACKED_SOURCES && !allowances.contains_key(identifier) {
+			allowances.retain(|_, x| {
+				let elapsed = (now - x.updated).num_milliseconds() as f64 / 60_000.0;
+				x.tokens + elapsed * max < max
+			});
+		}
+
+		let allowance = allowances
+			.entry(identifier.to_owned())
+			.or_insert(Allowance {
+				tokens: max,
+				updated: now,
+			});
+		let elapsed = (now - allowance.updated).num_milliseconds().max(0) as f64 / 60_000.0;
+		allowance.tokens = (allowance.tokens + elapsed * max).min(max);
+		allowance.updated = now;
+		if allowance.tokens < 1.0 {
+			return false;
+		}
+		allowance.tokens -= 1.0;
+		true
+	}
+}
+
+impl PoolPolicy for LimitsPolicy {
+	fn check(&self, tx: &Transaction, src: &TxSource, _stem: bool) -> Result<(), String> {
+		if let Some(max) = self.config.max_inputs {
+			if tx.inputs().len() > max {
+				return Err(format!("more than {} inputs", max));
+			}
+		}
+		if let Some(max) = self.config.max_outputs {
+			if tx.outputs().len() > max {
+				return Err(format!("more than {} outputs", max));
+			}
+		}
+		if let Some(fee_base) = self.config.min_fee_base.get(&src.debug_name) {
+			let threshold = (tx.tx_weight() as u64) * fee_base;
+			if tx.fee() < threshold {
+				return Err(format!("fee below {} for {}", threshold, src.debug_name));
+			}
+		}
+		if let Some(per_minute) = self.config.max_txs_per_minute {
+			if !self.take_allowance(&src.identifier, per_minute) {
+				return Err(format!(
+					"more than {} txs per minute from {}",
+					per_minute, src.identifier
+				));
+			}
+		}
+		Ok(())
+	}
+}
@@ -22,12 +22,13 @@ use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
 	Block, BlockHeader, BlockSums, Committed, Transaction, TxKernel, Weighting,
 };
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::types::{BlockChain, PoolEntry, PoolError};
 use grin_core as core;
 use grin_util as util;
 use std::cmp::Reverse;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::sync::Arc;
 
 pub struct Pool {
@@ -233,6 +234,20 @@ impl Pool {
 		Ok(new_sums)
 	}
 
+	/// Validates a package of txs, possibly spending each other's outputs,
+	/// against the current chain state on top of all the txs in the pool.
+	pub fn validate_package(
+		&self,
+		txs: &[Transaction],
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let mut all_txs = self.all_transactions();
+		all_txs.extend_from_slice(txs);
+		let agg_tx = transaction::aggregate(all_txs)?;
+		self.validate_raw_tx(&agg_tx, header, Weighting::NoLimit)?;
+		Ok(())
+	}
+
 	pub fn validate_raw_txs(
 		&self,
 		txs: &[Transaction],
@@ -305,79 +320,99 @@ impl Pool {
 
 	/// Buckets consist of a vec of txs and track the aggregate fee_to_weight.
 	/// We aggregate (cut-through) dependent transactions within a bucket *unless* adding a tx
-	/// would reduce the aggregate fee_to_weight, in which case we start a new bucket.
-	/// Note this new bucket will by definition have a lower fee_to_weight than the bucket
-	/// containing the tx it depends on.
-	/// Sorting the buckets by fee_to_weight will therefore preserve dependency ordering,
-	/// maximizing both cut-through and overall fees.
+	/// would reduce the aggregate fee_to_weight, in which case we start a new bucket
+	/// depending on the bucket(s) containing the txs it spends from.
+	/// A tx spending from several buckets merges them all into a single package
+	/// (along with any bucket sitting on a dependency path between them) and the
+	/// package fee_to_weight, covering the tx and all its ancestors, is what gets
+	/// compared.
+	/// Buckets are then ordered by fee_to_weight, a bucket never coming before the
+	/// buckets it depends on, maximizing both cut-through and overall fees.
 	pub fn bucket_transactions(&self, weighting: Weighting) -> Vec<Transaction> {
-		let mut tx_buckets: Vec<Bucket> = Vec::new();
+		self.buckets(weighting)
+			.into_iter()
+			.map(|x| x.raw_txs)
+			.flatten()
+			.collect()
+	}
+
+	// Buckets in block inclusion order, see `bucket_transactions`.
+	fn buckets(&self, weighting: Weighting) -> Vec<Bucket> {
+		// Buckets merged into another one are left empty, `merged_into`
+		// pointing at where their txs went (and at themselves otherwise).
+		let mut tx_buckets: Vec<Option<Bucket>> = Vec::new();
+		let mut merged_into: Vec<usize> = Vec::new();
 		let mut output_commits = HashMap::new();
 		let mut rejected = HashSet::new();
 
-		for entry in &self.entries {
-			// check the commits index to find parents and their position
-			// if single parent then we are good, we can bucket it with its parent
-			// if multiple parents then we need to combine buckets, but for now simply reject it (rare case)
-			let mut insert_pos = None;
+		for (pos, entry) in self.entries.iter().enumerate() {
+			// check the commits index to find the buckets of our parents
+			let mut parents = HashSet::new();
 			let mut is_rejected = false;
 
 			for input in entry.tx.inputs() {
 				if rejected.contains(&input.commitment()) {
 					// Depends on a rejected tx, so reject this one.
 					is_rejected = true;
-					continue;
-				} else if let Some(pos) = output_commits.get(&input.commitment()) {
-					if insert_pos.is_some() {
-						// Multiple dependencies so reject this tx (pick it up in next block).
-						is_rejected = true;
-						continue;
-					} else {
-						// Track the pos of the bucket we fall into.
-						insert_pos = Some(*pos);
-					}
-				}
-			}
-
-			// If this tx is rejected then store all output commitments in our rejected set.
-			if is_rejected {
-				for out in entry.tx.outputs() {
-					rejected.insert(out.commitment());
+				} else if let Some(idx) = output_commits.get(&input.commitment()) {
+					parents.insert(find_bucket(&merged_into, *idx));
 				}
-
-				// Done with this entry (rejected), continue to next entry.
-				continue;
 			}
 
-			match insert_pos {
-				None => {
-					// No parent tx, just add to the end in its own bucket.
-					// This is the common case for non 0-conf txs in the txpool.
-					// We assume the tx is valid here as we validated it on the way into the txpool.
-					insert_pos = Some(tx_buckets.len());
-					tx_buckets.push(Bucket::new(entry.tx.clone()));
-				}
-				Some(pos) => {
-					// We found a single parent tx, so aggregate in the bucket
-					// if the aggregate tx is a valid tx.
-					// Otherwise discard and let the next block pick this tx up.
-					let bucket = &tx_buckets[pos];
-
-					if let Ok(new_bucket) = bucket.aggregate_with_tx(
-						entry.tx.clone(),
-						weighting,
-						self.verifier_cache.clone(),
-					) {
-						if new_bucket.fee_to_weight >= bucket.fee_to_weight {
-							// Only aggregate if it would not reduce the fee_to_weight ratio.
-							tx_buckets[pos] = new_bucket;
-						} else {
-							// Otherwise put it in its own bucket at the end.
-							// Note: This bucket will have a lower fee_to_weight
-							// than the bucket it depends on.
-							tx_buckets.push(Bucket::new(entry.tx.clone()));
+			let mut insert_idx = None;
+			if !is_rejected && parents.is_empty() {
+				// No parent tx, just add to the end in its own bucket.
+				// This is the common case for non 0-conf txs in the txpool.
+				// We assume the tx is valid here as we validated it on the way into the txpool.
+				insert_idx = Some(tx_buckets.len());
+				merged_into.push(tx_buckets.len());
+				tx_buckets.push(Some(Bucket::new(entry.tx.clone(), pos)));
+			} else if !is_rejected {
+				// Aggregate with the parent buckets if the aggregate tx is a valid tx.
+				// Otherwise discard and let the next block pick this tx up.
+				let members = merge_set(&tx_buckets, &merged_into, &parents);
+				let package = Bucket::merge(
+					members.iter().filter_map(|x| tx_buckets[*x].as_ref()),
+					entry.tx.clone(),
+					pos,
+					weighting,
+					self.verifier_cache.clone(),
+				);
+				let max_fee_to_weight = parents
+					.iter()
+					.filter_map(|x| tx_buckets[*x].as_ref())
+					.map(|x| x.fee_to_weight)
+					.max()
+					.unwrap_or(0);
+
+				match package {
+					Ok(mut package) if package.fee_to_weight >= max_fee_to_weight => {
+						// Only aggregate if it would not reduce the fee_to_weight
+						// ratio of any parent bucket.
+						let target = *members.iter().min().expect("parent bucket");
+						package.deps = package
+							.deps
+							.iter()
+							.map(|x| find_bucket(&merged_into, *x))
+							.filter(|x| !members.contains(x))
+							.collect();
+						for idx in &members {
+							tx_buckets[*idx] = None;
+							merged_into[*idx] = target;
 						}
-					} else {
+						tx_buckets[target] = Some(package);
+						insert_idx = Some(target);
+					}
+					Ok(_) => {
+						// Otherwise put it in its own bucket at the end,
+						// only to be included after all its parent buckets.
+						let mut bucket = Bucket::new(entry.tx.clone(), pos);
+						bucket.deps = parents;
+						insert_idx = Some(tx_buckets.len());
+						merged_into.push(tx_buckets.len());
+						tx_buckets.push(Some(bucket));
+					}
+					Err(_) => {
 						// Aggregation failed so discard this new tx.
 						is_rejected = true;
 					}
@@ -385,28 +420,158 @@ impl Pool {
 			}
 
 			if is_rejected {
+				// If this tx is rejected then store all output commitments in our rejected set.
 				for out in entry.tx.outputs() {
 					rejected.insert(out.commitment());
 				}
-			} else if let Some(insert_pos) = insert_pos {
+			} else if let Some(insert_idx) = insert_idx {
 				// We successfully added this tx to our set of buckets.
 				// Update commits index for subsequent txs.
 				for out in entry.tx.outputs() {
-					output_commits.insert(out.commitment(), insert_pos);
+					output_commits.insert(out.commitment(), insert_idx);
 				}
 			}
 		}
 
-		// Sort them by fee_to_weight (descending).
+		// Order them by fee_to_weight (descending), holding back each bucket
+		// until all the buckets it depends on made it in.
 		// Txs with no dependencies will be toward the start of the vec.
 		// Txs with a big chain of dependencies will be toward the end of the vec.
-		tx_buckets.sort_unstable_by_key(|x| Reverse(x.fee_to_weight));
+		let mut pending = HashMap::new();
+		let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+		let mut ready = BinaryHeap::new();
+		for (idx, bucket) in tx_buckets.iter().enumerate() {
+			if let Some(bucket) = bucket {
+				let deps = bucket
+					.deps
+					.iter()
+					.map(|x| find_bucket(&merged_into, *x))
+					.filter(|x| *x != idx)
+					.collect::<HashSet<_>>();
+				if deps.is_empty() {
+					ready.push((bucket.fee_to_weight, Reverse(idx)));
+				}
+				for dep in &deps {
+					children.entry(*dep).or_insert_with(Vec::new).push(idx);
+				}
+				pending.insert(idx, deps.len());
+			}
+		}
 
-		tx_buckets
-			.into_iter()
-			.map(|x| x.raw_txs)
-			.flatten()
-			.collect()
+		let mut sorted = Vec::with_capacity(pending.len());
+		while let Some((_, Reverse(idx))) = ready.pop() {
+			for child in children.remove(&idx).unwrap_or_default() {
+				if let Some(count) = pending.get_mut(&child) {
+					*count -= 1;
+					if *count == 0 {
+						if let Some(bucket) = &tx_buckets[child] {
+							ready.push((bucket.fee_to_weight, Reverse(child)));
+						}
+					}
+				}
+			}
+			if let Some(bucket) = tx_buckets[idx].take() {
+				sorted.push(bucket);
+			}
+		}
+		if sorted.len() != pending.len() {
+			// a dependency cycle, which merging buckets should never leave
+			error!(
+				"buckets: {} of {} buckets left out, depending on each other",
+				pending.len() - sorted.len(),
+				pending.len()
+			);
+		}
+		debug_assert_eq!(sorted.len(), pending.len(), "bucket dependency cycle");
+		sorted
+	}
+
+	/// Lowest fee_to_weight of all the buckets, what a tx needs to beat to
+	/// get a place in a full pool.
+	pub fn lowest_fee_to_weight(&self) -> Option<u64> {
+		self.buckets(Weighting::NoLimit)
+			.last()
+			.map(|x| x.fee_to_weight)
+	}
+
+	/// Evicts the txs least likely to make it into a block. Those that can't
+	/// be bucketed at all go first, then the ones in the lowest fee_to_weight
+	/// bucket. As buckets always come after the buckets they depend on, the
+	/// only txs depending on evicted ones are unbucketed, they get evicted
+	/// along anyway. Returns the evicted entries.
+	pub fn evict_lowest_fee_bucket(&mut self) -> Vec<PoolEntry> {
+		let buckets = self.buckets(Weighting::NoLimit);
+		let bucketed = buckets
+			.iter()
+			.flat_map(|x| x.raw_txs.iter().map(|tx| tx.hash()))
+			.collect::<HashSet<_>>();
+		let lowest = buckets
+			.last()
+			.map(|x| x.raw_txs.iter().map(|tx| tx.hash()).collect::<HashSet<_>>())
+			.unwrap_or_default();
+		if self.entries.iter().all(|x| bucketed.contains(&x.tx.hash())) {
+			self.remove_with_descendants(|x| lowest.contains(&x.tx.hash()))
+		} else {
+			self.remove_with_descendants(|x| !bucketed.contains(&x.tx.hash()))
+		}
+	}
+
+	/// Entries spending any of the inputs the provided tx spends, along with
+	/// all the entries depending on them. Entries the tx includes all the
+	/// kernels of (the tx itself or an aggregate of it) aren't conflicts.
+	pub fn find_conflicts(&self, tx: &Transaction) -> Vec<PoolEntry> {
+		let inputs = tx
+			.inputs()
+			.iter()
+			.map(|x| x.commitment())
+			.collect::<HashSet<_>>();
+		let kernels = tx
+			.kernels()
+			.iter()
+			.map(|x| x.excess())
+			.collect::<HashSet<_>>();
+		self.with_descendants(|x| {
+			x.tx.inputs()
+				.iter()
+				.any(|y| inputs.contains(&y.commitment()))
+				&& !x.tx.kernels().iter().all(|y| kernels.contains(&y.excess()))
+		})
+	}
+
+	/// Removes the entries matching the filter from the pool, along with all
+	/// the entries depending on them. Returns the removed entries.
+	pub fn remove_with_descendants<F>(&mut self, filter: F) -> Vec<PoolEntry>
+	where
+		F: Fn(&PoolEntry) -> bool,
+	{
+		let removed = self.with_descendants(filter);
+		let hashes = removed.iter().map(|x| x.tx.hash()).collect::<HashSet<_>>();
+		self.entries.retain(|x| !hashes.contains(&x.tx.hash()));
+		removed
+	}
+
+	// Entries matching the filter and all the entries spending their outputs,
+	// directly or not. Relies on entries being in insertion order, a tx only
+	// ever makes it into the pool after the txs it depends on.
+	fn with_descendants<F>(&self, filter: F) -> Vec<PoolEntry>
+	where
+		F: Fn(&PoolEntry) -> bool,
+	{
+		let mut commits = HashSet::new();
+		let mut found = vec![];
+		for entry in &self.entries {
+			if filter(entry)
+				|| entry
+					.tx
+					.inputs()
+					.iter()
+					.any(|x| commits.contains(&x.commitment()))
+			{
+				commits.extend(entry.tx.outputs().iter().map(|x| x.commitment()));
+				found.push(entry.clone());
+			}
+		}
+		found
 	}
 
 	pub fn find_matching_transactions(&self, kernels: &[TxKernel]) -> Vec<Transaction> {
@@ -440,6 +605,14 @@ impl Pool {
 		});
 	}
 
+	/// Excesses of the kernels of all the txs in the pool.
+	pub fn kernel_excesses(&self) -> HashSet<Commitment> {
+		self.entries
+			.iter()
+			.flat_map(|x| x.tx.kernels().iter().map(|k| k.excess()))
+			.collect()
+	}
+
 	/// Size of the pool.
 	pub fn size(&self) -> usize {
 		self.entries.len()
@@ -454,29 +627,122 @@ impl Pool {
 struct Bucket {
 	raw_txs: Vec<Transaction>,
 	fee_to_weight: u64,
+	// position in the pool entries of each of the raw txs
+	positions: Vec<usize>,
+	// buckets containing txs some of ours spend from
+	deps: HashSet<usize>,
 }
 
 impl Bucket {
-	fn new(tx: Transaction) -> Bucket {
+	fn new(tx: Transaction, pos: usize) -> Bucket {
 		Bucket {
 			fee_to_weight: tx.fee_to_weight(),
 			raw_txs: vec![tx.clone()],
+			positions: vec![pos],
+			deps: HashSet::new(),
 		}
 	}
 
-	fn aggregate_with_tx(
-		&self,
+	// Aggregates the txs of all the provided buckets with the new tx, keeping
+	// them in pool order so they are always preceded by their parents.
+	fn merge<'a, I>(
+		buckets: I,
 		new_tx: Transaction,
+		pos: usize,
 		weighting: Weighting,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
-	) -> Result<Bucket, PoolError> {
-		let mut raw_txs = self.raw_txs.clone();
-		raw_txs.push(new_tx);
+	) -> Result<Bucket, PoolError>
+	where
+		I: Iterator<Item = &'a Bucket>,
+	{
+		let mut txs = vec![(pos, new_tx)];
+		let mut deps = HashSet::new();
+		for bucket in buckets {
+			txs.extend(
+				bucket
+					.positions
+					.iter()
+					.cloned()
+					.zip(bucket.raw_txs.iter().cloned()),
+			);
+			deps.extend(bucket.deps.iter().cloned());
+		}
+		txs.sort_by_key(|(pos, _)| *pos);
+		let (positions, raw_txs): (Vec<_>, Vec<_>) = txs.into_iter().unzip();
+
 		let agg_tx = transaction::aggregate(raw_txs.clone())?;
 		agg_tx.validate(weighting, verifier_cache)?;
 		Ok(Bucket {
 			fee_to_weight: agg_tx.fee_to_weight(),
-			raw_txs: raw_txs,
+			raw_txs,
+			positions,
+			deps,
 		})
 	}
 }
+
+// Bucket the txs of the provided one ended up in after merges.
+fn find_bucket(merged_into: &[usize], mut idx: usize) -> usize {
+	while merged_into[idx] != idx {
+		idx = merged_into[idx];
+	}
+	idx
+}
+
+// The parent buckets along with every bucket on a dependency path between two
+// of them. Those all need merging together so no bucket ends up depending on
+// a bucket that also depends on it.
+fn merge_set(
+	buckets: &[Option<Bucket>],
+	merged_into: &[usize],
+	parents: &HashSet<usize>,
+) -> HashSet<usize> {
+	let mut members = parents.clone();
+	if parents.len() < 2 {
+		return members;
+	}
+	let deps_of = |idx: usize| -> Vec<usize> {
+		buckets[idx]
+			.as_ref()
+			.map(|x| {
+				x.deps
+					.iter()
+					.map(|y| find_bucket(merged_into, *y))
+					.collect()
+			})
+			.unwrap_or_default()
+	};
+
+	let mut ancestors = HashSet::new();
+	let mut to_visit = parents.iter().cloned().collect::<Vec<_>>();
+	while let Some(idx) = to_visit.pop() {
+		for dep in deps_of(idx) {
+			if ancestors.insert(dep) {
+				to_visit.push(dep);
+			}
+		}
+	}
+
+	let mut descendants = HashSet::new();
+	loop {
+		let mut found = false;
+		for (idx, bucket) in buckets.iter().enumerate() {
+			if bucket.is_none() || parents.contains(&idx) || descendants.contains(&idx) {
+				continue;
+			}
+			if deps_of(idx)
+				.iter()
+				.any(|x| parents.contains(x) || descendants.contains(x))
+			{
+				descendants.insert(idx);
+				found = true;
+			}
+		}
+		if !found {
+			break;
+		}
+	}
+
+	members.extend(ancestors.intersection(&descendants).cloned());
+	members
+}
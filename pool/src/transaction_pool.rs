@@ -21,15 +21,35 @@ use self::core::core::hash::{Hash, Hashed};
 use self::core::core::id::ShortId;
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{transaction, Block, BlockHeader, Transaction, Weighting};
+use self::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
+use crate::conflict_log::ConflictLog;
+use crate::fee_estimator::FeeEstimator;
+use crate::policy::LimitsPolicy;
 use crate::pool::Pool;
-use crate::types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource};
+use crate::tx_tracker::{TxTracker, REORG_CHECK_DEPTH};
+use crate::types::{
+	BlockChain, PoolAdapter, PoolConfig, PoolConflict, PoolEntry, PoolError, PoolPolicy, TxSource,
+	TxStatus,
+};
 use chrono::prelude::*;
 use grin_core as core;
 use grin_util as util;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
 use std::sync::Arc;
 
+/// Version of the format pool entries get saved to disk with, pinned so the
+/// file doesn't change meaning along with the protocol version.
+const POOL_FILE_VERSION: ProtocolVersion = ProtocolVersion(1);
+
+/// Max size of a single saved entry, anything larger means the file is
+/// corrupted past that point.
+const MAX_SAVED_ENTRY_SIZE: u64 = 1 << 24;
+
 /// Transaction pool implementation.
 pub struct TransactionPool {
 	/// Pool Config
@@ -45,6 +65,14 @@ pub struct TransactionPool {
 	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	/// The pool adapter
 	pub adapter: Arc<dyn PoolAdapter>,
+	/// Fee estimates from the txs confirmed by recent blocks.
+	pub fee_estimator: FeeEstimator,
+	/// Admission policy of the txs submitted to us
+	pub policy: Arc<dyn PoolPolicy>,
+	/// Lifecycle of the txs that went through our pool
+	pub tx_tracker: TxTracker,
+	/// Recent txs received spending the same outputs as txs in our pool
+	pub conflicts: ConflictLog,
 }
 
 impl TransactionPool {
@@ -55,6 +83,7 @@ impl TransactionPool {
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		adapter: Arc<dyn PoolAdapter>,
 	) -> TransactionPool {
+		let policy = Arc::new(LimitsPolicy::new(config.policy.clone()));
 		TransactionPool {
 			config,
 			txpool: Pool::new(chain.clone(), verifier_cache.clone(), "txpool".to_string()),
@@ -67,6 +96,10 @@ impl TransactionPool {
 			blockchain: chain,
 			verifier_cache,
 			adapter,
+			fee_estimator: FeeEstimator::default(),
+			policy,
+			tx_tracker: TxTracker::default(),
+			conflicts: ConflictLog::default(),
 		}
 	}
 
@@ -93,10 +126,16 @@ impl TransactionPool {
 		debug!("added tx to reorg_cache: size now {}", cache.len());
 	}
 
+	// Adds the entry to the txpool, replacing the txs it conflicts with if it
+	// pays more. Stempool txs it conflicts with get dropped when reconciling
+	// the stempool. The txpool conflicts found get added to the provided ones,
+	// for the caller to report them. Stempool ones are never reported, not to
+	// leak txs still under embargo.
 	fn add_to_txpool(
 		&mut self,
 		mut entry: PoolEntry,
 		header: &BlockHeader,
+		conflicts: &mut Vec<PoolConflict>,
 	) -> Result<(), PoolError> {
 		// First deaggregate the tx based on current txpool txs.
 		if entry.tx.kernels().len() > 1 {
@@ -111,7 +150,43 @@ impl TransactionPool {
 				entry.src.debug_name = "deagg".to_string();
 			}
 		}
-		self.txpool.add_to_pool(entry.clone(), vec![], header)?;
+
+		// Txs spending the same inputs get replaced if this one pays a higher
+		// fee to weight than all of them (and all their descendants), at least
+		// their total fee, plus the minimum fee of its own weight so every
+		// replacement relayed costs something.
+		let replaced = self.txpool.find_conflicts(&entry.tx);
+		if !replaced.is_empty() {
+			let fee_to_weight = entry.tx.fee_to_weight();
+			let replaced_fee = replaced.iter().map(|x| x.tx.fee()).sum::<u64>();
+			let min_increment = (entry.tx.tx_weight() as u64) * self.config.accept_fee_base;
+			if replaced
+				.iter()
+				.any(|x| x.tx.fee_to_weight() >= fee_to_weight)
+				|| entry.tx.fee() < replaced_fee.saturating_add(min_increment)
+			{
+				conflicts.push(conflict(&entry, &replaced, false));
+				return Err(PoolError::LowFeeReplacement);
+			}
+			let backup = self.txpool.entries.clone();
+			let hashes = replaced.iter().map(|x| x.tx.hash()).collect::<HashSet<_>>();
+			self.txpool
+				.entries
+				.retain(|x| !hashes.contains(&x.tx.hash()));
+			if let Err(e) = self.txpool.add_to_pool(entry.clone(), vec![], header) {
+				self.txpool.entries = backup;
+				conflicts.push(conflict(&entry, &replaced, false));
+				return Err(e);
+			}
+			debug!(
+				"add_to_txpool: {} replaced {} txs",
+				entry.tx.hash(),
+				replaced.len()
+			);
+			conflicts.push(conflict(&entry, &replaced, true));
+		} else {
+			self.txpool.add_to_pool(entry.clone(), vec![], header)?;
+		}
 
 		// We now need to reconcile the stempool based on the new state of the txpool.
 		// Some stempool txs may no longer be valid and we need to evict them.
@@ -122,6 +197,15 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	// Logs and notifies the conflicts detected while adding txs.
+	fn report_conflicts(&self, conflicts: Vec<PoolConflict>) {
+		for conflict in conflicts {
+			if self.conflicts.record(conflict.clone()) {
+				self.adapter.tx_conflict_detected(&conflict);
+			}
+		}
+	}
+
 	/// Add the given tx to the pool, directing it to either the stempool or
 	/// txpool based on stem flag provided.
 	pub fn add_to_pool(
@@ -137,11 +221,174 @@ impl TransactionPool {
 			return Err(PoolError::DuplicateTx);
 		}
 
-		// Do we have the capacity to accept this transaction?
+		self.policy
+			.check(&tx, &src, stem)
+			.map_err(PoolError::PolicyRejected)?;
+
+		self.add_to_pool_at(src, tx, Utc::now(), stem, header)
+	}
+
+	/// Add an ordered package of txs, later ones possibly spending the outputs
+	/// of earlier ones, accepting either all of them or none. The package is
+	/// validated as a whole against the current chain state and txpool,
+	/// optionally aggregated into a single tx, and none of its txs gets relayed
+	/// or notified before all of them made it into the pool.
+	pub fn add_package_to_pool(
+		&mut self,
+		src: TxSource,
+		txs: Vec<Transaction>,
+		aggregate: bool,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		if txs.is_empty() {
+			return Err(PoolError::Other("empty tx package".to_string()));
+		}
+
+		for tx in &txs {
+			if !stem && self.txpool.contains_tx(tx.hash()) {
+				return Err(PoolError::DuplicateTx);
+			}
+			self.policy
+				.check(tx, &src, stem)
+				.map_err(PoolError::PolicyRejected)?;
+			self.is_acceptable(tx, stem)?;
+
+			tx.validate(Weighting::AsTransaction, self.verifier_cache.clone())
+				.map_err(PoolError::InvalidTx)?;
+			self.blockchain.verify_tx_lock_height(tx)?;
+			self.blockchain.verify_coinbase_maturity(tx)?;
+		}
+		self.txpool.validate_package(&txs, header)?;
+
+		let txs = if aggregate {
+			vec![transaction::aggregate(txs)?]
+		} else {
+			txs
+		};
+		if self.total_size() + txs.len() > self.config.max_pool_size
+			|| (stem && self.stempool.size() + txs.len() > self.config.max_stempool_size)
+		{
+			return Err(PoolError::OverCapacity);
+		}
+
+		// Everything got checked already, the txs still get staged in the pools
+		// first and only relayed and notified once all of them made it in.
+		let tx_at = Utc::now();
+		let entries = txs
+			.into_iter()
+			.map(|tx| PoolEntry {
+				src: src.clone(),
+				tx_at,
+				tx,
+			})
+			.collect::<Vec<_>>();
+		let mut conflicts = vec![];
+		let mut stem = stem
+			&& self
+				.stage_entries(&entries, true, header, &mut conflicts)
+				.is_ok();
+		if !stem {
+			if let Err(e) = self.stage_entries(&entries, false, header, &mut conflicts) {
+				for conflict in &mut conflicts {
+					conflict.replaced = false;
+				}
+				self.report_conflicts(conflicts);
+				return Err(e);
+			}
+		}
+
+		// Any problem relaying along the stem, fluff the whole package. If
+		// even that fails they stay in the stempool until their embargo expires.
+		if stem
+			&& !entries
+				.iter()
+				.all(|x| self.adapter.stem_tx_accepted(&x.src, &x.tx).is_ok())
+		{
+			let stempool = self.stempool.entries.clone();
+			let hashes = entries.iter().map(|x| x.tx.hash()).collect::<HashSet<_>>();
+			self.stempool
+				.entries
+				.retain(|x| !hashes.contains(&x.tx.hash()));
+			if self
+				.stage_entries(&entries, false, header, &mut conflicts)
+				.is_ok()
+			{
+				stem = false;
+			} else {
+				self.stempool.entries = stempool;
+			}
+		}
+		if !stem {
+			for entry in &entries {
+				self.add_to_reorg_cache(entry.clone());
+				self.adapter.tx_accepted(&entry.tx);
+			}
+		}
+		self.report_conflicts(conflicts);
+		self.track_pools(TxStatus::Conflicted);
+		Ok(())
+	}
+
+	// Adds all the entries, in order, to either the stempool or the txpool
+	// without relaying or notifying any of them. Both pools are left as they
+	// were if any of the entries can't be added.
+	fn stage_entries(
+		&mut self,
+		entries: &[PoolEntry],
+		stem: bool,
+		header: &BlockHeader,
+		conflicts: &mut Vec<PoolConflict>,
+	) -> Result<(), PoolError> {
+		let txpool = self.txpool.entries.clone();
+		let stempool = self.stempool.entries.clone();
+		for entry in entries {
+			let res = if stem {
+				self.add_to_stempool(entry.clone(), header)
+			} else {
+				self.add_to_txpool(entry.clone(), header, conflicts)
+			};
+			if let Err(e) = res {
+				self.txpool.entries = txpool;
+				self.stempool.entries = stempool;
+				return Err(e);
+			}
+		}
+		Ok(())
+	}
+
+	/// Adds a tx out of the stempool to the txpool, the admission policy
+	/// having been checked already when it first got submitted to us.
+	pub fn fluff_to_pool(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		if self.txpool.contains_tx(tx.hash()) {
+			return Err(PoolError::DuplicateTx);
+		}
+		self.add_to_pool_at(src, tx, Utc::now(), false, header)
+	}
+
+	// Adds a tx to the pool as if it was first received at the provided time.
+	fn add_to_pool_at(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		tx_at: DateTime<Utc>,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		// Do we have the capacity to accept this transaction? When full, only
+		// make room for txs paying more than the least paying ones in the pool.
 		let acceptability = self.is_acceptable(&tx, stem);
 		let mut evict = false;
 		if !stem && acceptability.as_ref().err() == Some(&PoolError::OverCapacity) {
-			evict = true;
+			match self.txpool.lowest_fee_to_weight() {
+				Some(lowest) if tx.fee_to_weight() > lowest => evict = true,
+				_ => return acceptability,
+			}
 		} else if acceptability.is_err() {
 			return acceptability;
 		}
@@ -157,11 +404,7 @@ impl TransactionPool {
 		// Check coinbase maturity before we go any further.
 		self.blockchain.verify_coinbase_maturity(&tx)?;
 
-		let entry = PoolEntry {
-			src,
-			tx_at: Utc::now(),
-			tx,
-		};
+		let entry = PoolEntry { src, tx_at, tx };
 
 		// If not stem then we are fluff.
 		// If this is a stem tx then attempt to stem.
@@ -169,13 +412,17 @@ impl TransactionPool {
 		if !stem
 			|| self
 				.add_to_stempool(entry.clone(), header)
-				.and_then(|_| self.adapter.stem_tx_accepted(&entry.tx))
+				.and_then(|_| self.adapter.stem_tx_accepted(&entry.src, &entry.tx))
 				.is_err()
 		{
-			self.add_to_txpool(entry.clone(), header)?;
+			let mut conflicts = vec![];
+			let res = self.add_to_txpool(entry.clone(), header, &mut conflicts);
+			self.report_conflicts(conflicts);
+			res?;
 			self.add_to_reorg_cache(entry.clone());
 			self.adapter.tx_accepted(&entry.tx);
 		}
+		self.track_pools(TxStatus::Conflicted);
 
 		// Transaction passed all the checks but we have to make space for it
 		if evict {
@@ -185,26 +432,133 @@ impl TransactionPool {
 		Ok(())
 	}
 
-	// Remove the last transaction from the flattened bucket transactions.
-	// No other tx depends on it, it has low fee_to_weight and is unlikely to participate in any cut-through.
-	pub fn evict_from_txpool(&mut self) {
-		// Get bucket transactions
-		let bucket_transactions = self.txpool.bucket_transactions(Weighting::NoLimit);
-
-		// Get last transaction and remove it
-		match bucket_transactions.last() {
-			Some(evictable_transaction) => {
-				// Remove transaction
-				self.txpool.entries = self
-					.txpool
+	/// Saves the entries of both the txpool and the stempool to the file at
+	/// the provided path, to be restored with `load_entries` after a restart.
+	/// Each entry is saved as a length prefixed record, after the version of
+	/// the file format. The file gets replaced at once so a crash never leaves
+	/// half of it.
+	pub fn save_entries<P: AsRef<Path>>(&self, path: P) -> Result<usize, PoolError> {
+		let entries = self
+			.txpool
+			.entries
+			.iter()
+			.map(|x| SavedEntry::new(x, false))
+			.chain(
+				self.stempool
 					.entries
 					.iter()
-					.filter(|x| x.tx != *evictable_transaction)
-					.map(|x| x.clone())
-					.collect::<Vec<_>>();
+					.map(|x| SavedEntry::new(x, true)),
+			)
+			.collect::<Vec<_>>();
+
+		let path = path.as_ref();
+		let tmp_path = path.with_extension("tmp");
+		let res = File::create(&tmp_path)
+			.and_then(|file| {
+				let mut file = BufWriter::new(file);
+				file.write_all(&POOL_FILE_VERSION.0.to_be_bytes())?;
+				for entry in &entries {
+					let data = ser::ser_vec_with_version(entry, POOL_FILE_VERSION)
+						.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+					file.write_all(&(data.len() as u64).to_be_bytes())?;
+					file.write_all(&data)?;
+				}
+				file.flush()
+			})
+			.and_then(|_| fs::rename(&tmp_path, path));
+		if res.is_err() {
+			let _ = fs::remove_file(&tmp_path);
+		}
+		res.map(|_| entries.len())
+			.map_err(|e| PoolError::Other(format!("failed to save pool: {}", e)))
+	}
+
+	/// Adds back the entries saved with `save_entries`, keeping the source and
+	/// time they were first received. Each of them gets validated again
+	/// against the current chain state and those that no longer apply (mined
+	/// or double spent in the meantime, ...) are dropped. Returns the number
+	/// of entries restored. The admission policy they went through already
+	/// is not checked again. An entry that can't be decoded is skipped, and
+	/// a corrupted record length stops the load, keeping what was restored.
+	pub fn load_entries<P: AsRef<Path>>(
+		&mut self,
+		path: P,
+		header: &BlockHeader,
+	) -> Result<usize, PoolError> {
+		let mut file = File::open(path)
+			.map(BufReader::new)
+			.map_err(|e| PoolError::Other(format!("failed to load pool: {}", e)))?;
+
+		let mut version = [0u8; 4];
+		file.read_exact(&mut version)
+			.map_err(|e| PoolError::Other(format!("failed to load pool: {}", e)))?;
+		let version = u32::from_be_bytes(version);
+		if version != POOL_FILE_VERSION.0 {
+			return Err(PoolError::Other(format!(
+				"failed to load pool: unsupported version {}",
+				version
+			)));
+		}
+
+		let mut restored = 0;
+		loop {
+			let mut len = [0u8; 8];
+			match file.read_exact(&mut len) {
+				Ok(_) => {}
+				Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+				Err(e) => {
+					warn!("load_entries: failed to read, restored {}: {}", restored, e);
+					break;
+				}
 			}
-			None => (),
+			let len = u64::from_be_bytes(len);
+			if len > MAX_SAVED_ENTRY_SIZE {
+				warn!(
+					"load_entries: invalid entry size {}, restored {}",
+					len, restored
+				);
+				break;
+			}
+			let mut data = vec![0u8; len as usize];
+			if let Err(e) = file.read_exact(&mut data) {
+				warn!(
+					"load_entries: truncated entry, restored {}: {}",
+					restored, e
+				);
+				break;
+			}
+			let saved: SavedEntry =
+				match ser::deserialize_with_version(&mut &data[..], POOL_FILE_VERSION) {
+					Ok(saved) => saved,
+					Err(e) => {
+						warn!("load_entries: skipping undecodable entry: {}", e);
+						continue;
+					}
+				};
+			let entry = saved.entry;
+			match self.add_to_pool_at(entry.src, entry.tx, entry.tx_at, saved.stem, header) {
+				Ok(_) => restored += 1,
+				Err(e) => debug!("load_entries: dropping saved tx: {:?}", e),
+			}
+		}
+		Ok(restored)
+	}
+
+	/// Evicts the lowest fee_to_weight buckets until the txpool is back to its
+	/// max size, along with the txs depending on them.
+	pub fn evict_from_txpool(&mut self) {
+		while self.total_size() > self.config.max_pool_size {
+			let evicted = self.txpool.evict_lowest_fee_bucket();
+			if evicted.is_empty() {
+				break;
+			}
+			debug!(
+				"evict_from_txpool: evicted {} txs, pool: {}",
+				evicted.len(),
+				self.total_size()
+			);
 		}
+		self.track_pools(TxStatus::Evicted);
 	}
 
 	// Old txs will "age out" after 30 mins.
@@ -225,9 +579,12 @@ impl TransactionPool {
 			entries.len(),
 			header.hash(),
 		);
+		// Our own cached txs conflicting with the pool aren't double spend
+		// attempts, no need to report them.
 		for entry in entries {
-			let _ = &self.add_to_txpool(entry.clone(), header);
+			let _ = &self.add_to_txpool(entry.clone(), header, &mut vec![]);
 		}
+		self.track_pools(TxStatus::Conflicted);
 		debug!(
 			"reconcile_reorg_cache: block: {:?} ... done.",
 			header.hash()
@@ -238,6 +595,45 @@ impl TransactionPool {
 	/// Reconcile the transaction pool (both txpool and stempool) against the
 	/// provided block.
 	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
+		// Record how long the txs confirmed by this block waited in our pool.
+		{
+			let kernels = block
+				.kernels()
+				.iter()
+				.map(|x| x.hash())
+				.collect::<HashSet<_>>();
+			let confirmed = self
+				.txpool
+				.entries
+				.iter()
+				.filter(|x| x.tx.kernels().iter().all(|k| kernels.contains(&k.hash())))
+				.cloned()
+				.collect::<Vec<_>>();
+			self.fee_estimator
+				.block_confirmed(block.header.timestamp, &confirmed);
+		}
+
+		// Update the status of the txs it confirms, or that are no longer
+		// confirmed on our chain after a reorg.
+		{
+			let kernels = block
+				.kernels()
+				.iter()
+				.map(|x| x.excess())
+				.collect::<HashSet<_>>();
+			let ancestors = match self.tx_tracker.lowest_confirmed_height() {
+				Some(lowest) => self.ancestor_hashes(&block.header, lowest),
+				None => HashMap::new(),
+			};
+			let changes = self.tx_tracker.block_confirmed(
+				block.header.height,
+				block.hash(),
+				&kernels,
+				&ancestors,
+			);
+			self.notify_tx_statuses(changes);
+		}
+
 		// First reconcile the txpool.
 		self.txpool.reconcile_block(block);
 		self.txpool.reconcile(None, &block.header)?;
@@ -249,9 +645,63 @@ impl TransactionPool {
 			self.stempool.reconcile(txpool_tx, &block.header)?;
 		}
 
+		self.track_pools(TxStatus::Conflicted);
 		Ok(())
 	}
 
+	// Hashes by height of the ancestors of the header, going down to the
+	// provided height at most as deep as we check for reorgs.
+	fn ancestor_hashes(&self, header: &BlockHeader, lowest: u64) -> HashMap<u64, Hash> {
+		let lowest = lowest.max(header.height.saturating_sub(REORG_CHECK_DEPTH));
+		let mut hashes = HashMap::new();
+		let mut prev_hash = header.prev_hash;
+		while hashes.len() as u64 + lowest < header.height {
+			match self.blockchain.get_block_header(&prev_hash) {
+				Ok(prev) => {
+					hashes.insert(prev.height, prev.hash());
+					prev_hash = prev.prev_hash;
+				}
+				Err(_) => break,
+			}
+		}
+		hashes
+	}
+
+	// Brings the status of the tracked txs in line with the content of the
+	// pools, the txs no longer in any of them getting the provided status.
+	fn track_pools(&self, dropped: TxStatus) {
+		let changes = self.tx_tracker.pools_updated(
+			&self.txpool.kernel_excesses(),
+			&self.stempool.kernel_excesses(),
+			dropped,
+		);
+		self.notify_tx_statuses(changes);
+	}
+
+	fn notify_tx_statuses(&self, changes: Vec<(Commitment, TxStatus)>) {
+		for (excess, status) in changes {
+			self.adapter.tx_status_changed(&excess, &status);
+		}
+	}
+
+	/// Recommended fee per unit of tx weight (comparable to `accept_fee_base`)
+	/// to get confirmed within the target number of blocks. Never lower than
+	/// what the pool accepts.
+	pub fn estimate_fee_base(&self, target_blocks: u64) -> u64 {
+		let mut fee_to_weight = self
+			.fee_estimator
+			.estimate(target_blocks, &self.txpool.entries)
+			.unwrap_or(0);
+		if self.total_size() >= self.config.max_pool_size {
+			if let Some(lowest) = self.txpool.lowest_fee_to_weight() {
+				fee_to_weight = fee_to_weight.max(lowest + 1);
+			}
+		}
+		// fee_to_weight is in thousandths of fee per unit of weight
+		let fee_base = (fee_to_weight + 999) / 1_000;
+		fee_base.max(self.config.accept_fee_base)
+	}
+
 	/// Retrieve individual transaction for the given kernel hash.
 	pub fn retrieve_tx_by_kernel_hash(&self, hash: Hash) -> Option<Transaction> {
 		self.txpool.retrieve_tx_by_kernel_hash(hash)
@@ -302,9 +752,71 @@ impl TransactionPool {
 	}
 
 	/// Returns a vector of transactions from the txpool so we can build a
-	/// block from them.
+	/// block from them. The txs selected are tracked as being in our
+	/// candidate block.
 	pub fn prepare_mineable_transactions(&self) -> Result<Vec<Transaction>, PoolError> {
-		self.txpool
-			.prepare_mineable_transactions(self.config.mineable_max_weight)
+		let txs = self
+			.txpool
+			.prepare_mineable_transactions(self.config.mineable_max_weight)?;
+		let kernels = txs
+			.iter()
+			.flat_map(|x| x.kernels().iter().map(|k| k.excess()))
+			.collect::<HashSet<_>>();
+		let changes = self.tx_tracker.candidate_selected(&kernels);
+		self.notify_tx_statuses(changes);
+		Ok(txs)
+	}
+}
+
+// Conflict of the tx of this entry with the pool entries spending some of
+// the same outputs.
+fn conflict(entry: &PoolEntry, conflicting: &[PoolEntry], replaced: bool) -> PoolConflict {
+	let pool_inputs = conflicting
+		.iter()
+		.flat_map(|x| x.tx.inputs().iter().map(|y| y.commitment()))
+		.collect::<HashSet<_>>();
+	PoolConflict {
+		tx: entry.tx.hash(),
+		inputs: entry
+			.tx
+			.inputs()
+			.iter()
+			.map(|x| x.commitment())
+			.filter(|x| pool_inputs.contains(x))
+			.collect(),
+		conflicting_txs: conflicting.iter().map(|x| x.tx.hash()).collect(),
+		src: entry.src.clone(),
+		replaced,
+		at: Utc::now(),
+	}
+}
+
+// A pool entry as saved to disk, along with the pool it was in.
+struct SavedEntry {
+	entry: PoolEntry,
+	stem: bool,
+}
+
+impl SavedEntry {
+	fn new(entry: &PoolEntry, stem: bool) -> SavedEntry {
+		SavedEntry {
+			entry: entry.clone(),
+			stem,
+		}
+	}
+}
+
+impl Writeable for SavedEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.stem as u8)?;
+		self.entry.write(writer)
+	}
+}
+
+impl Readable for SavedEntry {
+	fn read(reader: &mut dyn Reader) -> Result<SavedEntry, ser::Error> {
+		let stem = reader.read_u8()? == 1;
+		let entry = PoolEntry::read(reader)?;
+		Ok(SavedEntry { entry, stem })
 	}
 }
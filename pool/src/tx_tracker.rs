@@ -0,0 +1,203 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of the lifecycle of the txs going through our pool, from the
+//! time they get accepted to their confirmation or their removal.
+//!
+//! Txs get aggregated and deaggregated along the way, so each of their
+//! kernels is tracked on its own, keyed by its excess.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::prelude::{DateTime, Utc};
+
+use self::core::consensus;
+use self::core::core::hash::Hash;
+use self::util::secp::pedersen::Commitment;
+use self::util::RwLock;
+use crate::types::TxStatus;
+use grin_core as core;
+use grin_util as util;
+
+/// Number of txs we keep the status of, the oldest ones no longer in our
+/// pool being forgotten first.
+const MAX_TRACKED_TXS: usize = 10_000;
+
+/// How many blocks back confirmations are checked for reorgs.
+pub const REORG_CHECK_DEPTH: u64 = consensus::HOUR_HEIGHT;
+
+/// Last known status of a tracked tx and when it got there.
+#[derive(Clone, Debug)]
+pub struct TrackedTx {
+	pub status: TxStatus,
+	pub updated_at: DateTime<Utc>,
+}
+
+/// Status of the txs that went through our pool, by kernel excess. All the
+/// updates return the status changes they made, for them to be notified.
+#[derive(Default)]
+pub struct TxTracker {
+	txs: RwLock<HashMap<Commitment, TrackedTx>>,
+}
+
+impl TxTracker {
+	/// Current status of the tx with the provided kernel excess, if tracked.
+	pub fn status(&self, excess: &Commitment) -> Option<TrackedTx> {
+		self.txs.read().get(excess).cloned()
+	}
+
+	/// Brings the statuses in line with the kernels currently in the txpool
+	/// and stempool. Txs that were in the pool but no longer are in any of
+	/// them get the provided status.
+	pub fn pools_updated(
+		&self,
+		txpool: &HashSet<Commitment>,
+		stempool: &HashSet<Commitment>,
+		dropped: TxStatus,
+	) -> Vec<(Commitment, TxStatus)> {
+		let mut txs = self.txs.write();
+		let mut changes = vec![];
+
+		for excess in txpool {
+			match txs.get(excess).map(|x| x.status) {
+				Some(TxStatus::Fluffed) | Some(TxStatus::InCandidateBlock) => {}
+				_ => update(&mut txs, excess, TxStatus::Fluffed, &mut changes),
+			}
+		}
+		for excess in stempool.difference(txpool) {
+			update(&mut txs, excess, TxStatus::Stem, &mut changes);
+		}
+		let gone = txs
+			.iter()
+			.filter(|(k, v)| v.status.is_pending() && !txpool.contains(k) && !stempool.contains(k))
+			.map(|(k, _)| *k)
+			.collect::<Vec<_>>();
+		for excess in &gone {
+			update(&mut txs, excess, dropped, &mut changes);
+		}
+
+		prune(&mut txs);
+		changes
+	}
+
+	/// Marks the txs with a kernel in the block as confirmed by it. Txs
+	/// confirmed by a block that isn't part of the chain ending with this one
+	/// anymore are marked as reorged out, given the hashes of the block
+	/// ancestors by height.
+	pub fn block_confirmed(
+		&self,
+		height: u64,
+		hash: Hash,
+		kernels: &HashSet<Commitment>,
+		ancestors: &HashMap<u64, Hash>,
+	) -> Vec<(Commitment, TxStatus)> {
+		let mut txs = self.txs.write();
+		let mut changes = vec![];
+
+		for excess in kernels {
+			if txs.contains_key(excess) {
+				let status = TxStatus::Confirmed {
+					height,
+					block: hash,
+				};
+				update(&mut txs, excess, status, &mut changes);
+			}
+		}
+		let reorged = txs
+			.iter()
+			.filter(|(k, v)| match v.status {
+				TxStatus::Confirmed { height: h, block } if !kernels.contains(k) => {
+					h >= height || ancestors.get(&h).map(|x| *x != block).unwrap_or(false)
+				}
+				_ => false,
+			})
+			.map(|(k, _)| *k)
+			.collect::<Vec<_>>();
+		for excess in &reorged {
+			update(&mut txs, excess, TxStatus::ReorgedOut, &mut changes);
+		}
+		changes
+	}
+
+	/// Lowest height of the confirmations we track, to know how far back
+	/// reorgs need to be checked for.
+	pub fn lowest_confirmed_height(&self) -> Option<u64> {
+		self.txs
+			.read()
+			.values()
+			.filter_map(|x| match x.status {
+				TxStatus::Confirmed { height, .. } => Some(height),
+				_ => None,
+			})
+			.min()
+	}
+
+	/// Marks the txs with a kernel in the block template we just built as
+	/// being in a candidate block, those selected by a previous template but
+	/// not this one going back to fluffed.
+	pub fn candidate_selected(&self, kernels: &HashSet<Commitment>) -> Vec<(Commitment, TxStatus)> {
+		let mut txs = self.txs.write();
+		let mut changes = vec![];
+
+		let updated = txs
+			.iter()
+			.filter_map(|(k, v)| match v.status {
+				TxStatus::Fluffed if kernels.contains(k) => Some((*k, TxStatus::InCandidateBlock)),
+				TxStatus::InCandidateBlock if !kernels.contains(k) => Some((*k, TxStatus::Fluffed)),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		for (excess, status) in updated {
+			update(&mut txs, &excess, status, &mut changes);
+		}
+		changes
+	}
+}
+
+// Sets the status of a tx, recording it as a change if it's a new one.
+fn update(
+	txs: &mut HashMap<Commitment, TrackedTx>,
+	excess: &Commitment,
+	status: TxStatus,
+	changes: &mut Vec<(Commitment, TxStatus)>,
+) {
+	if txs.get(excess).map(|x| x.status) == Some(status) {
+		return;
+	}
+	txs.insert(
+		*excess,
+		TrackedTx {
+			status,
+			updated_at: Utc::now(),
+		},
+	);
+	changes.push((*excess, status));
+}
+
+// Forgets the txs that left our pool the longest ago when tracking too many.
+fn prune(txs: &mut HashMap<Commitment, TrackedTx>) {
+	if txs.len() <= MAX_TRACKED_TXS {
+		return;
+	}
+	let mut done = txs
+		.iter()
+		.filter(|(_, v)| !v.status.is_pending())
+		.map(|(k, v)| (v.updated_at, *k))
+		.collect::<Vec<_>>();
+	done.sort_by_key(|x| x.0);
+	let extra = txs.len() - MAX_TRACKED_TXS;
+	for (_, k) in done.into_iter().take(extra) {
+		txs.remove(&k);
+	}
+}
@@ -15,17 +15,21 @@
 //! The primary module containing the implementations of the transaction pool
 //! and its top-level members.
 
-use chrono::prelude::{DateTime, Utc};
+use chrono::prelude::{DateTime, TimeZone, Utc};
+use std::collections::BTreeMap;
 
 use self::core::core::block;
 use self::core::core::committed;
 use self::core::core::hash::Hash;
 use self::core::core::transaction::{self, Transaction};
 use self::core::core::{BlockHeader, BlockSums};
+use self::core::ser::{self, Readable, Reader, Writeable, Writer};
 use self::core::{consensus, global};
+use self::util::secp::pedersen::Commitment;
 use failure::Fail;
 use grin_core as core;
 use grin_keychain as keychain;
+use grin_util as util;
 
 /// Dandelion "epoch" length.
 const DANDELION_EPOCH_SECS: u16 = 600;
@@ -47,7 +51,8 @@ pub struct DandelionConfig {
 	#[serde(default = "default_dandelion_epoch_secs")]
 	pub epoch_secs: Option<u16>,
 	/// Dandelion embargo timer. Fluff and broadcast individual txs if not seen
-	/// on network before embargo expires.
+	/// on network before embargo expires. Each tx gets its own embargo, drawn
+	/// from an exponential distribution with this mean.
 	#[serde(default = "default_dandelion_embargo_secs")]
 	pub embargo_secs: Option<u16>,
 	/// Dandelion aggregation timer.
@@ -107,6 +112,16 @@ pub struct PoolConfig {
 	/// blocks.
 	#[serde = "default_mineable_max_weight"]
 	pub mineable_max_weight: usize,
+
+	/// How often (in seconds) the pool content gets saved to disk, to be
+	/// restored after a restart. It is always saved on shutdown, 0 to only
+	/// save it then.
+	#[serde(default = "default_persist_interval_secs")]
+	pub persist_interval_secs: u64,
+
+	/// Admission limits on top of the pool own checks, see `LimitsPolicy`.
+	#[serde(default)]
+	pub policy: PolicyConfig,
 }
 
 impl Default for PoolConfig {
@@ -116,6 +131,8 @@ impl Default for PoolConfig {
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
 			mineable_max_weight: default_mineable_max_weight(),
+			persist_interval_secs: default_persist_interval_secs(),
+			policy: PolicyConfig::default(),
 		}
 	}
 }
@@ -132,6 +149,26 @@ fn default_max_stempool_size() -> usize {
 fn default_mineable_max_weight() -> usize {
 	global::max_block_weight()
 }
+fn default_persist_interval_secs() -> u64 {
+	300
+}
+
+/// Admission limits enforced by the default pool policy on the txs submitted
+/// to us, those left unset are not enforced.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct PolicyConfig {
+	/// Maximum number of inputs of a transaction
+	pub max_inputs: Option<usize>,
+	/// Maximum number of outputs of a transaction
+	pub max_outputs: Option<usize>,
+	/// Minimum fee per unit of weight (like `accept_fee_base`) required from
+	/// the txs of a given source, by source name ("p2p", "push-api").
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub min_fee_base: BTreeMap<String, u64>,
+	/// Maximum number of txs a single source (peer address or API) can
+	/// submit per minute
+	pub max_txs_per_minute: Option<u32>,
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -145,6 +182,26 @@ pub struct PoolEntry {
 	pub tx: Transaction,
 }
 
+impl Writeable for PoolEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.src.write(writer)?;
+		writer.write_i64(self.tx_at.timestamp_millis())?;
+		self.tx.write(writer)
+	}
+}
+
+impl Readable for PoolEntry {
+	fn read(reader: &mut dyn Reader) -> Result<PoolEntry, ser::Error> {
+		let src = TxSource::read(reader)?;
+		let tx_at = Utc
+			.timestamp_millis_opt(reader.read_i64()?)
+			.single()
+			.ok_or(ser::Error::CorruptedData)?;
+		let tx = Transaction::read(reader)?;
+		Ok(PoolEntry { src, tx_at, tx })
+	}
+}
+
 /// Placeholder: the data representing where we heard about a tx from.
 ///
 /// Used to make decisions based on transaction acceptance priority from
@@ -161,6 +218,26 @@ pub struct TxSource {
 	pub identifier: String,
 }
 
+impl Writeable for TxSource {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&self.debug_name)?;
+		writer.write_bytes(&self.identifier)
+	}
+}
+
+impl Readable for TxSource {
+	fn read(reader: &mut dyn Reader) -> Result<TxSource, ser::Error> {
+		let debug_name = String::from_utf8(reader.read_bytes_len_prefix()?)
+			.map_err(|_| ser::Error::CorruptedData)?;
+		let identifier = String::from_utf8(reader.read_bytes_len_prefix()?)
+			.map_err(|_| ser::Error::CorruptedData)?;
+		Ok(TxSource {
+			debug_name,
+			identifier,
+		})
+	}
+}
+
 /// Possible errors when interacting with the transaction pool.
 #[derive(Debug, Fail, PartialEq)]
 pub enum PoolError {
@@ -198,6 +275,14 @@ pub enum PoolError {
 	/// Attempt to add a duplicate tx to the pool.
 	#[fail(display = "Duplicate tx")]
 	DuplicateTx,
+	/// Attempt to replace txs in the pool spending the same inputs with a tx
+	/// not paying a higher fee to weight than all of them, or not paying their
+	/// total fee plus its own minimum fee.
+	#[fail(display = "Replacement fee too low")]
+	LowFeeReplacement,
+	/// The pool admission policy rejected the transaction.
+	#[fail(display = "Rejected by pool policy: {}", _0)]
+	PolicyRejected(String),
 	/// Other kinds of error (not yet pulled out into meaningful errors).
 	#[fail(display = "General pool error {}", _0)]
 	Other(String),
@@ -252,8 +337,84 @@ pub trait PoolAdapter: Send + Sync {
 	/// The transaction pool has accepted this transaction as valid.
 	fn tx_accepted(&self, tx: &transaction::Transaction);
 
-	/// The stem transaction pool has accepted this transactions as valid.
-	fn stem_tx_accepted(&self, tx: &transaction::Transaction) -> Result<(), PoolError>;
+	/// The stem transaction pool has accepted this transactions as valid, as
+	/// received from the provided source.
+	fn stem_tx_accepted(
+		&self,
+		src: &TxSource,
+		tx: &transaction::Transaction,
+	) -> Result<(), PoolError>;
+
+	/// The tx with this kernel excess moved to a new stage of its lifecycle.
+	fn tx_status_changed(&self, excess: &Commitment, status: &TxStatus);
+
+	/// A tx spending the same outputs as txs already in our pool came in.
+	fn tx_conflict_detected(&self, conflict: &PoolConflict);
+}
+
+/// Where a tx that went through our pool is at in its lifecycle, as tracked
+/// for each of its kernels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxStatus {
+	/// In our stempool, relayed along the Dandelion stem or waiting to be
+	/// aggregated
+	Stem,
+	/// In our txpool and broadcast to our peers
+	Fluffed,
+	/// Selected by the last block template we built for mining
+	InCandidateBlock,
+	/// Confirmed by the block at this height
+	Confirmed { height: u64, block: Hash },
+	/// Evicted from the full pool to make room for txs paying more
+	Evicted,
+	/// Dropped for spending the same outputs as a confirmed tx or a tx paying
+	/// more
+	Conflicted,
+	/// The block confirming it got reorganized out of the chain
+	ReorgedOut,
+}
+
+impl TxStatus {
+	/// Whether the tx is still in our pool, waiting to be confirmed.
+	pub fn is_pending(&self) -> bool {
+		match self {
+			TxStatus::Stem | TxStatus::Fluffed | TxStatus::InCandidateBlock => true,
+			_ => false,
+		}
+	}
+}
+
+/// A tx received spending some of the same outputs as txs already in our
+/// pool, a possible double spend attempt.
+#[derive(Clone, Debug)]
+pub struct PoolConflict {
+	/// Hash of the tx received
+	pub tx: Hash,
+	/// Outputs spent both by the tx received and by txs in our pool
+	pub inputs: Vec<Commitment>,
+	/// Hashes of the txs in our pool it conflicts with, including the ones
+	/// spending their outputs
+	pub conflicting_txs: Vec<Hash>,
+	/// Where the tx received came from
+	pub src: TxSource,
+	/// Whether it replaced the conflicting txs by paying more, or got rejected
+	pub replaced: bool,
+	/// When the conflict was detected
+	pub at: DateTime<Utc>,
+}
+
+/// Admission policy for the txs submitted to the pool, checked before the
+/// pool own validation. Allows operators to enforce their own limits on what
+/// they accept and from whom.
+pub trait PoolPolicy: Send + Sync {
+	/// Accepts or rejects a tx submitted by the provided source, giving the
+	/// reason for rejections.
+	fn check(
+		&self,
+		tx: &transaction::Transaction,
+		src: &TxSource,
+		stem: bool,
+	) -> Result<(), String>;
 }
 
 /// Dummy adapter used as a placeholder for real implementations
@@ -262,7 +423,13 @@ pub struct NoopAdapter {}
 
 impl PoolAdapter for NoopAdapter {
 	fn tx_accepted(&self, _tx: &transaction::Transaction) {}
-	fn stem_tx_accepted(&self, _tx: &transaction::Transaction) -> Result<(), PoolError> {
+	fn stem_tx_accepted(
+		&self,
+		_src: &TxSource,
+		_tx: &transaction::Transaction,
+	) -> Result<(), PoolError> {
 		Ok(())
 	}
+	fn tx_status_changed(&self, _excess: &Commitment, _status: &TxStatus) {}
+	fn tx_conflict_detected(&self, _conflict: &PoolConflict) {}
 }
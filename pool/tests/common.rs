@@ -158,6 +158,8 @@ pub fn test_setup(
 			max_pool_size: 50,
 			max_stempool_size: 50,
 			mineable_max_weight: 10_000,
+			persist_interval_secs: 0,
+			policy: PolicyConfig::default(),
 		},
 		chain.clone(),
 		verifier_cache.clone(),
@@ -0,0 +1,147 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::PoolError;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+/// Test txs spending the same outputs as txs in the pool get logged as
+/// conflicts, whether they replace them or not. Conflicts with stempool txs
+/// aren't logged, as that would publish txs still under embargo.
+#[test]
+fn test_pool_conflicts() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_pool_conflicts".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache);
+
+		let tx_a = test_transaction(&keychain, vec![500, 600], vec![1_000]);
+		let tx_b = test_transaction(&keychain, vec![500], vec![450]);
+		let tx_c = test_transaction(&keychain, vec![600], vec![300]);
+
+		pool.add_to_pool(test_source(), tx_a.clone(), false, &header)
+			.unwrap();
+		assert!(pool.conflicts.is_empty());
+
+		// Paying less than the tx it double spends, tx_b gets rejected.
+		assert_eq!(
+			pool.add_to_pool(test_source(), tx_b.clone(), false, &header),
+			Err(PoolError::LowFeeReplacement)
+		);
+		assert_eq!(pool.conflicts.len(), 1);
+
+		// Receiving it again updates the conflict already logged.
+		assert_eq!(
+			pool.add_to_pool(test_source(), tx_b.clone(), false, &header),
+			Err(PoolError::LowFeeReplacement)
+		);
+		assert_eq!(pool.conflicts.len(), 1);
+
+		// Paying more, tx_c replaces it.
+		pool.add_to_pool(test_source(), tx_c.clone(), false, &header)
+			.unwrap();
+		assert_eq!(pool.total_size(), 1);
+
+		let conflicts = pool.conflicts.all();
+		assert_eq!(conflicts.len(), 2);
+
+		assert_eq!(conflicts[0].tx, tx_b.hash());
+		assert_eq!(conflicts[0].conflicting_txs, vec![tx_a.hash()]);
+		assert_eq!(conflicts[0].inputs, vec![tx_b.inputs()[0].commitment()]);
+		assert!(!conflicts[0].replaced);
+
+		assert_eq!(conflicts[1].tx, tx_c.hash());
+		assert_eq!(conflicts[1].conflicting_txs, vec![tx_a.hash()]);
+		assert_eq!(conflicts[1].inputs, vec![tx_c.inputs()[0].commitment()]);
+		assert!(conflicts[1].replaced);
+		assert_eq!(conflicts[1].src.identifier, test_source().identifier);
+
+		// Putting our own txs back from the reorg cache isn't a double spend
+		// attempt, even though tx_a conflicts with tx_c.
+		pool.reconcile_reorg_cache(&header).unwrap();
+		assert_eq!(pool.conflicts.len(), 2);
+
+		// Txs conflicting with stempool txs replace them without being logged.
+		let tx_d = test_transaction(&keychain, vec![700], vec![690]);
+		let tx_e = test_transaction(&keychain, vec![700], vec![650]);
+		pool.add_to_pool(test_source(), tx_d.clone(), true, &header)
+			.unwrap();
+		assert_eq!(pool.stempool.size(), 1);
+		assert_eq!(pool.conflicts.len(), 2);
+		pool.add_to_pool(test_source(), tx_e.clone(), false, &header)
+			.unwrap();
+		assert_eq!(pool.stempool.size(), 0);
+		assert_eq!(pool.txpool.size(), 2);
+		assert_eq!(pool.conflicts.len(), 2);
+		assert!(pool
+			.conflicts
+			.all()
+			.iter()
+			.all(|x| !x.conflicting_txs.contains(&tx_d.hash())));
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -0,0 +1,137 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use chrono::prelude::Utc;
+use chrono::Duration;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+/// Test fee estimates follow how long the txs of the pool waited to get
+/// confirmed depending on their fee.
+#[test]
+fn test_fee_estimate() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_fee_estimate".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, (1000..1020).collect());
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+		// Half of the txs pay a high fee, the others a low one.
+		let high_txs = (1000..1010)
+			.map(|x| test_transaction(&keychain, vec![x], vec![x - 500]))
+			.collect::<Vec<_>>();
+		let low_txs = (1010..1020)
+			.map(|x| test_transaction(&keychain, vec![x], vec![x - 100]))
+			.collect::<Vec<_>>();
+		let high_fee_base = (high_txs[0].fee_to_weight() + 999) / 1_000;
+		let low_fee_base = (low_txs[0].fee_to_weight() + 999) / 1_000;
+		assert!(high_fee_base > low_fee_base);
+
+		{
+			let mut write_pool = pool.write();
+			for tx in high_txs.iter().chain(low_txs.iter()) {
+				write_pool
+					.add_to_pool(test_source(), tx.clone(), false, &header)
+					.unwrap();
+			}
+			assert_eq!(write_pool.total_size(), 20);
+
+			// Without any data we can only recommend the minimum accepted.
+			assert_eq!(write_pool.estimate_fee_base(1), 0);
+		}
+
+		// The high fee txs get confirmed right away.
+		let block = add_block(header, high_txs.clone(), &mut chain);
+		let header = block.header;
+		{
+			let mut write_pool = pool.write();
+			write_pool.reconcile_block(&block).unwrap();
+			assert_eq!(write_pool.total_size(), 10);
+			assert_eq!(write_pool.estimate_fee_base(1), high_fee_base);
+		}
+
+		// The low fee ones only get confirmed after waiting for 10 blocks.
+		{
+			let mut write_pool = pool.write();
+			for entry in write_pool.txpool.entries.iter_mut() {
+				entry.tx_at = Utc::now() - Duration::seconds(590);
+			}
+			// Those waiting for longer than the target count against their
+			// fee level.
+			assert_eq!(write_pool.estimate_fee_base(5), high_fee_base);
+		}
+		let block = add_block(header, low_txs.clone(), &mut chain);
+		{
+			let mut write_pool = pool.write();
+			write_pool.reconcile_block(&block).unwrap();
+			assert_eq!(write_pool.total_size(), 0);
+
+			assert_eq!(write_pool.estimate_fee_base(1), high_fee_base);
+			assert_eq!(write_pool.estimate_fee_base(5), high_fee_base);
+			assert_eq!(write_pool.estimate_fee_base(10), low_fee_base);
+		}
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -0,0 +1,181 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::PoolError;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+/// Test conflicting txs paying a higher fee rate and more fees replace the
+/// ones in the pool and a full pool evicts its lowest fee rate txs first.
+#[test]
+fn test_fee_replacement_and_eviction() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_fee_eviction".to_string();
+	clean_output_dir(db_root.clone());
+
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	// Initialize a new pool with our chain adapter.
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			0,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700, 800, 900, 1000]);
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), initial_tx, false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 1);
+	}
+
+	// tx1 and its child tx2 spend an output of the initial tx.
+	let tx1 = test_transaction(&keychain, vec![500], vec![400]);
+	let tx2 = test_transaction(&keychain, vec![400], vec![390]);
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx1.clone(), false, &header)
+			.unwrap();
+		write_pool
+			.add_to_pool(test_source(), tx2.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// A conflicting tx paying a lower fee rate than tx1 is rejected.
+	{
+		let tx = test_transaction(&keychain, vec![500], vec![450]);
+		let mut write_pool = pool.write();
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::LowFeeReplacement)
+		);
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// A higher fee rate doesn't do if it doesn't pay as much as tx1 and tx2
+	// together.
+	{
+		let tx = test_transaction(&keychain, vec![500], vec![395]);
+		let mut write_pool = pool.write();
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::LowFeeReplacement)
+		);
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// Nor does it if it pays more, but not the minimum fee of its own weight
+	// on top of that.
+	{
+		let tx = test_transaction(&keychain, vec![500], vec![385]);
+		let mut write_pool = pool.write();
+		write_pool.config.accept_fee_base = 10;
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::LowFeeReplacement)
+		);
+		write_pool.config.accept_fee_base = 0;
+		assert_eq!(write_pool.total_size(), 3);
+	}
+
+	// One paying a higher fee rate than both tx1 and tx2 replaces them.
+	let tx3 = test_transaction(&keychain, vec![500], vec![300]);
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx3.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 2);
+		assert!(write_pool.txpool.contains_tx(tx3.hash()));
+		assert!(!write_pool.txpool.contains_tx(tx1.hash()));
+		assert!(!write_pool.txpool.contains_tx(tx2.hash()));
+	}
+
+	// Fill up the pool, making room for a tx paying more than the least
+	// paying ones evicts them.
+	let tx4 = test_transaction(&keychain, vec![600], vec![590]);
+	let tx5 = test_transaction(&keychain, vec![700], vec![650]);
+	let tx6 = test_transaction(&keychain, vec![800], vec![720]);
+	{
+		let mut write_pool = pool.write();
+		write_pool.config.max_pool_size = 3;
+		write_pool
+			.add_to_pool(test_source(), tx4.clone(), false, &header)
+			.unwrap();
+		write_pool
+			.add_to_pool(test_source(), tx5.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 4);
+
+		write_pool
+			.add_to_pool(test_source(), tx6.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 3);
+		assert!(write_pool.txpool.contains_tx(tx6.hash()));
+		assert!(!write_pool.txpool.contains_tx(tx4.hash()));
+		assert!(!write_pool.txpool.contains_tx(tx5.hash()));
+	}
+
+	// A tx paying no more than the least paying ones is turned away.
+	{
+		let mut write_pool = pool.write();
+		let tx7 = test_transaction(&keychain, vec![900], vec![899]);
+		write_pool
+			.add_to_pool(test_source(), tx7, false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 4);
+
+		let tx8 = test_transaction(&keychain, vec![1000], vec![999]);
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx8, false, &header),
+			Err(PoolError::OverCapacity)
+		);
+		assert_eq!(write_pool.total_size(), 4);
+	}
+}
@@ -0,0 +1,227 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction, Weighting};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+/// Test txs spending outputs of several unconfirmed txs (diamond shaped
+/// dependencies) get bucketed and mined along with their parents.
+#[test]
+fn test_multi_parent_bucketing() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_multi_parent".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![10, 20, 30, 40]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		// Initialize a new pool with our chain adapter.
+		let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+		// A first diamond, the tx joining both branches pays a high fee and
+		// gets packaged along with them.
+		let root_tx_1 = test_transaction(&keychain, vec![10, 20], vec![12, 14]);
+		let left_tx_1 = test_transaction(&keychain, vec![12], vec![11]);
+		let right_tx_1 = test_transaction(&keychain, vec![14], vec![13]);
+		let join_tx_1 = test_transaction(&keychain, vec![11, 13], vec![4]);
+
+		// A second one, the tx joining both branches pays a low fee and
+		// comes after them on its own.
+		let root_tx_2 = test_transaction(&keychain, vec![30, 40], vec![31, 35]);
+		let left_tx_2 = test_transaction(&keychain, vec![31], vec![29]);
+		let right_tx_2 = test_transaction(&keychain, vec![35], vec![33]);
+		let join_tx_2 = test_transaction(&keychain, vec![29, 33], vec![61]);
+
+		let all_txs = vec![
+			root_tx_1, left_tx_1, right_tx_1, join_tx_1, root_tx_2, left_tx_2, right_tx_2,
+			join_tx_2,
+		];
+
+		{
+			let mut write_pool = pool.write();
+			for tx in &all_txs {
+				write_pool
+					.add_to_pool(test_source(), tx.clone(), false, &header)
+					.unwrap();
+			}
+			assert_eq!(write_pool.total_size(), 8);
+		}
+
+		// All txs get bucketed, each one after all of its parents.
+		{
+			let read_pool = pool.read();
+			let bucketed = read_pool.txpool.bucket_transactions(Weighting::NoLimit);
+			assert_eq!(bucketed.len(), 8);
+			let pos = |tx: &Transaction| bucketed.iter().position(|x| x == tx).unwrap();
+			for (parents, child) in &[(vec![0], 1), (vec![0], 2), (vec![1, 2], 3)] {
+				for offset in &[0, 4] {
+					for parent in parents {
+						assert!(pos(&all_txs[parent + offset]) < pos(&all_txs[child + offset]));
+					}
+				}
+			}
+		}
+
+		let txs = pool.read().prepare_mineable_transactions().unwrap();
+
+		let block = add_block(header, txs, &mut chain);
+
+		// Check the block contains every one of our txs.
+		assert_eq!(block.kernels().len(), 9);
+		for tx in &all_txs {
+			assert!(block.kernels().contains(&tx.kernels()[0]));
+		}
+
+		// Now reconcile the transaction pool with the new block,
+		// nothing should be left.
+		{
+			let mut write_pool = pool.write();
+			write_pool.reconcile_block(&block).unwrap();
+
+			assert_eq!(write_pool.total_size(), 0);
+		}
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
+
+/// Test a tx spending outputs of several buckets only gets merged with them if
+/// that doesn't lower the fee_to_weight of any of them, even the best one.
+#[test]
+fn test_multi_parent_keeps_best_parent_fee_to_weight() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_multi_parent_fee".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![300, 400, 500]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		// Initialize a new pool with our chain adapter.
+		let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+		// A high and a low fee_to_weight parent, a child spending from both
+		// whose package would land in between, and an unrelated tx paying a
+		// bit less than the high fee parent, but more than the package.
+		let high_tx = test_transaction(&keychain, vec![300], vec![100]);
+		let low_tx = test_transaction(&keychain, vec![400], vec![399]);
+		let child_tx = test_transaction(&keychain, vec![100, 399], vec![490]);
+		let other_tx = test_transaction(&keychain, vec![500], vec![320]);
+		assert!(other_tx.fee_to_weight() < high_tx.fee_to_weight());
+
+		let all_txs = vec![high_tx, low_tx, child_tx, other_tx];
+		{
+			let mut write_pool = pool.write();
+			for tx in &all_txs {
+				write_pool
+					.add_to_pool(test_source(), tx.clone(), false, &header)
+					.unwrap();
+			}
+			assert_eq!(write_pool.total_size(), 4);
+		}
+
+		// Merging the child with both parents would have dragged the high fee
+		// parent behind the unrelated tx.
+		let read_pool = pool.read();
+		let bucketed = read_pool.txpool.bucket_transactions(Weighting::NoLimit);
+		assert_eq!(bucketed.len(), 4);
+		let pos = |tx: &Transaction| bucketed.iter().position(|x| x == tx).unwrap();
+		assert!(pos(&all_txs[0]) < pos(&all_txs[3]));
+		assert!(pos(&all_txs[0]) < pos(&all_txs[2]));
+		assert!(pos(&all_txs[1]) < pos(&all_txs[2]));
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -0,0 +1,221 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hash;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::{PoolAdapter, PoolConflict, PoolError, TxSource, TxStatus};
+use self::util::secp::pedersen::Commitment;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+// Pool adapter recording the txs relayed, fluffed or along the stem.
+#[derive(Default)]
+struct RelayAdapter {
+	fluffed: RwLock<Vec<Hash>>,
+	stemmed: RwLock<Vec<Hash>>,
+}
+
+impl PoolAdapter for RelayAdapter {
+	fn tx_accepted(&self, tx: &Transaction) {
+		self.fluffed.write().push(tx.hash());
+	}
+	fn stem_tx_accepted(&self, _src: &TxSource, tx: &Transaction) -> Result<(), PoolError> {
+		self.stemmed.write().push(tx.hash());
+		Ok(())
+	}
+	fn tx_status_changed(&self, _excess: &Commitment, _status: &TxStatus) {}
+	fn tx_conflict_detected(&self, _conflict: &PoolConflict) {}
+}
+
+/// Test packages of txs spending each other get accepted all at once, or
+/// not at all when any of them is invalid.
+#[test]
+fn test_tx_package() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_tx_package".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700, 800, 900]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		let adapter = Arc::new(RelayAdapter::default());
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache);
+		pool.adapter = adapter.clone();
+
+		// A child spending the output of its parent can't go in on its own.
+		let parent = test_transaction(&keychain, vec![500], vec![400]);
+		let child = test_transaction(&keychain, vec![400], vec![350]);
+		assert!(pool
+			.add_to_pool(test_source(), child.clone(), false, &header)
+			.is_err());
+
+		// But goes in along with its parent.
+		pool.add_package_to_pool(
+			test_source(),
+			vec![parent.clone(), child.clone()],
+			false,
+			false,
+			&header,
+		)
+		.unwrap();
+		assert_eq!(pool.total_size(), 2);
+		assert_eq!(*adapter.fluffed.read(), vec![parent.hash(), child.hash()]);
+
+		// Out of order, the package is rejected. The valid tx it starts with
+		// never gets relayed nor tracked.
+		let parent = test_transaction(&keychain, vec![600], vec![590]);
+		let child = test_transaction(&keychain, vec![590], vec![580]);
+		let other = test_transaction(&keychain, vec![700], vec![690]);
+		assert!(pool
+			.add_package_to_pool(
+				test_source(),
+				vec![other.clone(), child.clone(), parent.clone()],
+				false,
+				false,
+				&header,
+			)
+			.is_err());
+		assert_eq!(pool.total_size(), 2);
+		assert_eq!(adapter.fluffed.read().len(), 2);
+		assert!(pool
+			.tx_tracker
+			.status(&other.kernels()[0].excess())
+			.is_none());
+
+		// A single invalid tx rejects the whole package, leaving the pool as is.
+		let invalid = test_transaction(&keychain, vec![999], vec![990]);
+		assert!(pool
+			.add_package_to_pool(
+				test_source(),
+				vec![parent.clone(), other.clone(), invalid],
+				false,
+				false,
+				&header,
+			)
+			.is_err());
+		assert_eq!(pool.total_size(), 2);
+
+		// The package can also be aggregated into a single tx, cutting
+		// through the output the child spends.
+		pool.add_package_to_pool(
+			test_source(),
+			vec![parent.clone(), child.clone(), other.clone()],
+			true,
+			false,
+			&header,
+		)
+		.unwrap();
+		assert_eq!(pool.total_size(), 3);
+		let agg_tx = &pool.txpool.entries.last().unwrap().tx;
+		assert_eq!(agg_tx.kernels().len(), 3);
+		assert_eq!(agg_tx.inputs().len(), 2);
+		assert_eq!(agg_tx.outputs().len(), 2);
+
+		// Along the stem, the package txs get relayed in order.
+		let parent = test_transaction(&keychain, vec![800], vec![780]);
+		let child = test_transaction(&keychain, vec![780], vec![770]);
+		pool.add_package_to_pool(
+			test_source(),
+			vec![parent.clone(), child.clone()],
+			false,
+			true,
+			&header,
+		)
+		.unwrap();
+		assert_eq!(pool.stempool.size(), 2);
+		assert_eq!(*adapter.stemmed.read(), vec![parent.hash(), child.hash()]);
+		assert_eq!(adapter.fluffed.read().len(), 3);
+
+		// A single tx paying less than the minimum fee rejects the package.
+		pool.config.accept_fee_base = 3;
+		let parent = test_transaction(&keychain, vec![900], vec![880]);
+		let child = test_transaction(&keychain, vec![880], vec![870]);
+		assert_eq!(
+			pool.add_package_to_pool(
+				test_source(),
+				vec![parent.clone(), child.clone()],
+				false,
+				false,
+				&header,
+			),
+			Err(PoolError::LowFeeTransaction(child.tx_weight() as u64 * 3))
+		);
+		assert_eq!(pool.total_size(), 3);
+
+		// Nor can a package along the stem go over the stempool size.
+		pool.config.accept_fee_base = 0;
+		pool.config.max_stempool_size = 3;
+		assert_eq!(
+			pool.add_package_to_pool(
+				test_source(),
+				vec![parent.clone(), child.clone()],
+				false,
+				true,
+				&header,
+			),
+			Err(PoolError::OverCapacity)
+		);
+		assert_eq!(pool.stempool.size(), 2);
+		assert_eq!(adapter.stemmed.read().len(), 2);
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
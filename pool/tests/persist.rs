@@ -0,0 +1,156 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Test the pool content saved to disk gets restored in a new pool, minus
+/// the txs mined in the meantime.
+#[test]
+fn test_pool_save_and_load() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_pool_persist".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![10, 20, 30, 40]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		let path = Path::new("target").join(&db_root).join("txpool.bin");
+
+		let tx_1 = test_transaction(&keychain, vec![10], vec![8]);
+		let tx_2 = test_transaction(&keychain, vec![20], vec![17]);
+		let child_tx = test_transaction(&keychain, vec![17], vec![15]);
+		let stem_tx = test_transaction(&keychain, vec![30], vec![26]);
+
+		let saved_at = {
+			let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+			for tx in &[&tx_1, &tx_2, &child_tx] {
+				pool.add_to_pool(test_source(), (*tx).clone(), false, &header)
+					.unwrap();
+			}
+			pool.add_to_pool(test_source(), stem_tx.clone(), true, &header)
+				.unwrap();
+			assert_eq!(pool.total_size(), 3);
+			assert_eq!(pool.stempool.size(), 1);
+
+			assert_eq!(pool.save_entries(&path).unwrap(), 4);
+			pool.txpool
+				.entries
+				.iter()
+				.map(|x| x.tx_at.timestamp_millis())
+				.collect::<Vec<_>>()
+		};
+
+		// Everything comes back, in the pool it was in and with the time it
+		// was first received.
+		{
+			let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+			assert_eq!(pool.load_entries(&path, &header).unwrap(), 4);
+			assert_eq!(pool.total_size(), 3);
+			assert_eq!(pool.stempool.size(), 1);
+			assert!(pool.stempool.contains_tx(stem_tx.hash()));
+			assert_eq!(
+				pool.txpool
+					.entries
+					.iter()
+					.map(|x| x.tx_at.timestamp_millis())
+					.collect::<Vec<_>>(),
+				saved_at
+			);
+		}
+
+		// An entry that can't be decoded gets skipped, the ones after it still
+		// come back, and a truncated tail keeps what was restored.
+		{
+			let mut data = fs::read(&path).unwrap();
+			let len = u64::from_be_bytes(data[4..12].try_into().unwrap()) as usize;
+			for b in &mut data[12..12 + len] {
+				*b = 0xff;
+			}
+			data.extend_from_slice(&[0, 0, 1]);
+			let corrupted_path = path.with_extension("corrupted");
+			fs::write(&corrupted_path, data).unwrap();
+
+			let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+			assert_eq!(pool.load_entries(&corrupted_path, &header).unwrap(), 3);
+			assert!(!pool.txpool.contains_tx(tx_1.hash()));
+			assert!(pool.txpool.contains_tx(tx_2.hash()));
+			assert!(pool.txpool.contains_tx(child_tx.hash()));
+			assert!(pool.stempool.contains_tx(stem_tx.hash()));
+		}
+
+		// Once tx_1 is mined, only the others come back.
+		let block = add_block(header, vec![tx_1.clone()], &mut chain);
+		{
+			let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache.clone());
+			assert_eq!(pool.load_entries(&path, &block.header).unwrap(), 3);
+			assert_eq!(pool.total_size(), 2);
+			assert!(!pool.txpool.contains_tx(tx_1.hash()));
+			assert!(pool.txpool.contains_tx(tx_2.hash()));
+			assert!(pool.txpool.contains_tx(child_tx.hash()));
+		}
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
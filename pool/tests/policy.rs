@@ -0,0 +1,172 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::{LimitsPolicy, PolicyConfig, PoolError, PoolPolicy, TxSource};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+// Only lets txs with a single kernel in.
+struct SingleKernelPolicy;
+
+impl PoolPolicy for SingleKernelPolicy {
+	fn check(&self, tx: &Transaction, _src: &TxSource, _stem: bool) -> Result<(), String> {
+		if tx.kernels().len() > 1 {
+			return Err("too many kernels".to_owned());
+		}
+		Ok(())
+	}
+}
+
+fn source(name: &str, identifier: &str) -> TxSource {
+	TxSource {
+		debug_name: name.to_owned(),
+		identifier: identifier.to_owned(),
+	}
+}
+
+/// Test the txs submitted to the pool are held to its admission policy.
+#[test]
+fn test_pool_policy() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_pool_policy".to_string();
+	clean_output_dir(db_root.clone());
+
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			0,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header,
+		vec![100, 200, 300, 400, 500, 600, 700, 800],
+	);
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), initial_tx, false, &header)
+			.unwrap();
+
+		write_pool.policy = Arc::new(LimitsPolicy::new(PolicyConfig {
+			max_inputs: Some(1),
+			max_outputs: Some(2),
+			min_fee_base: vec![("p2p".to_owned(), 1)].into_iter().collect(),
+			max_txs_per_minute: Some(2),
+		}));
+	}
+
+	// Too many inputs or outputs.
+	{
+		let mut write_pool = pool.write();
+		let tx = test_transaction(&keychain, vec![100, 200], vec![290]);
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::PolicyRejected("more than 1 inputs".to_owned()))
+		);
+		let tx = test_transaction(&keychain, vec![100], vec![30, 31, 32]);
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx, false, &header),
+			Err(PoolError::PolicyRejected("more than 2 outputs".to_owned()))
+		);
+	}
+
+	// Peers have to pay more than the push API.
+	{
+		let mut write_pool = pool.write();
+		let tx = test_transaction(&keychain, vec![100], vec![99]);
+		assert_eq!(
+			write_pool.add_to_pool(source("p2p", "10.0.0.1"), tx.clone(), false, &header),
+			Err(PoolError::PolicyRejected("fee below 4 for p2p".to_owned()))
+		);
+		write_pool
+			.add_to_pool(source("push-api", "push-api"), tx, false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 2);
+	}
+
+	// A single peer can only submit so many txs at once, others still can.
+	{
+		let mut write_pool = pool.write();
+		let txs = vec![
+			test_transaction(&keychain, vec![200], vec![150]),
+			test_transaction(&keychain, vec![300], vec![250]),
+			test_transaction(&keychain, vec![400], vec![350]),
+		];
+		for tx in &txs[..2] {
+			write_pool
+				.add_to_pool(source("p2p", "10.0.0.2"), tx.clone(), false, &header)
+				.unwrap();
+		}
+		assert_eq!(
+			write_pool.add_to_pool(source("p2p", "10.0.0.2"), txs[2].clone(), false, &header),
+			Err(PoolError::PolicyRejected(
+				"more than 2 txs per minute from 10.0.0.2".to_owned()
+			))
+		);
+		write_pool
+			.add_to_pool(source("p2p", "10.0.0.3"), txs[2].clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 5);
+	}
+
+	// Custom policies can be plugged in.
+	{
+		let mut write_pool = pool.write();
+		write_pool.policy = Arc::new(SingleKernelPolicy);
+		let tx_1 = test_transaction(&keychain, vec![500], vec![450]);
+		let tx_2 = test_transaction(&keychain, vec![600], vec![550]);
+		let agg_tx = core::core::transaction::aggregate(vec![tx_1, tx_2]).unwrap();
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), agg_tx, false, &header),
+			Err(PoolError::PolicyRejected("too many kernels".to_owned()))
+		);
+		let tx = test_transaction(&keychain, vec![700], vec![650]);
+		write_pool
+			.add_to_pool(test_source(), tx, false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 6);
+	}
+}
@@ -0,0 +1,120 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+/// Test the pool entries as listed and looked up over the API: txpool and
+/// stempool entries kept apart, txs found by kernel in the pool holding them
+/// and mineable txs ordered by fee, parents first.
+#[test]
+fn test_pool_entries() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_pool_entries".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![100, 200, 300]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache);
+
+		let tx_low = test_transaction(&keychain, vec![100], vec![99]);
+		let tx_high = test_transaction(&keychain, vec![200], vec![190]);
+		let child = test_transaction(&keychain, vec![99], vec![90]);
+		let tx_stem = test_transaction(&keychain, vec![300], vec![290]);
+
+		for tx in &[&tx_low, &tx_high, &child] {
+			pool.add_to_pool(test_source(), (*tx).clone(), false, &header)
+				.unwrap();
+		}
+		pool.add_to_pool(test_source(), tx_stem.clone(), true, &header)
+			.unwrap();
+
+		// The txpool lists the fluffed txs in the order they came in, the
+		// stempool only the stem one.
+		assert_eq!(
+			pool.txpool.all_transactions(),
+			vec![tx_low.clone(), tx_high.clone(), child.clone()]
+		);
+		assert_eq!(pool.stempool.all_transactions(), vec![tx_stem.clone()]);
+
+		// Txs are found by kernel in the pool holding them only.
+		let kernel_hash = child.kernels()[0].hash();
+		assert_eq!(
+			pool.txpool.retrieve_tx_by_kernel_hash(kernel_hash),
+			Some(child.clone())
+		);
+		assert_eq!(pool.stempool.retrieve_tx_by_kernel_hash(kernel_hash), None);
+		let kernel_hash = tx_stem.kernels()[0].hash();
+		assert_eq!(pool.txpool.retrieve_tx_by_kernel_hash(kernel_hash), None);
+		assert_eq!(
+			pool.stempool.retrieve_tx_by_kernel_hash(kernel_hash),
+			Some(tx_stem.clone())
+		);
+
+		// Mineable txs come from the txpool only, the highest fee to weight
+		// first and the child right after its parent.
+		let mineable = pool.prepare_mineable_transactions().unwrap();
+		assert_eq!(mineable, vec![tx_high, tx_low, child]);
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -79,9 +79,10 @@ fn test_the_transaction_pool() {
 	}
 
 	// Test adding a tx that "double spends" an output currently spent by a tx
-	// already in the txpool. In this case we attempt to spend the original coinbase twice.
+	// already in the txpool. In this case we attempt to spend the original coinbase twice,
+	// with a lower fee so it doesn't replace the tx already in the pool.
 	{
-		let tx = test_transaction_spending_coinbase(&keychain, &header, vec![501]);
+		let tx = test_transaction_spending_coinbase(&keychain, &header, vec![59_999_000_000]);
 		let mut write_pool = pool.write();
 		assert!(write_pool
 			.add_to_pool(test_source(), tx, false, &header)
@@ -289,9 +290,10 @@ fn test_the_transaction_pool() {
 		}
 
 		// Test adding a tx that "double spends" an output currently spent by a tx
-		// already in the txpool. In this case we attempt to spend the original coinbase twice.
+		// already in the txpool. In this case we attempt to spend the original coinbase twice,
+		// with a lower fee so it doesn't replace the tx already in the pool.
 		{
-			let tx = test_transaction_spending_coinbase(&keychain, &header, vec![501]);
+			let tx = test_transaction_spending_coinbase(&keychain, &header, vec![59_999_000_000]);
 			let mut write_pool = pool.write();
 			assert!(write_pool
 				.add_to_pool(test_source(), tx, false, &header)
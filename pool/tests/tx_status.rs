@@ -0,0 +1,201 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::{PoolAdapter, PoolConflict, PoolError, TxSource, TxStatus};
+use self::util::secp::pedersen::Commitment;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+// Pool adapter recording the tx status changes notified.
+#[derive(Default)]
+struct StatusAdapter {
+	changes: RwLock<Vec<(Commitment, TxStatus)>>,
+}
+
+impl StatusAdapter {
+	fn changes_of(&self, tx: &Transaction) -> Vec<TxStatus> {
+		self.changes
+			.read()
+			.iter()
+			.filter(|(k, _)| *k == excess(tx))
+			.map(|(_, v)| *v)
+			.collect()
+	}
+}
+
+impl PoolAdapter for StatusAdapter {
+	fn tx_accepted(&self, _tx: &Transaction) {}
+	fn stem_tx_accepted(&self, _src: &TxSource, _tx: &Transaction) -> Result<(), PoolError> {
+		Ok(())
+	}
+	fn tx_status_changed(&self, excess: &Commitment, status: &TxStatus) {
+		self.changes.write().push((*excess, *status));
+	}
+	fn tx_conflict_detected(&self, _conflict: &PoolConflict) {}
+}
+
+fn excess(tx: &Transaction) -> Commitment {
+	tx.kernels()[0].excess()
+}
+
+/// Test txs are tracked through the pool to their confirmation, eviction,
+/// replacement or reorg, with each change of status notified.
+#[test]
+fn test_tx_status() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".grin_tx_status".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		let initial_tx =
+			test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700, 800, 900]);
+
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let fork_header = block.header.clone();
+		let header = block.header;
+
+		let adapter = Arc::new(StatusAdapter::default());
+		let mut pool = test_setup(Arc::new(chain.clone()), verifier_cache);
+		pool.adapter = adapter.clone();
+
+		let tx_a = test_transaction(&keychain, vec![500], vec![400]);
+		let tx_b = test_transaction(&keychain, vec![600], vec![550]);
+		let tx_c = test_transaction(&keychain, vec![700], vec![690]);
+		let tx_c2 = test_transaction(&keychain, vec![700], vec![650]);
+		let tx_d = test_transaction(&keychain, vec![800], vec![590]);
+		let tx_e = test_transaction(&keychain, vec![900], vec![880]);
+
+		let status = |pool: &pool::TransactionPool, tx: &Transaction| {
+			pool.tx_tracker.status(&excess(tx)).map(|x| x.status)
+		};
+
+		// Stem and fluff txs, tx_c2 replacing tx_c by paying more.
+		pool.add_to_pool(test_source(), tx_a.clone(), false, &header)
+			.unwrap();
+		pool.add_to_pool(test_source(), tx_b.clone(), true, &header)
+			.unwrap();
+		pool.add_to_pool(test_source(), tx_c.clone(), false, &header)
+			.unwrap();
+		pool.add_to_pool(test_source(), tx_e.clone(), false, &header)
+			.unwrap();
+		assert_eq!(status(&pool, &tx_c), Some(TxStatus::Fluffed));
+		pool.add_to_pool(test_source(), tx_c2.clone(), false, &header)
+			.unwrap();
+		assert_eq!(status(&pool, &tx_a), Some(TxStatus::Fluffed));
+		assert_eq!(status(&pool, &tx_b), Some(TxStatus::Stem));
+		assert_eq!(status(&pool, &tx_c), Some(TxStatus::Conflicted));
+		assert_eq!(status(&pool, &tx_c2), Some(TxStatus::Fluffed));
+		assert_eq!(status(&pool, &tx_d), None);
+
+		// Building a block template selects the txpool txs only.
+		assert_eq!(pool.prepare_mineable_transactions().unwrap().len(), 3);
+		assert_eq!(status(&pool, &tx_a), Some(TxStatus::InCandidateBlock));
+		assert_eq!(status(&pool, &tx_b), Some(TxStatus::Stem));
+		assert_eq!(status(&pool, &tx_e), Some(TxStatus::InCandidateBlock));
+
+		// tx_a gets confirmed.
+		let block = add_block(header, vec![tx_a.clone()], &mut chain);
+		let confirmed_hash = block.hash();
+		let header = block.header.clone();
+		pool.reconcile_block(&block).unwrap();
+		let confirmed = TxStatus::Confirmed {
+			height: 3,
+			block: confirmed_hash,
+		};
+		assert_eq!(status(&pool, &tx_a), Some(confirmed));
+		assert_eq!(status(&pool, &tx_c2), Some(TxStatus::InCandidateBlock));
+
+		// A full pool evicts the lowest paying txs to make room for tx_d.
+		pool.config.max_pool_size = 1;
+		pool.add_to_pool(test_source(), tx_d.clone(), false, &header)
+			.unwrap();
+		assert_eq!(pool.total_size(), 1);
+		assert_eq!(status(&pool, &tx_c2), Some(TxStatus::Evicted));
+		assert_eq!(status(&pool, &tx_e), Some(TxStatus::Evicted));
+		assert_eq!(status(&pool, &tx_d), Some(TxStatus::Fluffed));
+		assert_eq!(status(&pool, &tx_b), Some(TxStatus::Stem));
+
+		// Still confirmed as the chain grows.
+		let block = add_block(header, vec![], &mut chain);
+		pool.reconcile_block(&block).unwrap();
+		assert_eq!(status(&pool, &tx_a), Some(confirmed));
+
+		// Until a longer fork without it replaces the block confirming it.
+		let block = add_block(fork_header, vec![], &mut chain);
+		let block = add_block(block.header, vec![], &mut chain);
+		let block = add_block(block.header, vec![], &mut chain);
+		pool.reconcile_block(&block).unwrap();
+		assert_eq!(status(&pool, &tx_a), Some(TxStatus::ReorgedOut));
+
+		assert_eq!(
+			adapter.changes_of(&tx_a),
+			vec![
+				TxStatus::Fluffed,
+				TxStatus::InCandidateBlock,
+				confirmed,
+				TxStatus::ReorgedOut
+			]
+		);
+		assert_eq!(
+			adapter.changes_of(&tx_c),
+			vec![TxStatus::Fluffed, TxStatus::Conflicted]
+		);
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -15,6 +15,9 @@
 //! Modules common to all Grin server types
 
 pub mod adapters;
+pub mod header_skeleton;
+pub mod hooks;
 pub mod stats;
+pub mod sync_progress;
+pub mod txhashset_segments;
 pub mod types;
-pub mod hooks;
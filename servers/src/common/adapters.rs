@@ -15,29 +15,35 @@
 //! Adapters connecting new block, new transaction, and accepted transaction
 //! events to consumers of those events.
 
-use crate::util::RwLock;
+use crate::util::{Mutex, RwLock};
+use std::cmp;
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 use std::sync::{Arc, Weak};
 use std::thread;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
+use crate::chain::txhashset::{Desegmenter, Segmenter};
 use crate::chain::{self, BlockStatus, ChainAdapter, Options};
+use crate::common::header_skeleton::{HeaderSkeleton, RangeReceived};
 use crate::common::hooks::{ChainEvents, NetEvents};
 use crate::common::types::{
 	self, ChainValidationMode, DandelionEpoch, ServerConfig, SyncState, SyncStatus,
 };
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
 use crate::core::core::transaction::Transaction;
 use crate::core::core::verifier_cache::VerifierCache;
-use crate::core::core::{BlockHeader, BlockSums, CompactBlock};
+use crate::core::core::{BlockHeader, BlockSums, CompactBlock, OutputIdentifier, TxKernelEntry};
 use crate::core::pow::Difficulty;
 use crate::core::{core, global};
 use crate::p2p;
-use crate::p2p::types::PeerInfo;
+use crate::p2p::msg::{TxHashSetManifest, TxHashSetSegment};
+use crate::p2p::types::{PeerInfo, ReasonForBan};
 use crate::pool;
 use crate::pool::types::DandelionConfig;
+use crate::util::secp::pedersen::{Commitment, RangeProof};
 use crate::util::OneTime;
 use chrono::prelude::*;
 use chrono::Duration;
@@ -53,7 +59,10 @@ pub struct NetToChainAdapter {
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	peers: OneTime<Weak<p2p::Peers>>,
 	config: ServerConfig,
-	hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+	hooks: Arc<Vec<Box<dyn NetEvents + Send + Sync>>>,
+	// manifest of the last txhashset archive we served, along with the time
+	// that archive was last modified
+	txhashset_manifest: Mutex<Option<(TxHashSetManifest, SystemTime)>>,
 }
 
 impl p2p::ChainAdapter for NetToChainAdapter {
@@ -91,6 +100,7 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// nothing much we can do with a new transaction while syncing
 		if self.sync_state.is_syncing() {
@@ -99,12 +109,12 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 		let source = pool::TxSource {
 			debug_name: "p2p".to_string(),
-			identifier: "?.?.?.?".to_string(),
+			identifier: peer_info.addr.as_key(),
 		};
 
 		let header = self.chain().head_header()?;
 
-		for hook in &self.hooks {
+		for hook in self.hooks.iter() {
 			hook.on_transaction_received(&tx);
 		}
 
@@ -160,7 +170,7 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			match core::Block::hydrate_from(cb, vec![]) {
 				Ok(block) => {
 					if !self.sync_state.is_syncing() {
-						for hook in &self.hooks {
+						for hook in self.hooks.iter() {
 							hook.on_block_received(&block, &peer_info.addr);
 						}
 					}
@@ -201,7 +211,7 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			let block = match core::Block::hydrate_from(cb.clone(), txs) {
 				Ok(block) => {
 					if !self.sync_state.is_syncing() {
-						for hook in &self.hooks {
+						for hook in self.hooks.iter() {
 							hook.on_block_received(&block, &peer_info.addr);
 						}
 					}
@@ -288,6 +298,19 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			return Ok(false);
 		}
 
+		// ranges of the header skeleton we're syncing against are assembled
+		// in order before being added to our header chain
+		if let Some(skeleton) = self.sync_state.header_skeleton() {
+			match skeleton.headers_received(bhs, peer_info.addr) {
+				RangeReceived::Accepted => {
+					skeleton.apply(&self.chain(), &self.peers());
+					return Ok(true);
+				}
+				RangeReceived::Rejected => return Ok(false),
+				RangeReceived::Ignored => {}
+			}
+		}
+
 		// try to add headers to our header chain
 		match self.chain().sync_block_headers(bhs, self.chain_opts(true)) {
 			Ok(_) => Ok(true),
@@ -336,6 +359,68 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		Ok(headers)
 	}
 
+	fn locate_skeleton(
+		&self,
+		hash: Hash,
+		count: u16,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		let header = match self.find_common_header(&[hash]) {
+			Some(header) => header,
+			None => return Ok(vec![]),
+		};
+
+		let max_height = self.chain().header_head()?.height;
+
+		let txhashset = self.chain().txhashset();
+		let txhashset = txhashset.read();
+
+		let count = cmp::min(count as u32, p2p::MAX_SKELETON_HEADERS) as u64;
+		let mut headers = vec![];
+		for i in 1..=count {
+			let h = header.height + i * p2p::MAX_BLOCK_HEADERS as u64;
+			if h > max_height {
+				break;
+			}
+			if let Ok(header) = txhashset.get_header_by_height(h) {
+				headers.push(header);
+			} else {
+				error!("Failed to locate skeleton headers successfully.");
+				break;
+			}
+		}
+		Ok(headers)
+	}
+
+	fn skeleton_received(
+		&self,
+		headers: &[core::BlockHeader],
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		info!(
+			"Received header skeleton of {} headers from {}",
+			headers.len(),
+			peer_info.addr
+		);
+
+		// ignore skeletons we didn't ask for, or gave up on
+		let request = match self.sync_state.skeleton_request() {
+			Some(request) if request.peer == peer_info.addr => request,
+			_ => return Ok(true),
+		};
+		self.sync_state.set_skeleton_request(None);
+
+		match HeaderSkeleton::new(&request, headers.to_vec(), &self.chain()) {
+			Some(skeleton) => {
+				if !skeleton.is_done() {
+					self.sync_state
+						.set_header_skeleton(Some(Arc::new(skeleton)));
+				}
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+
 	/// Gets a full block by its hash.
 	fn get_block(&self, h: Hash) -> Option<core::Block> {
 		let b = self.chain().get_block(&h);
@@ -372,6 +457,37 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn txhashset_manifest(&self, h: Hash) -> Option<TxHashSetManifest> {
+		// hold the lock while building so concurrent requests for the same
+		// archive wait for the manifest instead of hashing it again
+		let mut cached = self.txhashset_manifest.lock();
+		let mut txhashset = self.txhashset_read(h)?;
+		let modified = match txhashset.reader.metadata().and_then(|m| m.modified()) {
+			Ok(modified) => modified,
+			Err(e) => {
+				warn!("Couldn't read txhashset archive for block {}: {:?}", h, e);
+				return None;
+			}
+		};
+		if let Some((manifest, at)) = cached.as_ref() {
+			if manifest.hash == h && *at == modified {
+				return Some(manifest.clone());
+			}
+		}
+
+		let height = self.chain().get_block_header(&h).ok()?.height;
+		match TxHashSetManifest::from_reader(h, height, &mut txhashset.reader) {
+			Ok(manifest) => {
+				*cached = Some((manifest.clone(), modified));
+				Some(manifest)
+			}
+			Err(e) => {
+				warn!("Couldn't build txhashset manifest for block {}: {:?}", h, e);
+				None
+			}
+		}
+	}
+
 	fn txhashset_receive_ready(&self) -> bool {
 		match self.sync_state.status() {
 			SyncStatus::TxHashsetDownload { .. } => true,
@@ -436,6 +552,141 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn txhashset_manifest_received(&self, manifest: TxHashSetManifest, peer_info: &PeerInfo) {
+		if let Some(segments) = self.sync_state.txhashset_segments() {
+			segments.manifest_received(manifest, peer_info.addr);
+		}
+	}
+
+	fn txhashset_segment_received(&self, segment: TxHashSetSegment, peer_info: &PeerInfo) -> bool {
+		match self.sync_state.txhashset_segments() {
+			Some(segments) => segments.segment_received(segment, peer_info.addr),
+			None => true,
+		}
+	}
+
+	fn get_output_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<OutputIdentifier>> {
+		self.segmenter(block_hash)
+			.and_then(|segmenter| segmenter.output_segment(identifier))
+			.map_err(|e| debug!("Couldn't provide output segment {:?}: {}", identifier, e))
+			.ok()
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.segmenter(block_hash)
+			.and_then(|segmenter| segmenter.rangeproof_segment(identifier))
+			.map_err(|e| {
+				debug!(
+					"Couldn't provide rangeproof segment {:?}: {}",
+					identifier, e
+				)
+			})
+			.ok()
+	}
+
+	fn get_kernel_segment(
+		&self,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Option<Segment<TxKernelEntry>> {
+		self.segmenter(block_hash)
+			.and_then(|segmenter| segmenter.kernel_segment(identifier))
+			.map_err(|e| debug!("Couldn't provide kernel segment {:?}: {}", identifier, e))
+			.ok()
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		// only checked against the leaf set of peers agreeing with ours
+		if !self.sync_state.is_leaf_set_peer(&peer_info.addr) {
+			return true;
+		}
+		self.receive_segment(block_hash, peer_info, |desegmenter| {
+			desegmenter.add_output_segment(segment)
+		})
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		if !self.sync_state.is_leaf_set_peer(&peer_info.addr) {
+			return true;
+		}
+		self.receive_segment(block_hash, peer_info, |desegmenter| {
+			desegmenter.add_rangeproof_segment(segment)
+		})
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernelEntry>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.receive_segment(block_hash, peer_info, |desegmenter| {
+			desegmenter.add_kernel_segment(segment)
+		})
+	}
+
+	fn get_output_bitmap(&self, block_hash: Hash) -> Option<Vec<u8>> {
+		self.segmenter(block_hash)
+			.map(|segmenter| segmenter.output_bitmap())
+			.map_err(|e| debug!("Couldn't provide output bitmap for {}: {}", block_hash, e))
+			.ok()
+	}
+
+	fn receive_output_bitmap(
+		&self,
+		block_hash: Hash,
+		bitmap: Vec<u8>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		let desegmenter = self.sync_state.desegmenter();
+		let mut desegmenter = desegmenter.write();
+		let res = match desegmenter.as_mut() {
+			Some(d) if d.header().hash() == block_hash => d.output_bitmap_received(&bitmap),
+			_ => return true,
+		};
+		match res {
+			Ok(true) => {
+				self.sync_state.add_leaf_set_peer(peer_info.addr);
+				true
+			}
+			Ok(false) => {
+				// either of us could be wrong, just don't use its segments
+				debug!(
+					"Output bitmap for {} from {} differs from ours",
+					block_hash, peer_info.addr
+				);
+				true
+			}
+			Err(e) => {
+				debug!(
+					"Invalid output bitmap for {} from {}: {}",
+					block_hash, peer_info.addr, e
+				);
+				self.peers()
+					.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+				false
+			}
+		}
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.chain().get_tmp_dir()
 	}
@@ -453,7 +704,7 @@ impl NetToChainAdapter {
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		config: ServerConfig,
-		hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+		hooks: Arc<Vec<Box<dyn NetEvents + Send + Sync>>>,
 	) -> NetToChainAdapter {
 		NetToChainAdapter {
 			sync_state,
@@ -463,6 +714,7 @@ impl NetToChainAdapter {
 			peers: OneTime::new(),
 			config,
 			hooks,
+			txhashset_manifest: Mutex::new(None),
 		}
 	}
 
@@ -485,6 +737,48 @@ impl NetToChainAdapter {
 			.expect("Failed to upgrade weak ref to our chain.")
 	}
 
+	// Only serve segments of a txhashset at least as recent as the one
+	// peers would get as an archive.
+	fn segmenter(&self, block_hash: Hash) -> Result<Segmenter, chain::Error> {
+		if self.sync_state.is_syncing() {
+			return Err(chain::ErrorKind::Other("still syncing".to_owned()).into());
+		}
+		self.chain().segmenter(block_hash)
+	}
+
+	// Hands a segment to the txhashset rebuild in progress. Invalid segments
+	// get the peer banned, anything else going wrong restarts the sync.
+	fn receive_segment<F>(&self, block_hash: Hash, peer_info: &PeerInfo, add: F) -> bool
+	where
+		F: FnOnce(&mut Desegmenter) -> Result<(), chain::Error>,
+	{
+		let desegmenter = self.sync_state.desegmenter();
+		let mut desegmenter = desegmenter.write();
+		let res = match desegmenter.as_mut() {
+			Some(d) if d.header().hash() == block_hash => add(d),
+			_ => return true,
+		};
+		match res {
+			Ok(_) => true,
+			Err(e) => {
+				if e.is_bad_data() {
+					debug!(
+						"Invalid segment for {} from {}: {}",
+						block_hash, peer_info.addr, e
+					);
+					self.peers()
+						.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+					false
+				} else {
+					error!("Failed to write segment for {}: {}", block_hash, e);
+					*desegmenter = None;
+					self.sync_state.set_sync_error(types::Error::Chain(e));
+					true
+				}
+			}
+		}
+	}
+
 	// Find the first locator hash that refers to a known header on our main chain.
 	fn find_common_header(&self, locator: &[Hash]) -> Option<BlockHeader> {
 		let txhashset = self.chain().txhashset();
@@ -696,14 +990,14 @@ impl NetToChainAdapter {
 pub struct ChainToPoolAndNetAdapter {
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: OneTime<Weak<p2p::Peers>>,
-	hooks: Vec<Box<dyn ChainEvents + Send + Sync>>,
+	hooks: Arc<Vec<Box<dyn ChainEvents + Send + Sync>>>,
 }
 
 impl ChainAdapter for ChainToPoolAndNetAdapter {
 	fn block_accepted(&self, b: &core::Block, status: BlockStatus, opts: Options) {
 		// not broadcasting blocks received through sync
 		if !opts.contains(chain::Options::SYNC) {
-			for hook in &self.hooks {
+			for hook in self.hooks.iter() {
 				hook.on_block_accepted(b, &status);
 			}
 			// If we mined the block then we want to broadcast the compact block.
@@ -747,7 +1041,7 @@ impl ChainToPoolAndNetAdapter {
 	/// Construct a ChainToPoolAndNetAdapter instance.
 	pub fn new(
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
-		hooks: Vec<Box<dyn ChainEvents + Send + Sync>>,
+		hooks: Arc<Vec<Box<dyn ChainEvents + Send + Sync>>>,
 	) -> ChainToPoolAndNetAdapter {
 		ChainToPoolAndNetAdapter {
 			tx_pool,
@@ -775,6 +1069,7 @@ impl ChainToPoolAndNetAdapter {
 pub struct PoolToNetAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
 	dandelion_epoch: Arc<RwLock<DandelionEpoch>>,
+	hooks: Arc<Vec<Box<dyn ChainEvents + Send + Sync>>>,
 }
 
 /// Adapter between the Dandelion monitor and the current Dandelion "epoch".
@@ -808,16 +1103,21 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 		self.peers().broadcast_transaction(tx);
 	}
 
-	fn stem_tx_accepted(&self, tx: &core::Transaction) -> Result<(), pool::PoolError> {
+	fn stem_tx_accepted(
+		&self,
+		src: &pool::TxSource,
+		tx: &core::Transaction,
+	) -> Result<(), pool::PoolError> {
 		// Take write lock on the current epoch.
 		// We need to be able to update the current relay peer if not currently connected.
 		let mut epoch = self.dandelion_epoch.write();
 
-		// If "stem" epoch attempt to relay the tx to the next Dandelion relay.
-		// Fallback to immediately fluffing the tx if we cannot stem for any reason.
+		// If "stem" epoch attempt to relay the tx to the next Dandelion relay
+		// for its source. Fallback to immediately fluffing the tx if we cannot
+		// stem for any reason.
 		// If "fluff" epoch then nothing to do right now (fluff via Dandelion monitor).
 		if epoch.is_stem() {
-			if let Some(peer) = epoch.relay_peer(&self.peers()) {
+			if let Some(peer) = epoch.relay_peer(&src.identifier, &self.peers()) {
 				match peer.send_stem_transaction(tx) {
 					Ok(_) => {
 						info!("Stemming this epoch, relaying to next peer.");
@@ -837,17 +1137,40 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 			Ok(())
 		}
 	}
+
+	fn tx_status_changed(&self, excess: &Commitment, status: &pool::TxStatus) {
+		for hook in self.hooks.iter() {
+			hook.on_tx_status_changed(excess, status);
+		}
+	}
+
+	fn tx_conflict_detected(&self, conflict: &pool::PoolConflict) {
+		for hook in self.hooks.iter() {
+			hook.on_tx_conflict(conflict);
+		}
+	}
 }
 
 impl PoolToNetAdapter {
 	/// Create a new pool to net adapter
-	pub fn new(config: DandelionConfig) -> PoolToNetAdapter {
+	pub fn new(
+		config: DandelionConfig,
+		hooks: Arc<Vec<Box<dyn ChainEvents + Send + Sync>>>,
+	) -> PoolToNetAdapter {
 		PoolToNetAdapter {
 			peers: OneTime::new(),
 			dandelion_epoch: Arc::new(RwLock::new(DandelionEpoch::new(config))),
+			hooks,
 		}
 	}
 
+	/// Relay the stem txs of the provided source currently go to.
+	pub fn relay_peer(&self, identifier: &str) -> Option<Arc<p2p::Peer>> {
+		self.dandelion_epoch
+			.write()
+			.relay_peer(identifier, &self.peers())
+	}
+
 	/// Setup the p2p server on the adapter
 	pub fn init(&self, peers: Arc<p2p::Peers>) {
 		self.peers.init(Arc::downgrade(&peers));
@@ -0,0 +1,453 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parallel header sync. Our most work peer sends us a skeleton of its header
+//! chain, every MAX_BLOCK_HEADERS-th header following our sync head. Once the
+//! proof of work of the skeleton headers is checked, the ranges in between
+//! are requested from several peers at once, each range checked to link both
+//! ends before being accepted. Ranges are applied to our header chain in
+//! order, as they become contiguous.
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::chain;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::{self, PeerAddr, ReasonForBan};
+use crate::util::{Mutex, RwLock};
+
+/// How many ranges past the next one to apply we request, bounding the
+/// number of headers we hold on to.
+const MAX_RANGES_AHEAD: usize = 32;
+
+/// How long we wait for a range before asking someone else.
+const RANGE_REQUEST_TIMEOUT_SECS: i64 = 30;
+
+/// How long we wait for a skeleton before giving up on it.
+const SKELETON_REQUEST_TIMEOUT_SECS: i64 = 10;
+
+/// How long we sync against a skeleton without applying any range.
+const SKELETON_STALL_SECS: i64 = 120;
+
+/// Skeleton we asked a peer for, following the provided header.
+#[derive(Clone, Debug)]
+pub struct SkeletonRequest {
+	pub peer: PeerAddr,
+	pub start_hash: Hash,
+	pub start_height: u64,
+	pub start_difficulty: Difficulty,
+	pub count: u16,
+	pub time: DateTime<Utc>,
+}
+
+impl SkeletonRequest {
+	/// Whether we've been waiting for the skeleton for too long.
+	pub fn is_expired(&self) -> bool {
+		Utc::now() - self.time > Duration::seconds(SKELETON_REQUEST_TIMEOUT_SECS)
+	}
+}
+
+/// What became of headers received while syncing against a skeleton.
+#[derive(Debug, PartialEq)]
+pub enum RangeReceived {
+	/// Not a response to one of our range requests.
+	Ignored,
+	/// Part of a range we requested.
+	Accepted,
+	/// Part of a range we requested but not linking with the rest of it, the
+	/// peer that sent it isn't asked again.
+	Rejected,
+}
+
+struct SkeletonState {
+	// range requests in flight, a single one per peer
+	requested: HashMap<usize, (PeerAddr, DateTime<Utc>)>,
+	// what we got so far of the range requested from a peer, as headers come
+	// in chunks
+	partial: HashMap<PeerAddr, Vec<BlockHeader>>,
+	// complete ranges waiting for the previous ones to be applied
+	received: BTreeMap<usize, (PeerAddr, Vec<BlockHeader>)>,
+	// peers that sent us a range of another chain or not linking up, not
+	// asked again
+	excluded: HashSet<PeerAddr>,
+	// next range to apply
+	next: usize,
+	last_progress: DateTime<Utc>,
+}
+
+/// Skeleton of the header chain of our most work peer, along with the state
+/// of the download of the ranges in between.
+pub struct HeaderSkeleton {
+	peer: PeerAddr,
+	start_hash: Hash,
+	start_height: u64,
+	headers: Vec<BlockHeader>,
+	state: RwLock<SkeletonState>,
+	apply_lock: Mutex<()>,
+}
+
+impl HeaderSkeleton {
+	/// Skeleton received in response to the provided request, None if it
+	/// isn't a valid one.
+	pub fn new(
+		request: &SkeletonRequest,
+		headers: Vec<BlockHeader>,
+		chain: &chain::Chain,
+	) -> Option<HeaderSkeleton> {
+		if headers.len() > request.count as usize {
+			return None;
+		}
+		let mut prev_difficulty = request.start_difficulty;
+		for (i, header) in headers.iter().enumerate() {
+			let height = request.start_height + (i as u64 + 1) * p2p::MAX_BLOCK_HEADERS as u64;
+			if header.height != height || header.total_difficulty() <= prev_difficulty {
+				return None;
+			}
+			if let Some(hash) = global::checkpoint(height) {
+				if hash != header.hash() {
+					debug!(
+						"header_skeleton: header {} at {} conflicts with checkpoint {}",
+						header.hash(),
+						height,
+						hash
+					);
+					return None;
+				}
+			}
+			if let Err(e) = chain.validate_header_pow(header) {
+				debug!(
+					"header_skeleton: invalid header {} at {}: {:?}",
+					header.hash(),
+					header.height,
+					e
+				);
+				return None;
+			}
+			prev_difficulty = header.total_difficulty();
+		}
+		Some(HeaderSkeleton {
+			peer: request.peer,
+			start_hash: request.start_hash,
+			start_height: request.start_height,
+			headers,
+			state: RwLock::new(SkeletonState {
+				requested: HashMap::new(),
+				partial: HashMap::new(),
+				received: BTreeMap::new(),
+				excluded: HashSet::new(),
+				next: 0,
+				last_progress: Utc::now(),
+			}),
+			apply_lock: Mutex::new(()),
+		})
+	}
+
+	/// The peer the skeleton comes from.
+	pub fn peer(&self) -> PeerAddr {
+		self.peer
+	}
+
+	/// Number of ranges applied so far and total number of ranges.
+	pub fn progress(&self) -> (usize, usize) {
+		(self.state.read().next, self.headers.len())
+	}
+
+	/// Whether all the ranges have been applied to our header chain.
+	pub fn is_done(&self) -> bool {
+		self.state.read().next >= self.headers.len()
+	}
+
+	/// Whether we haven't been able to apply any range in a while.
+	pub fn is_stalled(&self) -> bool {
+		Utc::now() - self.state.read().last_progress > Duration::seconds(SKELETON_STALL_SECS)
+	}
+
+	// Hash and height of the header the range follows.
+	fn range_start(&self, idx: usize) -> (Hash, u64) {
+		if idx == 0 {
+			(self.start_hash, self.start_height)
+		} else {
+			let header = &self.headers[idx - 1];
+			(header.hash(), header.height)
+		}
+	}
+
+	/// Assigns the ranges we still need to the provided candidate peers and
+	/// their heights. Returns the peers to ask along with the hash of the
+	/// header their range follows.
+	pub fn next_requests(&self, candidates: &[(PeerAddr, u64)]) -> Vec<(PeerAddr, Hash)> {
+		let now = Utc::now();
+		let mut state = self.state.write();
+
+		// forget timed out requests, along with what we got of them
+		let timeout = Duration::seconds(RANGE_REQUEST_TIMEOUT_SECS);
+		let expired = state
+			.requested
+			.iter()
+			.filter(|(_, (_, t))| now - *t > timeout)
+			.map(|(idx, (addr, _))| (*idx, *addr))
+			.collect::<Vec<_>>();
+		for (idx, addr) in expired {
+			state.requested.remove(&idx);
+			state.partial.remove(&addr);
+		}
+
+		let busy = state
+			.requested
+			.values()
+			.map(|(addr, _)| *addr)
+			.collect::<HashSet<_>>();
+		let mut free = candidates
+			.iter()
+			.filter(|(addr, _)| !busy.contains(addr) && !state.excluded.contains(addr))
+			.cloned()
+			.collect::<Vec<_>>();
+		// shortest peers first, they can only help with the first ranges
+		free.sort_by_key(|(_, height)| *height);
+		let mut free = free.into_iter();
+
+		let mut requests = vec![];
+		let end = std::cmp::min(self.headers.len(), state.next + MAX_RANGES_AHEAD);
+		for idx in state.next..end {
+			if state.requested.contains_key(&idx) || state.received.contains_key(&idx) {
+				continue;
+			}
+			// peers too short for this range are too short for the next ones
+			let height = self.headers[idx].height;
+			let addr = match free.find(|(_, h)| *h >= height) {
+				Some((addr, _)) => addr,
+				None => break,
+			};
+			state.requested.insert(idx, (addr, now));
+			state.partial.remove(&addr);
+			requests.push((addr, self.range_start(idx).0));
+		}
+		requests
+	}
+
+	/// Headers received from a peer, possibly a chunk of a range we asked it
+	/// for. Ranges linking both ends are queued to be applied, ranges of
+	/// another chain are dropped.
+	pub fn headers_received(&self, headers: &[BlockHeader], addr: PeerAddr) -> RangeReceived {
+		let mut state = self.state.write();
+		let idx = match state.requested.iter().find(|(_, (a, _))| *a == addr) {
+			Some((idx, _)) => *idx,
+			None => return RangeReceived::Ignored,
+		};
+
+		let (mut prev_hash, mut prev_height) = match state.partial.get(&addr).and_then(|p| p.last())
+		{
+			Some(last) => (last.hash(), last.height),
+			None => {
+				// the start of the response, which may not be to our
+				// range request
+				let start = self.range_start(idx);
+				match headers.first() {
+					Some(first) if first.prev_hash == start.0 => start,
+					_ => return RangeReceived::Ignored,
+				}
+			}
+		};
+		let end = &self.headers[idx];
+		for header in headers {
+			if header.prev_hash != prev_hash
+				|| header.height != prev_height + 1
+				|| header.height > end.height
+			{
+				state.requested.remove(&idx);
+				state.partial.remove(&addr);
+				state.excluded.insert(addr);
+				return RangeReceived::Rejected;
+			}
+			prev_hash = header.hash();
+			prev_height = header.height;
+		}
+
+		let mut range = state.partial.remove(&addr).unwrap_or_default();
+		range.extend_from_slice(headers);
+		if prev_height < end.height {
+			state.partial.insert(addr, range);
+			return RangeReceived::Accepted;
+		}
+		state.requested.remove(&idx);
+		if prev_hash == end.hash() {
+			state.received.insert(idx, (addr, range));
+		} else {
+			// a valid range of another chain, which the peer may well be on
+			debug!(
+				"header_skeleton: range {} from {} doesn't end at {}, not asking it again",
+				idx,
+				addr,
+				end.hash()
+			);
+			state.excluded.insert(addr);
+		}
+		RangeReceived::Accepted
+	}
+
+	/// Applies the ranges that are next in line to our header chain. A range
+	/// refused by the chain gets its sender banned if it's bad data, it's
+	/// requested again from someone else.
+	pub fn apply(&self, chain: &chain::Chain, peers: &p2p::Peers) {
+		// someone else is already applying ranges
+		let _guard = match self.apply_lock.try_lock() {
+			Some(guard) => guard,
+			None => return,
+		};
+		loop {
+			let (idx, addr, range) = {
+				let mut state = self.state.write();
+				let next = state.next;
+				match state.received.remove(&next) {
+					Some((addr, range)) => (next, addr, range),
+					None => return,
+				}
+			};
+			match chain.sync_block_headers(&range, chain::Options::SYNC) {
+				Ok(_) => {
+					let mut state = self.state.write();
+					state.next = idx + 1;
+					state.last_progress = Utc::now();
+				}
+				Err(e) => {
+					debug!(
+						"header_skeleton: range {} from {} refused by chain: {:?}",
+						idx, addr, e
+					);
+					if e.is_bad_data() {
+						peers.ban_peer(addr, ReasonForBan::BadBlockHeader);
+					}
+					return;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn header_chain(len: u64, timestamp: i64) -> Vec<BlockHeader> {
+		let mut headers = vec![BlockHeader::default()];
+		for height in 1..len {
+			let mut header = BlockHeader::default();
+			header.height = height;
+			header.prev_hash = headers.last().unwrap().hash();
+			header.timestamp = header.timestamp + Duration::seconds(timestamp + height as i64);
+			headers.push(header);
+		}
+		headers
+	}
+
+	fn skeleton(chain: &[BlockHeader]) -> HeaderSkeleton {
+		let range = p2p::MAX_BLOCK_HEADERS as usize;
+		HeaderSkeleton {
+			peer: addr(1),
+			start_hash: chain[0].hash(),
+			start_height: 0,
+			headers: chain.iter().skip(range).step_by(range).cloned().collect(),
+			state: RwLock::new(SkeletonState {
+				requested: HashMap::new(),
+				partial: HashMap::new(),
+				received: BTreeMap::new(),
+				excluded: HashSet::new(),
+				next: 0,
+				last_progress: Utc::now(),
+			}),
+			apply_lock: Mutex::new(()),
+		}
+	}
+
+	fn addr(n: u8) -> PeerAddr {
+		PeerAddr::Ip(format!("10.0.0.{}:3414", n).parse().unwrap())
+	}
+
+	#[test]
+	fn test_skeleton_requests() {
+		let chain = header_chain(3 * 512 + 1, 0);
+		let skeleton = skeleton(&chain);
+		assert_eq!(skeleton.progress(), (0, 3));
+
+		// the shortest peer able to serve a range gets it, one range per peer
+		let candidates = vec![(addr(2), 2000), (addr(3), 600), (addr(4), 100)];
+		let requests = skeleton.next_requests(&candidates);
+		assert_eq!(
+			requests,
+			vec![(addr(3), chain[0].hash()), (addr(2), chain[512].hash())]
+		);
+		assert!(skeleton.next_requests(&candidates).is_empty());
+	}
+
+	#[test]
+	fn test_skeleton_ranges_received() {
+		let chain = header_chain(3 * 512 + 1, 0);
+		let fork = header_chain(3 * 512 + 1, 1);
+		let skeleton = skeleton(&chain);
+		let candidates = vec![(addr(2), 2000), (addr(3), 2000), (addr(4), 2000)];
+		assert_eq!(skeleton.next_requests(&candidates).len(), 3);
+		let requested = skeleton.state.read().requested.clone();
+		let peer_for = |idx: usize| requested.get(&idx).unwrap().0;
+
+		// headers we didn't ask for, or not following the range start
+		assert_eq!(
+			skeleton.headers_received(&chain[1..33], addr(5)),
+			RangeReceived::Ignored
+		);
+		assert_eq!(
+			skeleton.headers_received(&chain[2..34], peer_for(0)),
+			RangeReceived::Ignored
+		);
+
+		// a range coming in chunks
+		for chunk in chain[1..513].chunks(32) {
+			assert_eq!(
+				skeleton.headers_received(chunk, peer_for(0)),
+				RangeReceived::Accepted
+			);
+		}
+		assert!(skeleton.state.read().received.contains_key(&0));
+
+		// a chunk not linking with the previous one
+		assert_eq!(
+			skeleton.headers_received(&chain[513..545], peer_for(1)),
+			RangeReceived::Accepted
+		);
+		assert_eq!(
+			skeleton.headers_received(&chain[546..578], peer_for(1)),
+			RangeReceived::Rejected
+		);
+		assert!(!skeleton.state.read().requested.contains_key(&1));
+		assert!(skeleton.state.read().excluded.contains(&peer_for(1)));
+		assert!(skeleton.next_requests(&[(peer_for(1), 2000)]).is_empty());
+
+		// a range of another chain, from the same start
+		let mut range = fork[1025..].to_vec();
+		range[0].prev_hash = chain[1024].hash();
+		for i in 1..range.len() {
+			range[i].prev_hash = range[i - 1].hash();
+		}
+		assert_eq!(
+			skeleton.headers_received(&range, peer_for(2)),
+			RangeReceived::Accepted
+		);
+		let state = skeleton.state.read();
+		assert!(!state.received.contains_key(&2));
+		assert!(state.excluded.contains(&peer_for(2)));
+	}
+}
@@ -19,11 +19,15 @@ extern crate hyper;
 extern crate hyper_rustls;
 extern crate tokio;
 
+use crate::api::PoolConflictPrintable;
 use crate::chain::BlockStatus;
-use crate::common::types::{ServerConfig, WebHooksConfig};
+use crate::common::types::{NetworkAlert, ServerConfig, WebHooksConfig};
 use crate::core::core;
 use crate::core::core::hash::Hashed;
 use crate::p2p::types::PeerAddr;
+use crate::pool::{PoolConflict, TxStatus};
+use crate::util;
+use crate::util::secp::pedersen::Commitment;
 use futures::future::Future;
 use hyper::client::HttpConnector;
 use hyper::header::HeaderValue;
@@ -42,6 +46,7 @@ pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + S
 	if config.webhook_config.block_received_url.is_some()
 		|| config.webhook_config.tx_received_url.is_some()
 		|| config.webhook_config.header_received_url.is_some()
+		|| config.webhook_config.network_alert_url.is_some()
 	{
 		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
 	}
@@ -52,7 +57,10 @@ pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + S
 pub fn init_chain_hooks(config: &ServerConfig) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
 	let mut list: Vec<Box<ChainEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
-	if config.webhook_config.block_accepted_url.is_some() {
+	if config.webhook_config.block_accepted_url.is_some()
+		|| config.webhook_config.tx_status_url.is_some()
+		|| config.webhook_config.tx_conflict_url.is_some()
+	{
 		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
 	}
 	list
@@ -69,6 +77,10 @@ pub trait NetEvents {
 
 	/// Triggers when a new block header arrives
 	fn on_header_received(&self, header: &core::BlockHeader, addr: &PeerAddr) {}
+
+	/// Triggers when we start suspecting we're isolated from the rest of the
+	/// network, once per kind of alert until it clears
+	fn on_network_alert(&self, alert: &NetworkAlert) {}
 }
 
 #[allow(unused_variables)]
@@ -76,6 +88,14 @@ pub trait NetEvents {
 pub trait ChainEvents {
 	/// Triggers when a new block is accepted by the chain (might be a Reorg or a Fork)
 	fn on_block_accepted(&self, block: &core::Block, status: &BlockStatus) {}
+
+	/// Triggers when a tx accepted by our pool gets to a new status (fluffed,
+	/// confirmed, evicted, ...), identified by its kernel excess
+	fn on_tx_status_changed(&self, excess: &Commitment, status: &TxStatus) {}
+
+	/// Triggers when a tx spending the same outputs as txs in our pool comes
+	/// in, whether it replaced them or got rejected
+	fn on_tx_conflict(&self, conflict: &PoolConflict) {}
 }
 
 /// Basic Logger
@@ -112,6 +132,14 @@ impl NetEvents for EventLogger {
 			addr
 		);
 	}
+
+	fn on_network_alert(&self, alert: &NetworkAlert) {
+		warn!(
+			"Network alert ({}): {}, we may be partitioned or eclipsed.",
+			alert.kind(),
+			alert
+		);
+	}
 }
 
 impl ChainEvents for EventLogger {
@@ -144,6 +172,25 @@ impl ChainEvents for EventLogger {
 			}
 		}
 	}
+
+	fn on_tx_status_changed(&self, excess: &Commitment, status: &TxStatus) {
+		debug!(
+			"tx_status_changed: {} now {:?}",
+			util::to_hex(excess.0.to_vec()),
+			status
+		);
+	}
+
+	fn on_tx_conflict(&self, conflict: &PoolConflict) {
+		info!(
+			"tx_conflict: {} from {} spends {} outputs of {} pool txs (replaced: {})",
+			conflict.tx,
+			conflict.src.debug_name,
+			conflict.inputs.len(),
+			conflict.conflicting_txs.len(),
+			conflict.replaced
+		);
+	}
 }
 
 fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
@@ -176,6 +223,12 @@ struct WebHook {
 	block_received_url: Option<hyper::Uri>,
 	/// url to POST block data when a new block is accepted by our node (might be a reorg or a fork)
 	block_accepted_url: Option<hyper::Uri>,
+	/// url to POST alerts when we may be isolated from the rest of the network
+	network_alert_url: Option<hyper::Uri>,
+	/// url to POST status changes of the txs accepted by our pool
+	tx_status_url: Option<hyper::Uri>,
+	/// url to POST the txs received conflicting with txs in our pool
+	tx_conflict_url: Option<hyper::Uri>,
 	/// The hyper client to be used for all requests
 	client: Client<HttpsConnector<HttpConnector>>,
 	/// The tokio event loop
@@ -189,6 +242,9 @@ impl WebHook {
 		header_received_url: Option<hyper::Uri>,
 		block_received_url: Option<hyper::Uri>,
 		block_accepted_url: Option<hyper::Uri>,
+		network_alert_url: Option<hyper::Uri>,
+		tx_status_url: Option<hyper::Uri>,
+		tx_conflict_url: Option<hyper::Uri>,
 		nthreads: u16,
 		timeout: u16,
 	) -> WebHook {
@@ -209,6 +265,9 @@ impl WebHook {
 			block_received_url,
 			header_received_url,
 			block_accepted_url,
+			network_alert_url,
+			tx_status_url,
+			tx_conflict_url,
 			client,
 			runtime: Runtime::new().unwrap(),
 		}
@@ -221,6 +280,9 @@ impl WebHook {
 			parse_url(&config.header_received_url),
 			parse_url(&config.block_received_url),
 			parse_url(&config.block_accepted_url),
+			parse_url(&config.network_alert_url),
+			parse_url(&config.tx_status_url),
+			parse_url(&config.tx_conflict_url),
 			config.nthreads,
 			config.timeout,
 		)
@@ -293,6 +355,25 @@ impl ChainEvents for WebHook {
 			);
 		}
 	}
+
+	/// Triggers when a tx accepted by our pool changes status
+	fn on_tx_status_changed(&self, excess: &Commitment, status: &TxStatus) {
+		let payload = json!({
+			"excess": util::to_hex(excess.0.to_vec()),
+			"status": status
+		});
+		if !self.make_request(&payload, &self.tx_status_url) {
+			error!("Failed to serialize status {:?} of tx {:?}", status, excess);
+		}
+	}
+
+	/// Triggers when a tx conflicting with txs in our pool comes in
+	fn on_tx_conflict(&self, conflict: &PoolConflict) {
+		let payload = PoolConflictPrintable::from_conflict(conflict);
+		if !self.make_request(&payload, &self.tx_conflict_url) {
+			error!("Failed to serialize conflict of tx {}", conflict.tx);
+		}
+	}
 }
 
 impl NetEvents for WebHook {
@@ -338,4 +419,16 @@ impl NetEvents for WebHook {
 			);
 		}
 	}
+
+	/// Triggers when we may be isolated from the rest of the network
+	fn on_network_alert(&self, alert: &NetworkAlert) {
+		let payload = json!({
+			"kind": alert.kind(),
+			"message": alert.to_string(),
+			"data": alert
+		});
+		if !self.make_request(&payload, &self.network_alert_url) {
+			error!("Failed to serialize network alert {:?}", alert);
+		}
+	}
 }
@@ -25,7 +25,8 @@ use crate::core::core::hash::Hash;
 use chrono::prelude::*;
 
 use crate::chain;
-use crate::common::types::SyncStatus;
+use crate::common::sync_progress::StageMetrics;
+use crate::common::types::{NetworkAlert, SyncStatus};
 use crate::p2p;
 
 /// Server state info collection struct, to be passed around into internals
@@ -34,12 +35,15 @@ use crate::p2p;
 pub struct ServerStateInfo {
 	/// Stratum stats
 	pub stratum_stats: Arc<RwLock<StratumStats>>,
+	/// Alerts currently raised by the network partition detector
+	pub network_alerts: Arc<RwLock<Vec<NetworkAlert>>>,
 }
 
 impl Default for ServerStateInfo {
 	fn default() -> ServerStateInfo {
 		ServerStateInfo {
 			stratum_stats: Arc::new(RwLock::new(StratumStats::default())),
+			network_alerts: Arc::new(RwLock::new(vec![])),
 		}
 	}
 }
@@ -55,12 +59,16 @@ pub struct ServerStats {
 	pub header_head: chain::Tip,
 	/// Whether we're currently syncing
 	pub sync_status: SyncStatus,
+	/// Progress of each stage of the current (or last) sync
+	pub sync_stages: Vec<StageMetrics>,
 	/// Handle to current stratum server stats
 	pub stratum_stats: StratumStats,
 	/// Peer stats
 	pub peer_stats: Vec<PeerStats>,
 	/// Difficulty calculation statistics
 	pub diff_stats: DiffStats,
+	/// Signs we may be partitioned or eclipsed
+	pub network_alerts: Vec<NetworkAlert>,
 }
 
 /// Struct to return relevant information about stratum workers
@@ -0,0 +1,367 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks the progress of each sync stage over time, to derive processing
+//! rates and an estimate of the time left from the coarse `SyncStatus`
+//! updates.
+
+use std::cmp;
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+
+use crate::api;
+use crate::common::types::SyncStatus;
+
+/// The stages a sync goes through, in order. State sync ones are skipped
+/// when syncing an archive node or when close enough to the chain head.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum SyncStage {
+	/// Downloading block headers
+	HeaderSync,
+	/// Downloading the txhashset, as a zip or as MMR segments
+	TxHashsetDownload,
+	/// Validating kernels and rangeproofs of the downloaded txhashset
+	TxHashsetValidation,
+	/// Downloading full blocks
+	BodySync,
+}
+
+impl SyncStage {
+	/// Name of the stage, as exposed by the API
+	pub fn name(&self) -> &'static str {
+		match self {
+			SyncStage::HeaderSync => "header_sync",
+			SyncStage::TxHashsetDownload => "txhashset_download",
+			SyncStage::TxHashsetValidation => "txhashset_validation",
+			SyncStage::BodySync => "body_sync",
+		}
+	}
+}
+
+/// Name of a sync status, as exposed by the API
+pub fn status_name(status: &SyncStatus) -> &'static str {
+	match status {
+		SyncStatus::Initial => "initial",
+		SyncStatus::NoSync => "no_sync",
+		SyncStatus::AwaitingPeers(_) => "awaiting_peers",
+		SyncStatus::HeaderSync { .. } => "header_sync",
+		SyncStatus::TxHashsetDownload { .. } => "txhashset_download",
+		SyncStatus::TxHashsetSetup => "txhashset_setup",
+		SyncStatus::TxHashsetValidation { .. } => "txhashset_validation",
+		SyncStatus::TxHashsetSave => "txhashset_save",
+		SyncStatus::TxHashsetDone => "txhashset_done",
+		SyncStatus::BodySync { .. } => "body_sync",
+		SyncStatus::Shutdown => "shutdown",
+	}
+}
+
+/// Stage, unit, processed and total units of a sync status, if it's one
+/// reporting progress.
+fn progress(
+	status: &SyncStatus,
+	download_unit: &'static str,
+) -> Option<(SyncStage, &'static str, u64, u64)> {
+	match *status {
+		SyncStatus::HeaderSync {
+			current_height,
+			highest_height,
+		} => Some((
+			SyncStage::HeaderSync,
+			"headers",
+			current_height,
+			highest_height,
+		)),
+		SyncStatus::TxHashsetDownload {
+			downloaded_size,
+			total_size,
+			..
+		} => Some((
+			SyncStage::TxHashsetDownload,
+			download_unit,
+			downloaded_size,
+			total_size,
+		)),
+		SyncStatus::TxHashsetValidation {
+			kernels,
+			kernel_total,
+			rproofs,
+			rproof_total,
+		} => Some((
+			SyncStage::TxHashsetValidation,
+			"items",
+			kernels + rproofs,
+			kernel_total + rproof_total,
+		)),
+		SyncStatus::BodySync {
+			current_height,
+			highest_height,
+		} => Some((
+			SyncStage::BodySync,
+			"blocks",
+			current_height,
+			highest_height,
+		)),
+		_ => None,
+	}
+}
+
+/// Progress of a single sync stage.
+#[derive(Debug, Clone)]
+pub struct StageMetrics {
+	/// The stage
+	pub stage: SyncStage,
+	/// What's being processed (headers, bytes, segments, items or blocks)
+	pub unit: &'static str,
+	/// When we entered the stage
+	pub start_time: DateTime<Utc>,
+	/// Last progress update
+	pub update_time: DateTime<Utc>,
+	/// When we left the stage, none while it's running
+	pub end_time: Option<DateTime<Utc>>,
+	/// Units already processed when we entered the stage, the rate only
+	/// accounts for what was processed since
+	start_processed: u64,
+	/// Units processed so far
+	pub processed: u64,
+	/// Total units to process, 0 when not known yet
+	pub total: u64,
+}
+
+impl StageMetrics {
+	fn new(
+		stage: SyncStage,
+		unit: &'static str,
+		processed: u64,
+		total: u64,
+		now: DateTime<Utc>,
+	) -> StageMetrics {
+		StageMetrics {
+			stage,
+			unit,
+			start_time: now,
+			update_time: now,
+			end_time: None,
+			start_processed: processed,
+			processed,
+			total,
+		}
+	}
+
+	/// Whether we're still in this stage
+	pub fn is_running(&self) -> bool {
+		self.end_time.is_none()
+	}
+
+	/// Average units processed per second since the stage started
+	pub fn rate(&self) -> f64 {
+		let elapsed = (self.update_time - self.start_time).num_milliseconds();
+		if elapsed <= 0 {
+			return 0.0;
+		}
+		self.processed.saturating_sub(self.start_processed) as f64 * 1000.0 / elapsed as f64
+	}
+
+	/// Percentage of the stage done
+	pub fn percent(&self) -> u64 {
+		if self.total == 0 {
+			return 0;
+		}
+		cmp::min(self.processed as u128 * 100 / self.total as u128, 100) as u64
+	}
+
+	/// Estimated time left to complete the stage at the current rate, none
+	/// if the stage is over or we can't tell yet
+	pub fn eta(&self) -> Option<Duration> {
+		if !self.is_running() || self.total == 0 {
+			return None;
+		}
+		let rate = self.rate();
+		if rate <= 0.0 {
+			return None;
+		}
+		let left = self.total.saturating_sub(self.processed) as f64;
+		Some(Duration::seconds((left / rate).ceil() as i64))
+	}
+}
+
+impl From<&StageMetrics> for api::SyncStageProgress {
+	fn from(metrics: &StageMetrics) -> api::SyncStageProgress {
+		api::SyncStageProgress {
+			stage: metrics.stage.name().to_owned(),
+			unit: metrics.unit.to_owned(),
+			start_time: metrics.start_time.timestamp(),
+			end_time: metrics.end_time.map(|t| t.timestamp()),
+			processed: metrics.processed,
+			total: metrics.total,
+			percent: metrics.percent(),
+			rate: metrics.rate(),
+			eta_secs: metrics.eta().map(|d| d.num_seconds()),
+		}
+	}
+}
+
+/// Progress of all the stages of the current (or last) sync.
+#[derive(Debug, Default)]
+pub struct SyncMetrics {
+	stages: Vec<StageMetrics>,
+	finished: bool,
+}
+
+impl SyncMetrics {
+	/// Records a sync status update. Entering a stage ends the one that was
+	/// running, leaving sync altogether ends it too and the next sync starts
+	/// from a clean slate. Going back to a stage picks up where it left,
+	/// unless its progress went backwards (e.g. restarted download).
+	pub fn record(&mut self, status: &SyncStatus, download_unit: &'static str, now: DateTime<Utc>) {
+		let (stage, unit, processed, total) = match progress(status, download_unit) {
+			Some(p) => p,
+			None => {
+				if *status == SyncStatus::NoSync {
+					self.finished = true;
+				}
+				self.end_running(None, now);
+				return;
+			}
+		};
+
+		if self.finished {
+			self.stages.clear();
+			self.finished = false;
+		}
+		self.end_running(Some(stage), now);
+
+		match self.stages.iter_mut().find(|s| s.stage == stage) {
+			Some(s) => {
+				if s.unit == unit && processed >= s.processed {
+					s.end_time = None;
+					s.update_time = now;
+					s.processed = processed;
+					s.total = total;
+				} else {
+					*s = StageMetrics::new(stage, unit, processed, total, now);
+				}
+			}
+			None => {
+				self.stages
+					.push(StageMetrics::new(stage, unit, processed, total, now));
+				self.stages.sort_by_key(|s| s.stage);
+			}
+		}
+	}
+
+	fn end_running(&mut self, except: Option<SyncStage>, now: DateTime<Utc>) {
+		for s in self.stages.iter_mut() {
+			if s.is_running() && Some(s.stage) != except {
+				s.end_time = Some(now);
+			}
+		}
+	}
+
+	/// Progress of the stages seen so far, in sync order
+	pub fn stages(&self) -> Vec<StageMetrics> {
+		self.stages.clone()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn header_sync(current_height: u64, highest_height: u64) -> SyncStatus {
+		SyncStatus::HeaderSync {
+			current_height,
+			highest_height,
+		}
+	}
+
+	#[test]
+	fn stage_rate_and_eta() {
+		let t0 = Utc::now();
+		let mut metrics = SyncMetrics::default();
+		metrics.record(&header_sync(1_000, 11_000), "bytes", t0);
+		metrics.record(
+			&header_sync(3_000, 11_000),
+			"bytes",
+			t0 + Duration::seconds(10),
+		);
+
+		let stages = metrics.stages();
+		assert_eq!(stages.len(), 1);
+		let s = &stages[0];
+		assert_eq!(s.stage, SyncStage::HeaderSync);
+		assert_eq!(s.unit, "headers");
+		assert_eq!(s.rate(), 200.0);
+		assert_eq!(s.percent(), 27);
+		assert_eq!(s.eta(), Some(Duration::seconds(40)));
+	}
+
+	#[test]
+	fn stage_transitions() {
+		let t0 = Utc::now();
+		let mut metrics = SyncMetrics::default();
+		metrics.record(&header_sync(0, 100), "bytes", t0);
+		metrics.record(
+			&SyncStatus::TxHashsetDownload {
+				start_time: t0,
+				prev_update_time: t0,
+				update_time: t0,
+				prev_downloaded_size: 0,
+				downloaded_size: 5,
+				total_size: 20,
+			},
+			"segments",
+			t0 + Duration::seconds(1),
+		);
+		metrics.record(
+			&SyncStatus::TxHashsetSetup,
+			"bytes",
+			t0 + Duration::seconds(2),
+		);
+
+		let stages = metrics.stages();
+		assert_eq!(stages.len(), 2);
+		assert_eq!(stages[0].end_time, Some(t0 + Duration::seconds(1)));
+		assert_eq!(stages[1].stage, SyncStage::TxHashsetDownload);
+		assert_eq!(stages[1].unit, "segments");
+		assert_eq!(stages[1].end_time, Some(t0 + Duration::seconds(2)));
+		assert_eq!(stages[1].eta(), None);
+
+		// back to header sync, resumed
+		metrics.record(&header_sync(50, 100), "bytes", t0 + Duration::seconds(5));
+		let stages = metrics.stages();
+		assert!(stages[0].is_running());
+		assert_eq!(stages[0].start_time, t0);
+		assert_eq!(stages[0].rate(), 10.0);
+
+		// progress going backwards restarts the stage
+		metrics.record(&header_sync(10, 100), "bytes", t0 + Duration::seconds(6));
+		assert_eq!(metrics.stages()[0].start_time, t0 + Duration::seconds(6));
+
+		// the next sync after we're done starts over
+		metrics.record(&SyncStatus::NoSync, "bytes", t0 + Duration::seconds(7));
+		assert!(metrics.stages().iter().all(|s| !s.is_running()));
+		metrics.record(
+			&SyncStatus::BodySync {
+				current_height: 100,
+				highest_height: 110,
+			},
+			"bytes",
+			t0 + Duration::seconds(8),
+		);
+		let stages = metrics.stages();
+		assert_eq!(stages.len(), 1);
+		assert_eq!(stages[0].stage, SyncStage::BodySync);
+	}
+}
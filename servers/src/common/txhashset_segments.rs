@@ -0,0 +1,346 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chunked txhashset download. The archive is split in fixed size segments
+//! listed in a manifest, segments are requested in parallel from all the
+//! peers serving a matching manifest, verified against it and saved in the
+//! tmp dir as they come in, so an interrupted download can be resumed after
+//! a restart.
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::core::core::hash::Hash;
+use crate::core::ser;
+use crate::p2p::msg::{TxHashSetManifest, TxHashSetSegment};
+use crate::p2p::PeerAddr;
+use crate::util::RwLock;
+
+const SEGMENTS_DIR: &str = "txhashset_segments";
+const TARGET_FILE: &str = "target";
+const MANIFEST_FILE: &str = "manifest";
+const ARCHIVE_FILE: &str = "txhashset.zip";
+
+/// Maximum number of segment requests in flight with a single peer.
+const MAX_IN_FLIGHT_PER_PEER: usize = 4;
+
+/// How long we wait for a segment before requesting it again, possibly from
+/// another peer.
+const SEGMENT_REQUEST_TIMEOUT_SECS: i64 = 60;
+
+#[derive(Default)]
+struct DownloadState {
+	manifest: Option<TxHashSetManifest>,
+	// peers that sent us a manifest matching ours
+	peers: HashSet<PeerAddr>,
+	received: HashSet<u64>,
+	requested: HashMap<u64, (PeerAddr, DateTime<Utc>)>,
+}
+
+/// Download state of the txhashset archive at a given block, backed by the
+/// segments saved so far in the tmp dir.
+pub struct TxHashSetSegments {
+	dir: PathBuf,
+	hash: Hash,
+	height: u64,
+	state: RwLock<DownloadState>,
+}
+
+impl TxHashSetSegments {
+	/// Block hash and height of the txhashset download interrupted by a
+	/// restart, if any.
+	pub fn interrupted(tmp_dir: &Path) -> Option<(Hash, u64)> {
+		let mut file = File::open(tmp_dir.join(SEGMENTS_DIR).join(TARGET_FILE)).ok()?;
+		ser::deserialize(&mut file).ok()
+	}
+
+	/// Opens the download of the txhashset at the provided block. Resumes
+	/// from what was saved in the tmp dir if it's for the same block, starts
+	/// afresh otherwise.
+	pub fn open(tmp_dir: &Path, hash: Hash, height: u64) -> Result<TxHashSetSegments, ser::Error> {
+		let dir = tmp_dir.join(SEGMENTS_DIR);
+		let mut state = DownloadState::default();
+		if TxHashSetSegments::interrupted(tmp_dir) == Some((hash, height)) {
+			state.manifest = File::open(dir.join(MANIFEST_FILE))
+				.ok()
+				.and_then(|mut file| ser::deserialize(&mut file).ok());
+		} else {
+			if dir.exists() {
+				fs::remove_dir_all(&dir)?;
+			}
+			fs::create_dir_all(&dir)?;
+			write_file(&dir.join(TARGET_FILE), &ser::ser_vec(&(hash, height))?)?;
+		}
+
+		let segments = TxHashSetSegments {
+			dir,
+			hash,
+			height,
+			state: RwLock::new(state),
+		};
+		segments.load_segments(&mut segments.state.write());
+		Ok(segments)
+	}
+
+	/// Hash of the block the txhashset is downloaded for.
+	pub fn hash(&self) -> Hash {
+		self.hash
+	}
+
+	/// Height of the block the txhashset is downloaded for.
+	pub fn height(&self) -> u64 {
+		self.height
+	}
+
+	/// A peer sent us its manifest. We download against the first one we get
+	/// and only use peers sending the exact same one. A different manifest
+	/// replaces ours when no peer serves ours anymore, keeping the saved
+	/// segments that still match.
+	pub fn manifest_received(&self, manifest: TxHashSetManifest, peer: PeerAddr) {
+		if manifest.hash != self.hash || manifest.height != self.height {
+			return;
+		}
+		let mut state = self.state.write();
+		let replace = match state.manifest {
+			Some(ref m) => *m != manifest && state.peers.is_empty(),
+			None => true,
+		};
+		if replace {
+			debug!(
+				"txhashset_segments: downloading {} segments ({} bytes) of {} against manifest from {}",
+				manifest.segment_count(),
+				manifest.total_size,
+				self.hash,
+				peer
+			);
+			match ser::ser_vec(&manifest) {
+				Ok(data) => {
+					if let Err(e) = write_file(&self.dir.join(MANIFEST_FILE), &data) {
+						warn!("txhashset_segments: failed to save manifest: {:?}", e);
+					}
+				}
+				Err(e) => warn!("txhashset_segments: failed to save manifest: {:?}", e),
+			}
+			state.manifest = Some(manifest.clone());
+			state.requested.clear();
+			self.load_segments(&mut state);
+		}
+		if state.manifest.as_ref() == Some(&manifest) {
+			state.peers.insert(peer);
+		}
+	}
+
+	/// A peer sent us a segment. Returns false if the segment doesn't match
+	/// the manifest the peer sent us, segments we did not ask for are simply
+	/// ignored.
+	pub fn segment_received(&self, segment: TxHashSetSegment, peer: PeerAddr) -> bool {
+		if segment.hash != self.hash {
+			return true;
+		}
+		let mut state = self.state.write();
+		if !state.peers.contains(&peer) {
+			return true;
+		}
+		let valid = match state.manifest {
+			Some(ref m) => m.verify_segment(segment.index, &segment.data),
+			None => false,
+		};
+		if !valid {
+			debug!(
+				"txhashset_segments: invalid segment {} from {}",
+				segment.index, peer
+			);
+			state.peers.remove(&peer);
+			state.requested.retain(|_, (p, _)| *p != peer);
+			return false;
+		}
+
+		state.requested.remove(&segment.index);
+		if state.received.contains(&segment.index) {
+			return true;
+		}
+		match write_file(&self.segment_path(segment.index), &segment.data) {
+			Ok(_) => {
+				state.received.insert(segment.index);
+			}
+			Err(e) => error!(
+				"txhashset_segments: failed to save segment {}: {:?}",
+				segment.index, e
+			),
+		}
+		true
+	}
+
+	/// Only keep downloading from the peers matching the provided filter,
+	/// typically the ones we're still connected to.
+	pub fn retain_peers<F>(&self, f: F)
+	where
+		F: Fn(&PeerAddr) -> bool,
+	{
+		let mut state = self.state.write();
+		state.peers.retain(|p| f(p));
+		let peers = state.peers.clone();
+		state.requested.retain(|_, (p, _)| peers.contains(p));
+	}
+
+	/// Peers serving a manifest matching ours.
+	pub fn manifest_peers(&self) -> Vec<PeerAddr> {
+		self.state.read().peers.iter().cloned().collect()
+	}
+
+	/// Picks the next segments to request and the peers to request them
+	/// from, spreading requests over the peers serving our manifest. Requests
+	/// that timed out get assigned again.
+	pub fn next_requests(&self) -> Vec<(PeerAddr, u64)> {
+		let now = Utc::now();
+		let mut state = self.state.write();
+		let state = &mut *state;
+		let count = match state.manifest {
+			Some(ref m) => m.segment_count(),
+			None => return vec![],
+		};
+
+		let timeout = Duration::seconds(SEGMENT_REQUEST_TIMEOUT_SECS);
+		state.requested.retain(|_, (_, t)| now - *t < timeout);
+		let mut in_flight: HashMap<PeerAddr, usize> = state.peers.iter().map(|p| (*p, 0)).collect();
+		for (p, _) in state.requested.values() {
+			if let Some(n) = in_flight.get_mut(p) {
+				*n += 1;
+			}
+		}
+
+		let mut requests = vec![];
+		for index in 0..count {
+			if state.received.contains(&index) || state.requested.contains_key(&index) {
+				continue;
+			}
+			let peer = in_flight
+				.iter()
+				.filter(|(_, n)| **n < MAX_IN_FLIGHT_PER_PEER)
+				.min_by_key(|(_, n)| **n)
+				.map(|(p, _)| *p);
+			let peer = match peer {
+				Some(p) => p,
+				None => break,
+			};
+			if let Some(n) = in_flight.get_mut(&peer) {
+				*n += 1;
+			}
+			state.requested.insert(index, (peer, now));
+			requests.push((peer, index));
+		}
+		requests
+	}
+
+	/// Number of bytes downloaded so far, along with the total archive size
+	/// (0 until we get a manifest).
+	pub fn downloaded_size(&self) -> (u64, u64) {
+		let state = self.state.read();
+		match state.manifest {
+			Some(ref m) => (
+				state.received.iter().map(|i| m.segment_len(*i)).sum(),
+				m.total_size,
+			),
+			None => (0, 0),
+		}
+	}
+
+	/// Whether all the segments listed in our manifest have been downloaded.
+	pub fn is_complete(&self) -> bool {
+		let state = self.state.read();
+		match state.manifest {
+			Some(ref m) => state.received.len() as u64 == m.segment_count(),
+			None => false,
+		}
+	}
+
+	/// Puts the downloaded segments back together into the txhashset
+	/// archive.
+	pub fn assemble(&self) -> Result<File, ser::Error> {
+		let state = self.state.read();
+		let count = state
+			.manifest
+			.as_ref()
+			.map(|m| m.segment_count())
+			.unwrap_or(0);
+		let path = self.dir.join(ARCHIVE_FILE);
+		{
+			let mut archive = File::create(&path)?;
+			for index in 0..count {
+				archive.write_all(&fs::read(self.segment_path(index))?)?;
+			}
+			archive.sync_all()?;
+		}
+		Ok(File::open(&path)?)
+	}
+
+	/// Removes everything saved for this download.
+	pub fn clean(&self) {
+		if let Err(e) = fs::remove_dir_all(&self.dir) {
+			warn!(
+				"txhashset_segments: failed to clean {:?}. err: {}",
+				self.dir, e
+			);
+		}
+	}
+
+	fn segment_path(&self, index: u64) -> PathBuf {
+		self.dir.join(format!("segment_{}", index))
+	}
+
+	// Checks the segments saved in the tmp dir against the current manifest,
+	// removing the ones that don't match.
+	fn load_segments(&self, state: &mut DownloadState) {
+		state.received.clear();
+		let manifest = match state.manifest {
+			Some(ref m) => m,
+			None => return,
+		};
+		for index in 0..manifest.segment_count() {
+			let path = self.segment_path(index);
+			if let Ok(data) = fs::read(&path) {
+				if manifest.verify_segment(index, &data) {
+					state.received.insert(index);
+				} else {
+					let _ = fs::remove_file(&path);
+				}
+			}
+		}
+		if !state.received.is_empty() {
+			debug!(
+				"txhashset_segments: resuming download of {} with {}/{} segments",
+				self.hash,
+				state.received.len(),
+				manifest.segment_count()
+			);
+		}
+	}
+}
+
+// Writes through a temporary file so a crash never leaves a partial file
+// behind.
+fn write_file(path: &Path, data: &[u8]) -> Result<(), ser::Error> {
+	let tmp_path = path.with_extension("tmp");
+	{
+		let mut file = File::create(&tmp_path)?;
+		file.write_all(data)?;
+		file.sync_all()?;
+	}
+	fs::rename(&tmp_path, path)?;
+	Ok(())
+}
@@ -13,7 +13,11 @@
 // limitations under the License.
 
 //! Server types
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::convert::From;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 use chrono::prelude::{DateTime, Utc};
@@ -21,6 +25,10 @@ use rand::prelude::*;
 
 use crate::api;
 use crate::chain;
+use crate::chain::txhashset::Desegmenter;
+use crate::common::header_skeleton::{HeaderSkeleton, SkeletonRequest};
+use crate::common::sync_progress::{self, StageMetrics, SyncMetrics};
+use crate::common::txhashset_segments::TxHashSetSegments;
 use crate::core::global::ChainTypes;
 use crate::core::{core, libtx, pow};
 use crate::keychain;
@@ -179,6 +187,10 @@ pub struct ServerConfig {
 	/// Test miner wallet URL
 	pub test_miner_wallet_url: Option<String>,
 
+	/// Header checkpoints to enforce on top of the hard-coded ones
+	#[serde(default)]
+	pub checkpoints: Option<Vec<Checkpoint>>,
+
 	/// Configuration for the peer-to-peer server
 	pub p2p_config: p2p::P2PConfig,
 
@@ -218,11 +230,21 @@ impl Default for ServerConfig {
 			run_tui: Some(true),
 			run_test_miner: Some(false),
 			test_miner_wallet_url: None,
+			checkpoints: None,
 			webhook_config: WebHooksConfig::default(),
 		}
 	}
 }
 
+/// Block hash the header chain must have at a given height
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+	/// Height of the checkpoint
+	pub height: u64,
+	/// Hash of the block at that height, hex encoded
+	pub hash: String,
+}
+
 /// Stratum (Mining server) configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StratumServerConfig {
@@ -272,6 +294,12 @@ pub struct WebHooksConfig {
 	pub block_received_url: Option<String>,
 	/// url to POST block data when a new block is accepted by our node (might be a reorg or a fork)
 	pub block_accepted_url: Option<String>,
+	/// url to POST alerts when our node may be partitioned or eclipsed
+	pub network_alert_url: Option<String>,
+	/// url to POST status changes of the txs accepted by our pool
+	pub tx_status_url: Option<String>,
+	/// url to POST the txs received conflicting with txs in our pool
+	pub tx_conflict_url: Option<String>,
 	/// number of worker threads in the tokio runtime
 	#[serde(default = "default_nthreads")]
 	pub nthreads: u16,
@@ -295,6 +323,9 @@ impl Default for WebHooksConfig {
 			header_received_url: None,
 			block_received_url: None,
 			block_accepted_url: None,
+			network_alert_url: None,
+			tx_status_url: None,
+			tx_conflict_url: None,
 			nthreads: default_nthreads(),
 			timeout: default_timeout(),
 		}
@@ -347,10 +378,91 @@ pub enum SyncStatus {
 	Shutdown,
 }
 
+/// Signs that our node may be cut off from the rest of the network, either
+/// isolated, on a minority partition or eclipsed by its peers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum NetworkAlert {
+	/// We aren't connected to any peer
+	Isolated,
+	/// Peers report more work than our header chain has, and we're not
+	/// catching up with them
+	Behind {
+		/// Number of peers ahead of us
+		peers_ahead: usize,
+		/// Number of connected peers
+		peer_count: usize,
+		/// Height of our header chain
+		height: u64,
+		/// Highest height reported by a peer
+		max_peer_height: u64,
+	},
+	/// Our peers come from too few network groups, they could all be under
+	/// the control of the same party
+	LowDiversity {
+		/// Number of distinct network groups
+		net_groups: usize,
+		/// Number of connected peers
+		peer_count: usize,
+	},
+	/// We haven't seen a new header for much longer than expected and none
+	/// of our peers has either
+	Stalled {
+		/// Seconds since our header chain last moved
+		secs: i64,
+	},
+}
+
+impl NetworkAlert {
+	/// Short name of the alert, the same across all its instances
+	pub fn kind(&self) -> &'static str {
+		match self {
+			NetworkAlert::Isolated => "isolated",
+			NetworkAlert::Behind { .. } => "behind",
+			NetworkAlert::LowDiversity { .. } => "low_diversity",
+			NetworkAlert::Stalled { .. } => "stalled",
+		}
+	}
+}
+
+impl fmt::Display for NetworkAlert {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			NetworkAlert::Isolated => write!(f, "no connected peers"),
+			NetworkAlert::Behind {
+				peers_ahead,
+				peer_count,
+				height,
+				max_peer_height,
+			} => write!(
+				f,
+				"{} of {} peers ahead of us (up to {} vs {}) and not catching up",
+				peers_ahead, peer_count, max_peer_height, height
+			),
+			NetworkAlert::LowDiversity {
+				net_groups,
+				peer_count,
+			} => write!(
+				f,
+				"{} peers from only {} network groups",
+				peer_count, net_groups
+			),
+			NetworkAlert::Stalled { secs } => {
+				write!(f, "no new header from any peer in {}s", secs)
+			}
+		}
+	}
+}
+
 /// Current sync state. Encapsulates the current SyncStatus.
 pub struct SyncState {
 	current: RwLock<SyncStatus>,
 	sync_error: Arc<RwLock<Option<Error>>>,
+	txhashset_segments: RwLock<Option<Arc<TxHashSetSegments>>>,
+	desegmenter: Arc<RwLock<Option<Desegmenter>>>,
+	leaf_set_peers: RwLock<HashSet<p2p::PeerAddr>>,
+	skeleton_request: RwLock<Option<SkeletonRequest>>,
+	header_skeleton: RwLock<Option<Arc<HeaderSkeleton>>>,
+	metrics: RwLock<SyncMetrics>,
 }
 
 impl SyncState {
@@ -359,6 +471,12 @@ impl SyncState {
 		SyncState {
 			current: RwLock::new(SyncStatus::Initial),
 			sync_error: Arc::new(RwLock::new(None)),
+			txhashset_segments: RwLock::new(None),
+			desegmenter: Arc::new(RwLock::new(None)),
+			leaf_set_peers: RwLock::new(HashSet::new()),
+			skeleton_request: RwLock::new(None),
+			header_skeleton: RwLock::new(None),
+			metrics: RwLock::new(SyncMetrics::default()),
 		}
 	}
 
@@ -384,6 +502,7 @@ impl SyncState {
 		debug!("sync_state: sync_status: {:?} -> {:?}", *status, new_status,);
 
 		*status = new_status;
+		self.record_progress(&new_status);
 	}
 
 	/// Update txhashset downloading progress
@@ -391,12 +510,31 @@ impl SyncState {
 		if let SyncStatus::TxHashsetDownload { .. } = new_status {
 			let mut status = self.current.write();
 			*status = new_status;
+			self.record_progress(&new_status);
 			true
 		} else {
 			false
 		}
 	}
 
+	// Keeps track of the progress of each stage, the txhashset download is
+	// reported in segments rather than bytes when rebuilt from segments.
+	fn record_progress(&self, status: &SyncStatus) {
+		let download_unit = if self.desegmenter.read().is_some() {
+			"segments"
+		} else {
+			"bytes"
+		};
+		self.metrics
+			.write()
+			.record(status, download_unit, Utc::now());
+	}
+
+	/// Progress, rate and ETA of each stage of the current (or last) sync
+	pub fn sync_stages(&self) -> Vec<StageMetrics> {
+		self.metrics.read().stages()
+	}
+
 	/// Communicate sync error
 	pub fn set_sync_error(&self, error: Error) {
 		*self.sync_error.write() = Some(error);
@@ -411,6 +549,63 @@ impl SyncState {
 	pub fn clear_sync_error(&self) {
 		*self.sync_error.write() = None;
 	}
+
+	/// Chunked txhashset download in progress, if any
+	pub fn txhashset_segments(&self) -> Option<Arc<TxHashSetSegments>> {
+		self.txhashset_segments.read().clone()
+	}
+
+	/// Set (or clear) the chunked txhashset download in progress
+	pub fn set_txhashset_segments(&self, segments: Option<Arc<TxHashSetSegments>>) {
+		*self.txhashset_segments.write() = segments;
+	}
+
+	/// Txhashset being rebuilt from MMR segments (PIBD), if any
+	pub fn desegmenter(&self) -> Arc<RwLock<Option<Desegmenter>>> {
+		self.desegmenter.clone()
+	}
+
+	/// Peers that sent us the same output leaf set as the one the txhashset
+	/// is rebuilt against, the only ones we take output and rangeproof
+	/// segments from
+	pub fn leaf_set_peers(&self) -> HashSet<p2p::PeerAddr> {
+		self.leaf_set_peers.read().clone()
+	}
+
+	/// Whether the peer sent us the output leaf set we rebuild against
+	pub fn is_leaf_set_peer(&self, addr: &p2p::PeerAddr) -> bool {
+		self.leaf_set_peers.read().contains(addr)
+	}
+
+	/// Add a peer that sent us the output leaf set we rebuild against
+	pub fn add_leaf_set_peer(&self, addr: p2p::PeerAddr) {
+		self.leaf_set_peers.write().insert(addr);
+	}
+
+	/// Forget the output leaf set peers, when starting a new rebuild
+	pub fn clear_leaf_set_peers(&self) {
+		self.leaf_set_peers.write().clear();
+	}
+
+	/// Header skeleton we're waiting for, if any
+	pub fn skeleton_request(&self) -> Option<SkeletonRequest> {
+		self.skeleton_request.read().clone()
+	}
+
+	/// Set (or clear) the header skeleton we're waiting for
+	pub fn set_skeleton_request(&self, request: Option<SkeletonRequest>) {
+		*self.skeleton_request.write() = request;
+	}
+
+	/// Header skeleton we're syncing headers against, if any
+	pub fn header_skeleton(&self) -> Option<Arc<HeaderSkeleton>> {
+		self.header_skeleton.read().clone()
+	}
+
+	/// Set (or clear) the header skeleton we're syncing headers against
+	pub fn set_header_skeleton(&self, skeleton: Option<Arc<HeaderSkeleton>>) {
+		*self.header_skeleton.write() = skeleton;
+	}
 }
 
 impl chain::TxHashsetWriteStatus for SyncState {
@@ -455,6 +650,7 @@ impl chain::TxHashsetWriteStatus for SyncState {
 				}
 			}
 		}
+		self.record_progress(&status);
 	}
 
 	fn on_save(&self) {
@@ -466,8 +662,22 @@ impl chain::TxHashsetWriteStatus for SyncState {
 	}
 }
 
+impl api::SyncProgressSource for SyncState {
+	fn sync_progress(&self) -> api::SyncProgress {
+		api::SyncProgress {
+			status: sync_progress::status_name(&self.status()).to_owned(),
+			stages: self.sync_stages().iter().map(|s| s.into()).collect(),
+		}
+	}
+}
+
+/// Number of outbound stem relays of a Dandelion epoch.
+pub const DANDELION_RELAYS: usize = 2;
+
 /// A node is either "stem" of "fluff" for the duration of a single epoch.
-/// A node also maintains an outbound relay peer for the epoch.
+/// Following Dandelion++, a node also picks two outbound relay peers for the
+/// epoch, each source (inbound peer or local submission) having all its stem
+/// txs forwarded to the same one of them until the epoch ends.
 #[derive(Debug)]
 pub struct DandelionEpoch {
 	config: DandelionConfig,
@@ -475,8 +685,10 @@ pub struct DandelionEpoch {
 	start_time: Option<i64>,
 	// Are we in "stem" mode or "fluff" mode for this epoch?
 	is_stem: bool,
-	// Our current Dandelion relay peer (effective for this epoch).
-	relay_peer: Option<Arc<p2p::Peer>>,
+	// Our outbound Dandelion relay peers (effective for this epoch).
+	relay_peers: [Option<Arc<p2p::Peer>>; DANDELION_RELAYS],
+	// Random for each epoch, mapping sources to relays.
+	route_salt: u64,
 }
 
 impl DandelionEpoch {
@@ -486,7 +698,8 @@ impl DandelionEpoch {
 			config,
 			start_time: None,
 			is_stem: true,
-			relay_peer: None,
+			relay_peers: Default::default(),
+			route_salt: thread_rng().gen(),
 		}
 	}
 
@@ -504,10 +717,15 @@ impl DandelionEpoch {
 
 	/// Transition to next Dandelion epoch.
 	/// Select stem/fluff based on configured stem_probability.
-	/// Choose a new outbound stem relay peer.
+	/// Choose new outbound stem relay peers and a new mapping of the sources
+	/// to them.
 	pub fn next_epoch(&mut self, peers: &Arc<p2p::Peers>) {
 		self.start_time = Some(Utc::now().timestamp());
-		self.relay_peer = peers.outgoing_connected_peers().first().cloned();
+		self.route_salt = thread_rng().gen();
+		self.relay_peers = Default::default();
+		for route in 0..DANDELION_RELAYS {
+			self.relay_peers[route] = self.choose_relay(route, peers);
+		}
 
 		// If stem_probability == 90 then we stem 90% of the time.
 		let mut rng = rand::thread_rng();
@@ -517,10 +735,14 @@ impl DandelionEpoch {
 			.expect("stem_probability config missing");
 		self.is_stem = rng.gen_range(0, 100) < stem_probability;
 
-		let addr = self.relay_peer.clone().map(|p| p.info.addr);
+		let addrs = self
+			.relay_peers
+			.iter()
+			.map(|p| p.as_ref().map(|p| p.info.addr))
+			.collect::<Vec<_>>();
 		info!(
-			"DandelionEpoch: next_epoch: is_stem: {} ({}%), relay: {:?}",
-			self.is_stem, stem_probability, addr
+			"DandelionEpoch: next_epoch: is_stem: {} ({}%), relays: {:?}",
+			self.is_stem, stem_probability, addrs
 		);
 	}
 
@@ -529,30 +751,68 @@ impl DandelionEpoch {
 		self.is_stem
 	}
 
-	/// What is our current relay peer?
-	/// If it is not connected then choose a new one.
-	pub fn relay_peer(&mut self, peers: &Arc<p2p::Peers>) -> Option<Arc<p2p::Peer>> {
-		let mut update_relay = false;
-		if let Some(peer) = &self.relay_peer {
-			if !peer.is_connected() {
+	/// Relay the stem txs of the provided source (identified as in
+	/// `TxSource`) go to, the same one for the whole epoch.
+	/// If it is not connected then choose a new one for its route, the
+	/// sources routed to the other relay not being affected.
+	pub fn relay_peer(
+		&mut self,
+		identifier: &str,
+		peers: &Arc<p2p::Peers>,
+	) -> Option<Arc<p2p::Peer>> {
+		let route = self.route(identifier);
+		let current = self.relay_peers[route]
+			.as_ref()
+			.and_then(|p| peers.get_connected_peer(p.info.addr));
+		if current.is_some() {
+			self.relay_peers[route] = current;
+		} else {
+			if let Some(peer) = &self.relay_peers[route] {
 				info!(
 					"DandelionEpoch: relay_peer: {:?} not connected, choosing a new one.",
 					peer.info.addr
 				);
-				update_relay = true;
 			}
-		} else {
-			update_relay = true;
-		}
-
-		if update_relay {
-			self.relay_peer = peers.outgoing_connected_peers().first().cloned();
+			self.relay_peers[route] = self.choose_relay(route, peers);
 			info!(
 				"DandelionEpoch: relay_peer: new peer chosen: {:?}",
-				self.relay_peer.clone().map(|p| p.info.addr)
+				self.relay_peers[route].clone().map(|p| p.info.addr)
 			);
 		}
 
-		self.relay_peer.clone()
+		// Only fall back to the other route while we don't have enough peers.
+		self.relay_peers[route].clone().or_else(|| {
+			self.relay_peers
+				.iter()
+				.flatten()
+				.find_map(|p| peers.get_connected_peer(p.info.addr))
+		})
+	}
+
+	// Route of the stem txs of a source for this epoch.
+	fn route(&self, identifier: &str) -> usize {
+		let mut hasher = DefaultHasher::new();
+		self.route_salt.hash(&mut hasher);
+		identifier.hash(&mut hasher);
+		(hasher.finish() % DANDELION_RELAYS as u64) as usize
+	}
+
+	// Random outbound peer to relay a route to, distinct from the relays of
+	// the other routes.
+	fn choose_relay(&self, route: usize, peers: &Arc<p2p::Peers>) -> Option<Arc<p2p::Peer>> {
+		let others = self
+			.relay_peers
+			.iter()
+			.enumerate()
+			.filter(|(r, _)| *r != route)
+			.filter_map(|(_, p)| p.as_ref().map(|p| p.info.addr))
+			.collect::<Vec<_>>();
+		peers
+			.outgoing_connected_peers()
+			.into_iter()
+			.filter(|p| !others.contains(&p.info.addr))
+			.collect::<Vec<_>>()
+			.choose(&mut thread_rng())
+			.cloned()
 	}
 }
@@ -15,6 +15,7 @@
 //! Grin P2P / API server
 
 pub mod dandelion_monitor;
+pub mod pool_persist;
 pub mod seed;
 pub mod server;
 pub mod sync;
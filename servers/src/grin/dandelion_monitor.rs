@@ -13,13 +13,15 @@
 // limitations under the License.
 
 use chrono::prelude::Utc;
+use rand::distributions::Exp;
 use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::common::adapters::DandelionAdapter;
-use crate::core::core::hash::Hashed;
+use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::pool::{DandelionConfig, Pool, PoolEntry, PoolError, TransactionPool, TxSource};
@@ -29,10 +31,10 @@ use crate::util::{RwLock, StopState};
 /// With Dandelion, transaction can be broadcasted in stem or fluff phase.
 /// When sent in stem phase, the transaction is relayed to only node: the
 /// dandelion relay. In order to maintain reliability a timer is started for
-/// each transaction sent in stem phase. This function will monitor the
-/// stempool and test if the timer is expired for each transaction. In that case
-/// the transaction will be sent in fluff phase (to multiple peers) instead of
-/// sending only to the peer relay.
+/// each transaction sent in stem phase, its length drawn at random as in
+/// Dandelion++. This function will monitor the stempool and test if the timer
+/// is expired for each transaction. In that case the transaction will be sent
+/// in fluff phase (to multiple peers) instead of sending only to the peer relay.
 pub fn monitor_transactions(
 	dandelion_config: DandelionConfig,
 	tx_pool: Arc<RwLock<TransactionPool>>,
@@ -49,6 +51,8 @@ pub fn monitor_transactions(
 			let mut last_run = Instant::now()
 				.checked_sub(Duration::from_secs(20))
 				.unwrap_or_else(|| Instant::now());
+			// Embargo expiry of each stem tx, by tx hash.
+			let mut embargoes = HashMap::new();
 			loop {
 				// Halt Dandelion monitor if we have been notified that we are stopping.
 				if stop_state.is_stopped() {
@@ -69,9 +73,10 @@ pub fn monitor_transactions(
 					}
 
 					// Now find all expired entries based on embargo timer.
-					let _ = process_expired_entries(&dandelion_config, &tx_pool).map_err(|e| {
-						error!("dand_mon: Problem processing expired entries. {:?}", e);
-					});
+					let _ = process_expired_entries(&dandelion_config, &tx_pool, &mut embargoes)
+						.map_err(|e| {
+							error!("dand_mon: Problem processing expired entries. {:?}", e);
+						});
 
 					// Handle the tx above *before* we transition to next epoch.
 					// This gives us an opportunity to do the final "fluff" before we start
@@ -89,7 +94,7 @@ pub fn monitor_transactions(
 }
 
 // Query the pool for transactions older than the cutoff.
-// Used for both periodic fluffing and handling expired embargo timer.
+// Used for periodic fluffing.
 fn select_txs_cutoff(pool: &Pool, cutoff_secs: u16) -> Vec<PoolEntry> {
 	let cutoff = Utc::now().timestamp() - cutoff_secs as i64;
 	pool.entries
@@ -154,22 +159,53 @@ fn process_fluff_phase(
 		identifier: "?.?.?.?".to_string(),
 	};
 
-	tx_pool.add_to_pool(src, agg_tx, false, &header)?;
+	tx_pool.fluff_to_pool(src, agg_tx, &header)?;
 	Ok(())
 }
 
+// Query the pool for the transactions whose embargo expired, drawing one for
+// the transactions we haven't seen yet. Embargoes follow an exponential
+// distribution (averaging embargo_secs) so the node fluffing a tx whose
+// stem failed can't be told apart from the others along the stem.
+fn select_txs_embargo_expired(
+	pool: &Pool,
+	embargo_secs: u16,
+	embargoes: &mut HashMap<Hash, i64>,
+) -> Vec<PoolEntry> {
+	let hashes = pool
+		.entries
+		.iter()
+		.map(|x| x.tx.hash())
+		.collect::<HashSet<_>>();
+	embargoes.retain(|h, _| hashes.contains(h));
+
+	let exp = Exp::new(1.0 / embargo_secs.max(1) as f64);
+	let mut rng = thread_rng();
+	let now = Utc::now().timestamp();
+	pool.entries
+		.iter()
+		.filter(|x| {
+			let expiry = embargoes
+				.entry(x.tx.hash())
+				.or_insert_with(|| x.tx_at.timestamp() + rng.sample(&exp).ceil() as i64);
+			*expiry <= now
+		})
+		.cloned()
+		.collect()
+}
+
 fn process_expired_entries(
 	dandelion_config: &DandelionConfig,
 	tx_pool: &Arc<RwLock<TransactionPool>>,
+	embargoes: &mut HashMap<Hash, i64>,
 ) -> Result<(), PoolError> {
 	// Take a write lock on the txpool for the duration of this processing.
 	let mut tx_pool = tx_pool.write();
 
 	let embargo_secs = dandelion_config
 		.embargo_secs
-		.expect("embargo_secs config missing")
-		+ thread_rng().gen_range(0, 31);
-	let expired_entries = select_txs_cutoff(&tx_pool.stempool, embargo_secs);
+		.expect("embargo_secs config missing");
+	let expired_entries = select_txs_embargo_expired(&tx_pool.stempool, embargo_secs, embargoes);
 
 	if expired_entries.is_empty() {
 		return Ok(());
@@ -186,7 +222,7 @@ fn process_expired_entries(
 
 	for entry in expired_entries {
 		let txhash = entry.tx.hash();
-		match tx_pool.add_to_pool(src.clone(), entry.tx, false, &header) {
+		match tx_pool.fluff_to_pool(src.clone(), entry.tx, &header) {
 			Ok(_) => info!(
 				"dand_mon: embargo expired for {}, fluffed successfully.",
 				txhash
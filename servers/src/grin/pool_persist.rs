@@ -0,0 +1,84 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeps the content of the transaction pool across node restarts. The pool
+//! gets saved to a file in the db root on a regular basis and on shutdown,
+//! and restored from it on startup.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::pool::TransactionPool;
+use crate::util::{RwLock, StopState};
+
+/// Name of the file the pool gets saved to, in the db root.
+const POOL_FILE: &str = "txpool.bin";
+
+fn pool_file(db_root: &str) -> PathBuf {
+	Path::new(db_root).join(POOL_FILE)
+}
+
+/// Adds back the txs saved by a previous run, if any, those no longer valid
+/// against the current chain being dropped.
+pub fn restore_pool(tx_pool: &Arc<RwLock<TransactionPool>>, db_root: &str) {
+	let path = pool_file(db_root);
+	if !path.exists() {
+		return;
+	}
+	let mut tx_pool = tx_pool.write();
+	let res = tx_pool
+		.chain_head()
+		.and_then(|header| tx_pool.load_entries(&path, &header));
+	match res {
+		Ok(count) => info!("pool_persist: restored {} txs", count),
+		Err(e) => warn!("pool_persist: failed to restore txs: {:?}", e),
+	}
+}
+
+/// Saves the pool every `interval_secs` (if not 0), and one last time when
+/// we are stopping.
+pub fn persist_pool(
+	interval_secs: u64,
+	tx_pool: Arc<RwLock<TransactionPool>>,
+	db_root: String,
+	stop_state: Arc<StopState>,
+) -> std::io::Result<thread::JoinHandle<()>> {
+	let path = pool_file(&db_root);
+	thread::Builder::new()
+		.name("pool_persist".to_string())
+		.spawn(move || {
+			let interval = Duration::from_secs(interval_secs);
+			let mut last_save = Instant::now();
+			loop {
+				if stop_state.is_stopped() {
+					break;
+				}
+				if interval_secs > 0 && last_save.elapsed() >= interval {
+					save_pool(&tx_pool, &path);
+					last_save = Instant::now();
+				}
+				thread::sleep(Duration::from_secs(1));
+			}
+			save_pool(&tx_pool, &path);
+		})
+}
+
+fn save_pool(tx_pool: &Arc<RwLock<TransactionPool>>, path: &Path) {
+	match tx_pool.read().save_entries(path) {
+		Ok(count) => debug!("pool_persist: saved {} txs", count),
+		Err(e) => error!("pool_persist: failed to save txs: {:?}", e),
+	}
+}
@@ -21,16 +21,35 @@ use chrono::prelude::{DateTime, Utc};
 use chrono::{Duration, MIN_DATE};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::ToSocketAddrs;
 use std::sync::{mpsc, Arc};
 use std::{cmp, str, thread, time};
 
+use crate::chain::{self, Tip};
+use crate::common::hooks::NetEvents;
+use crate::common::types::NetworkAlert;
+use crate::core::consensus;
 use crate::core::global;
 use crate::p2p;
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{PeerAddr, PeerInfo};
 use crate::p2p::ChainAdapter;
-use crate::util::StopState;
+use crate::util::{RwLock, StopState};
+
+/// How often we look for signs of being partitioned or eclipsed
+const NETWORK_CHECK_INTERVAL_SECS: i64 = 60;
+
+/// Peers need to be this many blocks ahead of our header chain to count as
+/// ahead, so regular propagation delays don't raise alerts
+const PEER_AHEAD_MIN_BLOCKS: u64 = 5;
+
+/// Below this many network groups among our peers, they may all be run by
+/// the same party
+const MIN_NET_GROUPS: usize = 3;
+
+/// Our header chain not moving for this many block intervals is very
+/// unlikely to be bad luck (less than 1 in 10^13 at 30)
+const STALLED_BLOCK_INTERVALS: i64 = 30;
 
 // DNS Seeds with contact email associated
 const MAINNET_DNS_SEEDS: &'static [&'static str] = &[
@@ -54,6 +73,9 @@ pub fn connect_and_monitor(
 	capabilities: p2p::Capabilities,
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr> + Send>,
 	preferred_peers: Option<Vec<PeerAddr>>,
+	chain: Arc<chain::Chain>,
+	net_hooks: Arc<Vec<Box<dyn NetEvents + Send + Sync>>>,
+	network_alerts: Arc<RwLock<Vec<NetworkAlert>>>,
 	stop_state: Arc<StopState>,
 ) -> std::io::Result<thread::JoinHandle<()>> {
 	thread::Builder::new()
@@ -65,17 +87,23 @@ pub fn connect_and_monitor(
 			// max peer count
 			let (tx, rx) = mpsc::channel();
 
+			// reconnect to our anchors first, before anybody else gets a chance
+			connect_to_anchors(p2p_server.clone(), capabilities);
+
 			// check seeds first
 			connect_to_seeds_and_preferred_peers(
 				peers.clone(),
 				tx.clone(),
 				seed_list,
 				preferred_peers.clone(),
+				p2p_server.config.proxy_mode(),
 			);
 
 			let mut prev = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_ping = Utc::now();
+			let mut prev_network_check = Utc::now();
+			let mut detector = PartitionDetector::new(global::is_production_mode(), Utc::now());
 			let mut start_attempt = 0;
 			let mut connecting_history: HashMap<PeerAddr, DateTime<Utc>> = HashMap::new();
 
@@ -117,6 +145,9 @@ pub fn connect_and_monitor(
 						preferred_peers.clone(),
 					);
 
+					// keep our extra block-relay-only connections up
+					connect_block_relay_only(p2p_server.clone());
+
 					prev = Utc::now();
 					start_attempt = cmp::min(6, start_attempt + 1);
 				}
@@ -133,6 +164,13 @@ pub fn connect_and_monitor(
 					}
 				}
 
+				// Look for signs we're cut off from the rest of the network
+				if Utc::now() - prev_network_check > Duration::seconds(NETWORK_CHECK_INTERVAL_SECS)
+				{
+					check_network(&mut detector, &chain, &peers, &net_hooks, &network_alerts);
+					prev_network_check = Utc::now();
+				}
+
 				thread::sleep(time::Duration::from_secs(1));
 			}
 		})
@@ -225,15 +263,11 @@ fn monitor_peers(
 		let _ = peers.update_state(defuncts[0].addr, p2p::State::Healthy);
 	}
 
-	// find some peers from our db
-	// and queue them up for a connection attempt
-	let new_peers = peers.find_peers(
-		p2p::State::Healthy,
-		p2p::Capabilities::UNKNOWN,
-		config.peer_max_count() as usize,
-	);
+	// pick some peers from the address manager, from network groups we are
+	// not connected to yet, and queue them up for a connection attempt
+	let new_peers = peers.outbound_candidates(config.peer_max_count() as usize);
 
-	for p in new_peers.iter().filter(|p| !peers.is_known(p.addr)) {
+	for p in new_peers.iter() {
 		trace!(
 			"monitor_peers: on {}:{}, queue to soon try {}",
 			config.host,
@@ -244,13 +278,167 @@ fn monitor_peers(
 	}
 }
 
+/// Compares our header chain against what our peers report, and looks at
+/// how diverse they are and how long since our header chain last moved, to
+/// tell when we may be on a minority partition or eclipsed by our peers.
+pub struct PartitionDetector {
+	// network group diversity and stalls are only meaningful on a real
+	// network with regular blocks
+	production: bool,
+	last_header_head: Option<Tip>,
+	header_head_moved: DateTime<Utc>,
+	alerts: Vec<NetworkAlert>,
+}
+
+impl PartitionDetector {
+	/// New detector, considering our header chain moved as of now.
+	pub fn new(production: bool, now: DateTime<Utc>) -> PartitionDetector {
+		PartitionDetector {
+			production,
+			last_header_head: None,
+			header_head_moved: now,
+			alerts: vec![],
+		}
+	}
+
+	/// Alerts raised as of the last check.
+	pub fn alerts(&self) -> &[NetworkAlert] {
+		&self.alerts
+	}
+
+	/// Checks our header head against our connected peers, returning the
+	/// alerts raised by this check that weren't already.
+	pub fn check(
+		&mut self,
+		header_head: &Tip,
+		peers: &[PeerInfo],
+		now: DateTime<Utc>,
+	) -> Vec<NetworkAlert> {
+		let moved = self
+			.last_header_head
+			.as_ref()
+			.map(|h| h.last_block_h != header_head.last_block_h)
+			.unwrap_or(false);
+		let stuck = self.last_header_head.is_some() && !moved;
+		if moved {
+			self.header_head_moved = now;
+		}
+		self.last_header_head = Some(header_head.clone());
+
+		let mut alerts = vec![];
+		if peers.is_empty() {
+			alerts.push(NetworkAlert::Isolated);
+		}
+
+		let ahead = peers
+			.iter()
+			.filter(|p| {
+				p.total_difficulty() > header_head.total_difficulty
+					&& p.height() >= header_head.height + PEER_AHEAD_MIN_BLOCKS
+			})
+			.collect::<Vec<_>>();
+		if stuck && !ahead.is_empty() {
+			alerts.push(NetworkAlert::Behind {
+				peers_ahead: ahead.len(),
+				peer_count: peers.len(),
+				height: header_head.height,
+				max_peer_height: ahead.iter().map(|p| p.height()).max().unwrap_or(0),
+			});
+		}
+
+		if self.production {
+			let net_groups = peers
+				.iter()
+				.map(|p| p.addr.net_group())
+				.collect::<HashSet<_>>()
+				.len();
+			if net_groups < cmp::min(MIN_NET_GROUPS, peers.len()) {
+				alerts.push(NetworkAlert::LowDiversity {
+					net_groups,
+					peer_count: peers.len(),
+				});
+			}
+
+			let since_moved = now - self.header_head_moved;
+			let stalled_after =
+				Duration::seconds(STALLED_BLOCK_INTERVALS * consensus::BLOCK_TIME_SEC as i64);
+			if ahead.is_empty() && since_moved > stalled_after {
+				alerts.push(NetworkAlert::Stalled {
+					secs: since_moved.num_seconds(),
+				});
+			}
+		}
+
+		for cleared in self
+			.alerts
+			.iter()
+			.filter(|a| alerts.iter().all(|b| b.kind() != a.kind()))
+		{
+			info!("Network alert ({}) cleared.", cleared.kind());
+		}
+		let raised = alerts
+			.iter()
+			.filter(|a| self.alerts.iter().all(|b| b.kind() != a.kind()))
+			.cloned()
+			.collect();
+		self.alerts = alerts;
+		raised
+	}
+}
+
+// Runs the partition detector, passing newly raised alerts to the network
+// event hooks and publishing the current ones for the server stats.
+fn check_network(
+	detector: &mut PartitionDetector,
+	chain: &chain::Chain,
+	peers: &p2p::Peers,
+	net_hooks: &[Box<dyn NetEvents + Send + Sync>],
+	network_alerts: &RwLock<Vec<NetworkAlert>>,
+) {
+	let header_head = match chain.header_head() {
+		Ok(head) => head,
+		Err(e) => {
+			error!("check_network: failed to get header head: {:?}", e);
+			return;
+		}
+	};
+	let peer_infos = peers
+		.connected_peers()
+		.iter()
+		.map(|p| p.info.clone())
+		.collect::<Vec<_>>();
+
+	for alert in detector.check(&header_head, &peer_infos, Utc::now()) {
+		for hook in net_hooks {
+			hook.on_network_alert(&alert);
+		}
+	}
+	*network_alerts.write() = detector.alerts().to_vec();
+}
+
+// Reconnect to the anchors saved on our last shutdown, synchronously so we
+// are connected to them before consulting any seed.
+fn connect_to_anchors(p2p: Arc<p2p::Server>, capab: p2p::Capabilities) {
+	let anchors = filter_by_proxy_mode(p2p.peers.anchors(), p2p.config.proxy_mode());
+	for addr in anchors {
+		match p2p.connect(addr) {
+			Ok(p) => {
+				debug!("connect_to_anchors: connected to anchor {}", addr);
+				let _ = p.send_peer_request(capab);
+			}
+			Err(e) => debug!("connect_to_anchors: failed to connect to {}: {:?}", addr, e),
+		}
+	}
+}
+
 // Check if we have any pre-existing peer in db. If so, start with those,
-// otherwise use the seeds provided.
+// otherwise use the seeds provided. Our proxy mode applies to all of them.
 fn connect_to_seeds_and_preferred_peers(
 	peers: Arc<p2p::Peers>,
 	tx: mpsc::Sender<PeerAddr>,
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr>>,
 	peers_preferred_list: Option<Vec<PeerAddr>>,
+	proxy_mode: p2p::ProxyMode,
 ) {
 	// check if we have some peers in db
 	// look for peers that are able to give us other peers (via PEER_LIST capability)
@@ -269,6 +457,7 @@ fn connect_to_seeds_and_preferred_peers(
 		None => trace!("No preferred peers"),
 	};
 
+	let peer_addrs = filter_by_proxy_mode(peer_addrs, proxy_mode);
 	if peer_addrs.len() == 0 {
 		warn!("No seeds were retrieved.");
 	}
@@ -293,7 +482,7 @@ fn listen_for_addrs(
 	// Does not block so addrs may be empty.
 	// We will take(max_peers) from this later but we want to drain the rx queue
 	// here to prevent it backing up.
-	let addrs: Vec<PeerAddr> = rx.try_iter().collect();
+	let addrs = filter_by_proxy_mode(rx.try_iter().collect(), p2p.config.proxy_mode());
 
 	// If we have a healthy number of outbound peers then we are done here.
 	if peers.peer_count() > peers.peer_outbound_count() && peers.healthy_peers_mix() {
@@ -355,6 +544,51 @@ fn listen_for_addrs(
 	}
 }
 
+/// Opens block-relay-only connections to peers from network groups we are
+/// not connected to yet, until we have as many as configured. These come on
+/// top of our regular outbound connections.
+fn connect_block_relay_only(p2p: Arc<p2p::Server>) {
+	let missing = (p2p.config.block_relay_only_count() as usize)
+		.saturating_sub(p2p.peers.block_relay_only_peers().len());
+	if missing == 0 {
+		return;
+	}
+
+	let candidates = p2p
+		.peers
+		.outbound_candidates(missing)
+		.into_iter()
+		.map(|p| p.addr)
+		.collect();
+	for addr in filter_by_proxy_mode(candidates, p2p.config.proxy_mode()) {
+		let p2p_c = p2p.clone();
+		thread::Builder::new()
+			.name("peer_connect".to_string())
+			.spawn(move || {
+				if let Err(e) = p2p_c.connect_block_relay_only(addr) {
+					debug!(
+						"connect_block_relay_only: failed to connect to {}: {:?}",
+						addr, e
+					);
+					let _ = p2p_c.peers.update_state(addr, p2p::State::Defunct);
+				}
+			})
+			.expect("failed to launch peer_connect thread");
+	}
+}
+
+/// Applies our proxy mode to the addresses queued for connection. Onion peers
+/// go first when proxied connections are preferred and are the only ones kept
+/// when proxied connections are required.
+fn filter_by_proxy_mode(mut addrs: Vec<PeerAddr>, proxy_mode: p2p::ProxyMode) -> Vec<PeerAddr> {
+	match proxy_mode {
+		p2p::ProxyMode::OnionOnly => {}
+		p2p::ProxyMode::Prefer => addrs.sort_by_key(|addr| !addr.is_onion()),
+		p2p::ProxyMode::Require => addrs.retain(|addr| addr.is_onion()),
+	}
+	addrs
+}
+
 pub fn dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 	Box::new(|| {
 		let mut addresses: Vec<PeerAddr> = vec![];
@@ -371,7 +605,7 @@ pub fn dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 					&mut (addrs
 						.map(|mut addr| {
 							addr.set_port(if global::is_floonet() { 13414 } else { 3414 });
-							PeerAddr(addr)
+							PeerAddr::Ip(addr)
 						})
 						.filter(|addr| !temp_addresses.contains(addr))
 						.collect()),
@@ -389,3 +623,93 @@ pub fn dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 pub fn predefined_seeds(addrs: Vec<PeerAddr>) -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 	Box::new(move || addrs.clone())
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::hash::Hash;
+	use crate::core::pow::Difficulty;
+	use crate::p2p::msg::ProtocolVersion;
+	use crate::p2p::types::PeerLiveInfo;
+
+	fn tip(height: u64) -> Tip {
+		Tip {
+			height,
+			last_block_h: Hash::from_vec(&height.to_le_bytes()),
+			prev_block_h: Hash::from_vec(&(height.saturating_sub(1)).to_le_bytes()),
+			total_difficulty: Difficulty::from_num(height * 10),
+		}
+	}
+
+	fn peer(ip: &str, height: u64) -> PeerInfo {
+		let mut live_info = PeerLiveInfo::new(Difficulty::from_num(height * 10));
+		live_info.height = height;
+		PeerInfo {
+			capabilities: p2p::Capabilities::FULL_NODE,
+			user_agent: "test".to_owned(),
+			version: ProtocolVersion::local(),
+			addr: PeerAddr::Ip(format!("{}:3414", ip).parse().unwrap()),
+			direction: p2p::Direction::Outbound,
+			live_info: Arc::new(RwLock::new(live_info)),
+		}
+	}
+
+	#[test]
+	fn isolated_and_behind() {
+		let t0 = Utc::now();
+		let mut detector = PartitionDetector::new(false, t0);
+		assert_eq!(
+			detector.check(&tip(10), &[], t0),
+			vec![NetworkAlert::Isolated]
+		);
+		// only raised once
+		assert!(detector.check(&tip(10), &[], t0).is_empty());
+
+		// peers ahead while we're still catching up
+		let peers = vec![peer("1.1.1.1", 100), peer("2.2.2.2", 12)];
+		assert!(detector.check(&tip(20), &peers, t0).is_empty());
+		assert!(detector.alerts().is_empty());
+
+		// not moving anymore
+		assert_eq!(
+			detector.check(&tip(20), &peers, t0),
+			vec![NetworkAlert::Behind {
+				peers_ahead: 1,
+				peer_count: 2,
+				height: 20,
+				max_peer_height: 100,
+			}]
+		);
+		assert!(detector.check(&tip(100), &peers, t0).is_empty());
+		assert!(detector.alerts().is_empty());
+	}
+
+	#[test]
+	fn eclipsed() {
+		let t0 = Utc::now();
+		let mut detector = PartitionDetector::new(true, t0);
+		let peers = vec![
+			peer("1.1.1.1", 10),
+			peer("1.1.2.2", 10),
+			peer("1.1.3.3", 10),
+			peer("2.2.2.2", 10),
+		];
+		assert_eq!(
+			detector.check(&tip(10), &peers, t0),
+			vec![NetworkAlert::LowDiversity {
+				net_groups: 2,
+				peer_count: 4,
+			}]
+		);
+
+		// nobody sees new blocks for too long
+		let later = t0 + Duration::minutes(31);
+		assert_eq!(
+			detector.check(&tip(10), &peers, later),
+			vec![NetworkAlert::Stalled { secs: 31 * 60 }]
+		);
+		assert_eq!(detector.alerts().len(), 2);
+		assert!(detector.check(&tip(11), &peers, later).is_empty());
+		assert_eq!(detector.alerts().len(), 1);
+	}
+}
@@ -16,6 +16,7 @@
 //! the peer-to-peer server, the blockchain and the transaction pool) and acts
 //! as a facade.
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
@@ -32,21 +33,24 @@ use crate::api;
 use crate::api::TLSConfig;
 use crate::chain;
 use crate::common::adapters::{
-	ChainToPoolAndNetAdapter, NetToChainAdapter, PoolToChainAdapter, PoolToNetAdapter,
+	ChainToPoolAndNetAdapter, DandelionAdapter, NetToChainAdapter, PoolToChainAdapter,
+	PoolToNetAdapter,
 };
 use crate::common::hooks::{init_chain_hooks, init_net_hooks};
 use crate::common::stats::{DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats};
 use crate::common::types::{Error, ServerConfig, StratumServerConfig, SyncState, SyncStatus};
-use crate::core::core::hash::{Hashed, ZERO_HASH};
+use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use crate::core::core::Transaction;
 use crate::core::{consensus, genesis, global, pow};
-use crate::grin::{dandelion_monitor, seed, sync};
+use crate::grin::{dandelion_monitor, pool_persist, seed, sync};
 use crate::mining::stratumserver;
 use crate::mining::test_miner::Miner;
 use crate::p2p;
 use crate::p2p::types::PeerAddr;
 use crate::pool;
 use crate::util::file::get_first_line;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::{RwLock, StopState};
 
 /// Grin server holding internal structures.
@@ -59,6 +63,8 @@ pub struct Server {
 	pub chain: Arc<chain::Chain>,
 	/// in-memory transaction pool
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
+	/// Relays the txs accepted by our pool, along Dandelion routes for stem txs
+	pool_net_adapter: Arc<PoolToNetAdapter>,
 	/// Shared cache for verification results when
 	/// verifying rangeproof and kernel signatures.
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
@@ -73,6 +79,7 @@ pub struct Server {
 	connect_thread: Option<JoinHandle<()>>,
 	sync_thread: JoinHandle<()>,
 	dandelion_thread: JoinHandle<()>,
+	pool_persist_thread: JoinHandle<()>,
 }
 
 impl Server {
@@ -154,8 +161,13 @@ impl Server {
 		// We cache rangeproof verification and kernel signature verification.
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
+		let chain_hooks = Arc::new(init_chain_hooks(&config));
+
 		let pool_adapter = Arc::new(PoolToChainAdapter::new());
-		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(config.dandelion_config.clone()));
+		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(
+			config.dandelion_config.clone(),
+			chain_hooks.clone(),
+		));
 		let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
 			config.pool_config.clone(),
 			pool_adapter.clone(),
@@ -165,10 +177,7 @@ impl Server {
 
 		let sync_state = Arc::new(SyncState::new());
 
-		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
-			tx_pool.clone(),
-			init_chain_hooks(&config),
-		));
+		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(tx_pool.clone(), chain_hooks));
 
 		let genesis = match config.chain_type {
 			global::ChainTypes::AutomatedTesting => genesis::genesis_dev(),
@@ -179,6 +188,18 @@ impl Server {
 
 		info!("Starting server, genesis block: {}", genesis.hash());
 
+		if let Some(ref checkpoints) = config.checkpoints {
+			let mut extra = BTreeMap::new();
+			for c in checkpoints {
+				let hash = Hash::from_hex(&c.hash).map_err(|_| {
+					Error::ArgumentError(format!("Invalid checkpoint hash: {}", c.hash))
+				})?;
+				extra.insert(c.height, hash);
+			}
+			info!("Enforcing {} configured header checkpoints", extra.len());
+			global::set_extra_checkpoints(extra);
+		}
+
 		let shared_chain = Arc::new(chain::Chain::init(
 			config.db_root.clone(),
 			chain_adapter.clone(),
@@ -190,13 +211,14 @@ impl Server {
 
 		pool_adapter.set_chain(shared_chain.clone());
 
+		let net_hooks = Arc::new(init_net_hooks(&config));
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			verifier_cache.clone(),
 			config.clone(),
-			init_net_hooks(&config),
+			net_hooks.clone(),
 		));
 
 		let p2p_server = Arc::new(p2p::Server::new(
@@ -213,6 +235,10 @@ impl Server {
 		pool_net_adapter.init(p2p_server.peers.clone());
 		net_adapter.init(p2p_server.peers.clone());
 
+		// Bring back the txs we had in our pool before the last shutdown.
+		pool_persist::restore_pool(&tx_pool, &config.db_root);
+
+		let state_info = ServerStateInfo::default();
 		let mut connect_thread = None;
 
 		if config.p2p_config.seeding_type != p2p::Seeding::Programmatic {
@@ -229,6 +255,14 @@ impl Server {
 						));
 					}
 				},
+				p2p::Seeding::DNSSeed
+					if config.p2p_config.proxy_mode() == p2p::ProxyMode::Require =>
+				{
+					// looking them up would go around the proxy, and only give
+					// us peers we won't connect to anyway
+					warn!("DNS seeds are not used when the proxy is required");
+					seed::predefined_seeds(vec![])
+				}
 				p2p::Seeding::DNSSeed => seed::dns_seeds(),
 				_ => unreachable!(),
 			};
@@ -238,6 +272,9 @@ impl Server {
 				config.p2p_config.capabilities,
 				seeder,
 				config.p2p_config.peers_preferred.clone(),
+				shared_chain.clone(),
+				net_hooks,
+				state_info.network_alerts.clone(),
 				stop_state.clone(),
 			)?);
 		}
@@ -286,6 +323,7 @@ impl Server {
 			shared_chain.clone(),
 			tx_pool.clone(),
 			p2p_server.peers.clone(),
+			sync_state.clone(),
 			api_secret,
 			tls_conf,
 		);
@@ -299,22 +337,29 @@ impl Server {
 			stop_state.clone(),
 		)?;
 
+		let pool_persist_thread = pool_persist::persist_pool(
+			config.pool_config.persist_interval_secs,
+			tx_pool.clone(),
+			config.db_root.clone(),
+			stop_state.clone(),
+		)?;
+
 		warn!("Grin server started.");
 		Ok(Server {
 			config,
 			p2p: p2p_server,
 			chain: shared_chain,
 			tx_pool,
+			pool_net_adapter,
 			verifier_cache,
 			sync_state,
-			state_info: ServerStateInfo {
-				..Default::default()
-			},
+			state_info,
 			stop_state,
 			lock_file,
 			connect_thread,
 			sync_thread,
 			dandelion_thread,
+			pool_persist_thread,
 		})
 	}
 
@@ -337,6 +382,44 @@ impl Server {
 		self.p2p.peers.peer_count()
 	}
 
+	/// Peer the Dandelion stem txs from the provided source (identified as in
+	/// `TxSource`) get relayed to in the current epoch, mostly useful for tests
+	pub fn dandelion_relay(&self, identifier: &str) -> Option<PeerAddr> {
+		self.pool_net_adapter
+			.relay_peer(identifier)
+			.map(|p| p.info.addr)
+	}
+
+	/// Whether the current Dandelion epoch is over (or didn't start yet),
+	/// mostly useful for tests
+	pub fn dandelion_epoch_expired(&self) -> bool {
+		self.pool_net_adapter.is_expired()
+	}
+
+	/// Adds a tx to our pool as if it was pushed through the API, stemming
+	/// it or broadcasting it to our peers. Mostly useful for tests.
+	pub fn push_tx(&self, tx: Transaction, stem: bool) -> Result<(), Error> {
+		let source = pool::TxSource {
+			debug_name: "push-api".to_string(),
+			identifier: "push-api".to_string(),
+		};
+		let header = self.chain.head_header()?;
+		self.tx_pool
+			.write()
+			.add_to_pool(source, tx, stem, &header)
+			.map_err(|e| e.into())
+	}
+
+	/// Last known status of the tx with the provided kernel excess in our
+	/// pool, mostly useful for tests
+	pub fn tx_status(&self, excess: &Commitment) -> Option<pool::TxStatus> {
+		self.tx_pool
+			.read()
+			.tx_tracker
+			.status(excess)
+			.map(|t| t.status)
+	}
+
 	/// Start a minimal "stratum" mining service on a separate thread
 	pub fn start_stratum_server(&self, config: StratumServerConfig) {
 		let edge_bits = global::min_edge_bits();
@@ -402,6 +485,29 @@ impl Server {
 			});
 	}
 
+	/// Mines `count` blocks with the internal miner on the calling thread,
+	/// returning their hashes once they've all been added to the chain.
+	/// Rewards are burnt. Only meant for automated testing.
+	pub fn mine_test_blocks(&self, count: u64) -> Vec<Hash> {
+		let config = StratumServerConfig {
+			attempt_time_per_block: 2,
+			burn_reward: true,
+			enable_stratum_server: None,
+			stratum_server_addr: None,
+			wallet_listener_url: String::new(),
+			minimum_share_difficulty: 1,
+		};
+		let mut miner = Miner::new(
+			config,
+			self.chain.clone(),
+			self.tx_pool.clone(),
+			self.verifier_cache.clone(),
+			self.stop_state.clone(),
+		);
+		miner.set_debug_output_id(format!("Port {}", self.config.p2p_config.port));
+		miner.mine_blocks(count)
+	}
+
 	/// The chain head
 	pub fn head(&self) -> Result<chain::Tip, Error> {
 		self.chain.head().map_err(|e| e.into())
@@ -497,9 +603,11 @@ impl Server {
 			head: self.head()?,
 			header_head: self.header_head()?,
 			sync_status: self.sync_state.status(),
+			sync_stages: self.sync_state.sync_stages(),
 			stratum_stats: stratum_stats,
 			peer_stats: peer_stats,
 			diff_stats: diff_stats,
+			network_alerts: self.state_info.network_alerts.read().clone(),
 		})
 	}
 
@@ -527,6 +635,11 @@ impl Server {
 				Err(e) => error!("failed to join to dandelion_monitor thread: {:?}", e),
 				Ok(_) => info!("dandelion_monitor thread stopped"),
 			}
+
+			match self.pool_persist_thread.join() {
+				Err(e) => error!("failed to join to pool_persist thread: {:?}", e),
+				Ok(_) => info!("pool_persist thread stopped"),
+			}
 		}
 		// this call is blocking and makes sure all peers stop, however
 		// we can't be sure that we stoped a listener blocked on accept, so we don't join the p2p thread
@@ -14,13 +14,23 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::cmp;
 use std::sync::Arc;
 
 use crate::chain;
+use crate::common::header_skeleton::{HeaderSkeleton, SkeletonRequest};
 use crate::common::types::{Error, SyncState, SyncStatus};
 use crate::core::core::hash::{Hash, Hashed};
 use crate::p2p::{self, types::ReasonForBan, Peer};
 
+/// Minimum number of header ranges we need to be behind to sync headers
+/// against a skeleton, from several peers at once.
+const MIN_SKELETON_RANGES: u64 = 4;
+
+/// How long we wait before asking for another skeleton when the previous
+/// one didn't work out.
+const SKELETON_RETRY_SECS: i64 = 60;
+
 pub struct HeaderSync {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -31,6 +41,8 @@ pub struct HeaderSync {
 
 	syncing_peer: Option<Arc<Peer>>,
 	stalling_ts: Option<DateTime<Utc>>,
+
+	prev_skeleton_request: Option<DateTime<Utc>>,
 }
 
 impl HeaderSync {
@@ -47,6 +59,7 @@ impl HeaderSync {
 			prev_header_sync: (Utc::now(), 0, 0),
 			syncing_peer: None,
 			stalling_ts: None,
+			prev_skeleton_request: None,
 		}
 	}
 
@@ -55,6 +68,17 @@ impl HeaderSync {
 		header_head: &chain::Tip,
 		highest_height: u64,
 	) -> Result<bool, chain::Error> {
+		if let Some(skeleton) = self.sync_state.header_skeleton() {
+			return Ok(self.skeleton_sync(&skeleton, header_head, highest_height));
+		}
+		if let Some(request) = self.sync_state.skeleton_request() {
+			if !request.is_expired() {
+				return Ok(false);
+			}
+			debug!("sync: no header skeleton from {}", request.peer);
+			self.sync_state.set_skeleton_request(None);
+		}
+
 		if !self.header_sync_due(header_head) {
 			return Ok(false);
 		}
@@ -98,6 +122,9 @@ impl HeaderSync {
 				highest_height: highest_height,
 			});
 
+			if self.request_skeleton()? {
+				return Ok(true);
+			}
 			self.syncing_peer = self.header_sync();
 			return Ok(true);
 		}
@@ -175,6 +202,117 @@ impl HeaderSync {
 		}
 	}
 
+	// Asks our most work peer for a skeleton of its header chain when we're
+	// far enough behind, to get the headers in between from several peers.
+	fn request_skeleton(&mut self) -> Result<bool, chain::Error> {
+		if let Some(prev) = self.prev_skeleton_request {
+			if Utc::now() - prev < Duration::seconds(SKELETON_RETRY_SECS) {
+				return Ok(false);
+			}
+		}
+		let peer = match self.peers.most_work_peer() {
+			Some(peer) => peer,
+			None => return Ok(false),
+		};
+		if !peer.supports_header_skeleton() {
+			return Ok(false);
+		}
+
+		let sync_head = self.chain.get_sync_head()?;
+		let range = p2p::MAX_BLOCK_HEADERS as u64;
+		let ranges = peer.info.height().saturating_sub(sync_head.height) / range;
+		let helpers = self
+			.peers
+			.connected_peers()
+			.iter()
+			.filter(|p| p.info.height() >= sync_head.height + range)
+			.count();
+		if ranges < MIN_SKELETON_RANGES || helpers < 2 {
+			return Ok(false);
+		}
+
+		let start = self.chain.get_block_header(&sync_head.last_block_h)?;
+		let count = cmp::min(ranges, p2p::MAX_SKELETON_HEADERS as u64) as u16;
+		self.prev_skeleton_request = Some(Utc::now());
+		self.sync_state.set_skeleton_request(Some(SkeletonRequest {
+			peer: peer.info.addr,
+			start_hash: start.hash(),
+			start_height: start.height,
+			start_difficulty: start.total_difficulty(),
+			count,
+			time: Utc::now(),
+		}));
+		if let Err(e) = peer.send_header_skeleton_request(start.hash(), count) {
+			debug!(
+				"sync: send_header_skeleton_request to {} err! {:?}",
+				peer.info.addr, e
+			);
+			self.sync_state.set_skeleton_request(None);
+			return Ok(false);
+		}
+		Ok(true)
+	}
+
+	// Requests the ranges of the skeleton we still need from our peers and
+	// applies the ones received. Back to syncing from a single peer once
+	// the skeleton is done, or when it stalls.
+	fn skeleton_sync(
+		&mut self,
+		skeleton: &HeaderSkeleton,
+		header_head: &chain::Tip,
+		highest_height: u64,
+	) -> bool {
+		// ranges may be left over when received while another one was being
+		// applied
+		skeleton.apply(&self.chain, &self.peers);
+
+		let done = skeleton.is_done();
+		if done || skeleton.is_stalled() {
+			let (applied, total) = skeleton.progress();
+			if done {
+				// we can go for the next skeleton right away
+				self.prev_skeleton_request = None;
+			} else {
+				info!(
+					"sync: header skeleton from {} stalled at {}/{} ranges",
+					skeleton.peer(),
+					applied,
+					total
+				);
+			}
+			self.sync_state.set_header_skeleton(None);
+			// ask for more headers right away
+			self.prev_header_sync = (Utc::now(), 0, 0);
+			return true;
+		}
+
+		if let SyncStatus::HeaderSync { .. } = self.sync_state.status() {
+			self.sync_state.update(SyncStatus::HeaderSync {
+				current_height: header_head.height,
+				highest_height,
+			});
+		}
+
+		let candidates = self
+			.peers
+			.connected_peers()
+			.iter()
+			.map(|p| (p.info.addr, p.info.height()))
+			.collect::<Vec<_>>();
+		for (addr, hash) in skeleton.next_requests(&candidates) {
+			if let Some(peer) = self.peers.get_connected_peer(addr) {
+				debug!(
+					"sync: request_headers: asking {} for headers following {}",
+					addr, hash
+				);
+				if let Err(e) = peer.send_header_request(vec![hash]) {
+					debug!("sync: send_header_request to {} err! {:?}", addr, e);
+				}
+			}
+		}
+		true
+	}
+
 	fn header_sync(&mut self) -> Option<Arc<Peer>> {
 		if let Ok(header_head) = self.chain.header_head() {
 			let difficulty = header_head.total_difficulty;
@@ -14,13 +14,40 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::chain;
+use crate::chain::{self, txhashset::SegmentType};
+use crate::common::txhashset_segments::TxHashSetSegments;
 use crate::common::types::{Error, SyncState, SyncStatus};
-use crate::core::core::hash::Hashed;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::SegmentIdentifier;
+use crate::core::core::BlockHeader;
 use crate::core::global;
-use crate::p2p::{self, Peer};
+use crate::p2p::{self, Peer, PeerAddr, ReasonForBan};
+
+/// Maximum number of peers we ask for a txhashset manifest at once.
+const MAX_MANIFEST_PEERS: usize = 4;
+
+/// How long we wait for a matching manifest before asking again.
+const MANIFEST_REQUEST_INTERVAL_SECS: i64 = 30;
+
+/// How many segments of each MMR we want in flight (or waiting to be
+/// applied) at once when rebuilding the txhashset from segments.
+const MAX_DESIRED_SEGMENTS: usize = 16;
+
+/// Maximum number of segment requests in flight to a single peer.
+const MAX_SEGMENT_REQUESTS_PER_PEER: usize = 8;
+
+/// How long we wait for a segment before asking someone else.
+const SEGMENT_REQUEST_TIMEOUT_SECS: i64 = 60;
+
+/// Maximum number of peers we ask for the output leaf set at once.
+const MAX_OUTPUT_BITMAP_PEERS: usize = 4;
+
+/// How long we wait for a peer agreeing with our output leaf set before
+/// asking again.
+const OUTPUT_BITMAP_REQUEST_INTERVAL_SECS: i64 = 30;
 
 /// Fast sync has 3 "states":
 /// * syncing headers
@@ -35,6 +62,18 @@ pub struct StateSync {
 
 	prev_state_sync: Option<DateTime<Utc>>,
 	state_sync_peer: Option<Arc<Peer>>,
+
+	// chunked download, when peers support it
+	segments: Option<Arc<TxHashSetSegments>>,
+	download_start: DateTime<Utc>,
+	prev_manifest_request: Option<DateTime<Utc>>,
+	// only resume a download interrupted by a restart on our first attempt
+	resume_interrupted: bool,
+
+	// segmented MMR download (PIBD), when peers support it
+	pibd: bool,
+	segment_requests: HashMap<(SegmentType, SegmentIdentifier), (PeerAddr, DateTime<Utc>)>,
+	prev_bitmap_request: Option<DateTime<Utc>>,
 }
 
 impl StateSync {
@@ -49,6 +88,13 @@ impl StateSync {
 			chain,
 			prev_state_sync: None,
 			state_sync_peer: None,
+			segments: None,
+			download_start: Utc::now(),
+			prev_manifest_request: None,
+			resume_interrupted: true,
+			pibd: false,
+			segment_requests: HashMap::new(),
+			prev_bitmap_request: None,
 		}
 	}
 
@@ -126,11 +172,8 @@ impl StateSync {
 
 			if go {
 				self.state_sync_peer = None;
-				match self.request_state(&header_head) {
-					Ok(peer) => {
-						self.state_sync_peer = Some(peer);
-					}
-					Err(e) => self.sync_state.set_sync_error(Error::P2P(e)),
+				if let Err(e) = self.request_state(&header_head) {
+					self.sync_state.set_sync_error(Error::P2P(e));
 				}
 
 				// to avoid the confusing log,
@@ -155,37 +198,42 @@ impl StateSync {
 				});
 			}
 		}
+
+		if let Some(segments) = self.segments.clone() {
+			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+				self.download_segments(&segments);
+			}
+		}
+		if self.pibd {
+			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+				self.download_mmr_segments();
+			}
+		}
 		true
 	}
 
-	fn request_state(&self, header_head: &chain::Tip) -> Result<Arc<Peer>, p2p::Error> {
-		let threshold = global::state_sync_threshold() as u64;
+	fn request_state(&mut self, header_head: &chain::Tip) -> Result<(), p2p::Error> {
+		if self
+			.peers
+			.most_work_peers()
+			.iter()
+			.any(|p| p.supports_segments())
+		{
+			return self.request_mmr_segments(header_head);
+		}
+
+		let segment_peers = self
+			.peers
+			.most_work_peers()
+			.into_iter()
+			.filter(|p| p.supports_txhashset_segments())
+			.collect::<Vec<_>>();
+		if !segment_peers.is_empty() {
+			return self.request_segmented_state(header_head, &segment_peers);
+		}
 
 		if let Some(peer) = self.peers.most_work_peer() {
-			// ask for txhashset at state_sync_threshold
-			let mut txhashset_head = self
-				.chain
-				.get_block_header(&header_head.prev_block_h)
-				.map_err(|e| {
-					error!(
-						"chain error during getting a block header {}: {:?}",
-						&header_head.prev_block_h, e
-					);
-					p2p::Error::Internal
-				})?;
-			for _ in 0..threshold {
-				txhashset_head = self
-					.chain
-					.get_previous_header(&txhashset_head)
-					.map_err(|e| {
-						error!(
-							"chain error during getting a previous block header {}: {:?}",
-							txhashset_head.hash(),
-							e
-						);
-						p2p::Error::Internal
-					})?;
-			}
+			let txhashset_head = self.txhashset_head(header_head)?;
 			let bhash = txhashset_head.hash();
 			debug!(
 				"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
@@ -198,11 +246,369 @@ impl StateSync {
 				error!("state_sync: send_txhashset_request err! {:?}", e);
 				return Err(e);
 			}
-			return Ok(peer.clone());
+			self.state_sync_peer = Some(peer);
+			return Ok(());
 		}
 		Err(p2p::Error::PeerException)
 	}
 
+	// Starts (or resumes) a chunked download, asking the peers for the
+	// manifest of their txhashset archive.
+	fn request_segmented_state(
+		&mut self,
+		header_head: &chain::Tip,
+		peers: &[Arc<Peer>],
+	) -> Result<(), p2p::Error> {
+		let tmp_dir = self.chain.get_tmp_dir();
+		let interrupted = if self.resume_interrupted {
+			TxHashSetSegments::interrupted(&tmp_dir).filter(|(h, height)| {
+				header_head.height.saturating_sub(*height) < global::cut_through_horizon() as u64
+					&& self
+						.chain
+						.get_block_header(h)
+						.and_then(|header| self.chain.is_on_current_chain(&header))
+						.is_ok()
+			})
+		} else {
+			None
+		};
+		self.resume_interrupted = false;
+		let (hash, height) = match interrupted {
+			Some(target) => target,
+			None => {
+				let header = self.txhashset_head(header_head)?;
+				(header.hash(), header.height)
+			}
+		};
+
+		let segments = TxHashSetSegments::open(&tmp_dir, hash, height).map_err(|e| {
+			error!("state_sync: failed to open txhashset segments: {:?}", e);
+			p2p::Error::Internal
+		})?;
+		let segments = Arc::new(segments);
+		debug!(
+			"state_sync: chunked txhashset download for {} at {}, header head: {} / {}",
+			hash, height, header_head.height, header_head.last_block_h,
+		);
+		self.sync_state
+			.set_txhashset_segments(Some(segments.clone()));
+		self.segments = Some(segments);
+		self.download_start = Utc::now();
+		self.request_manifests(hash, height, peers);
+		Ok(())
+	}
+
+	// Starts rebuilding the txhashset from segments of its MMRs, each one
+	// validated against the header roots as soon as it's received.
+	fn request_mmr_segments(&mut self, header_head: &chain::Tip) -> Result<(), p2p::Error> {
+		let header = self.txhashset_head(header_head)?;
+		let desegmenter = self.chain.desegmenter(&header).map_err(|e| {
+			error!("state_sync: failed to start txhashset desegmenter: {:?}", e);
+			p2p::Error::Internal
+		})?;
+		debug!(
+			"state_sync: segmented txhashset download for {} at {}, header head: {} / {}",
+			header.hash(),
+			header.height,
+			header_head.height,
+			header_head.last_block_h,
+		);
+		*self.sync_state.desegmenter().write() = Some(desegmenter);
+		self.sync_state.clear_leaf_set_peers();
+		self.segment_requests.clear();
+		self.prev_bitmap_request = None;
+		self.pibd = true;
+		self.download_start = Utc::now();
+		Ok(())
+	}
+
+	// Spreads requests for the segments we still need over the peers serving
+	// them, tracks progress and writes the txhashset once it's complete.
+	fn download_mmr_segments(&mut self) {
+		let (desired, block_hash, complete, (applied, total)) = {
+			let desegmenter = self.sync_state.desegmenter();
+			let desegmenter = desegmenter.read();
+			match desegmenter.as_ref() {
+				Some(d) => (
+					d.next_desired_segments(MAX_DESIRED_SEGMENTS),
+					d.header().hash(),
+					d.is_complete(),
+					d.progress(),
+				),
+				// dropped on error, the sync error takes it from here
+				None => return,
+			}
+		};
+		if complete {
+			self.write_mmr_segments();
+			return;
+		}
+
+		let now = Utc::now();
+		let timeout = Duration::seconds(SEGMENT_REQUEST_TIMEOUT_SECS);
+		let desired_set = desired.iter().cloned().collect::<HashSet<_>>();
+		self.segment_requests
+			.retain(|k, (_, t)| desired_set.contains(k) && now - *t < timeout);
+
+		let peers = self
+			.peers
+			.most_work_peers()
+			.into_iter()
+			.filter(|p| p.supports_segments())
+			.collect::<Vec<_>>();
+
+		// Output and rangeproof segments are checked against the output leaf
+		// set, we only ask for them to peers that sent us the same one.
+		let leaf_set_peers = self.sync_state.leaf_set_peers();
+		if !peers.iter().any(|p| leaf_set_peers.contains(&p.info.addr)) {
+			let due = self
+				.prev_bitmap_request
+				.map(|t| now - t > Duration::seconds(OUTPUT_BITMAP_REQUEST_INTERVAL_SECS))
+				.unwrap_or(true);
+			if due {
+				self.request_output_bitmaps(block_hash, &peers);
+			}
+		}
+
+		let mut in_flight = HashMap::new();
+		for (addr, _) in self.segment_requests.values() {
+			*in_flight.entry(*addr).or_insert(0) += 1;
+		}
+		for key in desired {
+			if self.segment_requests.contains_key(&key) {
+				continue;
+			}
+			let (segment_type, identifier) = key;
+			let peer = peers
+				.iter()
+				.filter(|p| {
+					segment_type == SegmentType::Kernel || leaf_set_peers.contains(&p.info.addr)
+				})
+				.filter(|p| {
+					in_flight.get(&p.info.addr).cloned().unwrap_or(0)
+						< MAX_SEGMENT_REQUESTS_PER_PEER
+				})
+				.min_by_key(|p| in_flight.get(&p.info.addr).cloned().unwrap_or(0));
+			let peer = match peer {
+				Some(peer) => peer,
+				None => continue,
+			};
+			if let Err(e) = peer.send_segment_request(segment_type, block_hash, identifier) {
+				debug!(
+					"state_sync: send_segment_request to {} err! {:?}",
+					peer.info.addr, e
+				);
+				continue;
+			}
+			*in_flight.entry(peer.info.addr).or_insert(0) += 1;
+			self.segment_requests.insert(key, (peer.info.addr, now));
+		}
+
+		// progress is reported in segments rather than bytes here
+		if let SyncStatus::TxHashsetDownload {
+			update_time,
+			downloaded_size: prev_downloaded_size,
+			..
+		} = self.sync_state.status()
+		{
+			if applied != prev_downloaded_size {
+				// the download timeout only applies when not making progress
+				self.prev_state_sync = Some(now);
+				self.sync_state
+					.update_txhashset_download(SyncStatus::TxHashsetDownload {
+						start_time: self.download_start,
+						prev_update_time: update_time,
+						update_time: now,
+						prev_downloaded_size,
+						downloaded_size: applied,
+						total_size: total,
+					});
+			}
+		}
+	}
+
+	fn write_mmr_segments(&mut self) {
+		self.pibd = false;
+		self.segment_requests.clear();
+		let desegmenter = match self.sync_state.desegmenter().write().take() {
+			Some(desegmenter) => desegmenter,
+			None => return,
+		};
+		let hash = desegmenter.header().hash();
+		match self
+			.chain
+			.txhashset_write_segments(desegmenter, self.sync_state.as_ref())
+		{
+			Ok(_) => info!("Rebuilt valid txhashset from segments for {}.", hash),
+			Err(e) => {
+				// every segment was checked against the header roots and the
+				// output leaf set, the latter is what's left to blame
+				self.chain.clean_txhashset_sandbox();
+				error!("Failed to save txhashset rebuilt from segments: {}", e);
+				if e.is_bad_data() {
+					for addr in self.sync_state.leaf_set_peers() {
+						self.peers.ban_peer(addr, ReasonForBan::BadTxHashSet);
+					}
+				}
+				self.sync_state.set_sync_error(Error::Chain(e));
+			}
+		}
+	}
+
+	fn request_output_bitmaps(&mut self, block_hash: Hash, peers: &[Arc<Peer>]) {
+		for peer in peers.iter().take(MAX_OUTPUT_BITMAP_PEERS) {
+			if let Err(e) = peer.send_output_bitmap_request(block_hash) {
+				debug!(
+					"state_sync: send_output_bitmap_request to {} err! {:?}",
+					peer.info.addr, e
+				);
+			}
+		}
+		self.prev_bitmap_request = Some(Utc::now());
+	}
+
+	fn request_manifests(&mut self, hash: Hash, height: u64, peers: &[Arc<Peer>]) {
+		for peer in peers.iter().take(MAX_MANIFEST_PEERS) {
+			if let Err(e) = peer.send_txhashset_manifest_request(height, hash) {
+				debug!(
+					"state_sync: send_txhashset_manifest_request to {} err! {:?}",
+					peer.info.addr, e
+				);
+			}
+		}
+		self.prev_manifest_request = Some(Utc::now());
+	}
+
+	// Requests the next segments from our peers, tracks download progress
+	// and writes the txhashset once all segments are in.
+	fn download_segments(&mut self, segments: &Arc<TxHashSetSegments>) {
+		let peers = self.peers.clone();
+		segments.retain_peers(|addr| {
+			peers
+				.get_connected_peer(*addr)
+				.map(|p| p.is_connected())
+				.unwrap_or(false)
+		});
+
+		let now = Utc::now();
+		if segments.manifest_peers().is_empty() {
+			let due = self
+				.prev_manifest_request
+				.map(|t| now - t > Duration::seconds(MANIFEST_REQUEST_INTERVAL_SECS))
+				.unwrap_or(true);
+			if due {
+				let candidates = self
+					.peers
+					.most_work_peers()
+					.into_iter()
+					.filter(|p| p.supports_txhashset_segments())
+					.collect::<Vec<_>>();
+				self.request_manifests(segments.hash(), segments.height(), &candidates);
+			}
+		}
+
+		for (addr, index) in segments.next_requests() {
+			if let Some(peer) = self.peers.get_connected_peer(addr) {
+				if let Err(e) = peer.send_txhashset_segment_request(segments.hash(), index) {
+					debug!(
+						"state_sync: send_txhashset_segment_request to {} err! {:?}",
+						addr, e
+					);
+				}
+			}
+		}
+
+		let (downloaded_size, total_size) = segments.downloaded_size();
+		if let SyncStatus::TxHashsetDownload {
+			update_time,
+			downloaded_size: prev_downloaded_size,
+			..
+		} = self.sync_state.status()
+		{
+			if downloaded_size != prev_downloaded_size {
+				// the download timeout only applies when not making progress
+				self.prev_state_sync = Some(now);
+				self.sync_state
+					.update_txhashset_download(SyncStatus::TxHashsetDownload {
+						start_time: self.download_start,
+						prev_update_time: update_time,
+						update_time: now,
+						prev_downloaded_size,
+						downloaded_size,
+						total_size,
+					});
+			}
+		}
+
+		if segments.is_complete() {
+			self.write_segments(segments);
+		}
+	}
+
+	fn write_segments(&mut self, segments: &TxHashSetSegments) {
+		self.segments = None;
+		self.sync_state.set_txhashset_segments(None);
+
+		let txhashset_data = match segments.assemble() {
+			Ok(file) => file,
+			Err(e) => {
+				error!("state_sync: failed to assemble txhashset segments: {:?}", e);
+				segments.clean();
+				self.sync_state
+					.set_sync_error(Error::P2P(p2p::Error::Internal));
+				return;
+			}
+		};
+
+		let res =
+			self.chain
+				.txhashset_write(segments.hash(), txhashset_data, self.sync_state.as_ref());
+		segments.clean();
+		match res {
+			Ok(_) => info!("Received valid txhashset data for {}.", segments.hash()),
+			Err(e) => {
+				self.chain.clean_txhashset_sandbox();
+				error!("Failed to save txhashset archive: {}", e);
+				if e.is_bad_data() {
+					for addr in segments.manifest_peers() {
+						self.peers.ban_peer(addr, ReasonForBan::BadTxHashSet);
+					}
+				}
+				self.sync_state.set_sync_error(Error::Chain(e));
+			}
+		}
+	}
+
+	// The txhashset we ask for, state_sync_threshold blocks behind our
+	// header head.
+	fn txhashset_head(&self, header_head: &chain::Tip) -> Result<BlockHeader, p2p::Error> {
+		let threshold = global::state_sync_threshold() as u64;
+		let mut txhashset_head = self
+			.chain
+			.get_block_header(&header_head.prev_block_h)
+			.map_err(|e| {
+				error!(
+					"chain error during getting a block header {}: {:?}",
+					&header_head.prev_block_h, e
+				);
+				p2p::Error::Internal
+			})?;
+		for _ in 0..threshold {
+			txhashset_head = self
+				.chain
+				.get_previous_header(&txhashset_head)
+				.map_err(|e| {
+					error!(
+						"chain error during getting a previous block header {}: {:?}",
+						txhashset_head.hash(),
+						e
+					);
+					p2p::Error::Internal
+				})?;
+		}
+		Ok(txhashset_head)
+	}
+
 	// For now this is a one-time thing (it can be slow) at initial startup.
 	fn state_sync_due(&mut self) -> (bool, bool) {
 		let now = Utc::now();
@@ -225,5 +631,13 @@ impl StateSync {
 	fn state_sync_reset(&mut self) {
 		self.prev_state_sync = None;
 		self.state_sync_peer = None;
+		self.segments = None;
+		self.prev_manifest_request = None;
+		self.sync_state.set_txhashset_segments(None);
+		self.pibd = false;
+		self.segment_requests.clear();
+		self.prev_bitmap_request = None;
+		*self.sync_state.desegmenter().write() = None;
+		self.sync_state.clear_leaf_set_peers();
 	}
 }
@@ -38,6 +38,7 @@ use grin_util as util;
 pub mod common;
 mod grin;
 mod mining;
+pub mod simulation;
 
 pub use crate::common::stats::{DiffBlock, PeerStats, ServerStats, StratumStats, WorkerStats};
 pub use crate::common::types::{ServerConfig, StratumServerConfig};
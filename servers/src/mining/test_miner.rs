@@ -189,4 +189,41 @@ impl Miner {
 
 		info!("(Server ID: {}) test miner exit.", self.debug_output_id);
 	}
+
+	/// Mines blocks on top of the chain head, burning their reward, until
+	/// `count` of them have been added to the chain or we're asked to stop.
+	/// Returns the hashes of the mined blocks.
+	pub fn mine_blocks(&self, count: u64) -> Vec<Hash> {
+		let mut mined = vec![];
+		while (mined.len() as u64) < count && !self.stop_state.is_stopped() {
+			let head = self.chain.head_header().unwrap();
+			let mut latest_hash = head.hash();
+
+			let (mut b, _) = mine_block::get_block(
+				&self.chain,
+				&self.tx_pool,
+				self.verifier_cache.clone(),
+				None,
+				None,
+			);
+			if !self.inner_mining_loop(
+				&mut b,
+				&head,
+				self.config.attempt_time_per_block,
+				&mut latest_hash,
+			) {
+				continue;
+			}
+
+			let hash = b.hash();
+			match self.chain.process_block(b, chain::Options::MINE) {
+				Ok(_) => mined.push(hash),
+				Err(e) => error!(
+					"(Server ID: {}) Error validating mined block: {:?}",
+					self.debug_output_id, e
+				),
+			}
+		}
+		mined
+	}
 }
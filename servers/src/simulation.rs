@@ -0,0 +1,24 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local multi-node network simulation, to exercise relay, sync and reorg
+//! behaviour in integration tests. Nodes are full servers running in the same
+//! process, talking to each other over loopback links with configurable
+//! latency that can be cut to partition the network.
+
+mod link;
+mod network;
+
+pub use self::link::Link;
+pub use self::network::{Network, Topology};
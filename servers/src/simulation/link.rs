@@ -0,0 +1,189 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Loopback TCP relay standing between two simulated nodes, delaying the
+//! traffic going through it and cutting it on demand.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::util::{Mutex, RwLock};
+
+struct LinkState {
+	target: SocketAddr,
+	latency: RwLock<Duration>,
+	up: AtomicBool,
+	stopped: AtomicBool,
+	// both ends of every connection relayed, to cut them
+	streams: Mutex<Vec<TcpStream>>,
+}
+
+impl LinkState {
+	fn relay(state: &Arc<LinkState>, client: TcpStream) -> io::Result<()> {
+		if !state.up.load(Ordering::Relaxed) {
+			let _ = client.shutdown(Shutdown::Both);
+			return Ok(());
+		}
+		client.set_nonblocking(false)?;
+		let upstream = TcpStream::connect_timeout(&state.target, Duration::from_secs(2))?;
+		{
+			let mut streams = state.streams.lock();
+			streams.push(client.try_clone()?);
+			streams.push(upstream.try_clone()?);
+		}
+		LinkState::pump(state.clone(), client.try_clone()?, upstream.try_clone()?)?;
+		LinkState::pump(state.clone(), upstream, client)
+	}
+
+	// Forwards what's read on `from` to `to`, each chunk held back for the
+	// link latency at the time it was read. Closing either side closes both.
+	fn pump(state: Arc<LinkState>, mut from: TcpStream, mut to: TcpStream) -> io::Result<()> {
+		let (tx, rx) = mpsc::channel::<(Instant, Vec<u8>)>();
+		thread::Builder::new()
+			.name("sim_link_read".to_string())
+			.spawn(move || {
+				let mut buf = [0u8; 8192];
+				loop {
+					match from.read(&mut buf) {
+						Ok(0) | Err(_) => break,
+						Ok(n) => {
+							let at = Instant::now() + *state.latency.read();
+							if tx.send((at, buf[..n].to_vec())).is_err() {
+								break;
+							}
+						}
+					}
+				}
+				let _ = from.shutdown(Shutdown::Both);
+			})?;
+		thread::Builder::new()
+			.name("sim_link_write".to_string())
+			.spawn(move || {
+				for (at, data) in rx {
+					let now = Instant::now();
+					if at > now {
+						thread::sleep(at - now);
+					}
+					if to.write_all(&data).is_err() {
+						break;
+					}
+				}
+				let _ = to.shutdown(Shutdown::Both);
+			})?;
+		Ok(())
+	}
+
+	fn cut(&self) {
+		for stream in self.streams.lock().drain(..) {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+/// A relay listening on a loopback port and forwarding all connections it
+/// accepts to a target address. Traffic is delayed by the link latency, and
+/// while the link is down existing connections are closed and new ones
+/// refused.
+pub struct Link {
+	addr: SocketAddr,
+	state: Arc<LinkState>,
+}
+
+impl Link {
+	/// Starts relaying to the provided target address.
+	pub fn new(target: SocketAddr, latency: Duration) -> io::Result<Link> {
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		listener.set_nonblocking(true)?;
+		let addr = listener.local_addr()?;
+		let state = Arc::new(LinkState {
+			target,
+			latency: RwLock::new(latency),
+			up: AtomicBool::new(true),
+			stopped: AtomicBool::new(false),
+			streams: Mutex::new(vec![]),
+		});
+
+		let accept_state = state.clone();
+		thread::Builder::new()
+			.name("sim_link".to_string())
+			.spawn(move || loop {
+				if accept_state.stopped.load(Ordering::Relaxed) {
+					break;
+				}
+				match listener.accept() {
+					Ok((client, _)) => {
+						if let Err(e) = LinkState::relay(&accept_state, client) {
+							debug!("sim link to {}: failed to relay: {:?}", target, e);
+						}
+					}
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+						thread::sleep(Duration::from_millis(10));
+					}
+					Err(e) => {
+						debug!("sim link to {}: failed to accept: {:?}", target, e);
+						thread::sleep(Duration::from_millis(10));
+					}
+				}
+			})?;
+
+		Ok(Link { addr, state })
+	}
+
+	/// Address to connect to for the traffic to go through the link.
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// Address the link forwards to.
+	pub fn target(&self) -> SocketAddr {
+		self.state.target
+	}
+
+	/// Whether the link lets traffic through.
+	pub fn is_up(&self) -> bool {
+		self.state.up.load(Ordering::Relaxed)
+	}
+
+	/// Current latency of the link, in each direction.
+	pub fn latency(&self) -> Duration {
+		*self.state.latency.read()
+	}
+
+	/// Changes the latency, applied to whatever is read from now on.
+	pub fn set_latency(&self, latency: Duration) {
+		*self.state.latency.write() = latency;
+	}
+
+	/// Brings the link down, closing all connections going through it.
+	pub fn cut(&self) {
+		self.state.up.store(false, Ordering::Relaxed);
+		self.state.cut();
+	}
+
+	/// Brings the link back up. Connections have to be opened again.
+	pub fn restore(&self) {
+		self.state.up.store(true, Ordering::Relaxed);
+	}
+}
+
+impl Drop for Link {
+	fn drop(&mut self) {
+		self.state.stopped.store(true, Ordering::Relaxed);
+		self.cut();
+	}
+}
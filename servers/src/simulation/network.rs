@@ -0,0 +1,388 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A network of in-process nodes connected through simulated links.
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chain::Tip;
+use crate::common::types::{Error, ServerConfig};
+use crate::core::core::hash::Hash;
+use crate::core::global::{self, ChainTypes};
+use crate::grin::server::Server;
+use crate::p2p::{self, PeerAddr};
+use crate::simulation::link::Link;
+
+/// How long we try to (re)connect nodes for before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the nodes of a simulated network are connected to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topology {
+	/// Each node connected to the next one
+	Line,
+	/// A line whose last node connects back to the first one
+	Ring,
+	/// All nodes connected to the first one
+	Star,
+	/// All nodes connected to each other
+	Full,
+	/// Explicit connections, the first node of each pair connecting out to
+	/// the second one
+	Custom(Vec<(usize, usize)>),
+}
+
+impl Topology {
+	/// Connections between `count` nodes, as (outbound, inbound) pairs.
+	pub fn edges(&self, count: usize) -> Vec<(usize, usize)> {
+		let mut edges = match self {
+			Topology::Line => (1..count).map(|i| (i - 1, i)).collect(),
+			Topology::Ring => {
+				let mut edges = Topology::Line.edges(count);
+				if count > 2 {
+					edges.push((count - 1, 0));
+				}
+				edges
+			}
+			Topology::Star => (1..count).map(|i| (i, 0)).collect(),
+			Topology::Full => (0..count)
+				.flat_map(|i| (i + 1..count).map(move |j| (i, j)))
+				.collect(),
+			Topology::Custom(edges) => edges.clone(),
+		};
+		edges.retain(|&(a, b)| a != b && a < count && b < count);
+		edges.dedup();
+		edges
+	}
+}
+
+struct Edge {
+	from: usize,
+	to: usize,
+	link: Link,
+}
+
+/// A set of servers running in-process on `AutomatedTesting`, each on its
+/// own loopback port, connected to each other through links that can be
+/// slowed down or cut to simulate latency and network partitions.
+///
+/// Seeding is programmatic, nodes only connect along the topology edges.
+/// As there's no seeder monitoring peers either, the network pings all of
+/// them whenever it checks on the nodes.
+pub struct Network {
+	dir: String,
+	nodes: Vec<Option<Server>>,
+	edges: Vec<Edge>,
+}
+
+impl Network {
+	/// Starts `count` nodes under the provided directory and connects them
+	/// according to the topology.
+	pub fn start(dir: &str, count: usize, topology: Topology) -> Result<Network, Error> {
+		Network::start_with(dir, count, topology, |_, _| {})
+	}
+
+	/// Same as `start`, letting the caller adjust the configuration of each
+	/// node (by index) before it's started.
+	pub fn start_with<F>(
+		dir: &str,
+		count: usize,
+		topology: Topology,
+		mut configure: F,
+	) -> Result<Network, Error>
+	where
+		F: FnMut(usize, &mut ServerConfig),
+	{
+		global::set_mining_mode(ChainTypes::AutomatedTesting);
+		let _ = fs::remove_dir_all(dir);
+
+		let mut network = Network {
+			dir: dir.to_owned(),
+			nodes: vec![],
+			edges: vec![],
+		};
+		for n in 0..count {
+			let mut config = Network::node_config(dir, n)?;
+			configure(n, &mut config);
+			network.nodes.push(Some(Server::new(config)?));
+		}
+		for (from, to) in topology.edges(count) {
+			network.connect(from, to)?;
+		}
+		network.wait_connected()?;
+		Ok(network)
+	}
+
+	fn node_config(dir: &str, n: usize) -> Result<ServerConfig, Error> {
+		let p2p_config = p2p::P2PConfig {
+			host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+			port: free_port()?,
+			seeding_type: p2p::Seeding::Programmatic,
+			..p2p::P2PConfig::default()
+		};
+		Ok(ServerConfig {
+			db_root: format!("{}/node{}/.grin", dir, n),
+			api_http_addr: format!("127.0.0.1:{}", free_port()?),
+			api_secret_path: None,
+			chain_type: ChainTypes::AutomatedTesting,
+			skip_sync_wait: Some(true),
+			run_tui: Some(false),
+			run_test_miner: Some(false),
+			stratum_mining_config: None,
+			p2p_config,
+			..ServerConfig::default()
+		})
+	}
+
+	/// Directory the nodes keep their data in.
+	pub fn dir(&self) -> &str {
+		&self.dir
+	}
+
+	/// Number of nodes.
+	pub fn len(&self) -> usize {
+		self.nodes.len()
+	}
+
+	/// The node at the provided index.
+	pub fn node(&self, n: usize) -> &Server {
+		self.nodes[n].as_ref().expect("node stopped")
+	}
+
+	/// Connects node `from` to node `to` through a new link, with the
+	/// same latency as the first existing link.
+	pub fn connect(&mut self, from: usize, to: usize) -> Result<(), Error> {
+		let target = self.node(to).config.p2p_config.port;
+		let latency = self
+			.edges
+			.first()
+			.map(|e| e.link.latency())
+			.unwrap_or_default();
+		let link = Link::new(([127, 0, 0, 1], target).into(), latency)?;
+		self.open(from, &link)?;
+		self.edges.push(Edge { from, to, link });
+		Ok(())
+	}
+
+	// Opens a connection through the link, retrying while the node on the
+	// other end may still be starting up.
+	fn open(&self, from: usize, link: &Link) -> Result<(), Error> {
+		let deadline = Instant::now() + CONNECT_TIMEOUT;
+		loop {
+			match self.node(from).connect_peer(PeerAddr::Ip(link.addr())) {
+				Ok(_) => return Ok(()),
+				Err(e) => {
+					if Instant::now() > deadline {
+						return Err(e);
+					}
+					thread::sleep(Duration::from_millis(100));
+				}
+			}
+		}
+	}
+
+	/// Address node `from` knows node `to` by, when it connected out to it.
+	pub fn peer_addr(&self, from: usize, to: usize) -> Option<PeerAddr> {
+		self.edges
+			.iter()
+			.find(|e| (e.from, e.to) == (from, to))
+			.map(|e| PeerAddr::Ip(e.link.addr()))
+	}
+
+	/// Sets the latency of the link between the two nodes, in both
+	/// directions.
+	pub fn set_latency(&self, a: usize, b: usize, latency: Duration) {
+		for e in self.edges_between(a, b) {
+			e.link.set_latency(latency);
+		}
+	}
+
+	/// Sets the latency of all links.
+	pub fn set_latency_all(&self, latency: Duration) {
+		for e in &self.edges {
+			e.link.set_latency(latency);
+		}
+	}
+
+	/// Cuts the link between the two nodes.
+	pub fn cut(&self, a: usize, b: usize) -> Result<(), Error> {
+		for e in self.edges_between(a, b) {
+			e.link.cut();
+		}
+		self.wait_connected()
+	}
+
+	/// Splits the network, cutting all links between nodes that aren't in
+	/// the same group. Nodes not in any group end up isolated.
+	pub fn partition(&self, groups: &[&[usize]]) -> Result<(), Error> {
+		let group = |n: usize| groups.iter().position(|g| g.contains(&n));
+		for e in &self.edges {
+			if group(e.from).is_none() || group(e.from) != group(e.to) {
+				e.link.cut();
+			}
+		}
+		self.wait_connected()
+	}
+
+	/// Isolates a node from all the others.
+	pub fn isolate(&self, n: usize) -> Result<(), Error> {
+		for e in self.edges.iter().filter(|e| e.from == n || e.to == n) {
+			e.link.cut();
+		}
+		self.wait_connected()
+	}
+
+	/// Restores all cut links and reconnects the nodes through them.
+	pub fn heal(&self) -> Result<(), Error> {
+		for e in self.edges.iter().filter(|e| !e.link.is_up()) {
+			e.link.restore();
+			self.open(e.from, &e.link)?;
+		}
+		self.wait_connected()
+	}
+
+	// Waits for each node to be connected to exactly the peers it has a link
+	// up with, letting nodes notice the connections that were cut.
+	fn wait_connected(&self) -> Result<(), Error> {
+		let connected = self.wait_for(CONNECT_TIMEOUT, |net| {
+			(0..net.len()).all(|n| {
+				let expected = net
+					.edges
+					.iter()
+					.filter(|e| e.link.is_up() && (e.from == n || e.to == n))
+					.count();
+				net.node(n).peer_count() as usize == expected
+			})
+		});
+		if connected {
+			Ok(())
+		} else {
+			Err(Error::General(
+				"simulated network failed to (re)connect".to_owned(),
+			))
+		}
+	}
+
+	fn edges_between(&self, a: usize, b: usize) -> impl Iterator<Item = &Edge> {
+		self.edges
+			.iter()
+			.filter(move |e| (e.from, e.to) == (a, b) || (e.from, e.to) == (b, a))
+	}
+
+	/// Mines blocks on top of the chain of the provided node with the test
+	/// miner, returning their hashes once they've all been added.
+	pub fn mine(&self, n: usize, count: u64) -> Vec<Hash> {
+		self.node(n).mine_test_blocks(count)
+	}
+
+	/// Chain heads of all the nodes.
+	pub fn heads(&self) -> Vec<Tip> {
+		(0..self.len())
+			.map(|n| self.node(n).head().expect("failed to read head"))
+			.collect()
+	}
+
+	/// Polls the condition until it holds, for at most `timeout`. All nodes
+	/// ping their peers before each check so they keep exchanging their
+	/// heights and drop the connections that went away.
+	pub fn wait_for<F>(&self, timeout: Duration, mut cond: F) -> bool
+	where
+		F: FnMut(&Network) -> bool,
+	{
+		let deadline = Instant::now() + timeout;
+		loop {
+			for n in 0..self.len() {
+				let _ = self.node(n).ping_peers();
+			}
+			if cond(self) {
+				return true;
+			}
+			if Instant::now() > deadline {
+				return false;
+			}
+			thread::sleep(Duration::from_millis(100));
+		}
+	}
+
+	/// Whether the provided nodes all have the same chain head.
+	pub fn converged(&self, nodes: &[usize]) -> bool {
+		let mut heads = nodes.iter().map(|&n| self.node(n).head().ok());
+		match heads.next() {
+			Some(Some(first)) => heads.all(|h| h.as_ref() == Some(&first)),
+			Some(None) => false,
+			None => true,
+		}
+	}
+
+	/// Waits for the provided nodes to agree on their chain head.
+	pub fn wait_converged(&self, nodes: &[usize], timeout: Duration) -> bool {
+		self.wait_for(timeout, |net| net.converged(nodes))
+	}
+
+	/// Waits for the provided nodes to reach at least the given height.
+	pub fn wait_for_height(&self, nodes: &[usize], height: u64, timeout: Duration) -> bool {
+		self.wait_for(timeout, |net| {
+			nodes.iter().all(|&n| {
+				net.node(n)
+					.head()
+					.map(|h| h.height >= height)
+					.unwrap_or(false)
+			})
+		})
+	}
+
+	/// Asserts all nodes end up with the same chain head within the timeout,
+	/// panicking with every node's head otherwise.
+	pub fn assert_converged(&self, timeout: Duration) {
+		let all = (0..self.len()).collect::<Vec<_>>();
+		if !self.wait_converged(&all, timeout) {
+			let heads = self
+				.heads()
+				.iter()
+				.enumerate()
+				.map(|(n, h)| format!("node {}: {} at {}", n, h.last_block_h, h.height))
+				.collect::<Vec<_>>();
+			panic!("nodes did not converge:\n{}", heads.join("\n"));
+		}
+	}
+
+	/// Stops all nodes and links.
+	pub fn stop(mut self) {
+		self.stop_nodes();
+	}
+
+	fn stop_nodes(&mut self) {
+		self.edges.clear();
+		for node in self.nodes.iter_mut() {
+			if let Some(node) = node.take() {
+				node.stop();
+			}
+		}
+	}
+}
+
+impl Drop for Network {
+	fn drop(&mut self) {
+		self.stop_nodes();
+	}
+}
+
+// Port the OS considers free right now, for a node to listen on.
+fn free_port() -> Result<u16, Error> {
+	let listener = TcpListener::bind("127.0.0.1:0")?;
+	Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,259 @@
+// Copyright 2019 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_servers as servers;
+use grin_util as util;
+
+use chrono::prelude::{DateTime, NaiveDateTime, Utc};
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::core::core::{Block, Transaction};
+use crate::core::libtx::{self, build, ProofBuilder};
+use crate::core::{consensus, global, pow};
+use crate::keychain::{ExtKeychain, Identifier, Keychain};
+use crate::pool::{DandelionConfig, TxStatus};
+use crate::servers::simulation::{Network, Topology};
+use crate::servers::Server;
+
+fn clean_output_dir(dir_name: &str) {
+	let _ = fs::remove_dir_all(dir_name);
+}
+
+// Mines a block on top of the chain of the node, paying its reward to the
+// provided key so it can be spent afterwards.
+fn mine_reward_block(node: &Server, keychain: &ExtKeychain, key_id: &Identifier) {
+	let prev = node.chain.head_header().unwrap();
+	let next = consensus::next_difficulty(prev.height + 1, node.chain.difficulty_iter().unwrap());
+	let reward =
+		libtx::reward::output(keychain, &ProofBuilder::new(keychain), key_id, 0, false).unwrap();
+	let mut b = Block::new(&prev, vec![], next.difficulty, reward).unwrap();
+	let now = Utc::now().timestamp().max(prev.timestamp.timestamp() + 1);
+	b.header.timestamp = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now, 0), Utc);
+	b.header.pow.secondary_scaling = next.secondary_scaling;
+	node.chain.set_txhashset_roots(&mut b).unwrap();
+	pow::pow_size(
+		&mut b.header,
+		next.difficulty,
+		global::proofsize(),
+		global::min_edge_bits(),
+	)
+	.unwrap();
+	node.chain.process_block(b, chain::Options::MINE).unwrap();
+}
+
+// Tx spending the coinbase output of the provided key.
+fn spend_coinbase(keychain: &ExtKeychain, key_id: Identifier, out_id: Identifier) -> Transaction {
+	let fee = 10 * consensus::MILLI_GRIN;
+	build::transaction(
+		vec![
+			build::coinbase_input(consensus::REWARD, key_id),
+			build::output(consensus::REWARD - fee, out_id),
+			build::with_fee(fee),
+		],
+		keychain,
+		&ProofBuilder::new(keychain),
+	)
+	.unwrap()
+}
+
+#[test]
+fn topology_edges() {
+	assert_eq!(Topology::Line.edges(3), vec![(0, 1), (1, 2)]);
+	assert_eq!(Topology::Ring.edges(3), vec![(0, 1), (1, 2), (2, 0)]);
+	assert_eq!(Topology::Ring.edges(2), vec![(0, 1)]);
+	assert_eq!(Topology::Star.edges(3), vec![(1, 0), (2, 0)]);
+	assert_eq!(Topology::Full.edges(3), vec![(0, 1), (0, 2), (1, 2)]);
+	assert_eq!(
+		Topology::Custom(vec![(0, 0), (0, 2), (1, 5)]).edges(3),
+		vec![(0, 2)]
+	);
+}
+
+// Blocks mined on one end of a line of nodes get relayed all the way, each
+// hop delayed by the link latency.
+#[test]
+fn relay_along_line() {
+	util::init_test_logger();
+	let test_dir = "target/.grin_sim_relay";
+	{
+		let net = Network::start(test_dir, 3, Topology::Line).unwrap();
+		assert_eq!(net.node(1).peer_count(), 2);
+
+		net.mine(0, 3);
+		net.assert_converged(Duration::from_secs(60));
+		assert_eq!(net.node(2).head().unwrap().height, 3);
+
+		let latency = Duration::from_millis(300);
+		net.set_latency_all(latency);
+		net.mine(0, 1);
+		let start = Instant::now();
+		assert!(net.wait_for_height(&[2], 4, Duration::from_secs(60)));
+		assert!(start.elapsed() >= latency * 2);
+		net.assert_converged(Duration::from_secs(60));
+		net.stop();
+	}
+	clean_output_dir(test_dir);
+}
+
+// Both sides of a partition mine their own fork, once healed everyone
+// reorgs to the one with the most work.
+#[test]
+fn partition_and_reorg() {
+	util::init_test_logger();
+	let test_dir = "target/.grin_sim_partition";
+	{
+		let net = Network::start(test_dir, 4, Topology::Ring).unwrap();
+		net.mine(0, 2);
+		net.assert_converged(Duration::from_secs(60));
+
+		net.partition(&[&[0, 1], &[2, 3]]).unwrap();
+		assert_eq!(net.node(0).peer_count(), 1);
+		net.mine(0, 2);
+		let fork_tip = net.mine(2, 5).pop().unwrap();
+		assert!(net.wait_converged(&[0, 1], Duration::from_secs(60)));
+		assert!(net.wait_converged(&[2, 3], Duration::from_secs(60)));
+		assert!(!net.converged(&[0, 2]));
+
+		net.heal().unwrap();
+		assert_eq!(net.node(0).peer_count(), 2);
+		net.assert_converged(Duration::from_secs(120));
+		let head = net.node(1).head().unwrap();
+		assert_eq!(head.last_block_h, fork_tip);
+		assert_eq!(head.height, 7);
+		net.stop();
+	}
+	clean_output_dir(test_dir);
+}
+
+// Within an epoch, the stem txs of each source always go to the same one of
+// two outbound relays, the sources of a relay that goes away all moving to
+// the same replacement.
+#[test]
+fn dandelion_stem_routes() {
+	util::init_test_logger();
+	let test_dir = "target/.grin_sim_dandelion";
+	{
+		// node 0 connects out to nodes 1, 2 and 3, nodes 4 and 5 to node 0.
+		// Epochs outlast the test, so routes can't change under us, and nodes
+		// 1 to 3 fluff, holding on to the stem txs they get for as long.
+		let topology = Topology::Custom(vec![(0, 1), (0, 2), (0, 3), (4, 0), (5, 0)]);
+		let net = Network::start_with(test_dir, 6, topology, |n, config| {
+			let stem_probability = if (1..4).contains(&n) { 0 } else { 100 };
+			config.dandelion_config = DandelionConfig {
+				epoch_secs: Some(u16::max_value()),
+				embargo_secs: Some(u16::max_value()),
+				aggregation_secs: Some(u16::max_value()),
+				stem_probability: Some(stem_probability),
+			};
+		})
+		.unwrap();
+		assert!(net.wait_for(Duration::from_secs(30), |net| {
+			(0..net.len()).all(|n| !net.node(n).dandelion_epoch_expired())
+		}));
+		let outbound = (1..4)
+			.map(|n| net.peer_addr(0, n).unwrap())
+			.collect::<Vec<_>>();
+
+		let keychain = ExtKeychain::from_random_seed(false).unwrap();
+		let coinbases = (1..10)
+			.map(|i| ExtKeychain::derive_key_id(1, i, 0, 0, 0))
+			.collect::<Vec<_>>();
+		for key_id in &coinbases {
+			mine_reward_block(net.node(0), &keychain, key_id);
+		}
+		net.mine(0, global::coinbase_maturity());
+		net.assert_converged(Duration::from_secs(60));
+
+		// Stem txs pushed on nodes 4 and 5 go through node 0 as coming from
+		// them, the ones pushed on node 0 as coming from its API. Each source
+		// keeps going through the same relay.
+		let mut txs = coinbases.iter().enumerate().map(|(i, key_id)| {
+			let out_id = ExtKeychain::derive_key_id(2, i as u32, 0, 0, 0);
+			spend_coinbase(&keychain, key_id.clone(), out_id)
+		});
+		let mut paths = vec![];
+		for _ in 0..3 {
+			for &source in &[4, 5, 0] {
+				let tx = txs.next().unwrap();
+				let excess = tx.kernels()[0].excess;
+				net.node(source).push_tx(tx, true).unwrap();
+				let mut relay = None;
+				assert!(net.wait_for(Duration::from_secs(30), |net| {
+					relay =
+						(1..4).find(|&n| net.node(n).tx_status(&excess) == Some(TxStatus::Stem));
+					relay.is_some()
+				}));
+				paths.push((source, relay.unwrap()));
+			}
+		}
+		for &source in &[4, 5, 0] {
+			let relays = paths
+				.iter()
+				.filter(|(s, _)| *s == source)
+				.map(|(_, r)| *r)
+				.collect::<HashSet<_>>();
+			assert_eq!(relays.len(), 1);
+		}
+		let relays = paths.iter().map(|(_, r)| *r).collect::<HashSet<_>>();
+		assert!(relays.len() <= 2);
+		let local = paths.iter().find(|(s, _)| *s == 0).unwrap().1;
+		assert_eq!(
+			net.node(0).dandelion_relay("push-api"),
+			Some(outbound[local - 1])
+		);
+
+		let mut sources = (1..20).map(|i| format!("10.0.0.{}", i)).collect::<Vec<_>>();
+		sources.push("push-api".to_owned());
+		let routes = |net: &Network| {
+			sources
+				.iter()
+				.map(|s| net.node(0).dandelion_relay(s).unwrap())
+				.collect::<Vec<_>>()
+		};
+
+		let before = routes(&net);
+		for _ in 0..5 {
+			assert_eq!(routes(&net), before);
+		}
+		let relays = before.iter().cloned().collect::<HashSet<_>>();
+		assert_eq!(relays.len(), 2);
+		assert!(relays.iter().all(|r| outbound.contains(r)));
+
+		// Losing one of the relays only reroutes its own sources.
+		let lost = before[0];
+		let n = (1..4).find(|&n| outbound[n - 1] == lost).unwrap();
+		net.cut(0, n).unwrap();
+		let after = routes(&net);
+		let replacement = after[0];
+		assert!(replacement != lost);
+		assert!(outbound.contains(&replacement));
+		for (old, new) in before.iter().zip(after.iter()) {
+			if *old == lost {
+				assert_eq!(*new, replacement);
+			} else {
+				assert_eq!(new, old);
+				assert!(*new != replacement);
+			}
+		}
+		assert_eq!(routes(&net), after);
+		net.stop();
+	}
+	clean_output_dir(test_dir);
+}
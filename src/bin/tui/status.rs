@@ -15,6 +15,7 @@
 //! Basic status view definition
 
 use chrono::prelude::Utc;
+use chrono::Duration;
 use cursive::direction::Orientation;
 use cursive::traits::Identifiable;
 use cursive::view::View;
@@ -24,10 +25,12 @@ use cursive::Cursive;
 use crate::tui::constants::VIEW_BASIC_STATUS;
 use crate::tui::types::TUIStatusListener;
 
+use crate::servers::common::sync_progress::StageMetrics;
 use crate::servers::common::types::SyncStatus;
 use crate::servers::ServerStats;
 
 const NANO_TO_MILLIS: f64 = 1.0 / 1_000_000.0;
+const PROGRESS_BAR_WIDTH: u64 = 30;
 
 pub struct TUIStatusView;
 
@@ -41,11 +44,21 @@ impl TUIStatusListener for TUIStatusView {
 						.child(TextView::new("Current Status:               "))
 						.child(TextView::new("Starting").with_id("basic_current_status")),
 				)
+				.child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Sync Progress:                "))
+						.child(TextView::new("  ").with_id("basic_sync_progress")),
+				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
 						.child(TextView::new("Connected Peers:              "))
 						.child(TextView::new("0").with_id("connected_peers")),
 				)
+				.child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Network Alerts:               "))
+						.child(TextView::new("None").with_id("network_alerts")),
+				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal).child(TextView::new(
 						"------------------------------------------------",
@@ -241,9 +254,31 @@ impl TUIStatusListener for TUIStatusView {
 		c.call_on_id("basic_current_status", |t: &mut TextView| {
 			t.set_content(basic_status);
 		});
+		let sync_progress = stats
+			.sync_stages
+			.iter()
+			.find(|s| s.is_running())
+			.map(progress_bar)
+			.unwrap_or_else(|| "  ".to_string());
+		c.call_on_id("basic_sync_progress", |t: &mut TextView| {
+			t.set_content(sync_progress);
+		});
 		c.call_on_id("connected_peers", |t: &mut TextView| {
 			t.set_content(stats.peer_count.to_string());
 		});
+		let network_alerts = if stats.network_alerts.is_empty() {
+			"None".to_string()
+		} else {
+			stats
+				.network_alerts
+				.iter()
+				.map(|a| a.to_string())
+				.collect::<Vec<_>>()
+				.join("; ")
+		};
+		c.call_on_id("network_alerts", |t: &mut TextView| {
+			t.set_content(network_alerts);
+		});
 		c.call_on_id("tip_hash", |t: &mut TextView| {
 			t.set_content(stats.head.last_block_h.to_string() + "...");
 		});
@@ -273,3 +308,34 @@ impl TUIStatusListener for TUIStatusView {
 		});*/
 	}
 }
+
+/// Progress bar of a sync stage, with its rate and time left, along the lines
+/// of "[#########---------------------] 30% 215.3 headers/s, ETA 1h02m"
+fn progress_bar(stage: &StageMetrics) -> String {
+	let percent = stage.percent();
+	let done = (percent * PROGRESS_BAR_WIDTH / 100) as usize;
+	let eta = match stage.eta() {
+		Some(eta) => format_duration(eta),
+		None => "unknown".to_string(),
+	};
+	format!(
+		"[{}{}] {}% {:.1} {}/s, ETA {}",
+		"#".repeat(done),
+		"-".repeat(PROGRESS_BAR_WIDTH as usize - done),
+		percent,
+		stage.rate(),
+		stage.unit,
+		eta,
+	)
+}
+
+fn format_duration(d: Duration) -> String {
+	let secs = d.num_seconds();
+	if secs >= 3600 {
+		format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+	} else if secs >= 60 {
+		format!("{}m{:02}s", secs / 60, secs % 60)
+	} else {
+		format!("{}s", secs)
+	}
+}
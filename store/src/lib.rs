@@ -41,6 +41,13 @@ const SEP: u8 = b':';
 
 use byteorder::{BigEndian, WriteBytesExt};
 
+use crate::core::ser::ProtocolVersion;
+
+/// Serialization version of everything in our db and data files. Upgrading
+/// the p2p protocol version leaves it alone, changing it would require a
+/// migration of existing data. Version 2 is needed for onion peer addresses.
+pub const STORAGE_VERSION: ProtocolVersion = ProtocolVersion(2);
+
 pub use crate::lmdb::*;
 
 /// Build a db key from a prefix and a byte vector identifier.
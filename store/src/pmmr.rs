@@ -304,6 +304,44 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.flush()
 	}
 
+	/// Appends a leaf at the provided position along with its hash, when
+	/// rebuilding the MMR from segments. Spent leaves don't go in the leaf set.
+	pub fn append_leaf(
+		&mut self,
+		pos: u64,
+		data: &T::E,
+		hash: Hash,
+		spent: bool,
+	) -> io::Result<()> {
+		self.data_file.append(data)?;
+		self.hash_file.append(&hash)?;
+		if self.prunable && !spent {
+			self.leaf_set.add(pos);
+		}
+		Ok(())
+	}
+
+	/// Appends the hash of a parent node, when rebuilding the MMR from
+	/// segments.
+	pub fn append_hash(&mut self, hash: Hash) -> io::Result<()> {
+		self.hash_file.append(&hash)?;
+		Ok(())
+	}
+
+	/// Appends the root of a pruned subtree at the provided position, when
+	/// rebuilding the MMR from segments. Nothing beneath it is stored.
+	pub fn append_pruned_root(&mut self, pos: u64, hash: Hash) -> io::Result<()> {
+		self.hash_file.append(&hash)?;
+		self.prune_list.add(pos);
+		Ok(())
+	}
+
+	/// Saves the prune list, only needed after appending pruned roots as
+	/// compaction saves it otherwise.
+	pub fn sync_prune_list(&mut self) -> io::Result<()> {
+		self.prune_list.flush()
+	}
+
 	/// Discard the current, non synced state of the backend.
 	pub fn discard(&mut self) {
 		self.hash_file.discard();
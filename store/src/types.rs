@@ -18,6 +18,7 @@ use tempfile::tempfile;
 use crate::core::ser::{
 	self, BinWriter, FixedLength, Readable, Reader, StreamingReader, Writeable, Writer,
 };
+use crate::STORAGE_VERSION;
 use std::fmt::Debug;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
@@ -268,7 +269,8 @@ where
 
 	/// Append element to append-only file by serializing it to bytes and appending the bytes.
 	fn append_elmt(&mut self, data: &T) -> io::Result<()> {
-		let mut bytes = ser::ser_vec(data).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+		let mut bytes = ser::ser_vec_with_version(data, STORAGE_VERSION)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 		self.append(&mut bytes)?;
 		Ok(())
 	}
@@ -415,7 +417,8 @@ where
 
 	fn read_as_elmt(&self, pos: u64) -> io::Result<T> {
 		let data = self.read(pos)?;
-		ser::deserialize(&mut &data[..]).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+		ser::deserialize_with_version(&mut &data[..], STORAGE_VERSION)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
 	}
 
 	// Read length bytes starting at offset from the buffer.
@@ -470,11 +473,14 @@ where
 		{
 			let reader = File::open(&self.path)?;
 			let mut buf_reader = BufReader::new(reader);
-			let mut streaming_reader =
-				StreamingReader::new(&mut buf_reader, time::Duration::from_secs(1));
+			let mut streaming_reader = StreamingReader::new(
+				&mut buf_reader,
+				STORAGE_VERSION,
+				time::Duration::from_secs(1),
+			);
 
 			let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
-			let mut bin_writer = BinWriter::new(&mut buf_writer);
+			let mut bin_writer = BinWriter::new(&mut buf_writer, STORAGE_VERSION);
 
 			let mut current_pos = 0;
 			let mut prune_pos = prune_pos;
@@ -517,11 +523,14 @@ where
 			{
 				let reader = File::open(&self.path)?;
 				let mut buf_reader = BufReader::new(reader);
-				let mut streaming_reader =
-					StreamingReader::new(&mut buf_reader, time::Duration::from_secs(1));
+				let mut streaming_reader = StreamingReader::new(
+					&mut buf_reader,
+					STORAGE_VERSION,
+					time::Duration::from_secs(1),
+				);
 
 				let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
-				let mut bin_writer = BinWriter::new(&mut buf_writer);
+				let mut bin_writer = BinWriter::new(&mut buf_writer, STORAGE_VERSION);
 
 				let mut current_offset = 0;
 				while let Ok(_) = T::read(&mut streaming_reader) {
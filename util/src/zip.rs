@@ -25,7 +25,9 @@ use zip as zip_rs;
 
 /// Compress a source directory recursively into a zip file.
 /// Permissions are set to 644 by default to avoid any
-/// unwanted execution bits.
+/// unwanted execution bits. Entries are added in a fixed order with a fixed
+/// modification time so compressing the same content always produces the
+/// exact same archive.
 pub fn compress(src_dir: &Path, dst_file: &File) -> ZipResult<()> {
 	if !Path::new(src_dir).is_dir() {
 		return Err(ZipError::Io(io::Error::new(
@@ -36,10 +38,16 @@ pub fn compress(src_dir: &Path, dst_file: &File) -> ZipResult<()> {
 
 	let options = FileOptions::default()
 		.compression_method(zip_rs::CompressionMethod::Stored)
-		.unix_permissions(0o644);
+		.unix_permissions(0o644)
+		.last_modified_time(time::Tm {
+			tm_year: 80,
+			tm_mday: 1,
+			..time::empty_tm()
+		});
 
 	let mut zip = zip_rs::ZipWriter::new(dst_file);
-	let walkdir = WalkDir::new(src_dir.to_str().unwrap());
+	let walkdir =
+		WalkDir::new(src_dir.to_str().unwrap()).sort_by(|a, b| a.file_name().cmp(b.file_name()));
 	let it = walkdir.into_iter();
 
 	for dent in it.filter_map(|e| e.ok()) {